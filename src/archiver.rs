@@ -1,75 +1,3092 @@
-use crate::backup_logic::BackupMonth;
+use crate::backup_logic::{ArchiveKind, BackupMode, BackupPeriod};
+use crate::error::BackupError;
+use crate::file_scanner::{FileEntry, SymlinkPolicy, extended_length_path};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, Timelike, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use std::fs::{self, File};
-use std::io::{self, Read, Write};
-use std::path::{Path, PathBuf};
-use uuid::Uuid;
+use std::io::{self, BufReader, Cursor, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use zip::ZipArchive;
 use zip::write::{FileOptions, ZipWriter};
 
-/// 将文件列表归档到一个 ZIP 文件中
+/// 每个归档中清单文件的条目名
+pub const MANIFEST_NAME: &str = "MANIFEST.json";
+
+/// ZIP 不支持符号链接条目，保留的链接写为 `<链接名>.symlink` 文本文件，内容为链接目标
+pub const SYMLINK_PLACEHOLDER_SUFFIX: &str = ".symlink";
+
+/// 单个条目超过该大小时需要启用 Zip64 扩展
+const ZIP64_THRESHOLD: u64 = u32::MAX as u64;
+
+/// 默认以 Stored 方式写入的扩展名（这些格式本身已经压缩，再次压缩几乎没有收益）
+pub const DEFAULT_STORE_EXTENSIONS: &[&str] = &[
+    "dat", "silk", "amr", "mp3", "m4a", "aac", "mp4", "mov", "avi", "mkv", "jpg", "jpeg", "png",
+    "gif", "webp", "heic", "zip", "rar", "7z", "gz", "zst", "xz",
+];
+
+/// 归档文件格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ArchiveFormat {
+    /// A ZIP archive (default)
+    #[default]
+    Zip,
+    /// A tar archive compressed with gzip
+    TarGz,
+    /// A tar archive compressed with zstd
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// 该格式对应的文件扩展名（不含前导 `.`）
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+}
+
+/// 将 0-9 的压缩级别映射到 zstd 的 1-19 级
+fn zstd_level(compression_level: u32) -> i32 {
+    1 + 2 * compression_level.min(9) as i32
+}
+
+/// 创建归档时使用的选项
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveOptions<'a> {
+    /// 归档格式
+    pub format: ArchiveFormat,
+    /// 压缩级别 (0-9)，0 表示仅存储不压缩
+    pub compression_level: u32,
+    /// 可选的密码，提供时使用 AES-256 加密每个条目（仅 ZIP 支持）
+    pub password: Option<&'a str>,
+    /// 分卷大小上限（字节），`None` 表示不分卷
+    pub split_size: Option<u64>,
+    /// 以 Stored 方式写入的扩展名（小写、不含 `.`），为空表示不启用该启发式（仅 ZIP 生效）
+    pub store_extensions: Vec<String>,
+    /// 文件被占用（例如被微信独占锁定）时的重试次数
+    pub locked_file_retries: u32,
+    /// 每次重试前等待的基础时长，第 N 次重试等待 N 倍时长
+    pub retry_delay: Duration,
+    /// 追加模式：将同月最新归档中的条目合并进新归档，并替换旧归档（仅 ZIP 支持）
+    pub append: bool,
+    /// 可复现模式：条目按路径排序、时间戳固定为文件自身的修改时间，
+    /// 文件名使用源文件最新修改时间和内容摘要，相同输入生成逐字节相同的归档
+    pub reproducible: bool,
+    /// 写完后、重命名为最终文件名之前校验每个分卷的完整性
+    pub verify: bool,
+    /// 创建归档的时刻，用于文件名中的时间戳；`None` 时取当前时间
+    pub now: Option<DateTime<Utc>>,
+    /// 同时在工作线程中并行压缩的文件总大小上限（字节），0 表示不并行压缩（仅 ZIP 生效）
+    ///
+    /// 并行压缩的条目先压缩到内存中，因此该值也是额外内存占用的上限；
+    /// 超过上限的单个文件仍然流式写入。
+    pub max_parallel_bytes: u64,
+    /// 产生本次备份的模式，记录在归档元数据中
+    pub backup_mode: Option<BackupMode>,
+    /// 本次备份的增量截止时间（只归档此后修改的文件），记录在归档元数据中
+    pub last_backup_time: Option<DateTime<Utc>>,
+    /// 下次增量备份的截止时间（扫描开始的时间），记录在归档元数据中，供 --rebuild-cache 使用
+    pub next_cutoff: Option<DateTime<Utc>>,
+    /// 划分月份使用的时区（--month-boundary-tz），记录在归档元数据中
+    pub month_boundary_tz: Option<String>,
+    /// 归档类型，记录在清单中；`None` 表示独立归档（不参与全量/增量链）
+    pub kind: Option<ArchiveKind>,
+    /// 增量归档所基于的全量归档文件名，记录在清单中
+    pub base_archive: Option<String>,
+    /// 按顶层目录拆分时的分组名（例如微信账号 `wxid_*`），写入归档文件名：
+    /// `2025-07_wxid_abc123_backup_<时间戳>.zip`
+    pub group: Option<String>,
+    /// 严格模式：任何文件因被占用或无法读取而被跳过时整个归档失败，不生成归档
+    pub fail_on_skip: bool,
+    /// 符号链接的处理方式；为 `Preserve` 时写入链接本身，否则读取链接指向的内容
+    pub symlinks: SymlinkPolicy,
+    /// 除 `base_source_path` 外的其他源目录（重复的 --from），记录在备份元数据中
+    pub extra_source_paths: Vec<PathBuf>,
+    /// 多个源共享目标目录时写入归档文件名的源标记（见 [`source_tag`]）；
+    /// 追加模式只合并带有相同标记的归档
+    pub source: Option<String>,
+}
+
+/// 因无法读取而被跳过的文件
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// 一次归档的结果统计
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct ArchiveStats {
+    /// 创建的所有归档文件（分卷）的路径
+    pub archive_paths: Vec<PathBuf>,
+    /// 未经压缩直接存储的文件数
+    pub stored_files: usize,
+    /// 经过压缩写入的文件数
+    pub compressed_files: usize,
+    /// 被占用（重试后仍无法打开）或读取出错而被跳过的文件
+    pub skipped_files: Vec<SkippedFile>,
+    /// 扫描之后、归档之前被删除而跳过的文件数
+    pub vanished_files: usize,
+    /// 写入归档的源文件总字节数（未压缩）
+    pub uncompressed_bytes: u64,
+    /// 生成的所有归档文件（分卷）的总字节数
+    pub compressed_bytes: u64,
+    /// 使用 age 加密后所有归档文件的总字节数，由调用方在加密后填写；未加密时为 `None`
+    pub encrypted_bytes: Option<u64>,
+    /// 创建归档所用的时间
+    #[serde(rename = "ElapsedSeconds", serialize_with = "serialize_seconds")]
+    pub elapsed: Duration,
+    /// 本次写入的文件的清单记录（不含追加模式下从旧归档保留的条目）；
+    /// 已经写入归档中的清单，序列化时省略
+    #[serde(skip)]
+    pub archived_entries: Vec<ManifestEntry>,
+}
+
+/// 以秒为单位序列化时长，例如 `1.25`
+fn serialize_seconds<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl ArchiveStats {
+    /// 写入归档的文件总数
+    pub fn archived_files(&self) -> usize {
+        self.stored_files + self.compressed_files
+    }
+
+    /// 未能写入归档的文件总数（被占用、无法读取或已被删除）
+    pub fn skipped_count(&self) -> usize {
+        self.skipped_files.len() + self.vanished_files
+    }
+}
+
+/// 归档过程中的进度快照
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveProgress<'p> {
+    /// 已处理（写入或跳过）的文件数
+    pub files_done: usize,
+    /// 需要归档的文件总数
+    pub files_total: usize,
+    /// 已写入的源文件字节数
+    pub bytes_done: u64,
+    /// 需要归档的源文件总字节数（扫描时的大小）
+    pub bytes_total: u64,
+    /// 当前正在处理的文件
+    pub current_file: &'p Path,
+}
+
+/// 归档过程中通过回调报告给调用方的事件
+///
+/// 归档器本身不直接输出任何内容，由调用方决定如何展示（或在静默模式下忽略）。
+#[derive(Debug, Clone)]
+pub enum ArchiveEvent<'p> {
+    /// 进度更新，在每次读取数据块和每个文件处理完毕后触发
+    Progress(ArchiveProgress<'p>),
+    /// 不影响归档继续进行的警告
+    Warning(String),
+}
+
+/// 清单中单个文件的记录
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ManifestEntry {
+    /// 归档内的相对路径（`/` 分隔）
+    pub path: String,
+    /// 文件大小（字节）
+    pub size: u64,
+    /// 源文件的修改时间
+    pub modified: Option<DateTime<Utc>>,
+    /// 源文件内容的 SHA-256（小写十六进制）
+    pub sha256: String,
+}
+
+/// 写入每个归档末尾的 `MANIFEST.json`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+    /// 归档类型，独立归档（及旧版本创建的归档）中没有该字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<ArchiveKind>,
+    /// 增量归档所基于的全量归档文件名（与本归档位于同一目录）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_archive: Option<String>,
+}
+
+/// 以 JSON 形式写入 ZIP 归档注释的备份元数据，用于追溯归档的来源
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ArchiveInfo {
+    /// 创建归档的工具版本
+    pub tool_version: String,
+    /// 源目录 (--from)；有多个源目录时为第一个
+    pub source_path: String,
+    /// 其余的源目录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_source_paths: Vec<String>,
+    /// 备份的月份，例如 `2025-07`
+    pub backup_month: String,
+    /// 产生本次备份的模式
+    pub backup_mode: Option<String>,
+    /// 增量截止时间，只有此后修改的文件被归档
+    pub last_backup_time: Option<DateTime<Utc>>,
+    /// 下次增量备份的截止时间，即本次扫描开始的时间；旧版本的归档和可复现模式的归档没有该字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cutoff: Option<DateTime<Utc>>,
+    /// 划分月份使用的时区，例如 `local` 或 `Asia/Shanghai`；旧版本的归档没有该字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub month_boundary_tz: Option<String>,
+    /// 归档（分卷）中的文件数
+    pub file_count: usize,
+    /// 创建时间；可复现模式下为源文件的最新修改时间
+    pub created_at: DateTime<Utc>,
+}
+
+/// 读取 ZIP 归档注释中的备份元数据
+///
+/// 归档没有注释或注释不是元数据（例如由旧版本创建）时返回 `None`。
+/// 读取失败时返回 `io::Error` 而不是 [`BackupError`]：读取已有的归档不属于备份过程，
+/// list、verify 等调用方自己加上归档名报告。
+pub fn read_archive_info(archive_path: &Path) -> io::Result<Option<ArchiveInfo>> {
+    let archive = ZipArchive::new(BufReader::new(File::open(archive_path)?))?;
+    Ok(serde_json::from_slice(archive.comment()).ok())
+}
+
+/// 读取归档内的 `MANIFEST.json`；没有清单的归档（旧版本创建）返回空的清单
+///
+/// tar 归档的清单位于末尾，需要解压整个归档才能读到。
+/// 与 [`read_archive_info`] 一样返回 `io::Error`，由恢复、校验和比较报告。
+pub fn read_manifest(
+    archive_path: &Path,
+    format: ArchiveFormat,
+    password: Option<&str>,
+) -> io::Result<Manifest> {
+    let file = BufReader::new(File::open(archive_path)?);
+    match format {
+        ArchiveFormat::Zip => {
+            let mut archive = ZipArchive::new(file)?;
+            let entry = match password {
+                Some(password) => archive.by_name_decrypt(MANIFEST_NAME, password.as_bytes()),
+                None => archive.by_name(MANIFEST_NAME),
+            };
+            match entry {
+                Ok(entry) => parse_manifest(entry),
+                Err(zip::result::ZipError::FileNotFound) => Ok(Manifest::default()),
+                Err(e) => Err(e.into()),
+            }
+        }
+        ArchiveFormat::TarGz => tar_manifest(flate2::read::GzDecoder::new(file)),
+        ArchiveFormat::TarZst => tar_manifest(zstd::Decoder::with_buffer(file)?),
+    }
+}
+
+fn tar_manifest<R: Read>(stream: R) -> io::Result<Manifest> {
+    for entry in tar::Archive::new(stream).entries()? {
+        let entry = entry?;
+        if entry.path()?.as_os_str() == MANIFEST_NAME {
+            return parse_manifest(entry);
+        }
+    }
+    Ok(Manifest::default())
+}
+
+fn parse_manifest<R: Read>(reader: R) -> io::Result<Manifest> {
+    serde_json::from_reader(reader).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// 条目的元数据，由调用方预先从源文件读取
+#[derive(Debug, Clone, Copy)]
+struct EntryMeta {
+    size: u64,
+    mode: Option<u32>,
+    modified: Option<DateTime<Utc>>,
+}
+
+impl EntryMeta {
+    fn from_metadata(metadata: &fs::Metadata) -> Self {
+        EntryMeta {
+            size: metadata.len(),
+            mode: file_mode(metadata),
+            modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+        }
+    }
+}
+
+/// 在读取的同时计算 SHA-256，保证摘要与写入归档的字节完全一致
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    pub(crate) fn hex_digest(self) -> String {
+        self.hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// 符号链接条目在清单中记录的摘要：链接目标路径的 SHA-256
+pub(crate) fn link_digest(target: &str) -> String {
+    let mut reader = HashingReader::new(target.as_bytes());
+    io::copy(&mut reader, &mut io::sink()).expect("reading from memory cannot fail");
+    reader.hex_digest()
+}
+
+/// 每读取一个数据块就把读取的字节数报告给回调
+///
+/// 同时记录读取是否出错，用于区分源文件的读取错误和写入归档时的错误
+struct ProgressReader<'p, R> {
+    inner: R,
+    on_read: &'p mut dyn FnMut(u64),
+    failed: bool,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf).inspect_err(|_| self.failed = true)?;
+        if n > 0 {
+            (self.on_read)(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+/// 条目实际使用的写入方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryMethod {
+    Stored,
+    Compressed,
+}
+
+/// 为归档过程中的错误附加出错文件的路径，方便定位问题
+fn archive_error(file_path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(
+        err.kind(),
+        format!("Failed to archive '{}': {}", file_path.display(), err),
+    )
+}
+
+/// 判断错误是否由文件被其他进程占用引起
+///
+/// Windows 上对应 ERROR_SHARING_VIOLATION (32) 和 ERROR_LOCK_VIOLATION (33)，
+/// 另外权限被拒绝也可能是锁定造成的，同样视为可重试。
+fn is_lock_error(err: &io::Error) -> bool {
+    if err.kind() == io::ErrorKind::PermissionDenied {
+        return true;
+    }
+    cfg!(windows) && matches!(err.raw_os_error(), Some(32) | Some(33))
+}
+
+/// 执行 `op`，在遇到锁定类错误时最多重试 `retries` 次，每次等待时间线性递增
+fn retry_on_lock<T>(
+    retries: u32,
+    delay: Duration,
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(e) if attempt < retries && is_lock_error(&e) => {
+                attempt += 1;
+                thread::sleep(delay * attempt);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// 将相对路径转换为归档条目名（统一使用 `/` 分隔）
+///
+/// 扫描时已跳过文件名不是合法 Unicode 的文件，这里的有损转换只作兜底。
+/// 含非 ASCII 字符的条目名由 zip 写入时设置 UTF-8 标志（通用标志位 11），
+/// 解压工具据此按 UTF-8 而不是本地代码页解读中文文件名。
+pub fn entry_name(relative_path: &Path) -> String {
+    relative_path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// 将时间转换为 ZIP 条目时间（UTC）；早于 1980 年或缺失时使用 ZIP 的最早时间
+fn zip_datetime(time: Option<DateTime<Utc>>) -> zip::DateTime {
+    time.and_then(|t| {
+        zip::DateTime::from_date_and_time(
+            u16::try_from(t.year()).ok()?,
+            t.month() as u8,
+            t.day() as u8,
+            t.hour() as u8,
+            t.minute() as u8,
+            t.second() as u8,
+        )
+        .ok()
+    })
+    .unwrap_or_default()
+}
+
+/// 读取文件的 Unix 权限位；非 Unix 平台返回 `None`
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// 不同归档格式的统一写入接口
+trait ArchiveWriter {
+    /// 添加一个目录条目
+    fn add_directory(&mut self, name: &str) -> io::Result<()>;
+
+    /// 添加一个文件条目，内容从 `reader` 中读取，返回实际使用的写入方式
+    fn add_file(
+        &mut self,
+        name: &str,
+        reader: &mut dyn Read,
+        meta: &EntryMeta,
+    ) -> io::Result<EntryMethod>;
+
+    /// 添加一个符号链接条目，返回实际使用的条目名
+    ///
+    /// 默认写入内容为链接目标的占位文本文件 `<name>.symlink`，供不支持符号链接的格式使用。
+    fn add_symlink(&mut self, name: &str, target: &str, meta: &EntryMeta) -> io::Result<String> {
+        let placeholder = format!("{}{}", name, SYMLINK_PLACEHOLDER_SUFFIX);
+        self.add_file(&placeholder, &mut target.as_bytes(), meta)?;
+        Ok(placeholder)
+    }
+
+    /// 丢弃写入到一半的文件条目，使归档可以继续写入后续条目
+    fn abort_file(&mut self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Skipping a partially written entry is only supported for zip archives",
+        ))
+    }
+
+    /// 添加一个已在工作线程中压缩好的条目
+    fn add_prepared(&mut self, _entry: PreparedEntry) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Parallel compression is only supported for zip archives",
+        ))
+    }
+
+    /// 从已有归档中原样复制（不重新压缩）名称不在 `written` 中的条目，
+    /// 返回旧清单中被保留下来的记录
+    fn copy_entries_from(
+        &mut self,
+        _source: &Path,
+        _written: &HashSet<String>,
+    ) -> io::Result<Vec<ManifestEntry>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Appending is only supported for zip archives",
+        ))
+    }
+
+    /// 记录归档的备份元数据；不支持归档注释的格式忽略
+    fn set_info(&mut self, _info: &ArchiveInfo) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// 写入归档尾部并关闭文件
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// ZIP 条目的写入选项，不持有输出文件，可在工作线程之间共享
+#[derive(Clone)]
+struct ZipEntryEncoder<'a> {
+    options: FileOptions<'a, ()>,
+    /// 已压缩格式文件使用的选项
+    stored_options: FileOptions<'a, ()>,
+    store_extensions: Vec<String>,
+    /// 未命中扩展名列表时的写入方式
+    default_method: EntryMethod,
+    reproducible: bool,
+}
+
+impl<'a> ZipEntryEncoder<'a> {
+    fn new(options: &ArchiveOptions<'a>) -> Self {
+        let mut file_options: FileOptions<()> = if options.compression_level == 0 {
+            FileOptions::default().compression_method(zip::CompressionMethod::Stored)
+        } else {
+            FileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated)
+                .compression_level(Some(options.compression_level as i64))
+        };
+        let mut stored_options: FileOptions<()> =
+            FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        if let Some(password) = options.password {
+            file_options = file_options.with_aes_encryption(zip::AesMode::Aes256, password);
+            stored_options = stored_options.with_aes_encryption(zip::AesMode::Aes256, password);
+        }
+        ZipEntryEncoder {
+            options: file_options,
+            stored_options,
+            store_extensions: options.store_extensions.clone(),
+            default_method: if options.compression_level == 0 {
+                EntryMethod::Stored
+            } else {
+                EntryMethod::Compressed
+            },
+            reproducible: options.reproducible,
+        }
+    }
+
+    /// 判断该条目是否应当不经压缩直接存储
+    fn should_store(&self, name: &str) -> bool {
+        Path::new(name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.store_extensions.contains(&ext.to_ascii_lowercase()))
+    }
+
+    /// 判断该条目是否会被压缩，只有这类条目才值得交给工作线程
+    fn compresses(&self, name: &str) -> bool {
+        self.default_method == EntryMethod::Compressed && !self.should_store(name)
+    }
+
+    fn add_directory<W: Write + Seek>(&self, zip: &mut ZipWriter<W>, name: &str) -> io::Result<()> {
+        let mut options = self.options;
+        if self.reproducible {
+            options = options.last_modified_time(zip::DateTime::default());
+        }
+        zip.add_directory(name, options)?;
+        Ok(())
+    }
+
+    fn add_file<W: Write + Seek>(
+        &self,
+        zip: &mut ZipWriter<W>,
+        name: &str,
+        reader: &mut dyn Read,
+        meta: &EntryMeta,
+    ) -> io::Result<EntryMethod> {
+        let (base_options, method) = if self.should_store(name) {
+            (self.stored_options, EntryMethod::Stored)
+        } else {
+            (self.options, self.default_method)
+        };
+
+        // 超过 4 GB 的文件需要 Zip64 条目；整个归档的 Zip64 目录由 zip crate 自动处理
+        let mut options = base_options.large_file(meta.size >= ZIP64_THRESHOLD);
+        if let Some(mode) = meta.mode {
+            options = options.unix_permissions(mode);
+        }
+        if meta.modified.is_some() || self.reproducible {
+            options = options.last_modified_time(zip_datetime(meta.modified));
+        }
+        log::trace!("Archiving {} ({} bytes, {:?})", name, meta.size, method);
+        zip.start_file(name, options)?;
+        copy_chunked(reader, zip)?;
+        Ok(method)
+    }
+
+    /// 在内存中把单个文件压缩成只含一个条目的 ZIP，之后由写入器原样复制进归档
+    fn prepare_file(&self, name: &str, file: File, meta: &EntryMeta) -> io::Result<PreparedEntry> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let mut reader = HashingReader::new(file);
+        let method = self.add_file(&mut zip, name, &mut reader, meta)?;
+        let data = zip.finish()?.into_inner();
+        Ok(PreparedEntry {
+            data,
+            method,
+            sha256: reader.hex_digest(),
+        })
+    }
+}
+
+/// 已在工作线程中压缩完成、等待写入归档的条目
+struct PreparedEntry {
+    /// 只含该条目的完整 ZIP 数据
+    data: Vec<u8>,
+    method: EntryMethod,
+    sha256: String,
+}
+
+/// ZIP 格式的写入器
+struct ZipArchiveWriter<'a> {
+    zip: ZipWriter<File>,
+    encoder: ZipEntryEncoder<'a>,
+    password: Option<&'a str>,
+}
+
+impl<'a> ZipArchiveWriter<'a> {
+    fn new(file: File, options: &ArchiveOptions<'a>) -> Self {
+        ZipArchiveWriter {
+            zip: ZipWriter::new(file),
+            encoder: ZipEntryEncoder::new(options),
+            password: options.password,
+        }
+    }
+}
+
+impl ArchiveWriter for ZipArchiveWriter<'_> {
+    fn add_directory(&mut self, name: &str) -> io::Result<()> {
+        self.encoder.add_directory(&mut self.zip, name)
+    }
+
+    fn add_file(
+        &mut self,
+        name: &str,
+        reader: &mut dyn Read,
+        meta: &EntryMeta,
+    ) -> io::Result<EntryMethod> {
+        self.encoder.add_file(&mut self.zip, name, reader, meta)
+    }
+
+    fn abort_file(&mut self) -> io::Result<()> {
+        Ok(self.zip.abort_file()?)
+    }
+
+    fn add_prepared(&mut self, entry: PreparedEntry) -> io::Result<()> {
+        let mut archive = ZipArchive::new(Cursor::new(entry.data))?;
+        self.zip.raw_copy_file(archive.by_index_raw(0)?)?;
+        Ok(())
+    }
+
+    fn copy_entries_from(
+        &mut self,
+        source: &Path,
+        written: &HashSet<String>,
+    ) -> io::Result<Vec<ManifestEntry>> {
+        let mut archive = ZipArchive::new(BufReader::new(File::open(source)?))?;
+
+        // 读取旧清单（如果存在），保留未被新文件覆盖的记录
+        let old_manifest: Option<Manifest> = match self.password {
+            Some(password) => archive
+                .by_name_decrypt(MANIFEST_NAME, password.as_bytes())
+                .ok()
+                .and_then(|f| serde_json::from_reader(f).ok()),
+            None => archive
+                .by_name(MANIFEST_NAME)
+                .ok()
+                .and_then(|f| serde_json::from_reader(f).ok()),
+        };
+        let kept = old_manifest
+            .map(|m| m.entries)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|entry| !written.contains(&entry.path))
+            .collect();
+
+        for i in 0..archive.len() {
+            let entry = archive.by_index_raw(i)?;
+            let name = entry.name().trim_end_matches('/');
+            if name == MANIFEST_NAME || written.contains(name) {
+                continue;
+            }
+            self.zip.raw_copy_file(entry)?;
+        }
+        Ok(kept)
+    }
+
+    fn set_info(&mut self, info: &ArchiveInfo) -> io::Result<()> {
+        let comment = serde_json::to_string(info)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.zip.set_comment(comment);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.zip.finish()?;
+        Ok(())
+    }
+}
+
+/// tar 归档外层的压缩流，需要在结束时显式写入尾部
+trait CompressedStream: Write {
+    fn finish_stream(self) -> io::Result<()>;
+}
+
+impl CompressedStream for GzEncoder<File> {
+    fn finish_stream(self) -> io::Result<()> {
+        self.finish()?.sync_all()
+    }
+}
+
+impl CompressedStream for zstd::Encoder<'static, File> {
+    fn finish_stream(self) -> io::Result<()> {
+        self.finish()?.sync_all()
+    }
+}
+
+/// 只读取 `expected` 字节的读取器；来源提前结束（文件在归档期间缩短）时返回
+/// [`io::ErrorKind::UnexpectedEof`]，而不是写出比头部声明更短的 tar 条目，
+/// 那样之后的所有条目都会错位。条目已部分写入，无法跳过，整个归档失败
+struct ExactReader<'a> {
+    name: &'a str,
+    reader: &'a mut dyn Read,
+    expected: u64,
+    remaining: u64,
+}
+
+impl Read for ExactReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let n = self.reader.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "'{}' shrank from {} to {} bytes while it was archived",
+                    self.name,
+                    self.expected,
+                    self.expected - self.remaining
+                ),
+            ));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// tar 格式的写入器，外层压缩方式由 `W` 决定
+struct TarArchiveWriter<W: CompressedStream> {
+    builder: tar::Builder<W>,
+    /// 没有修改时间的条目（目录、清单）使用的时间戳
+    default_mtime: u64,
+}
+
+impl<W: CompressedStream> TarArchiveWriter<W> {
+    fn new(stream: W, default_mtime: u64) -> Self {
+        TarArchiveWriter {
+            builder: tar::Builder::new(stream),
+            default_mtime,
+        }
+    }
+
+    /// 条目头部记录的修改时间
+    fn entry_mtime(&self, meta: &EntryMeta) -> u64 {
+        match meta.modified {
+            Some(modified) => modified.timestamp().max(0) as u64,
+            None => self.default_mtime,
+        }
+    }
+}
+
+impl<W: CompressedStream> ArchiveWriter for TarArchiveWriter<W> {
+    fn add_directory(&mut self, name: &str) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        header.set_mtime(self.default_mtime);
+        self.builder
+            .append_data(&mut header, format!("{}/", name), io::empty())
+    }
+
+    fn add_file(
+        &mut self,
+        name: &str,
+        reader: &mut dyn Read,
+        meta: &EntryMeta,
+    ) -> io::Result<EntryMethod> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(meta.size);
+        header.set_mode(meta.mode.unwrap_or(0o644));
+        header.set_mtime(self.entry_mtime(meta));
+        // 只读取头部声明的长度，防止文件在归档期间增长导致 tar 结构损坏
+        let content = ExactReader {
+            name,
+            reader,
+            expected: meta.size,
+            remaining: meta.size,
+        };
+        self.builder.append_data(&mut header, name, content)?;
+        Ok(EntryMethod::Compressed)
+    }
+
+    fn add_symlink(&mut self, name: &str, target: &str, meta: &EntryMeta) -> io::Result<String> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(meta.mode.unwrap_or(0o777));
+        header.set_mtime(self.entry_mtime(meta));
+        self.builder.append_link(&mut header, name, target)?;
+        Ok(name.to_string())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.builder.into_inner()?.finish_stream()
+    }
+}
+
+/// 流式写入时每次读取的块大小，峰值内存与文件大小无关
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 以固定大小的块将 `reader` 的内容复制到 `writer`，返回复制的字节数
+fn copy_chunked<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+) -> io::Result<u64> {
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    let mut total = 0u64;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(total),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        debug_assert!(n <= COPY_BUFFER_SIZE);
+        writer.write_all(&buffer[..n])?;
+        total += n as u64;
+    }
+}
+
+/// 分卷模式下为每个条目预估的额外开销（文件头、目录记录等）
+const ENTRY_OVERHEAD: u64 = 1024;
+
+/// 将清单作为最后一个条目写入、记录备份元数据，然后结束当前归档
+///
+/// 返回清单内容的 SHA-256，可复现模式下用于生成文件名
+fn finish_with_manifest(
+    mut writer: Box<dyn ArchiveWriter + '_>,
+    manifest: &Manifest,
+    info: &ArchiveInfo,
+    modified: Option<DateTime<Utc>>,
+) -> io::Result<String> {
+    let content = serde_json::to_vec_pretty(manifest)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let meta = EntryMeta {
+        size: content.len() as u64,
+        mode: Some(0o644),
+        modified,
+    };
+    let mut reader = HashingReader::new(content.as_slice());
+    writer.add_file(MANIFEST_NAME, &mut reader, &meta)?;
+    writer.set_info(info)?;
+    writer.finish()?;
+    Ok(reader.hex_digest())
+}
+
+/// 归档文件名中 `_backup_` 之前的部分：月份（或周），以及按顶层目录拆分时的分组名
+fn archive_prefix(month: &BackupPeriod, group: Option<&str>) -> String {
+    match group {
+        Some(group) => format!("{}_{}", month.label(), group),
+        None => month.label(),
+    }
+}
+
+/// 被跳过文件列表的文件名后缀，追加在归档文件名之后
+pub const SKIP_REPORT_SUFFIX: &str = ".skipped_files.txt";
+
+/// 源标记的长度（十六进制位数）
+const SOURCE_TAG_LEN: usize = 8;
+
+/// 源标识（见 `CacheRecord::source_id`）在归档文件名中的标记：其 SHA-256 的前 8 位十六进制
+pub fn source_tag(source_id: &str) -> String {
+    let digest = Sha256::digest(source_id.as_bytes());
+    digest
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>()[..SOURCE_TAG_LEN]
+        .to_string()
+}
+
+/// 本工具生成的归档（及其旁边的被跳过文件列表）的文件名的各个部分
+///
+/// 完整形式为 `<月份或周>[_<分组>]_backup_<时间戳>[_<摘要>][@<源标记>][.part<N>].<扩展名>[.age][.skipped_files.txt]`，
+/// 例如 `2025-07_wxid_abc123_backup_20250801000000.part2.zip.age`。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveNameInfo {
+    /// 归档的数据月份 `YYYY-MM`，使用 --weekly 时为 ISO 周 `YYYY-Www`，
+    /// 使用 --since/--until 时为时间范围 `YYYY-MM-DD_to_YYYY-MM-DD`；
+    /// 只检查格式，不检查是否为有效的月份、周或日期
+    pub month: String,
+    /// 按顶层目录拆分时的分组名（账号）
+    pub group: Option<String>,
+    /// 创建时间戳 `YYYYMMDDHHMMSS`；同样只检查格式
+    pub timestamp: String,
+    /// 可复现模式在时间戳后追加的 16 位清单摘要
+    pub digest: Option<String>,
+    /// 多个源共享目标目录时区分各个源的 8 位源标记（见 [`source_tag`]）；
+    /// 最早备份到该目录的源的归档没有标记
+    pub source: Option<String>,
+    /// 分卷编号，从 1 开始
+    pub part: Option<usize>,
+    pub format: ArchiveFormat,
+    /// 是否为 age 加密后的归档（`.age` 后缀）
+    pub encrypted: bool,
+    /// 是否为归档旁的被跳过文件列表（`.skipped_files.txt` 后缀）
+    pub skip_report: bool,
+}
+
+impl ArchiveNameInfo {
+    /// 文件名中 `_backup_` 之前的部分
+    pub fn prefix(&self) -> String {
+        match &self.group {
+            Some(group) => format!("{}_{}", self.month, group),
+            None => self.month.clone(),
+        }
+    }
+
+    /// 同一系列的归档（同一月份、分组和源）共有的键：[`ArchiveNameInfo::prefix`]，
+    /// 带源标记时再加上 `@<源标记>`。清理、追加和恢复按系列区分归档
+    pub fn series(&self) -> String {
+        match &self.source {
+            Some(source) => format!("{}@{}", self.prefix(), source),
+            None => self.prefix(),
+        }
+    }
+
+    /// 是否为 --since/--until 按时间范围创建的归档
+    pub fn is_date_range(&self) -> bool {
+        self.month.contains("_to_")
+    }
+
+    /// 文件名中的创建时间；时间戳不是有效的日期时间时返回 `None`
+    pub fn created(&self) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(&self.timestamp, crate::cleaner::TIMESTAMP_FORMAT).ok()
+    }
+
+    /// 归档数据所属月份的第一天，每周的归档为该周的周一；
+    /// 时间范围的归档以及无效的月份或周返回 `None`
+    pub fn period_start(&self) -> Option<NaiveDate> {
+        BackupPeriod::from_label(&self.month).map(|period| period.date_range().0)
+    }
+
+    /// 按各个部分拼出文件名，扩展名总是小写
+    pub fn file_name(&self) -> String {
+        let mut name = format!("{}_backup_{}", self.prefix(), self.timestamp);
+        if let Some(digest) = &self.digest {
+            name.push('_');
+            name.push_str(digest);
+        }
+        if let Some(source) = &self.source {
+            name.push('@');
+            name.push_str(source);
+        }
+        if let Some(part) = self.part {
+            name.push_str(&format!(".part{}", part));
+        }
+        name.push('.');
+        name.push_str(self.format.extension());
+        if self.encrypted {
+            name.push_str(crate::encryption::ENCRYPTED_SUFFIX);
+        }
+        if self.skip_report {
+            name.push_str(SKIP_REPORT_SUFFIX);
+        }
+        name
+    }
+}
+
+/// 解析本工具生成的归档文件名；不是这种形式的文件名返回 `None`
+///
+/// 扩展名不区分大小写，例如复制到其他文件系统后变成 `.ZIP` 的归档也能识别。
+pub fn parse_archive_name(file_name: &str) -> Option<ArchiveNameInfo> {
+    let (prefix, rest) = file_name.rsplit_once("_backup_")?;
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+
+    // 月份（或 ISO 周、时间范围）及分组
+    let is_date = |s: &str| {
+        s.len() == 10
+            && s.bytes().enumerate().all(|(i, b)| match i {
+                4 | 7 => b == b'-',
+                _ => b.is_ascii_digit(),
+            })
+    };
+    let month_len = if prefix.get(10..14) == Some("_to_") {
+        24
+    } else if prefix.as_bytes().get(5) == Some(&b'W') {
+        8
+    } else {
+        7
+    };
+    let month = prefix.get(..month_len)?;
+    let bytes = month.as_bytes();
+    if month_len == 24 {
+        if !is_date(&month[..10]) || !is_date(&month[14..]) {
+            return None;
+        }
+    } else if !bytes[..4].iter().all(u8::is_ascii_digit)
+        || bytes[4] != b'-'
+        || !bytes[month_len - 2..].iter().all(u8::is_ascii_digit)
+    {
+        return None;
+    }
+    let group = match &prefix[month_len..] {
+        "" => None,
+        group => Some(
+            group
+                .strip_prefix('_')
+                .filter(|g| !g.is_empty())?
+                .to_string(),
+        ),
+    };
+
+    // 时间戳及可复现模式的摘要
+    let timestamp = rest.get(..14).filter(|ts| is_digits(ts))?;
+    let mut rest = &rest[14..];
+    let mut digest = None;
+    if let Some(after) = rest.strip_prefix('_') {
+        let hex = after
+            .get(..16)
+            .filter(|hex| hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')))?;
+        digest = Some(hex.to_string());
+        rest = &after[16..];
+    }
+    let mut source = None;
+    if let Some(after) = rest.strip_prefix('@') {
+        let hex = after
+            .get(..SOURCE_TAG_LEN)
+            .filter(|hex| hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')))?;
+        source = Some(hex.to_string());
+        rest = &after[SOURCE_TAG_LEN..];
+    }
+
+    // 其余部分为后缀，不区分大小写
+    let suffixes = rest.to_ascii_lowercase();
+    let mut rest = suffixes.as_str();
+    let mut part = None;
+    if let Some(after) = rest.strip_prefix(".part") {
+        let digits = after.find('.').map_or(after, |end| &after[..end]);
+        part = Some(digits.parse::<usize>().ok().filter(|_| is_digits(digits))?);
+        rest = &after[digits.len()..];
+    }
+    let format = [
+        ArchiveFormat::Zip,
+        ArchiveFormat::TarGz,
+        ArchiveFormat::TarZst,
+    ]
+    .into_iter()
+    .find(|format| {
+        rest.strip_prefix('.')
+            .and_then(|r| r.strip_prefix(format.extension()))
+            .is_some_and(|r| r.is_empty() || r.starts_with('.'))
+    })?;
+    rest = &rest[1 + format.extension().len()..];
+    let encrypted = match rest.strip_prefix(crate::encryption::ENCRYPTED_SUFFIX) {
+        Some(after) => {
+            rest = after;
+            true
+        }
+        None => false,
+    };
+    let skip_report = match rest {
+        "" => false,
+        SKIP_REPORT_SUFFIX => true,
+        _ => return None,
+    };
+
+    Some(ArchiveNameInfo {
+        month: month.to_string(),
+        group,
+        timestamp: timestamp.to_string(),
+        digest,
+        source,
+        part,
+        format,
+        encrypted,
+        skip_report,
+    })
+}
+
+/// 目录中由本工具创建的归档（不含被跳过文件列表，不进入子目录），按创建时间排序，
+/// 同一时间创建的按分卷编号和文件名排序
+///
+/// 返回 `io::Result`，供同样返回 `io::Result` 的恢复和比较直接使用；
+/// 备份时找不到的归档只是不被续写或重用，不作为错误。
+pub fn find_archives(directory: &Path) -> io::Result<Vec<(PathBuf, ArchiveNameInfo)>> {
+    let mut archives = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if let Some(info) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_archive_name)
+            && !info.skip_report
+            && path.is_file()
+        {
+            archives.push((path, info));
+        }
+    }
+    archives.sort_by(|(a_path, a), (b_path, b)| {
+        (&a.timestamp, a.part, a_path).cmp(&(&b.timestamp, b.part, b_path))
+    });
+    Ok(archives)
+}
+
+/// 同一次备份创建的一个归档：未分卷时只有一个文件，分卷时为按编号排列的各个分卷
+#[derive(Debug, Clone)]
+pub struct ArchiveSet {
+    /// 第一个文件的名称信息
+    pub info: ArchiveNameInfo,
+    pub paths: Vec<PathBuf>,
+    /// 各个文件的大小之和
+    pub size: u64,
+}
+
+impl ArchiveSet {
+    /// 只读取 ZIP 的中央目录得到的条目数，各分卷相加；
+    /// tar 归档需要解压才能计数，age 加密的归档无法读取，均返回 `None`；
+    /// 打不开的分卷返回 `io::Error`，list 把它显示在该归档的行中
+    pub fn entry_count(&self) -> io::Result<Option<usize>> {
+        if self.info.format != ArchiveFormat::Zip || self.info.encrypted {
+            return Ok(None);
+        }
+        let mut entries = 0;
+        for path in &self.paths {
+            let archive = ZipArchive::new(BufReader::new(File::open(path)?))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            entries += archive.len();
+        }
+        Ok(Some(entries))
+    }
+}
+
+/// 将 [`find_archives`] 找到的文件按归档分组：同一系列、时间戳、格式和加密方式的分卷属于同一个归档
+///
+/// 结果仍按创建时间排序。
+pub fn group_archive_sets(archives: Vec<(PathBuf, ArchiveNameInfo)>) -> Vec<ArchiveSet> {
+    let mut sets: Vec<ArchiveSet> = Vec::new();
+    for (path, info) in archives {
+        let size = fs::metadata(&path).map_or(0, |m| m.len());
+        let same_archive = |set: &&mut ArchiveSet| {
+            set.info.series() == info.series()
+                && set.info.timestamp == info.timestamp
+                && set.info.digest == info.digest
+                && set.info.format == info.format
+                && set.info.encrypted == info.encrypted
+                && set.info.part.is_some()
+                && info.part.is_some()
+        };
+        match sets.iter_mut().rev().find(same_archive) {
+            Some(set) => {
+                set.paths.push(path);
+                set.size += size;
+            }
+            None => sets.push(ArchiveSet {
+                info,
+                paths: vec![path],
+                size,
+            }),
+        }
+    }
+    sets
+}
+
+/// 查找目标目录中指定月份（及分组、源标记）最新的（非分卷）归档，用于追加模式
+fn find_latest_archive(
+    destination_path: &Path,
+    month: &BackupPeriod,
+    group: Option<&str>,
+    source: Option<&str>,
+    format: ArchiveFormat,
+) -> io::Result<Option<PathBuf>> {
+    let prefix = archive_prefix(month, group);
+
+    let mut latest: Option<(String, PathBuf)> = None;
+    for entry in fs::read_dir(destination_path)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let Some(info) = parse_archive_name(file_name) else {
+            continue;
+        };
+        // 只有未分卷、未加密、非可复现命名的归档可以作为追加的基础归档；
+        // 因此 --append 不能与 --encrypt-to、--reproducible 一起使用
+        if info.prefix() == prefix
+            && info.source.as_deref() == source
+            && info.format == format
+            && info.part.is_none()
+            && info.digest.is_none()
+            && !info.encrypted
+            && !info.skip_report
+        {
+            let ts = info.timestamp;
+            if latest.as_ref().is_none_or(|(latest_ts, _)| ts > *latest_ts) {
+                latest = Some((ts, path));
+            }
+        }
+    }
+    Ok(latest.map(|(_, path)| path))
+}
+
+/// 按选项的格式在指定路径创建一个归档写入器
+///
+/// `now` 为可复现模式之外没有修改时间的 tar 条目使用的时间戳。
+fn open_writer<'a>(
+    archive_path: &Path,
+    options: &ArchiveOptions<'a>,
+    now: DateTime<Utc>,
+) -> io::Result<Box<dyn ArchiveWriter + 'a>> {
+    let archive_file = File::create(extended_length_path(archive_path))?;
+    let default_mtime = if options.reproducible {
+        0
+    } else {
+        now.timestamp().max(0) as u64
+    };
+    let writer: Box<dyn ArchiveWriter + 'a> = match options.format {
+        ArchiveFormat::Zip => Box::new(ZipArchiveWriter::new(archive_file, options)),
+        ArchiveFormat::TarGz => {
+            // gzip 头部的 mtime 默认为 0，不会破坏可复现性
+            let level = Compression::new(options.compression_level);
+            let encoder = GzEncoder::new(archive_file, level);
+            Box::new(TarArchiveWriter::new(encoder, default_mtime))
+        }
+        ArchiveFormat::TarZst => {
+            let mut encoder =
+                zstd::Encoder::new(archive_file, zstd_level(options.compression_level))?;
+            // 写入内容校验和，便于创建后校验完整性
+            encoder.include_checksum(true)?;
+            Box::new(TarArchiveWriter::new(encoder, default_mtime))
+        }
+    };
+    Ok(writer)
+}
+
+/// 源文件在写入归档之前的状态
+enum SourceFile {
+    /// 已打开并读取了元数据，等待写入
+    Ready(EntryMeta, SourceContent),
+    /// 扫描之后已被删除
+    Vanished,
+    /// 重试后仍被其他程序占用或权限不足
+    Locked(io::Error),
+}
+
+/// 待写入条目的内容来源
+enum SourceContent {
+    /// 由写入器从文件流式读取
+    Stream(File),
+    /// 已由工作线程预先压缩
+    Prepared(PreparedEntry),
+    /// 保留的符号链接，内容为链接目标
+    Symlink(String),
+}
+
+/// 打开源文件并读取元数据；文件被占用时按选项重试
+fn open_source(file_path: &Path, options: &ArchiveOptions) -> io::Result<SourceFile> {
+    retry_on_lock(options.locked_file_retries, options.retry_delay, || {
+        read_source(file_path, options)
+    })
+    .map_or_else(skipped_source, |(meta, content)| {
+        Ok(SourceFile::Ready(meta, content))
+    })
+}
+
+/// 打开源文件并在工作线程中预压缩；打开或读取时文件被占用都按选项重新打开整个文件
+fn prepare_source(
+    file_path: &Path,
+    name: &str,
+    encoder: &ZipEntryEncoder,
+    options: &ArchiveOptions,
+) -> io::Result<SourceFile> {
+    retry_on_lock(options.locked_file_retries, options.retry_delay, || {
+        match read_source(file_path, options)? {
+            // 预压缩只读取源文件、写入内存，出错时可以直接重试或跳过该文件
+            (meta, SourceContent::Stream(file)) => {
+                let entry = encoder.prepare_file(name, file, &meta)?;
+                Ok((meta, SourceContent::Prepared(entry)))
+            }
+            source => Ok(source),
+        }
+    })
+    .map_or_else(skipped_source, |(meta, content)| {
+        Ok(SourceFile::Ready(meta, content))
+    })
+}
+
+/// 打开一次源文件并读取元数据，不重试
+fn read_source(
+    file_path: &Path,
+    options: &ArchiveOptions,
+) -> io::Result<(EntryMeta, SourceContent)> {
+    let open_path = extended_length_path(file_path);
+    if options.symlinks == SymlinkPolicy::Preserve {
+        let metadata = fs::symlink_metadata(&open_path)?;
+        if metadata.file_type().is_symlink() {
+            let target = fs::read_link(&open_path)?.to_string_lossy().into_owned();
+            let meta = EntryMeta {
+                size: target.len() as u64,
+                ..EntryMeta::from_metadata(&metadata)
+            };
+            return Ok((meta, SourceContent::Symlink(target)));
+        }
+    }
+    let file = File::open(&open_path)?;
+    let meta = EntryMeta::from_metadata(&file.metadata()?);
+    Ok((meta, SourceContent::Stream(file)))
+}
+
+/// 打开或读取源文件时的错误：文件已被删除、被占用或权限不足时只跳过该文件；
+/// 其他错误（例如打开的文件过多、内存不足）使整个归档失败，避免文件被静默遗漏
+fn skipped_source(e: io::Error) -> io::Result<SourceFile> {
+    if e.kind() == io::ErrorKind::NotFound {
+        Ok(SourceFile::Vanished)
+    } else if is_lock_error(&e) {
+        Ok(SourceFile::Locked(e))
+    } else {
+        Err(e)
+    }
+}
+
+/// 一批并行预压缩的最多文件数；与 `max_parallel_bytes` 共同限制同时打开的文件
+const MAX_BATCH_FILES: usize = 256;
+
+/// 写入中的归档临时文件的后缀
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+/// 未通过校验的归档被重命名时追加的后缀
+pub const CORRUPT_SUFFIX: &str = ".corrupt";
+
+/// 在路径的文件名后追加后缀，例如 `a.zip` -> `a.zip.partial`
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// 尚未提交的 `.partial` 临时文件；提交前被丢弃（出错提前返回）时自动删除
+#[derive(Default)]
+struct PartialFiles {
+    paths: Vec<PathBuf>,
+}
+
+impl Drop for PartialFiles {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// 归档未通过完整性校验时包装在 `io::Error` 中的错误
+#[derive(Debug)]
+struct VerificationFailed(String);
+
+impl std::fmt::Display for VerificationFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for VerificationFailed {}
+
+/// 判断 `create_archive` 返回的错误是否由归档未通过完整性校验引起
+pub fn is_verification_failure(err: &BackupError) -> bool {
+    err.io_error()
+        .and_then(io::Error::get_ref)
+        .is_some_and(|inner| inner.is::<VerificationFailed>())
+}
+
+/// 校验所有分卷的临时文件；任一分卷损坏时，将全部分卷以最终文件名加 `.corrupt` 保留下来
+fn verify_parts(
+    partials: &mut PartialFiles,
+    final_paths: &[PathBuf],
+    options: &ArchiveOptions,
+    on_event: &mut dyn FnMut(ArchiveEvent),
+) -> io::Result<()> {
+    let result = partials
+        .paths
+        .iter()
+        .zip(final_paths)
+        .try_for_each(|(partial, final_path)| {
+            crate::verifier::verify_archive(partial, options.format, options.password)
+                .map(|_| ())
+                .map_err(|e| format!("{}: {}", final_path.display(), e))
+        });
+    let Err(message) = result else {
+        return Ok(());
+    };
+
+    for (partial, final_path) in partials.paths.drain(..).zip(final_paths) {
+        if let Err(e) = fs::rename(&partial, with_suffix(final_path, CORRUPT_SUFFIX)) {
+            on_event(ArchiveEvent::Warning(format!(
+                "Failed to rename corrupt archive '{}': {}",
+                partial.display(),
+                e
+            )));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        VerificationFailed(message),
+    ))
+}
+
+/// 将文件列表归档到一个归档文件中
+///
+/// 文件直接从源目录流式写入归档，不再经过临时目录中转。
+/// ZIP 格式下需要压缩的文件按 `max_parallel_bytes` 分批在工作线程中并行压缩，
+/// 再按顺序写入归档，条目顺序与串行写入时相同。
+/// 设置了 `split_size` 时，会按大小拆分为多个 `.partN` 分卷。
+/// 每个归档（分卷）的最后一个条目是记录其中所有文件 SHA-256 的 `MANIFEST.json`。
+/// 归档先写入 `.partial` 临时文件，成功（并通过校验）后才重命名为最终文件名；
+/// 失败时返回 [`BackupError::ArchiveError`]，其中的路径为目标目录；
+/// 校验失败时返回的错误可用 [`is_verification_failure`] 识别。
 ///
 /// # Arguments
 /// * `base_source_path` - 源文件的根目录 (e.g., --from)
-/// * `files_to_backup` - 需要备份的文件绝对路径列表
+/// * `files_to_backup` - 需要备份的文件列表，条目名取自扫描时计算的相对路径
 /// * `destination_path` - 备份文件存放的目标目录 (e.g., --to)
 /// * `month` - 当前正在备份的月份，用于命名
+/// * `options` - 归档格式、压缩级别、密码等选项
+/// * `on_event` - 接收进度和警告事件的回调
 ///
 /// # Returns
-/// 成功时返回创建的 ZIP 文件的路径
+/// 成功时返回归档统计，其中包含创建的所有归档文件（分卷）的路径
 pub fn create_archive(
     base_source_path: &Path,
-    files_to_backup: &[PathBuf],
+    files_to_backup: &[FileEntry],
+    destination_path: &Path,
+    month: &BackupPeriod,
+    options: &ArchiveOptions,
+    on_event: &mut dyn FnMut(ArchiveEvent),
+) -> Result<ArchiveStats, BackupError> {
+    if options.password.is_some() && options.format != ArchiveFormat::Zip {
+        return Err(BackupError::ConfigError(
+            "Password protection is only supported for zip archives".to_string(),
+        ));
+    }
+    write_archive(
+        base_source_path,
+        files_to_backup,
+        destination_path,
+        month,
+        options,
+        on_event,
+    )
+    .map_err(|source| BackupError::ArchiveError {
+        path: destination_path.to_path_buf(),
+        source,
+    })
+}
+
+/// [`create_archive`] 的实现，返回底层的 I/O 错误
+fn write_archive(
+    base_source_path: &Path,
+    files_to_backup: &[FileEntry],
     destination_path: &Path,
-    month: &BackupMonth,
-) -> io::Result<PathBuf> {
-    // 1. 创建一个唯一的临时目录
-    let temp_dir_name = Uuid::new_v4().to_string();
-    let temp_path = destination_path.join(&temp_dir_name);
-    fs::create_dir_all(&temp_path)?;
-
-    // 2. 复制文件到临时目录，保持目录结构
-    for file_path in files_to_backup {
-        let relative_path = file_path.strip_prefix(base_source_path).map_err(|_| {
-            io::Error::new(io::ErrorKind::InvalidInput, "File path not in source base")
-        })?;
-        let dest_file_path = temp_path.join(relative_path);
-        if let Some(parent) = dest_file_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-        fs::copy(file_path, &dest_file_path)?;
-    }
-
-    // 3. 创建 ZIP 归档
-    let time_stamp = chrono::Local::now().format("%Y%m%d%H%M%S");
-    let zip_file_name = format!(
-        "{:04}-{:02}_backup_{}.zip",
-        month.year, month.month, time_stamp
-    );
-    let zip_path = destination_path.join(zip_file_name);
-    let zip_file = File::create(&zip_path)?;
-    let mut zip = ZipWriter::new(zip_file);
-    let options: FileOptions<()> =
-        FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
-
-    for entry in walkdir::WalkDir::new(&temp_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
+    month: &BackupPeriod,
+    options: &ArchiveOptions,
+    on_event: &mut dyn FnMut(ArchiveEvent),
+) -> io::Result<ArchiveStats> {
+    let started = Instant::now();
+    let now = options.now.unwrap_or_else(Utc::now);
+    // 可复现模式之外清单条目的修改时间
+    let manifest_modified = (!options.reproducible).then_some(now);
+
+    // 1. 创建（第一个）归档文件
+    let archive_name = ArchiveNameInfo {
+        month: month.label(),
+        group: options.group.clone(),
+        timestamp: now.with_timezone(&Local).format("%Y%m%d%H%M%S").to_string(),
+        digest: None,
+        source: options.source.clone(),
+        part: None,
+        format: options.format,
+        encrypted: false,
+        skip_report: false,
+    };
+    let part_path = |part: usize| {
+        let name = ArchiveNameInfo {
+            part: options.split_size.map(|_| part),
+            ..archive_name.clone()
+        };
+        destination_path.join(name.file_name())
+    };
+
+    let append_base = if options.append {
+        find_latest_archive(
+            destination_path,
+            month,
+            options.group.as_deref(),
+            options.source.as_deref(),
+            options.format,
+        )?
+    } else {
+        None
+    };
+
+    // 所有分卷先写入 `.partial` 临时文件，全部写完（并通过校验）后才重命名为最终文件名，
+    // 中途出错或进程被终止时不会留下看似完整的归档
+    let mut stats = ArchiveStats::default();
+    let mut partials = PartialFiles::default();
+    partials
+        .paths
+        .push(with_suffix(&part_path(1), PARTIAL_SUFFIX));
+    let mut writer = open_writer(&partials.paths[0], options, now)?;
+    // 当前分卷已写入的预估字节数
+    let mut part_bytes: u64 = 0;
+
+    // 可复现模式下按条目名排序，保证写入顺序与扫描顺序无关
+    let mut files = files_to_backup
+        .iter()
+        .map(|file| {
+            let relative_path = file.archive_path();
+            let name = entry_name(&relative_path);
+            (file, relative_path, name)
+        })
+        .collect::<Vec<_>>();
+    if options.reproducible {
+        files.sort_by(|a, b| a.2.cmp(&b.2));
+    }
+
+    let files_total = files.len();
+    let bytes_total: u64 = files_to_backup.iter().map(|f| f.size).sum();
+    let mut bytes_done: u64 = 0;
+
+    // 压缩级别为 0 时没有需要并行的工作；zip crate 原样复制条目时会丢失 AES 加密信息，
+    // 因此加密归档也只能串行写入
+    let encoder = (options.format == ArchiveFormat::Zip
+        && options.password.is_none()
+        && options.compression_level > 0
+        && options.max_parallel_bytes > 0)
+        .then(|| ZipEntryEncoder::new(options));
+
+    // 每个分卷的备份元数据，文件数取自该分卷的清单
+    let archive_info = |manifest: &Manifest| ArchiveInfo {
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        source_path: base_source_path.display().to_string(),
+        extra_source_paths: options
+            .extra_source_paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+        backup_month: month.label(),
+        backup_mode: options.backup_mode.map(|mode| format!("{:?}", mode)),
+        last_backup_time: options.last_backup_time,
+        // 扫描时间因运行而异，可复现模式不记录
+        next_cutoff: options.next_cutoff.filter(|_| !options.reproducible),
+        month_boundary_tz: options.month_boundary_tz.clone(),
+        file_count: manifest.entries.len(),
+        created_at: if options.reproducible {
+            let newest = manifest.entries.iter().filter_map(|e| e.modified).max();
+            newest.unwrap_or(DateTime::UNIX_EPOCH)
+        } else {
+            now
+        },
+    };
+
+    // 已写入当前分卷的目录条目，避免重复添加
+    let mut added_dirs: HashSet<String> = HashSet::new();
+    // 当前分卷的清单
+    let new_manifest = || Manifest {
+        entries: Vec::new(),
+        kind: options.kind,
+        base_archive: options.base_archive.clone(),
+    };
+    let mut manifest = new_manifest();
+
+    // 2. 逐批打开（并行压缩）源文件，再按顺序写入归档，保持目录结构
+    let mut batch_start = 0;
+    while batch_start < files.len() {
+        // 一批中需要压缩的文件总大小不超过 max_parallel_bytes，文件数不超过 MAX_BATCH_FILES；
+        // 单个超过上限的文件单独成批，按串行方式流式写入
+        let mut batch_end = batch_start;
+        if let Some(encoder) = &encoder {
+            let mut batch_bytes = 0;
+            while let Some((file, _, name)) = files.get(batch_end) {
+                let cost = if encoder.compresses(name) {
+                    file.size
+                } else {
+                    0
+                };
+                if batch_bytes + cost > options.max_parallel_bytes
+                    || batch_end - batch_start >= MAX_BATCH_FILES
+                {
+                    break;
+                }
+                batch_bytes += cost;
+                batch_end += 1;
+            }
+        }
+        // 不需要压缩的文件为 `None`，由下面的写入循环在写入时才打开，一次只占用一个文件描述符
+        let sources: Vec<Option<io::Result<SourceFile>>> = match &encoder {
+            Some(encoder) if batch_end > batch_start => files[batch_start..batch_end]
+                .par_iter()
+                .map(|(file, _, name)| {
+                    if !encoder.compresses(name) {
+                        return None;
+                    }
+                    Some(prepare_source(&file.path, name, encoder, options))
+                })
+                .collect(),
+            _ => {
+                batch_end = batch_start + 1;
+                vec![None]
+            }
+        };
+
+        'files: for (index, source) in (batch_start..batch_end).zip(sources) {
+            let (file, relative_path, name) = &files[index];
+            let file_path = file.path.as_path();
+            let source = source.unwrap_or_else(|| open_source(file_path, options));
+            let files_done = index;
+            on_event(ArchiveEvent::Progress(ArchiveProgress {
+                files_done,
+                files_total,
+                bytes_done,
+                bytes_total,
+                current_file: file_path,
+            }));
+
+            // 扫描之后被删除的文件直接跳过；被占用或权限不足的文件重试若干次后跳过并记录
+            // （严格模式下整个归档失败）；其他错误使整个归档失败
+            let (meta, content) = match source.map_err(|e| archive_error(file_path, e))? {
+                SourceFile::Vanished => {
+                    on_event(ArchiveEvent::Warning(format!(
+                        "'{}' disappeared before it could be archived. Skipping.",
+                        file_path.display()
+                    )));
+                    stats.vanished_files += 1;
+                    continue;
+                }
+                SourceFile::Locked(e) => {
+                    if options.fail_on_skip {
+                        return Err(archive_error(file_path, e));
+                    }
+                    stats.skipped_files.push(SkippedFile {
+                        path: file_path.to_path_buf(),
+                        reason: e.to_string(),
+                    });
+                    continue;
+                }
+                SourceFile::Ready(meta, content) => (meta, content),
+            };
+            let mut meta = meta;
+            let entry_bytes = meta.size + ENTRY_OVERHEAD;
+
+            // 分卷：加入该文件会超出限制时，先结束当前分卷再开始新的分卷
+            if let Some(split_size) = options.split_size {
+                if entry_bytes > split_size {
+                    on_event(ArchiveEvent::Warning(format!(
+                        "'{}' is larger than the split size and will be placed in its own part.",
+                        file_path.display()
+                    )));
+                }
+                if part_bytes > 0 && part_bytes + entry_bytes > split_size {
+                    finish_with_manifest(
+                        writer,
+                        &manifest,
+                        &archive_info(&manifest),
+                        manifest_modified,
+                    )?;
+                    manifest = new_manifest();
+                    let next_part = part_path(partials.paths.len() + 1);
+                    partials.paths.push(with_suffix(&next_part, PARTIAL_SUFFIX));
+                    writer = open_writer(partials.paths.last().unwrap(), options, now)?;
+                    part_bytes = 0;
+                    added_dirs.clear();
+                }
+            }
+
+            // 为相对路径隐含的每一级目录添加目录条目
+            if let Some(parent) = relative_path.parent() {
+                let mut dir = PathBuf::new();
+                for component in parent.components() {
+                    dir.push(component);
+                    let dir_name = entry_name(&dir);
+                    if !dir_name.is_empty() && added_dirs.insert(dir_name.clone()) {
+                        writer.add_directory(&dir_name)?;
+                        part_bytes += ENTRY_OVERHEAD;
+                    }
+                }
+            }
+
+            let (method, sha256, entry_path) = match content {
+                SourceContent::Stream(mut f) => {
+                    let start_bytes = bytes_done;
+                    let mut attempt = 0;
+                    'read: loop {
+                        let mut on_read = |n: u64| {
+                            bytes_done += n;
+                            on_event(ArchiveEvent::Progress(ArchiveProgress {
+                                files_done,
+                                files_total,
+                                bytes_done,
+                                bytes_total,
+                                current_file: file_path,
+                            }));
+                        };
+                        let mut reader = HashingReader::new(ProgressReader {
+                            inner: &mut f,
+                            on_read: &mut on_read,
+                            failed: false,
+                        });
+                        let e = match writer.add_file(name, &mut reader, &meta) {
+                            Ok(method) => break (method, reader.hex_digest(), name.clone()),
+                            // 源文件读取到一半被锁定：丢弃写入到一半的条目，重新打开并从头读取；
+                            // 其他读取错误、写入归档本身出错或格式不支持丢弃条目时整个归档失败
+                            Err(e) if reader.inner.failed && is_lock_error(&e) => e,
+                            Err(e) => return Err(archive_error(file_path, e)),
+                        };
+                        if writer.abort_file().is_err() {
+                            return Err(archive_error(file_path, e));
+                        }
+                        bytes_done = start_bytes;
+                        let mut locked = e;
+                        while attempt < options.locked_file_retries {
+                            attempt += 1;
+                            thread::sleep(options.retry_delay * attempt);
+                            match read_source(file_path, options) {
+                                Ok((reopened_meta, SourceContent::Stream(reopened))) => {
+                                    meta = reopened_meta;
+                                    f = reopened;
+                                    continue 'read;
+                                }
+                                Ok(_) => break,
+                                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                                    on_event(ArchiveEvent::Warning(format!(
+                                        "'{}' disappeared before it could be archived. Skipping.",
+                                        file_path.display()
+                                    )));
+                                    stats.vanished_files += 1;
+                                    continue 'files;
+                                }
+                                Err(e) if is_lock_error(&e) => locked = e,
+                                Err(e) => return Err(archive_error(file_path, e)),
+                            }
+                        }
+                        if options.fail_on_skip {
+                            return Err(archive_error(file_path, locked));
+                        }
+                        stats.skipped_files.push(SkippedFile {
+                            path: file_path.to_path_buf(),
+                            reason: locked.to_string(),
+                        });
+                        continue 'files;
+                    }
+                }
+                SourceContent::Prepared(entry) => {
+                    let (method, sha256) = (entry.method, entry.sha256.clone());
+                    writer
+                        .add_prepared(entry)
+                        .map_err(|e| archive_error(file_path, e))?;
+                    bytes_done += meta.size;
+                    (method, sha256, name.clone())
+                }
+                SourceContent::Symlink(target) => {
+                    let entry_path = writer
+                        .add_symlink(name, &target, &meta)
+                        .map_err(|e| archive_error(file_path, e))?;
+                    bytes_done += meta.size;
+                    (EntryMethod::Stored, link_digest(&target), entry_path)
+                }
+            };
+            let entry = ManifestEntry {
+                path: entry_path,
+                size: meta.size,
+                modified: meta.modified,
+                sha256,
+            };
+            stats.archived_entries.push(entry.clone());
+            manifest.entries.push(entry);
+            match method {
+                EntryMethod::Stored => stats.stored_files += 1,
+                EntryMethod::Compressed => stats.compressed_files += 1,
+            }
+            stats.uncompressed_bytes += meta.size;
+            part_bytes += entry_bytes;
+        }
+        batch_start = batch_end;
+    }
+
+    if let Some(last) = files_to_backup.last() {
+        on_event(ArchiveEvent::Progress(ArchiveProgress {
+            files_done: files_total,
+            files_total,
+            bytes_done,
+            bytes_total,
+            current_file: &last.path,
+        }));
+    }
+
+    // 3. 追加模式：合并旧归档中未被覆盖的条目（新文件优先），然后替换旧归档
+    if let Some(base) = &append_base {
+        let mut written: HashSet<String> =
+            manifest.entries.iter().map(|e| e.path.clone()).collect();
+        written.extend(added_dirs.iter().cloned());
+        let mut kept = writer.copy_entries_from(base, &written)?;
+        kept.append(&mut manifest.entries);
+        manifest.entries = kept;
+    }
+    let manifest_digest = finish_with_manifest(
+        writer,
+        &manifest,
+        &archive_info(&manifest),
+        manifest_modified,
+    )?;
+
+    let mut archive_paths: Vec<PathBuf> = (1..=partials.paths.len()).map(part_path).collect();
+
+    // 4. 可复现模式：以源文件最新修改时间（UTC，与运行机器的时区无关）和清单摘要确定最终文件名
+    if options.reproducible {
+        let newest = manifest.entries.iter().filter_map(|e| e.modified).max();
+        let time_stamp = newest
+            .map(|t| t.format("%Y%m%d%H%M%S").to_string())
+            .unwrap_or_else(|| "19800101000000".to_string());
+        let name = ArchiveNameInfo {
+            timestamp: time_stamp,
+            digest: Some(manifest_digest[..16].to_string()),
+            ..archive_name.clone()
+        };
+        archive_paths[0] = destination_path.join(name.file_name());
+    }
+
+    // 5. 校验：追加模式下删除旧归档前必须确认合并后的归档完好，否则保留旧归档
+    if let Some(base) = &append_base
+        && let Err(e) =
+            crate::verifier::verify_archive(&partials.paths[0], options.format, options.password)
+    {
+        return Err(io::Error::new(
+            e.kind(),
+            format!(
+                "Merged archive failed verification, kept '{}': {}",
+                base.display(),
+                e
+            ),
+        ));
+    }
+    if options.verify && append_base.is_none() {
+        verify_parts(&mut partials, &archive_paths, options, on_event)?;
+    }
+
+    // 6. 提交：将临时文件重命名为最终文件名，之后再删除被合并的旧归档（追加模式），
+    // 任何时刻目标目录中都至少有一个完整的归档
+    for (partial, final_path) in partials.paths.iter().zip(&archive_paths) {
+        fs::rename(partial, final_path)?;
+    }
+    partials.paths.clear();
+    if let Some(base) = &append_base
+        && !archive_paths.contains(base)
+        && let Err(e) = fs::remove_file(base)
     {
-        let path = entry.path();
-        let name = path.strip_prefix(&temp_path).unwrap();
-        if path.is_file() {
-            zip.start_file(name.to_string_lossy(), options)?;
-            let mut f = File::open(path)?;
-            let mut buffer = Vec::new();
-            f.read_to_end(&mut buffer)?;
-            zip.write_all(&buffer)?;
-        } else if !name.as_os_str().is_empty() {
-            zip.add_directory(name.to_string_lossy(), options)?;
+        // 合并后的归档已经包含旧归档的所有条目，旧归档只是多余
+        on_event(ArchiveEvent::Warning(format!(
+            "Failed to remove '{}' after merging it into '{}': {}",
+            base.display(),
+            archive_paths[0].display(),
+            e
+        )));
+    }
+
+    for archive_path in &archive_paths {
+        stats.compressed_bytes += fs::metadata(archive_path)?.len();
+    }
+    stats.archive_paths = archive_paths;
+    stats.elapsed = started.elapsed();
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup_logic::BackupMonth;
+    use std::fs;
+    use zip::ZipArchive;
+
+    const CONTENT: &str = "hello hello hello hello hello";
+
+    /// 在临时目录中创建测试源文件，返回 (测试根目录, 源目录, 目标目录, 文件列表)
+    fn setup() -> (PathBuf, PathBuf, PathBuf, Vec<PathBuf>) {
+        let root =
+            std::env::temp_dir().join(format!("dat-patch-archiver-{}", uuid::Uuid::new_v4()));
+        let source = root.join("in");
+        let dest = root.join("out");
+        fs::create_dir_all(source.join("sub")).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+
+        let files = vec![source.join("a.txt"), source.join("sub").join("b.txt")];
+        for file in &files {
+            fs::write(file, CONTENT.repeat(100)).unwrap();
+        }
+        (root, source, dest, files)
+    }
+
+    /// 为测试文件补上相对路径、当前大小和修改时间，构造扫描结果
+    fn entries(source: &Path, paths: &[PathBuf]) -> Vec<FileEntry> {
+        paths
+            .iter()
+            .map(|path| {
+                let metadata = fs::metadata(path).unwrap();
+                FileEntry {
+                    path: path.clone(),
+                    relative_path: path.strip_prefix(source).unwrap().to_path_buf(),
+                    size: metadata.len(),
+                    modified: metadata.modified().unwrap().into(),
+                    source_label: None,
+                }
+            })
+            .collect()
+    }
+
+    /// 构造扫描之后被删除或替换的文件的扫描结果
+    fn stale_entry(source: &Path, path: PathBuf) -> FileEntry {
+        FileEntry {
+            relative_path: path.strip_prefix(source).unwrap().to_path_buf(),
+            path,
+            size: 10,
+            modified: Utc::now(),
+            source_label: None,
+        }
+    }
+
+    fn test_month() -> BackupPeriod {
+        BackupPeriod::Month(BackupMonth {
+            year: 2025,
+            month: 7,
+        })
+    }
+
+    /// 按指定压缩级别归档，返回所有文件条目的压缩方式
+    fn archive_with_level(level: u32) -> Vec<zip::CompressionMethod> {
+        let (root, source, dest, files) = setup();
+        let zip_path = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                compression_level: level,
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap()
+        .archive_paths
+        .remove(0);
+
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let mut methods = Vec::new();
+        for i in 0..archive.len() {
+            let entry = archive.by_index(i).unwrap();
+            if entry.is_file() && entry.name() != MANIFEST_NAME {
+                methods.push(entry.compression());
+            }
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+        methods
+    }
+
+    #[test]
+    fn level_zero_uses_stored() {
+        let methods = archive_with_level(0);
+        assert_eq!(methods.len(), 2);
+        assert!(methods.iter().all(|m| *m == zip::CompressionMethod::Stored));
+    }
+
+    #[test]
+    fn level_nine_uses_deflated() {
+        let methods = archive_with_level(9);
+        assert_eq!(methods.len(), 2);
+        assert!(
+            methods
+                .iter()
+                .all(|m| *m == zip::CompressionMethod::Deflated)
+        );
+    }
+
+    #[test]
+    fn encrypted_archive_requires_password() {
+        let (root, source, dest, files) = setup();
+        let zip_path = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                compression_level: 6,
+                password: Some("secret"),
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap()
+        .archive_paths
+        .remove(0);
+
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        assert!(archive.by_name("a.txt").is_err());
+
+        let mut content = String::new();
+        archive
+            .by_name_decrypt("a.txt", b"secret")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, CONTENT.repeat(100));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn stats_count_files_bytes_and_skips() {
+        let (root, source, dest, files) = setup();
+        let mut scanned = entries(&source, &files);
+        scanned.push(stale_entry(&source, source.join("gone.txt")));
+
+        let stats = create_archive(
+            &source,
+            &scanned,
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                compression_level: 6,
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap();
+
+        assert_eq!(stats.archived_files(), 2);
+        assert_eq!(stats.skipped_count(), 1);
+        assert_eq!(stats.uncompressed_bytes, (CONTENT.len() * 100 * 2) as u64);
+        assert_eq!(
+            stats.compressed_bytes,
+            fs::metadata(&stats.archive_paths[0]).unwrap().len()
+        );
+        // 重复内容压缩后应明显变小
+        assert!(stats.compressed_bytes < stats.uncompressed_bytes);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn failed_archive_leaves_no_files_behind() {
+        let (root, source, dest, files) = setup();
+        // 目录可以被打开但无法读取，模拟归档中途的读取错误；严格模式下整个归档失败
+        let unreadable = source.join("unreadable.txt");
+        fs::create_dir(&unreadable).unwrap();
+        let scanned = [
+            entries(&source, &files),
+            vec![stale_entry(&source, unreadable)],
+        ]
+        .concat();
+
+        for max_parallel_bytes in [0, 1 << 20] {
+            let result = create_archive(
+                &source,
+                &scanned,
+                &dest,
+                &test_month(),
+                &ArchiveOptions {
+                    max_parallel_bytes,
+                    fail_on_skip: true,
+                    ..Default::default()
+                },
+                &mut |_| {},
+            );
+            assert!(result.is_err());
+            assert_eq!(fs::read_dir(&dest).unwrap().count(), 0);
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// 读取 ZIP 归档中的文件条目名（不含目录和清单）
+    fn zip_file_names(path: &Path) -> Vec<String> {
+        let archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        archive
+            .file_names()
+            .filter(|name| !name.ends_with('/') && *name != MANIFEST_NAME)
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn read_errors_fail_the_archive() {
+        let (root, source, dest, files) = setup();
+        // 目录可以被打开但读取时报错，模拟读取到一半出错的文件；
+        // 这不是文件被占用，跳过它会让文件被静默遗漏
+        let unreadable = source.join("sub").join("broken.txt");
+        fs::create_dir(&unreadable).unwrap();
+        let mut scanned = entries(&source, &files);
+        scanned.insert(1, stale_entry(&source, unreadable.clone()));
+
+        for max_parallel_bytes in [0, 1 << 20] {
+            let result = create_archive(
+                &source,
+                &scanned,
+                &dest,
+                &test_month(),
+                &ArchiveOptions {
+                    compression_level: 6,
+                    max_parallel_bytes,
+                    verify: true,
+                    ..Default::default()
+                },
+                &mut |_| {},
+            );
+
+            assert!(
+                matches!(result, Err(BackupError::ArchiveError { .. })),
+                "{:?}",
+                result
+            );
+            // 不留下归档或 .partial 临时文件
+            assert_eq!(fs::read_dir(&dest).unwrap().count(), 0);
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_without_read_permission_are_skipped() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (root, source, dest, files) = setup();
+        let unreadable = source.join("secret.txt");
+        fs::write(&unreadable, CONTENT).unwrap();
+        fs::set_permissions(&unreadable, fs::Permissions::from_mode(0o000)).unwrap();
+        // root 用户不受文件权限限制，无法模拟读取失败
+        if File::open(&unreadable).is_ok() {
+            fs::remove_dir_all(&root).unwrap();
+            return;
+        }
+
+        let stats = create_archive(
+            &source,
+            &entries(&source, &[files.clone(), vec![unreadable.clone()]].concat()),
+            &dest,
+            &test_month(),
+            &ArchiveOptions::default(),
+            &mut |_| {},
+        )
+        .unwrap();
+
+        assert_eq!(stats.archived_files(), 2);
+        assert_eq!(stats.skipped_files.len(), 1);
+        assert_eq!(stats.skipped_files[0].path, unreadable);
+        assert_eq!(zip_file_names(&stats.archive_paths[0]).len(), 2);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn vanished_file_leaves_no_directories_in_destination() {
+        let (root, source, dest, files) = setup();
+        let scanned = entries(&source, &files);
+        // 扫描之后删除文件，模拟复制失败
+        fs::remove_file(&files[1]).unwrap();
+
+        let stats = create_archive(
+            &source,
+            &scanned,
+            &dest,
+            &test_month(),
+            &ArchiveOptions::default(),
+            &mut |_| {},
+        )
+        .unwrap();
+
+        assert_eq!(stats.vanished_files, 1);
+        let leftovers: Vec<PathBuf> = fs::read_dir(&dest)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(leftovers, stats.archive_paths);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn archive_info_is_stored_in_zip_comment() {
+        let (root, source, dest, files) = setup();
+        let cutoff = Utc::now() - chrono::Duration::days(3);
+        let stats = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                backup_mode: Some(BackupMode::PreviousMonth),
+                last_backup_time: Some(cutoff),
+                next_cutoff: Some(cutoff + chrono::Duration::days(1)),
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap();
+
+        let info = read_archive_info(&stats.archive_paths[0]).unwrap().unwrap();
+        assert_eq!(info.tool_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.source_path, source.display().to_string());
+        assert_eq!(info.backup_month, "2025-07");
+        assert_eq!(info.backup_mode.as_deref(), Some("PreviousMonth"));
+        assert_eq!(info.last_backup_time, Some(cutoff));
+        assert_eq!(info.next_cutoff, Some(cutoff + chrono::Duration::days(1)));
+        assert_eq!(info.file_count, 2);
+        assert!(info.created_at > cutoff);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn paths_longer_than_max_path_are_scanned_and_archived() {
+        let (root, source, dest, _) = setup();
+        // 多层长目录名，使完整路径超过 260 个字符
+        let dir_names: Vec<String> = (0..6)
+            .map(|i| format!("{}-{}", "d".repeat(50), i))
+            .collect();
+        let dir = dir_names
+            .iter()
+            .fold(source.clone(), |dir, name| dir.join(name));
+        let file = dir.join("deep.txt");
+        assert!(file.as_os_str().len() > 260);
+        fs::create_dir_all(extended_length_path(&dir)).unwrap();
+        fs::write(extended_length_path(&file), CONTENT).unwrap();
+
+        let now = Local::now();
+        let month = BackupPeriod::Month(BackupMonth {
+            year: now.year(),
+            month: now.month(),
+        });
+        let scanned = crate::file_scanner::find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &crate::file_scanner::ScanFilters::default(),
+            &|_| {},
+        )
+        .unwrap()
+        .files;
+        assert!(scanned.iter().any(|f| f.path == file));
+
+        let stats = create_archive(
+            &source,
+            &scanned,
+            &dest,
+            &month,
+            &ArchiveOptions::default(),
+            &mut |_| {},
+        )
+        .unwrap();
+        let mut archive = ZipArchive::new(File::open(&stats.archive_paths[0]).unwrap()).unwrap();
+        let name = format!("{}/deep.txt", dir_names.join("/"));
+        let mut content = String::new();
+        archive
+            .by_name(&name)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, CONTENT);
+
+        fs::remove_dir_all(extended_length_path(&root)).unwrap();
+    }
+
+    #[test]
+    fn parallel_compression_matches_serial_output() {
+        let (root, source, dest, files) = setup();
+        // 一个超过并行上限、需要流式写入的文件，以及一个直接存储的媒体文件
+        let large = source.join("large.txt");
+        fs::write(&large, CONTENT.repeat(1000)).unwrap();
+        let media = source.join("sub").join("photo.jpg");
+        fs::write(&media, CONTENT).unwrap();
+        let scanned = entries(&source, &[files.clone(), vec![large, media]].concat());
+
+        // 归档并返回 (条目名, 压缩方式, 内容) 列表
+        let archive = |max_parallel_bytes: u64, dest: &Path| {
+            fs::create_dir_all(dest).unwrap();
+            let options = ArchiveOptions {
+                compression_level: 6,
+                store_extensions: vec!["jpg".to_string()],
+                max_parallel_bytes,
+                ..Default::default()
+            };
+            let stats = create_archive(
+                &source,
+                &scanned,
+                dest,
+                &test_month(),
+                &options,
+                &mut |_| {},
+            )
+            .unwrap();
+            assert_eq!((stats.stored_files, stats.compressed_files), (1, 3));
+            let mut zip = ZipArchive::new(File::open(&stats.archive_paths[0]).unwrap()).unwrap();
+            let mut result = Vec::new();
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).unwrap();
+                if entry.name() == MANIFEST_NAME {
+                    continue;
+                }
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content).unwrap();
+                result.push((entry.name().to_string(), entry.compression(), content));
+            }
+            result
+        };
+
+        let serial = archive(0, &dest.join("serial"));
+        let parallel = archive(CONTENT.len() as u64 * 300, &dest.join("parallel"));
+        assert_eq!(serial.len(), 5);
+        assert_eq!(parallel, serial);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn only_locked_or_missing_sources_are_skipped() {
+        let skipped = |kind| skipped_source(io::Error::from(kind));
+        assert!(matches!(
+            skipped(io::ErrorKind::NotFound),
+            Ok(SourceFile::Vanished)
+        ));
+        assert!(matches!(
+            skipped(io::ErrorKind::PermissionDenied),
+            Ok(SourceFile::Locked(_))
+        ));
+        // 打开的文件过多、内存不足之类的错误不能当作单个文件的问题跳过
+        assert!(skipped(io::ErrorKind::OutOfMemory).is_err());
+        #[cfg(unix)]
+        assert!(skipped_source(io::Error::from_raw_os_error(24)).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn stored_files_are_opened_one_at_a_time() {
+        let (root, source, dest, _) = setup();
+        let files: Vec<PathBuf> = (0..600)
+            .map(|i| {
+                let path = source.join(format!("{}.dat", i));
+                fs::write(&path, CONTENT).unwrap();
+                path
+            })
+            .collect();
+        let open_files = || fs::read_dir("/proc/self/fd").unwrap().count();
+        let before = open_files();
+        let mut most_open = 0;
+        let options = ArchiveOptions {
+            compression_level: 6,
+            store_extensions: vec!["dat".to_string()],
+            max_parallel_bytes: 1 << 20,
+            ..Default::default()
+        };
+        let stats = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &options,
+            &mut |_| most_open = most_open.max(open_files()),
+        )
+        .unwrap();
+        assert_eq!(stats.stored_files, 600);
+        assert!(stats.skipped_files.is_empty());
+        // 其他并行运行的测试也会打开文件，只检查远少于文件数
+        assert!(most_open < before + 100, "{} files open", most_open);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tar_gz_archive_mirrors_directory_layout() {
+        let (root, source, dest, files) = setup();
+        let archive_path = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                format: ArchiveFormat::TarGz,
+                compression_level: 6,
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap()
+        .archive_paths
+        .remove(0);
+        assert!(archive_path.to_string_lossy().ends_with(".tar.gz"));
+
+        let decoder = flate2::read::GzDecoder::new(File::open(&archive_path).unwrap());
+        let mut tar = tar::Archive::new(decoder);
+        let mut names = Vec::new();
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            if entry.header().entry_type().is_file() && name != MANIFEST_NAME {
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                assert_eq!(content, CONTENT.repeat(100));
+            }
+            names.push(name);
+        }
+        assert_eq!(names, vec!["a.txt", "sub/", "sub/b.txt", MANIFEST_NAME]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserved_symlinks_are_stored_as_links_or_placeholders() {
+        let (root, source, dest, files) = setup();
+        let link = source.join("link.txt");
+        std::os::unix::fs::symlink("sub/b.txt", &link).unwrap();
+        let scanned = entries(&source, &[files.clone(), vec![link]].concat());
+        let archive = |format| {
+            let options = ArchiveOptions {
+                format,
+                compression_level: 6,
+                symlinks: SymlinkPolicy::Preserve,
+                verify: true,
+                ..Default::default()
+            };
+            create_archive(
+                &source,
+                &scanned,
+                &dest,
+                &test_month(),
+                &options,
+                &mut |_| {},
+            )
+            .unwrap()
+            .archive_paths
+            .remove(0)
+        };
+
+        let tar_path = archive(ArchiveFormat::TarGz);
+        let decoder = flate2::read::GzDecoder::new(File::open(&tar_path).unwrap());
+        let mut tar = tar::Archive::new(decoder);
+        let link_entry = tar
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .find(|entry| entry.path().unwrap() == Path::new("link.txt"))
+            .unwrap();
+        assert_eq!(link_entry.header().entry_type(), tar::EntryType::Symlink);
+        assert_eq!(
+            link_entry.link_name().unwrap().unwrap(),
+            Path::new("sub/b.txt")
+        );
+
+        let zip_path = archive(ArchiveFormat::Zip);
+        let mut zip = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let mut placeholder = String::new();
+        zip.by_name("link.txt.symlink")
+            .unwrap()
+            .read_to_string(&mut placeholder)
+            .unwrap();
+        assert_eq!(placeholder, "sub/b.txt");
+        assert!(zip.by_name("link.txt").is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tar_zst_archive_round_trips() {
+        let (root, source, dest, files) = setup();
+        let archive_path = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                format: ArchiveFormat::TarZst,
+                compression_level: 1,
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap()
+        .archive_paths
+        .remove(0);
+        assert!(archive_path.to_string_lossy().ends_with(".tar.zst"));
+
+        let extract_dir = root.join("extracted");
+        let decoder = zstd::Decoder::new(File::open(&archive_path).unwrap()).unwrap();
+        tar::Archive::new(decoder).unpack(&extract_dir).unwrap();
+
+        for file in &files {
+            let relative = file.strip_prefix(&source).unwrap();
+            assert_eq!(
+                fs::read(extract_dir.join(relative)).unwrap(),
+                fs::read(file).unwrap()
+            );
         }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tar_entries_of_shrunk_files_are_errors() {
+        let (root, _, dest, _) = setup();
+        let file = File::create(dest.join("shrunk.tar.gz")).unwrap();
+        let mut writer = TarArchiveWriter::new(GzEncoder::new(file, Compression::new(1)), 0);
+        let meta = EntryMeta {
+            size: 10,
+            mode: None,
+            modified: None,
+        };
+        let error = writer
+            .add_file("shrunk.dat", &mut "abc".as_bytes(), &meta)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(
+            error.to_string().contains("from 10 to 3 bytes"),
+            "{}",
+            error
+        );
+
+        // 长度一致或文件增长时只写入头部声明的长度
+        let meta = EntryMeta { size: 3, ..meta };
+        assert!(
+            writer
+                .add_file("grown.dat", &mut "abcdef".as_bytes(), &meta)
+                .is_ok()
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn split_size_creates_multiple_parts() {
+        let (root, source, dest, mut files) = setup();
+        // 一个超过分卷大小的文件应单独成卷
+        let big_file = source.join("big.bin");
+        fs::write(&big_file, vec![0u8; 8 * 1024]).unwrap();
+        files.push(big_file);
+
+        let parts = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                compression_level: 6,
+                split_size: Some(4 * 1024),
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap()
+        .archive_paths;
+
+        assert_eq!(parts.len(), 3);
+        for (i, part) in parts.iter().enumerate() {
+            let name = part.file_name().unwrap().to_string_lossy();
+            assert!(name.ends_with(&format!(".part{}.zip", i + 1)), "{}", name);
+        }
+        let last = ZipArchive::new(File::open(&parts[2]).unwrap()).unwrap();
+        let mut names: Vec<_> = last.file_names().collect();
+        names.sort();
+        assert_eq!(names, vec![MANIFEST_NAME, "big.bin"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_permissions_are_preserved() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (root, source, dest, files) = setup();
+        fs::set_permissions(&files[0], fs::Permissions::from_mode(0o755)).unwrap();
+
+        let zip_path = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                compression_level: 6,
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap()
+        .archive_paths
+        .remove(0);
+
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let mode = archive.by_name("a.txt").unwrap().unix_mode().unwrap();
+        assert_eq!(mode & 0o777, 0o755);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn media_extensions_are_stored() {
+        let (root, source, dest, mut files) = setup();
+        let media_file = source.join("photo.JPG");
+        fs::write(&media_file, CONTENT.repeat(100)).unwrap();
+        files.push(media_file);
+
+        let stats = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                compression_level: 6,
+                store_extensions: vec!["jpg".to_string()],
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(stats.stored_files, 1);
+        assert_eq!(stats.compressed_files, 2);
+
+        let mut archive = ZipArchive::new(File::open(&stats.archive_paths[0]).unwrap()).unwrap();
+        let method = archive.by_name("photo.JPG").unwrap().compression();
+        assert_eq!(method, zip::CompressionMethod::Stored);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn files_larger_than_buffer_are_streamed_intact() {
+        let (root, source, dest, _) = setup();
+        let large_file = source.join("large.bin");
+        let content: Vec<u8> = (0..COPY_BUFFER_SIZE * 3 + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        fs::write(&large_file, &content).unwrap();
+
+        let zip_path = create_archive(
+            &source,
+            &entries(&source, std::slice::from_ref(&large_file)),
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                compression_level: 6,
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap()
+        .archive_paths
+        .remove(0);
+
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let mut extracted = Vec::new();
+        archive
+            .by_name("large.bin")
+            .unwrap()
+            .read_to_end(&mut extracted)
+            .unwrap();
+        assert_eq!(extracted, content);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn manifest_is_last_entry_with_matching_digests() {
+        let (root, source, dest, files) = setup();
+        let zip_path = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &ArchiveOptions {
+                compression_level: 6,
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap()
+        .archive_paths
+        .remove(0);
+
+        let mut archive = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let last_index = archive.len() - 1;
+        let mut manifest_file = archive.by_index(last_index).unwrap();
+        assert_eq!(manifest_file.name(), MANIFEST_NAME);
+        let manifest: Manifest = serde_json::from_reader(&mut manifest_file).unwrap();
+
+        assert_eq!(manifest.entries.len(), files.len());
+        for (entry, file) in manifest.entries.iter().zip(&files) {
+            let expected: String = Sha256::digest(fs::read(file).unwrap())
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            assert_eq!(entry.sha256, expected);
+            assert_eq!(entry.size, fs::metadata(file).unwrap().len());
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn lock_errors_are_retried_until_success() {
+        let mut attempts = 0;
+        let result = retry_on_lock(3, Duration::ZERO, || {
+            attempts += 1;
+            if attempts < 3 {
+                Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn retries_give_up_and_other_errors_are_not_retried() {
+        let mut attempts = 0;
+        let result: io::Result<()> = retry_on_lock(2, Duration::ZERO, || {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::PermissionDenied))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result: io::Result<()> = retry_on_lock(2, Duration::ZERO, || {
+            attempts += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn progress_and_warnings_are_reported_through_callback() {
+        let (root, source, dest, files) = setup();
+        let mut scanned = entries(&source, &files);
+        // 扫描之后被删除的文件只产生警告
+        scanned.push(stale_entry(&source, source.join("gone.txt")));
+
+        let mut last_progress = None;
+        let mut warnings = Vec::new();
+        create_archive(
+            &source,
+            &scanned,
+            &dest,
+            &test_month(),
+            &ArchiveOptions::default(),
+            &mut |event| match event {
+                ArchiveEvent::Progress(p) => {
+                    last_progress = Some((p.files_done, p.files_total, p.bytes_done, p.bytes_total))
+                }
+                ArchiveEvent::Warning(message) => warnings.push(message),
+            },
+        )
+        .unwrap();
+
+        let content_bytes = (CONTENT.len() * 100 * 2) as u64;
+        assert_eq!(
+            last_progress,
+            Some((3, 3, content_bytes, content_bytes + 10))
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("gone.txt"));
+        fs::remove_dir_all(&root).unwrap();
     }
-    zip.finish()?;
 
-    // 4. 删除临时目录
-    fs::remove_dir_all(&temp_path)?;
+    #[test]
+    fn append_merges_into_latest_archive() {
+        let (root, source, dest, files) = setup();
+        let options = ArchiveOptions {
+            compression_level: 6,
+            append: true,
+            ..Default::default()
+        };
+        create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &options,
+            &mut |_| {},
+        )
+        .unwrap();
+
+        // 修改一个已有文件并新增一个文件，然后以追加模式再次归档
+        fs::write(&files[0], "updated").unwrap();
+        let new_file = source.join("c.txt");
+        fs::write(&new_file, "new").unwrap();
+        let stats = create_archive(
+            &source,
+            &entries(&source, &[files[0].clone(), new_file]),
+            &dest,
+            &test_month(),
+            &options,
+            &mut |_| {},
+        )
+        .unwrap();
 
-    Ok(zip_path)
+        // 目标目录中仍然只有一个归档
+        let archives: Vec<_> = fs::read_dir(&dest).unwrap().collect();
+        assert_eq!(archives.len(), 1);
+
+        let mut archive = ZipArchive::new(File::open(&stats.archive_paths[0]).unwrap()).unwrap();
+        let mut content = String::new();
+        archive
+            .by_name("a.txt")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "updated");
+        assert!(archive.by_name("sub/b.txt").is_ok());
+        assert!(archive.by_name("c.txt").is_ok());
+
+        let manifest: Manifest =
+            serde_json::from_reader(archive.by_name(MANIFEST_NAME).unwrap()).unwrap();
+        let mut paths: Vec<_> = manifest.entries.iter().map(|e| e.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["a.txt", "c.txt", "sub/b.txt"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn reproducible_archives_are_byte_identical() {
+        let (root, source, dest, mut files) = setup();
+        let dest2 = root.join("out2");
+        fs::create_dir_all(&dest2).unwrap();
+        let options = ArchiveOptions {
+            compression_level: 6,
+            reproducible: true,
+            ..Default::default()
+        };
+
+        let first = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest,
+            &test_month(),
+            &options,
+            &mut |_| {},
+        )
+        .unwrap();
+        // 输入顺序不同、在另一时刻创建也应得到相同的结果
+        files.reverse();
+        let later = ArchiveOptions {
+            now: Some(Utc::now() + chrono::Duration::hours(25)),
+            ..options.clone()
+        };
+        let second = create_archive(
+            &source,
+            &entries(&source, &files),
+            &dest2,
+            &test_month(),
+            &later,
+            &mut |_| {},
+        )
+        .unwrap();
+
+        let first_path = &first.archive_paths[0];
+        let second_path = &second.archive_paths[0];
+        assert_eq!(first_path.file_name(), second_path.file_name());
+        assert_eq!(
+            fs::read(first_path).unwrap(),
+            fs::read(second_path).unwrap()
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn archive_names_are_parsed() {
+        let valid = [
+            "2025-07_backup_20250801000000.zip",
+            "2025-07_backup_20250801000000.tar.gz",
+            "2025-07_backup_20250801000000.tar.zst",
+            "2025-07_backup_20250801000000.part2.zip",
+            "2025-07_backup_20250801000000.zip.age",
+            "2025-07_backup_20250801000000.part12.tar.zst.age",
+            "2025-07_backup_20250801000000.zip.skipped_files.txt",
+            "2025-07_backup_20250801000000_0123456789abcdef.tar.gz",
+            "2025-07_backup_20250801000000@0123abcd.part2.zip.age",
+            "2025-07_backup_20250801000000_0123456789abcdef@0123abcd.zip",
+            "2025-07_wxid_abc123_backup_20250801000000.part1.zip.age",
+            "2025-W31_backup_20250801000000.zip",
+            "2025-W31_wxid_abc123_backup_20250801000000.part1.zip",
+            "2024-06-15_to_2024-09-01_backup_20250801000000.zip",
+            "2024-06-15_to_2024-09-01_wxid_abc123_backup_20250801000000.zip.age",
+            // 组名本身可以带 `.` 或 `_backup_`
+            "2025-07_wxid.a_b_backup_x_backup_20250801000000.zip",
+        ];
+        for name in valid {
+            let info = parse_archive_name(name).unwrap_or_else(|| panic!("{}", name));
+            assert_eq!(info.file_name(), name);
+        }
+
+        let info =
+            parse_archive_name("2025-07_wxid_abc123_backup_20250801000000.part3.TAR.ZST.Age")
+                .unwrap();
+        assert_eq!(
+            info,
+            ArchiveNameInfo {
+                month: "2025-07".to_string(),
+                group: Some("wxid_abc123".to_string()),
+                timestamp: "20250801000000".to_string(),
+                digest: None,
+                source: None,
+                part: Some(3),
+                format: ArchiveFormat::TarZst,
+                encrypted: true,
+                skip_report: false,
+            }
+        );
+        assert_eq!(info.prefix(), "2025-07_wxid_abc123");
+        assert_eq!(info.series(), "2025-07_wxid_abc123");
+        let tagged = parse_archive_name("2025-07_backup_20250801000000@0123abcd.zip").unwrap();
+        assert_eq!(tagged.source.as_deref(), Some("0123abcd"));
+        assert_eq!(tagged.prefix(), "2025-07");
+        assert_eq!(tagged.series(), "2025-07@0123abcd");
+        assert_eq!(source_tag("/home/a/WeChat Files").len(), 8);
+        assert_ne!(source_tag("a"), source_tag("b"));
+        let weekly = parse_archive_name("2025-W31_wxid_abc123_backup_20250801000000.zip").unwrap();
+        assert_eq!(weekly.month, "2025-W31");
+        assert_eq!(weekly.group.as_deref(), Some("wxid_abc123"));
+        let range =
+            parse_archive_name("2024-06-15_to_2024-09-01_wxid_abc123_backup_20250801000000.zip")
+                .unwrap();
+        assert_eq!(range.month, "2024-06-15_to_2024-09-01");
+        assert_eq!(range.group.as_deref(), Some("wxid_abc123"));
+        assert!(range.is_date_range());
+        assert!(!weekly.is_date_range());
+        // 创建时间和数据所属时间段的第一天
+        assert_eq!(
+            info.created(),
+            NaiveDate::from_ymd_opt(2025, 8, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+        );
+        assert_eq!(info.period_start(), NaiveDate::from_ymd_opt(2025, 7, 1));
+        assert_eq!(weekly.period_start(), NaiveDate::from_ymd_opt(2025, 7, 28));
+        assert_eq!(range.period_start(), None);
+        assert_eq!(
+            parse_archive_name("2025-07_backup_20250801000000.ZIP").map(|i| i.format),
+            Some(ArchiveFormat::Zip)
+        );
+        // 月份和时间戳只检查格式，由调用方判断是否为有效日期
+        let unchecked = parse_archive_name("2025-13_backup_20259999999999.zip").unwrap();
+        assert_eq!(unchecked.created(), None);
+        assert_eq!(unchecked.period_start(), None);
+
+        let invalid = [
+            "",
+            "notes.zip",
+            "2025-07_backup_20250801000000",
+            "2025-07_backup_20250801000000.rar",
+            "2025-07_backup_20250801000000.tar",
+            "2025-07_backup_20250801000000.zip.bak",
+            "2025-07_backup_20250801000000.zip.partial",
+            "2025-07_backup_20250801000000..zip",
+            "2025-07_backup_20250801000000.zip.age.age",
+            "2025-07_backup_20250801000000.zipx",
+            "2025-07_backup_2025080100000.zip",
+            "2025-07_backup_202508010000000.zip",
+            "2025-07_backup_20250801000000_0123456789ABCDEF.zip",
+            "2025-07_backup_20250801000000_0123.zip",
+            "2025-07_backup_20250801000000@0123.zip",
+            "2025-07_backup_20250801000000@0123ABCD.zip",
+            "2025-07_backup_20250801000000.part.zip",
+            "2025-07_backup_20250801000000.part+1.zip",
+            "2025-07_backup_20250801000000.skipped_files.txt",
+            "2025-7_backup_20250801000000.zip",
+            "2025-W3_backup_20250801000000.zip",
+            "2025-Wx1_backup_20250801000000.zip",
+            "2025-W31x_backup_20250801000000.zip",
+            "2025_07_backup_20250801000000.zip",
+            "2025-07-01_backup_20250801000000.zip",
+            "2024-06-15_to_2024-9-01_backup_20250801000000.zip",
+            "2024-06-15_to__backup_20250801000000.zip",
+            "2024-06-15_to_2024-09-01x_backup_20250801000000.zip",
+            "2025-07__backup_20250801000000.zip",
+            "x2025-07_backup_20250801000000.zip",
+            "年份-07_backup_20250801000000.zip",
+        ];
+        for name in invalid {
+            assert_eq!(parse_archive_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn split_archives_are_grouped_into_sets() {
+        let root = std::env::temp_dir().join(format!("dat-patch-sets-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let write_zip = |name: &str, entries: &[&str]| {
+            let mut zip = ZipWriter::new(File::create(root.join(name)).unwrap());
+            for entry in entries {
+                zip.start_file(*entry, FileOptions::<()>::default())
+                    .unwrap();
+                zip.write_all(CONTENT.as_bytes()).unwrap();
+            }
+            zip.finish().unwrap();
+        };
+        write_zip("2025-07_backup_20250801000000.part1.zip", &["a", "b"]);
+        write_zip("2025-07_backup_20250801000000.part2.zip", &["c"]);
+        write_zip("2025-07_wxid_a_backup_20250801000000.part1.zip", &["d"]);
+        fs::write(root.join("2025-07_backup_20250802000000.zip.age"), "age").unwrap();
+        fs::write(
+            root.join("2025-07_backup_20250802000000.zip.skipped_files.txt"),
+            "",
+        )
+        .unwrap();
+
+        let sets = group_archive_sets(find_archives(&root).unwrap());
+        let names: Vec<Vec<String>> = sets
+            .iter()
+            .map(|set| {
+                set.paths
+                    .iter()
+                    .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                vec![
+                    "2025-07_backup_20250801000000.part1.zip",
+                    "2025-07_backup_20250801000000.part2.zip"
+                ],
+                vec!["2025-07_wxid_a_backup_20250801000000.part1.zip"],
+                vec!["2025-07_backup_20250802000000.zip.age"],
+            ]
+        );
+        let part_sizes: u64 = sets[0]
+            .paths
+            .iter()
+            .map(|path| fs::metadata(path).unwrap().len())
+            .sum();
+        assert_eq!(sets[0].size, part_sizes);
+        assert_eq!(sets[0].entry_count().unwrap(), Some(3));
+        assert_eq!(sets[2].size, 3);
+        assert_eq!(sets[2].entry_count().unwrap(), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
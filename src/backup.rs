@@ -0,0 +1,2199 @@
+use crate::archiver::{
+    self, ArchiveEvent, ArchiveFormat, ArchiveOptions, ArchiveProgress, ArchiveStats,
+    DEFAULT_STORE_EXTENSIONS, SkippedFile,
+};
+use crate::backup_logic::{
+    self, ArchiveKind, BackupMode, BackupMonth, BackupPeriod, BackupWeek, DateRange,
+    DynamicThresholds, determine_backup_months,
+};
+use crate::cache::{self, CacheBackend, CacheStore};
+use crate::cleaner::{
+    self, CleanupEvent, CleanupReport, DeletionCandidate, RetentionBy, RetentionPolicy,
+};
+use crate::disk_space;
+use crate::encryption;
+use crate::error::{BackupError, archive_error, cache_error, scan_error};
+use crate::file_scanner::{self, ChangeDetection, MonthSource, ScanProgress, SymlinkPolicy};
+use crate::timezone::MonthBoundaryTz;
+use chrono::{DateTime, NaiveDate, Utc};
+use indicatif::HumanBytes;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// 接收备份过程中的事件的回调，可能在扫描线程中被调用
+pub type EventHandler = Box<dyn Fn(BackupEvent<'_>) + Send + Sync>;
+
+/// 滚动删除前确认待删除归档的回调，返回 `true` 时删除
+pub type ConfirmHandler = Box<dyn Fn(&[DeletionCandidate]) -> bool + Send + Sync>;
+
+/// 备份过程中通过 [`BackupOptions::on_event`] 报告给调用方的事件
+///
+/// [`run_backup`] 本身不输出任何内容，由调用方决定如何展示（或全部忽略）。
+/// 消息不带 `Warning:`、`Error:` 之类的前缀。
+#[derive(Debug, Clone)]
+pub enum BackupEvent<'p> {
+    /// 运行过程的说明，例如正在扫描的月份和创建的归档
+    Info(String),
+    /// 不影响结果的问题；命令行的 --silent 不输出
+    Warning(String),
+    /// 无人值守时也需要注意的问题，例如缓存丢失或文件无法读取；命令行在 --silent 下也输出
+    Alert(String),
+    /// 使一个月份或清理失败的错误；运行会继续，结果反映在 [`BackupReport`] 中
+    Error(String),
+    /// 扫描进度，在扫描线程中触发
+    ScanProgress(ScanProgress),
+    /// 一个月份的扫描结束
+    ScanFinished,
+    /// 归档进度
+    ArchiveProgress(ArchiveProgress<'p>),
+    /// 一个归档写入结束（无论成功与否）
+    ArchiveFinished,
+    /// 滚动删除结束；`dry_run` 时报告的是将要删除的归档
+    CleanupFinished {
+        report: &'p CleanupReport,
+        dry_run: bool,
+    },
+    /// --dry-run 时一个归档本应包含的文件；`label` 为归档名中 `_backup_` 之前的部分，
+    /// 例如 `2025-07` 或 `2025-07_wxid_abc123`
+    DryRunFiles {
+        label: &'p str,
+        /// 共享目标目录时本次运行的归档的源标记
+        source: Option<&'p str>,
+        files: &'p [file_scanner::FileEntry],
+    },
+}
+
+/// 一次备份运行的设置，与命令行参数一一对应
+///
+/// [`BackupOptions::new`] 使用与命令行相同的默认值。
+pub struct BackupOptions {
+    /// 要备份的源目录（--from），多个时各自位于归档中的顶层目录下
+    pub from: Vec<PathBuf>,
+    /// 与 `from` 一一对应的顶层目录名（--from-label）
+    pub from_label: Vec<String>,
+    /// 缓存记录中代替源路径的标识（--source-label）
+    pub source_label: Option<String>,
+    /// 备份目标目录（--to）
+    pub to: PathBuf,
+    /// 允许目标目录位于源目录内部并在扫描时排除它（--allow-nested-destination）
+    pub allow_nested_destination: bool,
+    /// 遗留的 `.partial` 文件和临时目录超过多少小时未修改才删除（--stale-grace-hours）
+    pub stale_grace_hours: u64,
+    /// 排除的 glob 模式（--exclude）
+    pub exclude: Vec<String>,
+    /// 不使用默认的临时文件排除模式（--no-default-excludes）
+    pub no_default_excludes: bool,
+    /// 备份源目录中本工具生成的归档和缓存文件（--include-own-artifacts）
+    pub include_own_artifacts: bool,
+    /// 只备份源目录中的这些子目录（--subdir）
+    pub subdir: Vec<PathBuf>,
+    /// 最大扫描深度（--max-depth）
+    pub max_depth: Option<NonZeroUsize>,
+    /// 确定文件所属月份的依据（--month-source）
+    pub month_source: MonthSource,
+    /// `month_source` 为 [`MonthSource::Path`] 时匹配年份和月份的正则（--month-pattern）
+    pub month_pattern: String,
+    /// 推迟最近这么多秒内修改的文件到下次运行（--settle-seconds）
+    pub settle_seconds: u32,
+    /// 修改时间超过现在这么多秒视为在未来（--future-skew-seconds）
+    pub future_skew_seconds: u32,
+    /// 把修改时间在未来的文件归入当月（--include-future-mtimes）
+    pub include_future_mtimes: bool,
+    /// 不读取源目录中的 .backupignore（--no-backupignore）
+    pub no_backupignore: bool,
+    /// 只备份匹配的 glob 模式（--include）
+    pub include: Vec<String>,
+    /// 只备份这些扩展名（--ext）
+    pub ext: Vec<String>,
+    /// 跳过小于此字节数的文件（--min-file-size），0 表示不限制
+    pub min_file_size: Option<u64>,
+    /// 跳过大于此字节数的文件（--max-file-size），0 表示不限制
+    pub max_file_size: Option<u64>,
+    /// 备份模式（-p、-n、-d、--month、--months-back、--catch-up、--since）
+    pub mode: BackupMode,
+    /// [`BackupMode::Explicit`] 备份的月份（--month）
+    pub months: Vec<BackupMonth>,
+    /// [`BackupMode::DateRange`] 的开始时间（--since）
+    pub since: Option<DateTime<Utc>>,
+    /// [`BackupMode::DateRange`] 的结束时间（--until），`None` 表示现在
+    pub until: Option<DateTime<Utc>>,
+    /// 按 ISO 周而不是月份备份（--weekly）
+    pub weekly: bool,
+    /// Dynamic 模式的 --dynamic-threshold-days
+    pub dynamic_threshold_days: u32,
+    /// Dynamic 模式的 --dynamic-lookahead-days
+    pub dynamic_lookahead_days: u32,
+    /// 按周的 Dynamic 模式的 --dynamic-week-threshold-days
+    pub dynamic_week_threshold_days: u32,
+    /// 代替今天计算备份月份的日期（--as-of）
+    pub as_of: Option<NaiveDate>,
+    /// 计算月份边界的时区（--month-boundary-tz）
+    pub month_boundary_tz: MonthBoundaryTz,
+    /// 忽略缓存中的截止时间，归档月份中的所有文件（--ignore-cache-cutoff）
+    pub ignore_cache_cutoff: bool,
+    /// 报告更详细的信息，例如被跳过的每个文件（--verbose）
+    pub verbose: bool,
+    /// 只报告将要备份和删除的内容，不写入任何文件（--dry-run）
+    pub dry_run: bool,
+    /// 保留备份的月数（--keep-months），0 表示不按时间删除
+    pub keep_months: u32,
+    /// 按创建时间还是备份的月份计算保留期限（--retention-by）
+    pub retention_by: RetentionBy,
+    /// 按时间删除时至少保留的归档数（--keep-min）
+    pub keep_min: usize,
+    /// 保留每周备份的周数（--keep-weeks）
+    pub keep_weeks: u32,
+    /// 滚动删除的策略（--retention-policy）
+    pub retention_policy: RetentionPolicy,
+    /// GFS 保留每月最新归档的月数（--gfs-monthly）
+    pub gfs_monthly: u32,
+    /// GFS 保留每年最新归档的年数（--gfs-yearly）
+    pub gfs_yearly: Option<u32>,
+    /// 目标目录中归档的总大小上限（--max-destination-size）
+    pub max_destination_size: Option<u64>,
+    /// 每个月份只保留最新的归档（--dedupe-months）
+    pub dedupe_months: bool,
+    /// 把删除的归档移入回收站（--trash）
+    pub trash: bool,
+    /// 清除回收站中超过这么多天的归档（--purge-trash-days）
+    pub purge_trash_days: Option<u32>,
+    /// 只报告将要删除的归档（--cleanup-dry-run）
+    pub cleanup_dry_run: bool,
+    /// 在目标目录的子目录中也滚动删除（--cleanup-recursive）
+    pub cleanup_recursive: bool,
+    /// 滚动删除失败不影响运行结果（--ignore-cleanup-errors）
+    pub ignore_cleanup_errors: bool,
+    /// 等待另一个实例结束的秒数（--lock-wait-seconds）
+    pub lock_wait_seconds: u64,
+    /// 其他需要滚动删除的归档的名称模式（--cleanup-extra-pattern）
+    pub cleanup_extra_pattern: Vec<Regex>,
+    /// `cleanup_extra_pattern` 中时间戳的格式（--cleanup-extra-format）
+    pub cleanup_extra_format: Option<String>,
+    /// 不经确认删除旧备份（--yes）
+    pub yes: bool,
+    /// 缓存中保留的记录数（--cache-history-limit），0 表示不限制
+    pub cache_history_limit: u64,
+    /// 从目标目录中的归档重建缓存记录（--rebuild-cache）
+    pub rebuild_cache: bool,
+    /// 代替 `<to>/.cache` 的缓存目录（--cache-dir）
+    pub cache_dir: Option<PathBuf>,
+    /// 缓存的存储方式（--cache-backend）
+    pub cache_backend: CacheBackend,
+    /// 压缩级别（--compression-level）
+    pub compression_level: u32,
+    /// 归档密码（--password、--password-file），只支持 zip 归档
+    pub password: Option<String>,
+    /// 符号链接的处理方式（--symlinks）
+    pub symlinks: SymlinkPolicy,
+    /// 归档格式（--archive-format）
+    pub archive_format: ArchiveFormat,
+    /// 分卷大小（--split-size）
+    pub split_size: Option<u64>,
+    /// 不压缩直接存储的扩展名（--store-extensions），`None` 表示默认列表
+    pub store_extensions: Option<Vec<String>>,
+    /// 所有文件都压缩（--no-store-heuristic）
+    pub no_store_heuristic: bool,
+    /// 不校验写入的归档（--no-verify）
+    pub no_verify: bool,
+    /// 被占用的文件的重试次数（--locked-file-retries）
+    pub locked_file_retries: u32,
+    /// 截止时间向前留出的重叠秒数（--overlap-seconds）
+    pub overlap_seconds: u32,
+    /// 有文件被跳过时该月份视为失败（--fail-on-skip）
+    pub fail_on_skip: bool,
+    /// 在归档旁写入被跳过文件的列表（--write-skip-report）
+    pub write_skip_report: bool,
+    /// 两次重试之间的毫秒数（--retry-delay-ms）
+    pub retry_delay_ms: u64,
+    /// 追加到当月已有的归档（--append）
+    pub append: bool,
+    /// 创建可重现的归档（--reproducible）
+    pub reproducible: bool,
+    /// 创建全量归档（--full）
+    pub full: bool,
+    /// 创建基于全量归档的增量归档（--incremental）
+    pub incremental: bool,
+    /// 归档加密给这些 age 接收者（--encrypt-to）
+    pub encrypt_to: Vec<age::x25519::Recipient>,
+    /// 每个顶层目录单独归档（--split-by-top-dir）
+    pub split_by_top_dir: bool,
+    /// 跳过内容未变化的文件（--dedup）
+    pub dedup: bool,
+    /// 判断文件是否变化的方式（--detect-changes）
+    pub detect_changes: ChangeDetection,
+    /// 并行压缩时在内存中缓冲的最大字节数（--max-parallel-bytes）
+    pub max_parallel_bytes: u64,
+    /// 扫描线程数（--scan-threads），`None` 表示按 CPU 数
+    pub scan_threads: Option<NonZeroUsize>,
+    /// 一个月份的扫描结果最多的文件数（--max-files）
+    pub max_files: Option<usize>,
+    /// 一个月份的扫描结果最大的总字节数（--max-total-size）
+    pub max_total_size: Option<u64>,
+    /// 超出 `max_files`、`max_total_size` 时仍然备份（--force）
+    pub force: bool,
+    /// 归档后目标磁盘至少剩余的字节数（--min-free-space）
+    pub min_free_space: Option<u64>,
+    /// 估计所需磁盘空间时的预期压缩率（--expected-compression-ratio）
+    pub expected_compression_ratio: f64,
+    /// 查询目标目录剩余空间的方式，默认通过操作系统查询
+    pub free_space: Box<dyn disk_space::FreeSpaceProvider + Send + Sync>,
+    /// 接收消息和进度的回调，`None` 时不报告任何内容
+    pub on_event: Option<EventHandler>,
+    /// 未设置 `yes` 时确认滚动删除的回调；`None` 时不删除需要确认的旧备份
+    pub confirm_cleanup: Option<ConfirmHandler>,
+}
+
+impl BackupOptions {
+    /// 创建把 `from` 备份到 `to` 的设置，其余选项为命令行的默认值
+    pub fn new(from: Vec<PathBuf>, to: impl Into<PathBuf>, mode: BackupMode) -> Self {
+        BackupOptions {
+            from,
+            from_label: Vec::new(),
+            source_label: None,
+            to: to.into(),
+            allow_nested_destination: false,
+            stale_grace_hours: 24,
+            exclude: Vec::new(),
+            no_default_excludes: false,
+            include_own_artifacts: false,
+            subdir: Vec::new(),
+            max_depth: None,
+            month_source: MonthSource::default(),
+            month_pattern: file_scanner::DEFAULT_MONTH_PATTERN.to_string(),
+            settle_seconds: 0,
+            future_skew_seconds: 24 * 60 * 60,
+            include_future_mtimes: false,
+            no_backupignore: false,
+            include: Vec::new(),
+            ext: Vec::new(),
+            min_file_size: None,
+            max_file_size: None,
+            mode,
+            months: Vec::new(),
+            since: None,
+            until: None,
+            weekly: false,
+            dynamic_threshold_days: 7,
+            dynamic_lookahead_days: 0,
+            dynamic_week_threshold_days: 2,
+            as_of: None,
+            month_boundary_tz: MonthBoundaryTz::default(),
+            ignore_cache_cutoff: false,
+            verbose: false,
+            dry_run: false,
+            keep_months: 6,
+            retention_by: RetentionBy::default(),
+            keep_min: 1,
+            keep_weeks: 0,
+            retention_policy: RetentionPolicy::default(),
+            gfs_monthly: 12,
+            gfs_yearly: None,
+            max_destination_size: None,
+            dedupe_months: false,
+            trash: false,
+            purge_trash_days: None,
+            cleanup_dry_run: false,
+            cleanup_recursive: false,
+            ignore_cleanup_errors: false,
+            lock_wait_seconds: 0,
+            cleanup_extra_pattern: Vec::new(),
+            cleanup_extra_format: None,
+            yes: false,
+            cache_history_limit: 500,
+            rebuild_cache: false,
+            cache_dir: None,
+            cache_backend: CacheBackend::default(),
+            compression_level: 6,
+            password: None,
+            symlinks: SymlinkPolicy::default(),
+            archive_format: ArchiveFormat::default(),
+            split_size: None,
+            store_extensions: None,
+            no_store_heuristic: false,
+            no_verify: false,
+            locked_file_retries: 3,
+            overlap_seconds: 2,
+            fail_on_skip: false,
+            write_skip_report: false,
+            retry_delay_ms: 500,
+            append: false,
+            reproducible: false,
+            full: false,
+            incremental: false,
+            encrypt_to: Vec::new(),
+            split_by_top_dir: false,
+            dedup: false,
+            detect_changes: ChangeDetection::default(),
+            max_parallel_bytes: 256 << 20,
+            scan_threads: None,
+            max_files: None,
+            max_total_size: None,
+            force: false,
+            min_free_space: None,
+            expected_compression_ratio: 1.0,
+            free_space: Box::new(disk_space::SystemFreeSpace),
+            on_event: None,
+            confirm_cleanup: None,
+        }
+    }
+}
+
+/// 本次运行中在扫描后被过滤掉、没有归档的文件，用于结束时的汇总
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct FilteredFiles {
+    /// 因内容未变化而被 --dedup 跳过的文件数
+    pub deduplicated: usize,
+    /// 按 --detect-changes 与变化检测索引比较后未变化的文件数
+    pub unchanged: usize,
+    /// 匹配默认排除模式的文件数
+    pub default_excluded: usize,
+    /// 匹配默认排除模式而未进入的目录，同一个目录只计一次
+    pub default_excluded_dirs: BTreeSet<PathBuf>,
+    /// 匹配 --exclude 的文件数
+    pub excluded: usize,
+    /// 匹配 --exclude 而未进入的目录；每个月份都会扫描一遍，同一个目录只计一次
+    pub excluded_dirs: BTreeSet<PathBuf>,
+    /// 超出 --min-file-size/--max-file-size 限制的文件数
+    pub outside_size_limits: usize,
+    /// --symlinks skip 跳过的符号链接，同样只计一次
+    pub skipped_symlinks: BTreeSet<PathBuf>,
+    /// 修改时间在未来的文件，同样只计一次
+    pub future_mtimes: BTreeSet<PathBuf>,
+    /// 源目录中本工具之前生成的归档和缓存文件，同样只计一次
+    pub own_artifacts: BTreeSet<PathBuf>,
+}
+
+/// 一个月份（或 ISO 周、时间范围）的备份结果
+///
+/// 与 [`BackupReport`] 一起序列化为命令行 `--output json` 的输出。
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct MonthReport {
+    pub period: BackupPeriod,
+    /// 失败时该月份的截止时间不推进，下次运行重新扫描
+    pub status: cache::MonthStatus,
+    /// 成功创建的归档的统计；使用 --split-by-top-dir 时每个分组一项，
+    /// 名称为 `<月份>_<分组>`，否则名称为月份
+    #[serde(serialize_with = "serialize_archives")]
+    pub archives: Vec<(String, ArchiveStats)>,
+    /// 被占用或无法读取而跳过的文件
+    pub skipped_files: Vec<cache::SkippedFileRecord>,
+    /// --dry-run 时本应归档的文件数
+    pub dry_run_files: usize,
+    /// --dry-run 时本应归档的总字节数
+    pub dry_run_bytes: u64,
+    /// 扫描或归档该月份时的错误，按发生顺序
+    pub errors: Vec<BackupError>,
+}
+
+/// [`run_backup`] 的结果
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct BackupReport {
+    pub mode: BackupMode,
+    /// 处理过的每个月份，按处理顺序；没有需要备份的月份时为空
+    pub months: Vec<MonthReport>,
+    /// 成功创建的归档数
+    pub succeeded_archives: usize,
+    /// 未能创建（创建失败、未通过校验、空间不足、扫描出错、未能加密）的归档数
+    pub failed_archives: usize,
+    pub filtered: FilteredFiles,
+    /// 滚动删除的结果；没有执行或出错时为 `None`
+    pub cleanup: Option<CleanupReport>,
+    /// 滚动删除出错或有旧备份未能删除，且没有设置 `ignore_cleanup_errors`
+    pub cleanup_failed: bool,
+    /// 是否写入了缓存（新的运行记录或清理后更新的记录）
+    pub cache_updated: bool,
+    /// 不属于某个月份的错误：滚动删除或写入缓存失败
+    pub errors: Vec<BackupError>,
+}
+
+/// 序列化后的一个归档：名称、写入的文件数和统计
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct NamedArchive<'a> {
+    name: &'a str,
+    archived_files: usize,
+    #[serde(flatten)]
+    stats: &'a ArchiveStats,
+}
+
+fn serialize_archives<S: serde::Serializer>(
+    archives: &[(String, ArchiveStats)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(archives.iter().map(|(name, stats)| NamedArchive {
+        name,
+        archived_files: stats.archived_files(),
+        stats,
+    }))
+}
+
+impl BackupReport {
+    /// 本次运行成功创建的所有归档的名称和统计
+    pub fn archives(&self) -> impl Iterator<Item = &(String, ArchiveStats)> {
+        self.months.iter().flat_map(|month| &month.archives)
+    }
+
+    /// --dry-run 时本应归档的文件数和总字节数
+    pub fn dry_run_totals(&self) -> (usize, u64) {
+        self.months.iter().fold((0, 0), |(files, bytes), month| {
+            (files + month.dry_run_files, bytes + month.dry_run_bytes)
+        })
+    }
+}
+
+/// 把事件交给 [`BackupOptions::on_event`]，没有回调时丢弃
+struct Events<'a>(Option<&'a (dyn Fn(BackupEvent<'_>) + Send + Sync)>);
+
+impl Events<'_> {
+    fn emit(&self, event: BackupEvent<'_>) {
+        if let Some(on_event) = self.0 {
+            on_event(event);
+        }
+    }
+
+    fn info(&self, message: impl Into<String>) {
+        self.emit(BackupEvent::Info(message.into()));
+    }
+
+    fn warning(&self, message: impl Into<String>) {
+        self.emit(BackupEvent::Warning(message.into()));
+    }
+
+    fn alert(&self, message: impl Into<String>) {
+        self.emit(BackupEvent::Alert(message.into()));
+    }
+
+    fn error(&self, message: impl Into<String>) {
+        self.emit(BackupEvent::Error(message.into()));
+    }
+
+    fn cleanup(&self, event: CleanupEvent) {
+        match event {
+            CleanupEvent::Info(message) => self.info(message),
+            CleanupEvent::Warning(message) => self.warning(message),
+        }
+    }
+}
+
+/// 错误的原因；消息中已经给出路径时使用，不再重复变体中的路径
+fn reason(error: &BackupError) -> String {
+    match error.io_error() {
+        Some(source) => source.to_string(),
+        None => error.to_string(),
+    }
+}
+
+/// 扫描进度的描述，例如 `120 directories, 5000 files, 37 matched`
+pub fn describe_scan_progress(progress: &ScanProgress) -> String {
+    format!(
+        "{} directories, {} files, {} matched",
+        progress.dirs_visited, progress.files_considered, progress.files_matched
+    )
+}
+
+/// 检查一个月份的扫描结果是否超出 --max-files/--max-total-size
+///
+/// # Returns
+/// 超出时返回说明文件数、总大小及最大的三个文件的信息，否则返回 `None`
+fn scan_limit_message(
+    label: &str,
+    files: &[file_scanner::FileEntry],
+    max_files: Option<usize>,
+    max_total_size: Option<u64>,
+) -> Option<String> {
+    let total_size: u64 = files.iter().map(|f| f.size).sum();
+    let mut exceeded = Vec::new();
+    if let Some(max) = max_files.filter(|&max| files.len() > max) {
+        exceeded.push(format!("--max-files {}", max));
+    }
+    if let Some(max) = max_total_size.filter(|&max| total_size > max) {
+        exceeded.push(format!("--max-total-size {}", HumanBytes(max)));
+    }
+    if exceeded.is_empty() {
+        return None;
+    }
+
+    let mut largest: Vec<&file_scanner::FileEntry> = files.iter().collect();
+    largest.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    let mut message = format!(
+        "The scan for {} found {} files ({}), more than {}. Largest files:",
+        label,
+        files.len(),
+        HumanBytes(total_size),
+        exceeded.join(" and ")
+    );
+    for file in largest.into_iter().take(3) {
+        message.push_str(&format!(
+            "\n  {} ({})",
+            file.path.display(),
+            HumanBytes(file.size)
+        ));
+    }
+    Some(message)
+}
+
+/// 在归档旁写入被跳过文件的列表（`<归档文件名>.skipped_files.txt`），每行一个文件及原因
+fn write_skip_report(archive_path: &Path, skipped_files: &[SkippedFile]) -> io::Result<PathBuf> {
+    let report_path = archiver::with_suffix(archive_path, archiver::SKIP_REPORT_SUFFIX);
+    let content: String = skipped_files
+        .iter()
+        .map(|skipped| format!("{}\t{}\n", skipped.path.display(), skipped.reason))
+        .collect();
+    fs::write(&report_path, content)?;
+    Ok(report_path)
+}
+
+/// 计算以 Stored 方式写入的扩展名列表（统一为小写、不含前导 `.`）
+fn store_extensions(options: &BackupOptions) -> Vec<String> {
+    if options.no_store_heuristic {
+        return Vec::new();
+    }
+    match &options.store_extensions {
+        Some(extensions) => extensions
+            .iter()
+            .map(|e| e.trim().trim_start_matches('.').to_ascii_lowercase())
+            .filter(|e| !e.is_empty())
+            .collect(),
+        None => DEFAULT_STORE_EXTENSIONS
+            .iter()
+            .map(|e| e.to_string())
+            .collect(),
+    }
+}
+
+/// 一个源目录 (--from) 及只作用于它的扫描设置
+struct SourceRoot {
+    path: PathBuf,
+    /// 归档中的顶层目录名；只有一个源目录且未指定 --from-label 时为 `None`
+    label: Option<String>,
+    /// 加载了该源目录 .backupignore 的扫描过滤规则
+    filters: file_scanner::ScanFilters,
+    /// 位于该源目录内部、需要在扫描时排除的目标目录
+    excluded_dirs: Vec<PathBuf>,
+}
+
+/// 确定每个源目录在归档中的顶层目录名，--from-label 数量不对或名称无效时返回错误
+fn source_labels(sources: &[PathBuf], labels: &[String]) -> Result<Vec<Option<String>>, String> {
+    if labels.is_empty() {
+        return Ok(match sources.len() {
+            1 => vec![None],
+            _ => (0..sources.len())
+                .map(|index| Some(format!("source{}", index)))
+                .collect(),
+        });
+    }
+    if labels.len() != sources.len() {
+        return Err(format!(
+            "--from-label was given {} times for {} --from paths",
+            labels.len(),
+            sources.len()
+        ));
+    }
+    for (index, label) in labels.iter().enumerate() {
+        if label.is_empty() || label == "." || label == ".." || label.contains(['/', '\\']) {
+            return Err(format!(
+                "--from-label '{}' must be a single folder name",
+                label
+            ));
+        }
+        if labels[..index].contains(label) {
+            return Err(format!("--from-label '{}' is given more than once", label));
+        }
+    }
+    Ok(labels.iter().cloned().map(Some).collect())
+}
+
+/// 把被跳过的文件转换为缓存记录，路径为文件在归档内的路径（由所属源目录确定）
+fn skipped_file_record(
+    sources: &[SourceRoot],
+    month: &str,
+    skipped: &SkippedFile,
+) -> cache::SkippedFileRecord {
+    let archive_path = sources
+        .iter()
+        .find_map(|source| {
+            let relative = skipped.path.strip_prefix(&source.path).ok()?;
+            Some(match &source.label {
+                Some(label) => Path::new(label).join(relative),
+                None => relative.to_path_buf(),
+            })
+        })
+        .unwrap_or_else(|| skipped.path.clone());
+    cache::SkippedFileRecord {
+        month: month.to_string(),
+        path: archiver::entry_name(&archive_path),
+        reason: skipped.reason.clone(),
+    }
+}
+
+/// 按 --cache-history-limit 把最旧的缓存记录移入 `.cache` 中按年份的归档文件
+///
+/// # Returns
+/// 移出了记录时返回 `true`；归档文件写入失败时记录留在缓存中，返回 `false`。
+fn trim_cache_history(
+    records: &mut Vec<cache::CacheRecord>,
+    limit: u64,
+    cache_folder: &Path,
+    events: &Events,
+) -> bool {
+    if limit == 0 {
+        return false;
+    }
+    let overflow = cache::limit_history(records, limit as usize, Utc::now());
+    if overflow.is_empty() {
+        return false;
+    }
+    match cache::archive_history(cache_folder, &overflow) {
+        Ok(()) => true,
+        Err(e) => {
+            events.warning(format!(
+                "Failed to move old cache records into '{}', they are kept: {}",
+                cache_folder.display(),
+                e
+            ));
+            records.extend(overflow);
+            records.sort_by_key(|r| r.end_time);
+            false
+        }
+    }
+}
+
+/// 本次运行的源在缓存记录中的标识（`SourceId`）：--source-label，
+/// 或规范化后的 --from 路径，多个时按给出的顺序以 `|` 连接
+pub fn source_id(sources: &[PathBuf], source_label: Option<&str>) -> String {
+    if let Some(label) = source_label {
+        return label.to_string();
+    }
+    sources
+        .iter()
+        .map(|path| {
+            fs::canonicalize(path)
+                .unwrap_or_else(|_| path.clone())
+                .display()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// --catch-up 备份的月份：从上次成功运行的截止时间所在的月份到当月
+///
+/// 上次成功的运行可能只备份了 --month、--weekly 或 --since 指定的时间段，之前的月份不一定
+/// 已经备份到月末，因此继续向前加上截止时间早于该月结束的月份，最多到最早一次成功运行的
+/// 截止时间所在的月份。没有成功的运行时返回 `None`。
+fn catch_up_months(
+    records: &[cache::CacheRecord],
+    source_id: &str,
+    now: DateTime<Utc>,
+    today: NaiveDate,
+    month_boundary_tz: &MonthBoundaryTz,
+) -> Option<Vec<BackupMonth>> {
+    let cutoff = |record: &cache::CacheRecord| record.cutoff_time.unwrap_or(record.start_time);
+    let last = cache::last_successful_backup(records, Some(source_id), now)?;
+    let first = cache::first_successful_backup(records, Some(source_id), now)?;
+    let earliest = backup_logic::months_between(cutoff(first), today)[0];
+    let mut months = backup_logic::months_between(cutoff(last), today);
+    while months[0] > earliest {
+        let previous = months[0].previous();
+        let month_end =
+            month_boundary_tz.start_of_day_utc(BackupPeriod::Month(months[0]).date_range().0);
+        let previous_cutoff = cache::get_last_backup_time_for_month(
+            records,
+            &BackupPeriod::Month(previous).label(),
+            source_id,
+            now,
+        );
+        if previous_cutoff >= month_end {
+            break;
+        }
+        months.insert(0, previous);
+    }
+    Some(months)
+}
+
+/// 编译 --month-pattern，模式无效或缺少年份、月份两个捕获组时返回错误
+fn month_pattern(pattern: &str) -> Result<Regex, BackupError> {
+    match Regex::new(pattern) {
+        Ok(regex) if regex.captures_len() >= 3 => Ok(regex),
+        Ok(_) => Err(BackupError::ConfigError(format!(
+            "--month-pattern '{}' needs two capture groups (year and month)",
+            pattern
+        ))),
+        Err(e) => Err(BackupError::ConfigError(format!(
+            "Invalid --month-pattern '{}': {}",
+            pattern, e
+        ))),
+    }
+}
+
+/// 列出不存在的路径，一次说明所有缺少的源目录或子目录
+fn missing_paths_message(kind: &str, paths: &[&Path]) -> String {
+    let quoted: Vec<String> = paths
+        .iter()
+        .map(|path| format!("'{}'", path.display()))
+        .collect();
+    if quoted.len() == 1 {
+        format!("The {} {} does not exist.", kind, quoted[0])
+    } else {
+        format!("The {}s {} do not exist.", kind, quoted.join(", "))
+    }
+}
+
+/// 检查相互矛盾的选项
+fn validate(options: &BackupOptions) -> Result<(), BackupError> {
+    let error = |message: &str| Err(BackupError::ConfigError(message.to_string()));
+    if options.from.is_empty() {
+        return error("At least one source path (--from) is required.");
+    }
+    // GFS 只保留每月最新的归档，会删除增量归档所依赖的全量归档
+    if options.retention_policy == RetentionPolicy::Gfs && (options.full || options.incremental) {
+        return error("--retention-policy gfs cannot be used with --full or --incremental.");
+    }
+    if options.weekly
+        && !matches!(
+            options.mode,
+            BackupMode::PreviousMonth | BackupMode::CurrentMonth | BackupMode::Dynamic
+        )
+    {
+        return error("--weekly can only be used with -p, -n or -d.");
+    }
+    // 路径中只有月份，无法确定文件属于哪一周或时间范围
+    if options.weekly && options.month_source == MonthSource::Path {
+        return error("--weekly cannot be used with --month-source path.");
+    }
+    if options.mode == BackupMode::DateRange && options.month_source == MonthSource::Path {
+        return error("--since cannot be used with --month-source path.");
+    }
+    if options.mode == BackupMode::DateRange && options.since.is_none() {
+        return error("A date range backup needs a start time (--since).");
+    }
+    if options.mode == BackupMode::Explicit && options.months.is_empty() {
+        return error("An explicit backup needs at least one month (--month).");
+    }
+    // 与命令行上的互斥选项一致：分卷和追加都会改变归档文件名和内容，
+    // 破坏可复现的文件名以及全量/增量链中的基础归档
+    let split = options.split_size.is_some();
+    let chain = if options.full {
+        "--full"
+    } else {
+        "--incremental"
+    };
+    let conflicts = [
+        (options.append && split, "--append", "--split-size"),
+        (
+            options.reproducible && split,
+            "--reproducible",
+            "--split-size",
+        ),
+        (
+            options.reproducible && options.append,
+            "--reproducible",
+            "--append",
+        ),
+        // 加密后的归档无法再合并新文件
+        (
+            options.append && !options.encrypt_to.is_empty(),
+            "--append",
+            "--encrypt-to",
+        ),
+        (
+            options.reproducible && options.password.is_some(),
+            "--reproducible",
+            "--password",
+        ),
+        (
+            options.full && options.incremental,
+            "--full",
+            "--incremental",
+        ),
+        (
+            (options.full || options.incremental) && split,
+            chain,
+            "--split-size",
+        ),
+        (
+            (options.full || options.incremental) && options.append,
+            chain,
+            "--append",
+        ),
+        (
+            (options.full || options.incremental) && options.dedupe_months,
+            chain,
+            "--dedupe-months",
+        ),
+    ];
+    if let Some((_, first, second)) = conflicts.iter().find(|(conflict, _, _)| *conflict) {
+        return error(&format!("{} cannot be used with {}.", first, second));
+    }
+    // 回收站位于目标目录中，移入回收站不会减小目标目录的总大小
+    if options.trash && options.max_destination_size.is_some() {
+        return error("--trash cannot be used with --max-destination-size.");
+    }
+    if options.append && options.archive_format != ArchiveFormat::Zip {
+        return error("--append is only supported for zip archives.");
+    }
+    if options.password.is_some() && options.archive_format != ArchiveFormat::Zip {
+        return error("Password protection is only supported for zip archives.");
+    }
+    Ok(())
+}
+
+/// 获取目标目录的运行锁；--dry-run 不写入目标目录，因此不加锁
+fn lock_destination(
+    options: &BackupOptions,
+    destination_path: &Path,
+    cache_folder: &Path,
+) -> Result<Option<cache::RunLock>, BackupError> {
+    if options.dry_run {
+        return Ok(None);
+    }
+    match cache::acquire_run_lock(cache_folder, Duration::from_secs(options.lock_wait_seconds)) {
+        Ok(lock) => Ok(Some(lock)),
+        // 报告的是目标目录而不是（可能由 --cache-dir 指定的）缓存目录
+        Err(BackupError::AlreadyRunning { source, .. }) => Err(BackupError::AlreadyRunning {
+            path: destination_path.to_path_buf(),
+            source,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// 按保留设置滚动删除旧备份，结果记入 `report`
+fn clean_destination(
+    options: &BackupOptions,
+    destination_path: &Path,
+    events: &Events<'_>,
+    report: &mut BackupReport,
+) {
+    let cleanup_dry_run = options.dry_run || options.cleanup_dry_run;
+    let retention = cleaner::RetentionOptions {
+        keep_months: options.keep_months,
+        keep_weeks: options.keep_weeks,
+        retention_by: options.retention_by,
+        keep_min: options.keep_min,
+        policy: options.retention_policy,
+        gfs_monthly: options.gfs_monthly,
+        gfs_yearly: options.gfs_yearly,
+        max_total_size: options.max_destination_size,
+        dedupe_months: options.dedupe_months,
+        trash: options.trash,
+        purge_trash_days: options.purge_trash_days,
+        recursive: options.cleanup_recursive,
+        password: options.password.clone(),
+        extra_patterns: options.cleanup_extra_pattern.clone(),
+        extra_timestamp_format: options.cleanup_extra_format.clone(),
+    };
+    // 滚动删除前的确认：没有 --yes 时交给调用方，调用方无法确认时不删除
+    let confirm = |candidates: &[DeletionCandidate]| {
+        if options.yes {
+            return true;
+        }
+        match &options.confirm_cleanup {
+            Some(confirm) => confirm(candidates),
+            None => {
+                events.alert(format!(
+                    "Not removing {} old backups without confirmation; pass --yes to remove them.",
+                    candidates.len()
+                ));
+                false
+            }
+        }
+    };
+    let mut cleanup_error = false;
+    if (options.keep_months > 0
+        || options.keep_weeks > 0
+        || options.retention_policy == RetentionPolicy::Gfs
+        || options.max_destination_size.is_some()
+        || options.dedupe_months
+        || options.purge_trash_days.is_some())
+        && destination_path.exists()
+    {
+        match cleaner::cleanup_old_backups(
+            destination_path,
+            &retention,
+            cleanup_dry_run,
+            &|event| events.cleanup(event),
+            &confirm,
+        ) {
+            Ok(cleanup) => {
+                events.emit(BackupEvent::CleanupFinished {
+                    report: &cleanup,
+                    dry_run: cleanup_dry_run,
+                });
+                report.cleanup = Some(cleanup);
+            }
+            Err(e) => {
+                events.error(format!("An error occurred during cleanup: {}", reason(&e)));
+                report.errors.push(e);
+                cleanup_error = true;
+            }
+        }
+    }
+    // 未能删除的旧备份会使目标目录的空间逐渐耗尽，除非 --ignore-cleanup-errors 否则视为失败
+    report.cleanup_failed = !options.ignore_cleanup_errors
+        && (cleanup_error
+            || report
+                .cleanup
+                .as_ref()
+                .is_some_and(|r| !r.failed.is_empty()));
+}
+
+/// 清理删除了归档时，标记归档已不存在的缓存记录；返回是否有记录改变
+fn prune_deleted_records(
+    report: &BackupReport,
+    cache_records: &mut [cache::CacheRecord],
+    destination_path: &Path,
+) -> bool {
+    report
+        .cleanup
+        .as_ref()
+        .is_some_and(|cleanup| !cleanup.deleted.is_empty())
+        && cache::prune_records_for_deleted(cache_records, destination_path, Utc::now()) > 0
+}
+
+/// 只滚动删除旧备份（命令行的 `clean`）：不扫描、不归档，按 `options` 中的保留设置清理
+/// `options.to`，并在 `.cache` 中标记已删除的归档
+///
+/// 只使用保留、缓存和锁相关的选项；`options.from` 可以为空。
+///
+/// # Returns
+/// 清理的结果在 [`BackupReport::cleanup`] 中，`months` 为空。目标目录不存在时返回
+/// [`BackupError::CleanupError`]，无法读写缓存或另一个实例正在运行时同 [`run_backup`]。
+pub fn run_cleanup(options: BackupOptions) -> Result<BackupReport, BackupError> {
+    let events = Events(options.on_event.as_deref());
+    let destination_path = options.to.clone();
+    if !destination_path.is_dir() {
+        return Err(BackupError::CleanupError {
+            source: io::Error::new(
+                io::ErrorKind::NotFound,
+                missing_paths_message("destination path", &[&destination_path]),
+            ),
+            path: destination_path,
+        });
+    }
+    let cache_folder = options
+        .cache_dir
+        .clone()
+        .unwrap_or_else(|| destination_path.join(".cache"));
+    if !cache_folder.exists() && !options.dry_run {
+        fs::create_dir_all(&cache_folder).map_err(cache_error(&cache_folder))?;
+    }
+    let _run_lock = lock_destination(&options, &destination_path, &cache_folder)?;
+    let cache_store = cache::open_store(options.cache_backend, &cache_folder, options.dry_run)?;
+    let mut cache_records = cache_store.read_records()?;
+
+    let mut report = BackupReport {
+        mode: options.mode,
+        months: Vec::new(),
+        succeeded_archives: 0,
+        failed_archives: 0,
+        filtered: FilteredFiles::default(),
+        cleanup: None,
+        cleanup_failed: false,
+        cache_updated: false,
+        errors: Vec::new(),
+    };
+    clean_destination(&options, &destination_path, &events, &mut report);
+    if !options.dry_run && prune_deleted_records(&report, &mut cache_records, &destination_path) {
+        match cache_store.write_records(&cache_records) {
+            Ok(()) => report.cache_updated = true,
+            Err(e) => {
+                events.error(format!("Failed to write to cache file: {}", reason(&e)));
+                report.errors.push(e);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// 运行一次备份：扫描各个月份的新文件并归档，滚动删除旧备份，更新 `.cache` 中的记录
+///
+/// 进度和消息通过 [`BackupOptions::on_event`] 报告，本函数不输出任何内容。
+///
+/// # Returns
+/// 各个月份的结果；单个月份或清理失败不视为错误，需要检查 [`BackupReport`]。
+/// 选项无效（[`BackupError::ConfigError`]）、源目录不存在（[`BackupError::ScanError`]）、
+/// 无法创建目标目录（[`BackupError::ArchiveError`]）、无法读写缓存（[`BackupError::CacheError`]）
+/// 或另一个实例正在运行（[`BackupError::AlreadyRunning`]）时返回错误，此时没有创建任何归档。
+pub fn run_backup(options: BackupOptions) -> Result<BackupReport, BackupError> {
+    let events = Events(options.on_event.as_deref());
+    validate(&options)?;
+    let destination_path = options.to.clone();
+    let labels =
+        source_labels(&options.from, &options.from_label).map_err(BackupError::ConfigError)?;
+
+    let script_start_time = Utc::now(); // 1. 记录脚本开始时间
+
+    // 0. 预检查；一次列出所有不存在的源目录
+    let missing_sources: Vec<&Path> = options
+        .from
+        .iter()
+        .map(PathBuf::as_path)
+        .filter(|path| !path.exists())
+        .collect();
+    if let Some(&first) = missing_sources.first() {
+        return Err(BackupError::ScanError {
+            path: first.to_path_buf(),
+            source: io::Error::new(
+                io::ErrorKind::NotFound,
+                missing_paths_message("source path", &missing_sources),
+            ),
+        });
+    }
+    // --subdir 必须存在于每个源目录中
+    let missing_subdirs: Vec<PathBuf> = options
+        .from
+        .iter()
+        .flat_map(|source| options.subdir.iter().map(move |subdir| source.join(subdir)))
+        .filter(|path| !path.is_dir())
+        .collect();
+    if let Some(first) = missing_subdirs.first() {
+        let missing: Vec<&Path> = missing_subdirs.iter().map(PathBuf::as_path).collect();
+        return Err(BackupError::ScanError {
+            path: first.clone(),
+            source: io::Error::new(
+                io::ErrorKind::NotFound,
+                missing_paths_message("subdirectory", &missing),
+            ),
+        });
+    }
+    // 试运行不创建目标目录，之后也不会写入或删除其中的任何文件
+    if !destination_path.exists() && options.dry_run {
+        events.info(format!(
+            "Dry run: The destination path '{}' does not exist and would be created.",
+            destination_path.display()
+        ));
+    } else if !destination_path.exists() {
+        events.info(format!(
+            "The destination path '{}' does not exist. Creating...",
+            destination_path.display()
+        ));
+        fs::create_dir_all(&destination_path).map_err(archive_error(&destination_path))?;
+    }
+
+    let mut scan_filters =
+        file_scanner::ScanFilters::new(&options.exclude, &options.include, &options.ext).map_err(
+            |e| BackupError::ConfigError(format!("Invalid --exclude or --include pattern: {}", e)),
+        )?;
+    // 0 表示不限制
+    scan_filters.min_file_size = options.min_file_size.filter(|&size| size > 0);
+    scan_filters.max_file_size = options.max_file_size.filter(|&size| size > 0);
+    scan_filters.symlinks = options.symlinks;
+    if !options.no_default_excludes {
+        scan_filters.use_default_excludes();
+    }
+    if !options.include_own_artifacts {
+        scan_filters.skip_own_artifacts();
+    }
+    scan_filters.max_depth = options.max_depth.map(NonZeroUsize::get);
+    scan_filters.future_cutoff =
+        Some(Utc::now() + chrono::Duration::seconds(options.future_skew_seconds as i64));
+    if options.include_future_mtimes {
+        let today = options.month_boundary_tz.date_of(Utc::now());
+        scan_filters.future_month = Some(if options.weekly {
+            BackupPeriod::IsoWeek(BackupWeek::containing(today))
+        } else {
+            determine_backup_months(
+                &BackupMode::CurrentMonth,
+                &DynamicThresholds::default(),
+                today,
+            )[0]
+            .into()
+        });
+    }
+    scan_filters.subdirs = options.subdir.clone();
+    scan_filters.month_boundary_tz = options.month_boundary_tz.clone();
+    if options.month_source == MonthSource::Path {
+        scan_filters.month_pattern = Some(month_pattern(&options.month_pattern)?);
+    }
+
+    // 扫描使用单独的线程池，线程数与压缩使用的全局线程池无关
+    let scan_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.scan_threads.map_or(0, NonZeroUsize::get))
+        .thread_name(|index| format!("scan-{}", index))
+        .build()
+        .map_err(|e| scan_error(&options.from[0])(io::Error::other(e)))?;
+
+    // 缓存目录：默认为目标目录中的 .cache，可用 --cache-dir 放在其他位置
+    let cache_folder = options
+        .cache_dir
+        .clone()
+        .unwrap_or_else(|| destination_path.join(".cache"));
+    // 指定的缓存目录先于扫描创建，它位于源目录内部时才能被识别并排除
+    if options.cache_dir.is_some() && !cache_folder.exists() && !options.dry_run {
+        fs::create_dir_all(&cache_folder).map_err(cache_error(&cache_folder))?;
+    }
+
+    let mut sources: Vec<SourceRoot> = Vec::new();
+    for (path, label) in options.from.iter().zip(labels) {
+        let mut filters = scan_filters.clone();
+        if !options.no_backupignore {
+            let ignore_path = path.join(file_scanner::BACKUP_IGNORE_FILE);
+            match filters.load_ignore_file(path, &ignore_path) {
+                Ok(true) => {
+                    events.info(format!("Using ignore rules from {}", ignore_path.display()))
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    return Err(BackupError::ConfigError(format!(
+                        "Invalid ignore file '{}': {}",
+                        ignore_path.display(),
+                        e
+                    )));
+                }
+            }
+        }
+
+        // 目标目录位于源目录内部时，扫描会把之前的归档和 .cache 也打包进去，归档每次都会变大
+        let nested = if destination_path.exists() {
+            file_scanner::nested_destination(path, &destination_path)
+        } else {
+            Ok(None)
+        };
+        let mut excluded_dirs = match nested {
+            // 排除整个源目录等于什么都不备份，--allow-nested-destination 也不适用
+            Ok(Some(nested)) if nested == *path => {
+                return Err(BackupError::ConfigError(format!(
+                    "The destination path '{}' is the source path itself. \
+                     Choose a destination outside the source.",
+                    destination_path.display()
+                )));
+            }
+            Ok(Some(nested)) if options.allow_nested_destination => vec![nested],
+            Ok(Some(_)) => {
+                return Err(BackupError::ConfigError(format!(
+                    "The destination path '{}' is inside the source path '{}'. \
+                     Choose another destination or pass --allow-nested-destination to exclude it from the backup.",
+                    destination_path.display(),
+                    path.display()
+                )));
+            }
+            Ok(None) => Vec::new(),
+            Err(e) => return Err(scan_error(path)(e)),
+        };
+        // --cache-dir 位于源目录内部时同样不备份，其中的锁文件和索引在运行期间不断变化
+        if options.cache_dir.is_some()
+            && cache_folder.exists()
+            && let Ok(Some(nested)) = file_scanner::nested_destination(path, &cache_folder)
+        {
+            if nested == *path {
+                return Err(BackupError::ConfigError(format!(
+                    "The cache folder '{}' is the source path itself. \
+                     Choose a cache folder outside the source.",
+                    cache_folder.display()
+                )));
+            }
+            excluded_dirs.push(nested);
+        }
+        sources.push(SourceRoot {
+            path: path.clone(),
+            label,
+            filters,
+            excluded_dirs,
+        });
+    }
+
+    // 1. 备份模式由调用方确定
+    let mode = options.mode;
+
+    // 2. 计算需要备份的月份；--catch-up 的月份在读取缓存后确定
+    let today = options
+        .as_of
+        .unwrap_or_else(|| options.month_boundary_tz.date_of(Utc::now()));
+    let months_to_backup: Vec<BackupPeriod> = if options.weekly {
+        backup_logic::determine_backup_weeks(&mode, options.dynamic_week_threshold_days, today)
+            .into_iter()
+            .map(BackupPeriod::IsoWeek)
+            .collect()
+    } else if mode == BackupMode::DateRange
+        && let Some(since) = options.since
+    {
+        let until = options.until.unwrap_or(script_start_time);
+        if since >= until {
+            return Err(BackupError::ConfigError(
+                "--since must be earlier than --until (or now).".to_string(),
+            ));
+        }
+        vec![BackupPeriod::Range(DateRange {
+            start: since,
+            end: until,
+        })]
+    } else if mode == BackupMode::Explicit {
+        backup_logic::explicit_backup_months(&options.months)
+            .into_iter()
+            .map(BackupPeriod::Month)
+            .collect()
+    } else {
+        determine_backup_months(
+            &mode,
+            &DynamicThresholds {
+                threshold_days: options.dynamic_threshold_days,
+                lookahead_days: options.dynamic_lookahead_days,
+            },
+            today,
+        )
+        .into_iter()
+        .map(BackupPeriod::Month)
+        .collect()
+    };
+    // 显式指定的月份通常用于重新创建损坏或丢失的归档，需要包含该月的所有文件；
+    // 时间范围同样包含其中的所有文件
+    let ignore_cutoff = options.ignore_cache_cutoff
+        || mode == BackupMode::Explicit
+        || mode == BackupMode::DateRange;
+
+    let mut report = BackupReport {
+        mode,
+        months: Vec::new(),
+        succeeded_archives: 0,
+        failed_archives: 0,
+        filtered: FilteredFiles::default(),
+        cleanup: None,
+        cleanup_failed: false,
+        cache_updated: false,
+        errors: Vec::new(),
+    };
+    if months_to_backup.is_empty() && mode != BackupMode::CatchUp {
+        return Ok(report);
+    }
+
+    // 3. 读取 .cache 并获取上次备份时间
+    if !cache_folder.exists() && !options.dry_run {
+        fs::create_dir_all(&cache_folder).map_err(cache_error(&cache_folder))?;
+    }
+    // 整个运行期间持有锁，防止同时运行的实例互相覆盖缓存、重复归档
+    let _run_lock = lock_destination(&options, &destination_path, &cache_folder)?;
+    // 删除之前被中断的运行遗留的 .partial 临时文件以及旧版本遗留的 UUID 临时目录，
+    // 只删除超过 --stale-grace-hours 未修改的，避免删除另一个实例正在写入的文件
+    let stale_age = Duration::from_secs(options.stale_grace_hours.saturating_mul(60 * 60));
+    if !options.dry_run
+        && let Err(e) = cleaner::remove_stale_partials(&destination_path, stale_age, &|event| {
+            events.cleanup(event)
+        })
+    {
+        events.warning(format!(
+            "Failed to remove stale partial archives: {}",
+            reason(&e)
+        ));
+    }
+    if !options.dry_run
+        && let Err(e) = cleaner::remove_stale_temp_dirs(&destination_path, stale_age, &|event| {
+            events.cleanup(event)
+        })
+    {
+        events.warning(format!(
+            "Failed to remove stale temporary directories: {}",
+            reason(&e)
+        ));
+    }
+
+    // 同一目标目录中其他源的记录不影响本次运行的截止时间
+    let source_id = source_id(&options.from, options.source_label.as_deref());
+
+    // --dry-run 不写入目标目录，只读打开缓存
+    let cache_store: Box<dyn CacheStore> =
+        cache::open_store(options.cache_backend, &cache_folder, options.dry_run)?;
+    log::debug!(
+        "Using the {:?} cache in '{}'",
+        options.cache_backend,
+        cache_folder.display()
+    );
+
+    let mut cache_records = cache_store.read_records()?;
+    if options.rebuild_cache {
+        cache_records = cache::records_from_archives(&destination_path)?;
+        // --dry-run 只使用重建的记录计算截止时间，不写入
+        if !options.dry_run {
+            cache_store.write_records(&cache_records)?;
+        }
+        events.info(format!(
+            "Rebuilt {} cache record(s) from the archives in {}",
+            cache_records.len(),
+            destination_path.display()
+        ));
+    } else if cache_records.is_empty()
+        && let Ok(records) = cache::records_from_archives(&destination_path)
+        && !records.is_empty()
+    {
+        // 缓存丢失时所有文件都会被当作新文件
+        events.alert(format!(
+            "No backup records found, but {} has {} archive(s). Everything since 1970 \
+             will be backed up again; run with --rebuild-cache to restore the records from them.",
+            destination_path.display(),
+            records.len()
+        ));
+    }
+    // 时间明显错误的记录不参与截止时间的计算
+    for record in &cache_records {
+        if let Some(reason) = cache::bogus_timestamp_reason(record, script_start_time) {
+            events.alert(format!(
+                "Ignoring cache record started at {} ({}). Check the system clock.",
+                record
+                    .start_time
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S"),
+                reason
+            ));
+        }
+    }
+
+    // 多个源共享目标目录时，后来的源的归档名带有源标记，不与其他源的归档混在一起；
+    // 缓存记录已重建（没有源标识）时，按目标目录中已有的带标记的归档继续使用标记
+    let source_tag = cache::archive_source_tag(&cache_records, &source_id).or_else(|| {
+        let tag = archiver::source_tag(&source_id);
+        archiver::find_archives(&destination_path)
+            .ok()?
+            .iter()
+            .any(|(_, info)| info.source.as_deref() == Some(tag.as_str()))
+            .then_some(tag)
+    });
+
+    // --catch-up 从上次成功运行的截止时间所在的月份备份到当月；
+    // 没有成功的运行时只备份当月，而不是从 1970 年开始的每个月
+    let months_to_backup = if mode == BackupMode::CatchUp {
+        let months = catch_up_months(
+            &cache_records,
+            &source_id,
+            script_start_time,
+            today,
+            &options.month_boundary_tz,
+        )
+        .unwrap_or_else(|| {
+            events.warning(
+                "No successful backup found in the cache; --catch-up backs up the current month only.",
+            );
+            backup_logic::months_between(script_start_time, today)
+        });
+        months.into_iter().map(BackupPeriod::Month).collect()
+    } else {
+        months_to_backup
+    };
+
+    // 之前的运行中被跳过、尚未归档的文件，扫描其月份时不论修改时间都会重新尝试
+    let previously_skipped =
+        cache::outstanding_skipped_files(&cache_records, &source_id, script_start_time).to_vec();
+    if !previously_skipped.is_empty() {
+        events.info(format!(
+            "Retrying {} files skipped by previous runs.",
+            previously_skipped.len()
+        ));
+    }
+
+    // 每个月份的截止时间向前留出重叠窗口，修改时间被截断到整秒的文件不会被遗漏
+    let overlap = chrono::Duration::seconds(options.overlap_seconds as i64);
+
+    // 启用 --dedup 时读取上次归档时记录的文件哈希
+    let mut hash_index = if options.dedup {
+        cache_store.read_file_hashes()?
+    } else {
+        cache::FileHashIndex::new()
+    };
+
+    // --detect-changes size+mtime/hash 时读取上次归档时记录的文件状态，损坏的索引视为空
+    let detect_changes = options.detect_changes != ChangeDetection::Mtime;
+    let mut scan_index = if detect_changes {
+        cache_store.read_scan_index()?
+    } else {
+        cache::ScanIndex::new()
+    };
+
+    let archive_options = ArchiveOptions {
+        format: options.archive_format,
+        compression_level: options.compression_level,
+        password: options.password.as_deref(),
+        split_size: options.split_size,
+        store_extensions: store_extensions(&options),
+        locked_file_retries: options.locked_file_retries,
+        fail_on_skip: options.fail_on_skip,
+        symlinks: options.symlinks,
+        retry_delay: Duration::from_millis(options.retry_delay_ms),
+        append: options.append,
+        reproducible: options.reproducible,
+        verify: !options.no_verify,
+        max_parallel_bytes: options.max_parallel_bytes,
+        backup_mode: Some(mode),
+        last_backup_time: None,
+        next_cutoff: None,
+        month_boundary_tz: Some(options.month_boundary_tz.name().to_string()),
+        kind: None,
+        base_archive: None,
+        group: None,
+        extra_source_paths: options.from[1..].to_vec(),
+        source: source_tag.clone(),
+        now: None,
+    };
+
+    events.info(format!("\nSelected backup mode: {:?}", mode));
+    events.info(format!("Months to be backed up: {:?}", months_to_backup));
+    for month in &months_to_backup {
+        let month_label = month.label();
+        events.info(format!(
+            "Last backup cutoff from cache for {}: {}",
+            month_label,
+            cache::get_last_backup_time_for_month(
+                &cache_records,
+                &month_label,
+                &source_id,
+                script_start_time
+            )
+            .with_timezone(&chrono::Local)
+        ));
+    }
+    events.info("\nStarting file scan...");
+
+    let filtered = &mut report.filtered;
+    // 本次创建的全量/增量归档，写入缓存记录供之后的增量归档查找基础
+    let mut archive_records: Vec<cache::ArchiveRecord> = Vec::new();
+    // 使用 --split-by-top-dir 时成功归档的分组（账号）
+    let mut archived_groups: BTreeSet<String> = BTreeSet::new();
+    // 本次创建的所有归档文件名，清理删除归档后据此标记缓存记录
+    let mut archive_files: Vec<String> = Vec::new();
+    // 每个处理过的月份的结果；失败的月份不推进截止时间，下次运行重新扫描
+    let mut month_results: Vec<cache::MonthResult> = Vec::new();
+    // --month-source path 时修改时间晚于截止时间、路径中是其他月份的文件及其月份
+    let mut other_path_months: Vec<(PathBuf, BackupMonth)> = Vec::new();
+
+    // 扫描之前记录时间作为下次增量备份的截止时间，归档期间被修改的文件留给下次备份
+    let scan_start_time = Utc::now();
+    // 推迟的文件修改时间晚于推迟界限，截止时间同样前移，保证下次备份会包含它们
+    let next_cutoff = if options.settle_seconds > 0 {
+        let settle_cutoff =
+            scan_start_time - chrono::Duration::seconds(options.settle_seconds as i64);
+        for source in &mut sources {
+            source.filters.settle_cutoff = Some(settle_cutoff);
+        }
+        settle_cutoff
+    } else {
+        scan_start_time
+    };
+
+    // 4. 遍历每个待备份月份，查找文件并归档
+    'months: for month in &months_to_backup {
+        let month_label = month.label();
+        let cached_cutoff = cache::get_last_backup_time_for_month(
+            &cache_records,
+            &month_label,
+            &source_id,
+            script_start_time,
+        );
+        let last_backup_time = if ignore_cutoff {
+            DateTime::UNIX_EPOCH
+        } else {
+            cached_cutoff - overlap
+        };
+        // 先记为失败，该月所有分组都成功（或没有需要备份的文件）后才推进截止时间
+        month_results.push(cache::MonthResult {
+            month: month_label.clone(),
+            cutoff: cached_cutoff,
+            archive_files: Vec::new(),
+            status: cache::MonthStatus::Failed,
+        });
+        report.months.push(MonthReport {
+            period: *month,
+            status: cache::MonthStatus::Failed,
+            archives: Vec::new(),
+            skipped_files: Vec::new(),
+            dry_run_files: 0,
+            dry_run_bytes: 0,
+            errors: Vec::new(),
+        });
+        let month_report = report.months.last_mut().unwrap();
+        let failed_before = report.failed_archives;
+        let archive_files_before = archive_files.len();
+
+        // 增量模式下各分组可能分别需要全量或增量归档，先扫描该月的所有文件，再按分组筛选；
+        // 不按修改时间检测变化时同样扫描所有文件，再与变化检测索引比较
+        let scan_since = if options.full || options.incremental || detect_changes {
+            DateTime::UNIX_EPOCH
+        } else {
+            last_backup_time
+        };
+        log::debug!(
+            "Scanning {} for files modified since {}",
+            month_label,
+            scan_since
+        );
+
+        events.info(format!(
+            "Scanning for new/updated files for month: {}...",
+            month_label
+        ));
+
+        // 该月之前被跳过的文件，按源目录转换为相对路径
+        let retry: HashSet<String> = previously_skipped
+            .iter()
+            .filter(|skipped| skipped.month == month_label)
+            .map(|skipped| skipped.path.clone())
+            .collect();
+        for source in &mut sources {
+            source.filters.retry_paths = retry
+                .iter()
+                .filter_map(|path| match &source.label {
+                    Some(label) => path.strip_prefix(label.as_str())?.strip_prefix('/'),
+                    None => Some(path.as_str()),
+                })
+                .map(PathBuf::from)
+                .collect();
+        }
+
+        // 分别扫描每个源目录，文件标记所属源目录的标签，在归档中位于各自的顶层目录下
+        let mut scanned = file_scanner::ScanResult::default();
+        for source in &sources {
+            // 之前的源目录的计数加上当前源目录的进度
+            let previous = scanned.progress;
+            let on_progress = |progress: ScanProgress| {
+                events.emit(BackupEvent::ScanProgress(previous + progress));
+            };
+            let result = scan_pool.install(|| match month {
+                BackupPeriod::Range(range) => file_scanner::find_files_in_range(
+                    &source.path,
+                    &range.start,
+                    &range.end,
+                    &source.excluded_dirs,
+                    &source.filters,
+                    &on_progress,
+                ),
+                _ => file_scanner::find_files_to_backup(
+                    &source.path,
+                    &scan_since,
+                    month,
+                    &source.excluded_dirs,
+                    &source.filters,
+                    &on_progress,
+                ),
+            });
+            match result {
+                Ok(mut result) => {
+                    for file in &mut result.files {
+                        file.source_label = source.label.clone();
+                    }
+                    scanned = scanned.merge(result);
+                }
+                Err(e) => {
+                    events.emit(BackupEvent::ScanFinished);
+                    events.error(format!(
+                        "Failed to scan '{}' for {}: {}",
+                        source.path.display(),
+                        month_label,
+                        reason(&e)
+                    ));
+                    month_report.errors.push(e);
+                    report.failed_archives += 1;
+                    continue 'months;
+                }
+            }
+        }
+        other_path_months.append(&mut scanned.other_path_months);
+        // 状态行清除后报告最终计数，保证它们出现在重定向的日志中
+        events.emit(BackupEvent::ScanFinished);
+        events.info(format!(
+            "Scanned {}: {}.",
+            month_label,
+            describe_scan_progress(&scanned.progress)
+        ));
+        if !scanned.skipped.is_empty() {
+            let mut message = format!(
+                "Could not read {} entries while scanning {}:",
+                scanned.skipped.len(),
+                month_label
+            );
+            for skipped in &scanned.skipped {
+                message.push_str(&format!(
+                    "\n  {} ({})",
+                    skipped.path.display(),
+                    skipped.reason
+                ));
+            }
+            events.alert(message);
+            month_report.skipped_files.extend(
+                scanned
+                    .skipped
+                    .iter()
+                    .map(|skipped| skipped_file_record(&sources, &month_label, skipped)),
+            );
+            if options.fail_on_skip {
+                report.failed_archives += 1;
+                continue;
+            }
+        }
+        if options.verbose && !scanned.size_filtered.is_empty() {
+            let mut message = format!(
+                "Skipped {} files outside the size limits for {}:",
+                scanned.size_filtered.len(),
+                month_label
+            );
+            for file in &scanned.size_filtered {
+                message.push_str(&format!(
+                    "\n  {} ({})",
+                    file.path.display(),
+                    HumanBytes(file.size)
+                ));
+            }
+            events.info(message);
+        }
+        if options.verbose {
+            for link in &scanned.skipped_symlinks {
+                if !filtered.skipped_symlinks.contains(link) {
+                    events.info(format!("Skipped symbolic link: {}", link.display()));
+                }
+            }
+        }
+        filtered.outside_size_limits += scanned.size_filtered.len();
+        filtered.excluded += scanned.excluded_files;
+        filtered.default_excluded += scanned.default_excluded_files;
+        filtered
+            .default_excluded_dirs
+            .extend(scanned.default_pruned_dirs);
+        // 即使本月没有其他文件需要归档也提示，说明文件为什么没有被备份
+        if scanned.deferred_files > 0 {
+            events.info(format!(
+                "Deferred {} files modified in the last {} seconds for {} to the next run.",
+                scanned.deferred_files, options.settle_seconds, month_label
+            ));
+        }
+        filtered.excluded_dirs.extend(scanned.pruned_dirs);
+        filtered.skipped_symlinks.extend(scanned.skipped_symlinks);
+        // 每个月份的扫描都会遇到同一批文件，只提示第一次出现的
+        let new_future_mtimes: Vec<PathBuf> = scanned
+            .future_mtimes
+            .into_iter()
+            .filter(|path| !filtered.future_mtimes.contains(path))
+            .collect();
+        if !new_future_mtimes.is_empty() {
+            let mut message = format!(
+                "{} files have modification times more than {} seconds in the future{}.",
+                new_future_mtimes.len(),
+                options.future_skew_seconds,
+                if options.include_future_mtimes {
+                    "; they are archived with the current month"
+                } else {
+                    " and are not backed up (see --include-future-mtimes)"
+                }
+            );
+            if options.verbose {
+                for path in &new_future_mtimes {
+                    message.push_str(&format!("\n  {}", path.display()));
+                }
+            }
+            events.warning(message);
+        }
+        filtered.future_mtimes.extend(new_future_mtimes);
+        let new_own_artifacts: Vec<PathBuf> = scanned
+            .own_artifacts
+            .into_iter()
+            .filter(|path| !filtered.own_artifacts.contains(path))
+            .collect();
+        if !new_own_artifacts.is_empty() {
+            let mut message = format!(
+                "Skipped {} backup archives and cache files made by this tool inside the source (see --include-own-artifacts).",
+                new_own_artifacts.len()
+            );
+            if options.verbose {
+                for path in &new_own_artifacts {
+                    message.push_str(&format!("\n  {}", path.display()));
+                }
+            }
+            events.warning(message);
+        }
+        filtered.own_artifacts.extend(new_own_artifacts);
+        // 在写入任何归档之前检查扫描结果的规模，避免 --from 指错目录时打包整个磁盘
+        if let Some(message) = scan_limit_message(
+            &month_label,
+            &scanned.files,
+            options.max_files,
+            options.max_total_size,
+        ) {
+            if !options.force {
+                let e = BackupError::ConfigError(format!(
+                    "{}\nCheck --from, or pass --force to back up anyway.",
+                    message
+                ));
+                events.error(e.to_string());
+                month_report.errors.push(e);
+                report.failed_archives += 1;
+                break;
+            }
+            events.warning(message);
+        }
+        let files = scanned.files;
+        // --split-by-top-dir 时每个顶层目录（例如微信账号）单独归档
+        let groups: Vec<(Option<String>, Vec<file_scanner::FileEntry>)> =
+            if options.split_by_top_dir {
+                file_scanner::group_by_top_dir(files)
+                    .into_iter()
+                    .map(|(group, files)| (Some(group), files))
+                    .collect()
+            } else {
+                vec![(None, files)]
+            };
+
+        for (group, files) in groups {
+            // 消息和汇总表中使用的名称，例如 `2025-07` 或 `2025-07_wxid_abc123`
+            let label = match &group {
+                Some(group) => format!("{}_{}", month_label, group),
+                None => month_label.clone(),
+            };
+
+            // 确定归档类型：增量归档基于最近的、仍存在的全量归档，没有时先创建全量归档
+            let (kind, base_archive) = if options.full || (options.incremental && ignore_cutoff) {
+                (Some(ArchiveKind::Full), None)
+            } else if options.incremental {
+                match cache::latest_full_archive(
+                    &cache_records,
+                    &month_label,
+                    group.as_deref(),
+                    &source_id,
+                )
+                .filter(|name| destination_path.join(name).is_file())
+                {
+                    Some(name) => (Some(ArchiveKind::Incremental), Some(name.to_string())),
+                    None => {
+                        events.info(format!(
+                            "No full archive found for {}; creating a full archive.",
+                            label
+                        ));
+                        (Some(ArchiveKind::Full), None)
+                    }
+                }
+            } else {
+                (None, None)
+            };
+            // 全量归档包含该月的所有文件，不受上次备份时间限制
+            let (since, files) = if kind == Some(ArchiveKind::Incremental) && !detect_changes {
+                (
+                    last_backup_time,
+                    file_scanner::modified_after(files, &last_backup_time, &retry),
+                )
+            } else {
+                (scan_since, files)
+            };
+            let month_options = ArchiveOptions {
+                last_backup_time: Some(since),
+                next_cutoff: Some(next_cutoff),
+                kind,
+                base_archive,
+                group: group.clone(),
+                ..archive_options.clone()
+            };
+
+            // 全量归档和忽略截止时间的归档必须包含所有文件，只记录其状态
+            let all_files = kind == Some(ArchiveKind::Full) || ignore_cutoff;
+            let (files, mut pending_index) = if !detect_changes {
+                (files, cache::ScanIndex::new())
+            } else if all_files {
+                let records = scan_pool
+                    .install(|| file_scanner::scan_index_records(&files, options.detect_changes));
+                (files, records)
+            } else {
+                let total = files.len();
+                let (files, records) = scan_pool.install(|| {
+                    file_scanner::detect_changes(files, &scan_index, options.detect_changes)
+                });
+                let unchanged = total - files.len();
+                if unchanged > 0 {
+                    events.info(format!(
+                        "Skipped {} unchanged files for {}.",
+                        unchanged, label
+                    ));
+                }
+                filtered.unchanged += unchanged;
+                (files, records)
+            };
+
+            // 全量归档和忽略截止时间的归档必须包含所有文件，不做去重
+            let files = if options.dedup && !all_files {
+                let (files, unchanged) = file_scanner::skip_unchanged_files(files, &hash_index);
+                if unchanged > 0 {
+                    events.info(format!(
+                        "Skipped {} files with unchanged content for {}.",
+                        unchanged, label
+                    ));
+                }
+                filtered.deduplicated += unchanged;
+                files
+            } else {
+                files
+            };
+            if files.is_empty() {
+                events.info(format!(
+                    "No new or updated files found for {}. Skipping.",
+                    label
+                ));
+                continue;
+            }
+            if options.dry_run {
+                events.emit(BackupEvent::DryRunFiles {
+                    label: &label,
+                    source: source_tag.as_deref(),
+                    files: &files,
+                });
+                let bytes: u64 = files.iter().map(|f| f.size).sum();
+                let mut message = format!(
+                    "Would back up {} files ({}) for {}.",
+                    files.len(),
+                    HumanBytes(bytes),
+                    label
+                );
+                if options.verbose {
+                    for file in &files {
+                        message.push_str(&format!(
+                            "\n  {} ({})",
+                            file.path.display(),
+                            HumanBytes(file.size)
+                        ));
+                    }
+                }
+                events.info(message);
+                month_report.dry_run_files += files.len();
+                month_report.dry_run_bytes += bytes;
+                continue;
+            }
+            events.info(format!(
+                "Found {} files ({}) to backup for {}. Archiving...",
+                files.len(),
+                HumanBytes(files.iter().map(|f| f.size).sum()),
+                label
+            ));
+
+            // 归档前确认目标磁盘空间足够，避免写到一半失败
+            let required =
+                disk_space::estimate_required_bytes(&files, options.expected_compression_ratio);
+            if let Err(e) = disk_space::check_free_space(
+                options.free_space.as_ref(),
+                &destination_path,
+                required,
+                options.min_free_space.unwrap_or(0),
+            ) {
+                events.error(format!("Not archiving {}: {}", label, reason(&e)));
+                month_report.errors.push(e);
+                report.failed_archives += 1;
+                break 'months;
+            }
+
+            let mut on_archive_event = |event: ArchiveEvent| match event {
+                ArchiveEvent::Progress(progress) => {
+                    events.emit(BackupEvent::ArchiveProgress(progress))
+                }
+                ArchiveEvent::Warning(message) => events.warning(message),
+            };
+            let result = archiver::create_archive(
+                &sources[0].path,
+                &files,
+                &destination_path,
+                month,
+                &month_options,
+                &mut on_archive_event,
+            );
+            // 在报告结果或错误之前结束进度
+            events.emit(BackupEvent::ArchiveFinished);
+
+            match result {
+                Ok(mut stats) => {
+                    let mut encryption_failed = false;
+                    // 归档已通过校验，加密给 --encrypt-to 指定的接收者并删除明文
+                    if !options.encrypt_to.is_empty() {
+                        let mut encrypted_bytes = 0;
+                        for archive_path in &mut stats.archive_paths {
+                            match encryption::encrypt_archive(archive_path, &options.encrypt_to) {
+                                Ok(encrypted_path) => {
+                                    encrypted_bytes +=
+                                        fs::metadata(&encrypted_path).map_or(0, |m| m.len());
+                                    *archive_path = encrypted_path;
+                                }
+                                Err(e) => {
+                                    events.error(format!(
+                                        "Failed to encrypt '{}', the unencrypted archive was kept: {}",
+                                        archive_path.display(),
+                                        reason(&e)
+                                    ));
+                                    month_report.errors.push(e);
+                                    encryption_failed = true;
+                                }
+                            }
+                        }
+                        stats.encrypted_bytes = Some(encrypted_bytes);
+                    }
+                    for archive_path in &stats.archive_paths {
+                        events.info(format!(
+                            "Successfully created archive: {}",
+                            archive_path.display()
+                        ));
+                    }
+                    events.info(format!(
+                        "Stored {} files without compression, compressed {} files.",
+                        stats.stored_files, stats.compressed_files
+                    ));
+                    if !stats.skipped_files.is_empty() {
+                        let mut message = format!(
+                            "Skipped {} locked or unreadable files for {}:",
+                            stats.skipped_files.len(),
+                            label
+                        );
+                        for skipped in &stats.skipped_files {
+                            message.push_str(&format!(
+                                "\n  {} ({})",
+                                skipped.path.display(),
+                                skipped.reason
+                            ));
+                        }
+                        events.alert(message);
+                        if options.write_skip_report {
+                            match write_skip_report(&stats.archive_paths[0], &stats.skipped_files) {
+                                Ok(report_path) => events.info(format!(
+                                    "Wrote skipped file list: {}",
+                                    report_path.display()
+                                )),
+                                Err(e) => events
+                                    .error(format!("Failed to write skipped file list: {}", e)),
+                            }
+                        }
+                    }
+                    month_report.skipped_files.extend(
+                        stats
+                            .skipped_files
+                            .iter()
+                            .map(|skipped| skipped_file_record(&sources, &month_label, skipped)),
+                    );
+                    if let Some(kind) = kind
+                        && let Some(file_name) = stats.archive_paths[0].file_name()
+                    {
+                        archive_records.push(cache::ArchiveRecord {
+                            month: month_label.clone(),
+                            group: group.clone(),
+                            kind,
+                            file_name: file_name.to_string_lossy().into_owned(),
+                        });
+                    }
+                    // 归档已通过校验，记录本次写入文件的哈希供下次去重
+                    if options.dedup {
+                        for entry in &stats.archived_entries {
+                            hash_index.insert(
+                                entry.path.clone(),
+                                cache::FileHashRecord {
+                                    size: entry.size,
+                                    modified: entry.modified,
+                                    sha256: entry.sha256.clone(),
+                                },
+                            );
+                        }
+                    }
+                    // 只记录实际写入归档的文件，被跳过的文件下次仍视为已变化
+                    for entry in &stats.archived_entries {
+                        if let Some(record) = pending_index.remove(&entry.path) {
+                            scan_index.insert(entry.path.clone(), record);
+                        }
+                    }
+                    if let Some(group) = group {
+                        archived_groups.insert(group);
+                    }
+                    archive_files.extend(
+                        stats
+                            .archive_paths
+                            .iter()
+                            .filter_map(|path| path.file_name())
+                            .map(|name| name.to_string_lossy().into_owned()),
+                    );
+                    if encryption_failed {
+                        report.failed_archives += 1;
+                    } else {
+                        report.succeeded_archives += 1;
+                    }
+                    month_report.archives.push((label, stats));
+                }
+                Err(e) if archiver::is_verification_failure(&e) => {
+                    events.error(format!(
+                        "Archive for {} failed verification and was renamed to .corrupt: {}",
+                        label,
+                        reason(&e)
+                    ));
+                    month_report.errors.push(e);
+                    report.failed_archives += 1;
+                }
+                Err(e) => {
+                    events.error(format!(
+                        "Failed to create the archive for {}: {}",
+                        label,
+                        reason(&e)
+                    ));
+                    month_report.errors.push(e);
+                    report.failed_archives += 1;
+                }
+            }
+        }
+
+        let month_result = month_results.last_mut().unwrap();
+        month_result.archive_files = archive_files[archive_files_before..].to_vec();
+        if report.failed_archives == failed_before {
+            month_result.cutoff = next_cutoff;
+            month_result.status = cache::MonthStatus::Succeeded;
+            month_report.status = cache::MonthStatus::Succeeded;
+        }
+    }
+
+    // 6. 滚动删除旧备份
+    clean_destination(&options, &destination_path, &events, &mut report);
+
+    if options.dry_run {
+        return Ok(report);
+    }
+
+    // 清理删除了归档时，标记归档已不存在的缓存记录
+    prune_deleted_records(&report, &mut cache_records, &destination_path);
+
+    // 5. 在 .cache 中记录本次运行；没有创建归档或全部归档失败的运行同样记录，
+    // 以便保存其状态和被跳过的文件（失败的运行不提供截止时间）
+    let script_end_time = Utc::now();
+    let backup_month_info = months_to_backup
+        .iter()
+        .map(BackupPeriod::label)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let archived_files: usize = report.archives().map(|(_, s)| s.archived_files()).sum();
+    let uncompressed_bytes: u64 = report.archives().map(|(_, s)| s.uncompressed_bytes).sum();
+    let compressed_bytes: u64 = report.archives().map(|(_, s)| s.compressed_bytes).sum();
+    let totals = format!(
+        "{} files, {} bytes -> {} bytes",
+        archived_files, uncompressed_bytes, compressed_bytes
+    );
+
+    // 本次运行中被占用或无法读取而跳过的文件
+    let mut skipped_records: Vec<cache::SkippedFileRecord> = report
+        .months
+        .iter()
+        .flat_map(|month| month.skipped_files.iter().cloned())
+        .collect();
+    // 因被占用而跳过的文件总数，非零时本次运行标记为部分完成
+    let skipped_file_count = skipped_records.len();
+    // 路径中的月份本次没有备份的文件：截止时间会越过它们的修改时间，
+    // 记为尚未归档，下次备份其路径中的月份时不论修改时间都会包含它们
+    let scanned_months: HashSet<String> = months_to_backup.iter().map(|m| m.label()).collect();
+    let mut unscanned: Vec<cache::SkippedFileRecord> = other_path_months
+        .iter()
+        .map(|(path, month)| (path, BackupPeriod::Month(*month).label()))
+        .filter(|(_, label)| !scanned_months.contains(label))
+        .map(|(path, label)| {
+            let skipped = SkippedFile {
+                path: path.clone(),
+                reason: format!(
+                    "its path is in {}, which was not backed up, although it was modified since the last backup",
+                    label
+                ),
+            };
+            skipped_file_record(&sources, &label, &skipped)
+        })
+        .collect();
+    unscanned.sort_by(|a, b| (&a.month, &a.path).cmp(&(&b.month, &b.path)));
+    unscanned.dedup_by(|a, b| a.month == b.month && a.path == b.path);
+    if !unscanned.is_empty() {
+        let mut message = format!(
+            "{} files modified since the last backup belong to months not backed up by their path; \
+             back those months up with --month:",
+            unscanned.len()
+        );
+        for skipped in &unscanned {
+            message.push_str(&format!("\n  {} ({})", skipped.path, skipped.month));
+        }
+        events.warning(message);
+    }
+    skipped_records.extend(unscanned);
+    // 成功扫描的月份中之前被跳过的文件已重新尝试，仍被跳过的在本次的列表中；
+    // 其他月份的留到下次运行
+    for skipped in previously_skipped {
+        let rescanned = month_results
+            .iter()
+            .any(|m| m.month == skipped.month && m.status == cache::MonthStatus::Succeeded);
+        let skipped_again = skipped_records
+            .iter()
+            .any(|s| s.month == skipped.month && s.path == skipped.path);
+        if !rescanned && !skipped_again {
+            skipped_records.push(skipped);
+        }
+    }
+
+    let new_record = cache::CacheRecord {
+        start_time: script_start_time,
+        end_time: script_end_time,
+        backup_info: if skipped_file_count > 0 {
+            format!(
+                "Partial backup for {}: {} ({} locked or unreadable files skipped)",
+                backup_month_info, totals, skipped_file_count
+            )
+        } else {
+            format!("Backup for {}: {}", backup_month_info, totals)
+        },
+        cutoff_time: Some(next_cutoff),
+        archives: archive_records,
+        accounts: archived_groups.into_iter().collect(),
+        source_paths: options
+            .from
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+        archive_files,
+        archives_deleted_time: None,
+        months: month_results,
+        status: Some(if report.failed_archives == 0 && skipped_file_count == 0 {
+            cache::RunStatus::Success
+        } else if report.succeeded_archives > 0 {
+            cache::RunStatus::Partial
+        } else {
+            cache::RunStatus::Failed
+        }),
+        files_archived: Some(archived_files),
+        bytes_archived: Some(uncompressed_bytes),
+        tool_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        month_boundary_tz: Some(options.month_boundary_tz.name().to_string()),
+        source_id: Some(source_id),
+        skipped_files: skipped_records,
+        extra: serde_json::Map::new(),
+    };
+
+    cache_records.push(new_record);
+    trim_cache_history(
+        &mut cache_records,
+        options.cache_history_limit,
+        &cache_folder,
+        &events,
+    );
+
+    match cache_store.write_records(&cache_records) {
+        Ok(_) => {
+            report.cache_updated = true;
+            events.info(format!(
+                "\nSuccessfully updated cache file: {}",
+                cache_store.location().display()
+            ));
+        }
+        Err(e) => {
+            events.error(format!("Failed to write to cache file: {}", reason(&e)));
+            report.errors.push(e);
+        }
+    }
+
+    if options.dedup
+        && let Err(e) = cache_store.write_file_hashes(&hash_index)
+    {
+        events.error(format!(
+            "Failed to write the file hash index: {}",
+            reason(&e)
+        ));
+        report.errors.push(e);
+    }
+
+    if detect_changes && let Err(e) = cache_store.write_scan_index(&scan_index) {
+        events.error(format!("Failed to write the scan index: {}", reason(&e)));
+        report.errors.push(e);
+    }
+
+    Ok(report)
+}
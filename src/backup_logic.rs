@@ -1,31 +1,224 @@
-use chrono::{Datelike, Local, NaiveDate};
+use chrono::{
+    DateTime, Datelike, Days, Local, LocalResult, Months, NaiveDate, TimeZone, Utc, Weekday,
+};
+use serde::{Deserialize, Serialize};
 
 /// 定义备份模式
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BackupMode {
     PreviousMonth,
     CurrentMonth,
     Dynamic,
+    /// 备份 --month 指定的月份
+    Explicit,
+    /// 备份包括当月在内的最近 N 个月
+    MonthsBack(u32),
+    /// 备份上次成功运行以来的所有月份（--catch-up）
+    CatchUp,
+    /// 备份 --since/--until 指定的时间范围，不按月份划分
+    DateRange,
+}
+
+/// 归档的类型
+///
+/// 全量归档包含月份范围内的所有文件；增量归档只包含上次备份后修改的文件，
+/// 并在清单中记录其所基于的全量归档，恢复时按顺序重放。
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Full,
+    Incremental,
+}
+
+/// Dynamic 模式的时间窗口
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DynamicThresholds {
+    /// 当月的前几天内同时备份上个月（--dynamic-threshold-days），0 表示不备份上个月
+    pub threshold_days: u32,
+    /// 当月的最后几天内同时备份下个月（--dynamic-lookahead-days），0 表示不备份下个月
+    pub lookahead_days: u32,
+}
+
+impl Default for DynamicThresholds {
+    fn default() -> Self {
+        Self {
+            threshold_days: 7,
+            lookahead_days: 0,
+        }
+    }
 }
 
 /// 定义要备份的年月
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct BackupMonth {
     pub year: i32,
     pub month: u32,
 }
 
+impl BackupMonth {
+    /// 上一个月
+    pub fn previous(&self) -> Self {
+        if self.month == 1 {
+            Self {
+                year: self.year - 1,
+                month: 12,
+            }
+        } else {
+            Self {
+                year: self.year,
+                month: self.month - 1,
+            }
+        }
+    }
+}
+
+/// 定义要备份的 ISO 周（--weekly），周一为一周的第一天
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BackupWeek {
+    /// ISO 周所属的年份，年初和年末的几天可能与日历年份不同
+    pub year: i32,
+    pub week: u32,
+}
+
+impl BackupWeek {
+    /// `date` 所在的 ISO 周
+    pub fn containing(date: NaiveDate) -> Self {
+        let week = date.iso_week();
+        Self {
+            year: week.year(),
+            week: week.week(),
+        }
+    }
+
+    /// 这一周的周一
+    pub fn first_day(&self) -> NaiveDate {
+        NaiveDate::from_isoywd_opt(self.year, self.week, Weekday::Mon).unwrap()
+    }
+
+    /// 上一周
+    pub fn previous(&self) -> Self {
+        Self::containing(self.first_day() - Days::new(7))
+    }
+}
+
+/// --since/--until 指定的时间范围，包含起点、不包含终点
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateRange {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// 一次备份的时间段：一个月，使用 --weekly 时的一个 ISO 周，或者 --since/--until 的时间范围
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BackupPeriod {
+    Month(BackupMonth),
+    IsoWeek(BackupWeek),
+    Range(DateRange),
+}
+
+impl BackupPeriod {
+    /// 归档文件名和缓存记录中使用的标签，例如 `2025-07`、`2025-W31` 或
+    /// `2024-06-15_to_2024-09-01`（起止时间的本地日期）
+    pub fn label(&self) -> String {
+        match self {
+            BackupPeriod::Month(month) => format!("{:04}-{:02}", month.year, month.month),
+            BackupPeriod::IsoWeek(week) => format!("{:04}-W{:02}", week.year, week.week),
+            BackupPeriod::Range(range) => format!(
+                "{}_to_{}",
+                range.start.with_timezone(&Local).format("%Y-%m-%d"),
+                range.end.with_timezone(&Local).format("%Y-%m-%d")
+            ),
+        }
+    }
+
+    /// 从标签解析时间段，见 [`BackupPeriod::label`]；不是有效的月份或周时返回 `None`，
+    /// 时间范围的标签丢失了时刻，同样返回 `None`
+    pub fn from_label(label: &str) -> Option<Self> {
+        let (year, rest) = label.split_once('-')?;
+        let digits = |s: &str| s.len() == 2 && s.bytes().all(|b| b.is_ascii_digit());
+        if year.len() != 4 || !year.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let year: i32 = year.parse().ok()?;
+        match rest.strip_prefix('W') {
+            Some(week) if digits(week) => {
+                let week: u32 = week.parse().ok()?;
+                NaiveDate::from_isoywd_opt(year, week, Weekday::Mon)?;
+                Some(BackupPeriod::IsoWeek(BackupWeek { year, week }))
+            }
+            None if digits(rest) => {
+                let month: u32 = rest.parse().ok()?;
+                (1..=12)
+                    .contains(&month)
+                    .then_some(BackupPeriod::Month(BackupMonth { year, month }))
+            }
+            _ => None,
+        }
+    }
+
+    /// 时间段的第一天和之后的第一天（本地日期）；时间范围返回覆盖它的所有日期
+    pub fn date_range(&self) -> (NaiveDate, NaiveDate) {
+        match self {
+            BackupPeriod::Month(month) => {
+                let start = NaiveDate::from_ymd_opt(month.year, month.month, 1).unwrap();
+                (start, start + Months::new(1))
+            }
+            BackupPeriod::IsoWeek(week) => {
+                let start = week.first_day();
+                (start, start + Days::new(7))
+            }
+            BackupPeriod::Range(range) => {
+                let last = range.end - chrono::Duration::nanoseconds(1);
+                (
+                    range.start.with_timezone(&Local).date_naive(),
+                    last.with_timezone(&Local).date_naive() + Days::new(1),
+                )
+            }
+        }
+    }
+}
+
+// 与内部的月份、周或时间范围相同，按月备份时的输出与引入周之前一致
+impl std::fmt::Debug for BackupPeriod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupPeriod::Month(month) => month.fmt(f),
+            BackupPeriod::IsoWeek(week) => week.fmt(f),
+            BackupPeriod::Range(range) => range.fmt(f),
+        }
+    }
+}
+
+// 序列化为标签，例如 `2025-07`，与归档文件名和缓存记录一致
+impl Serialize for BackupPeriod {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.label())
+    }
+}
+
+impl From<BackupMonth> for BackupPeriod {
+    fn from(month: BackupMonth) -> Self {
+        BackupPeriod::Month(month)
+    }
+}
+
 /// 根据模式确定需要备份的月份列表
 ///
 /// # Arguments
 ///
-/// * `mode` - 备份模式 (`PreviousMonth`, `CurrentMonth`, `Dynamic`)
+/// * `mode` - 备份模式 (`PreviousMonth`, `CurrentMonth`, `Dynamic`, `MonthsBack`)；
+///   `Explicit` 和 `CatchUp` 模式的月份由 [`explicit_backup_months`] 和
+///   [`months_between`] 确定，`DateRange` 模式不按月份备份，这里都返回空列表
+/// * `thresholds` - Dynamic 模式的时间窗口，其他模式忽略
+/// * `today` - 当前日期，通常是今天，可以用 --as-of 指定
 ///
 /// # Returns
 ///
 /// 一个包含 `BackupMonth` 的向量，按时间顺序排列。
-pub fn determine_backup_months(mode: &BackupMode) -> Vec<BackupMonth> {
-    let today = Local::now().date_naive();
+pub fn determine_backup_months(
+    mode: &BackupMode,
+    thresholds: &DynamicThresholds,
+    today: NaiveDate,
+) -> Vec<BackupMonth> {
     let mut result = Vec::new();
 
     let current_month = BackupMonth {
@@ -51,17 +244,52 @@ pub fn determine_backup_months(mode: &BackupMode) -> Vec<BackupMonth> {
         }
         BackupMode::Dynamic => {
             // PowerShell: (New-TimeSpan -Start $lastDayOfPrev -End $today).Days
-            // 如果今天与上个月最后一天相差7天以内
+            // 如果今天与上个月最后一天相差 threshold_days 天以内，同时备份上个月
             let days_diff = today
                 .signed_duration_since(last_day_of_previous_month)
                 .num_days();
-            if days_diff >= 0 && days_diff <= 7 {
-                // 同时备份上个月和当月
+            if (0..=thresholds.threshold_days as i64).contains(&days_diff) {
                 result.push(previous_month);
-                result.push(current_month);
+            }
+            result.push(current_month);
+            // 对称地，如果今天与下个月第一天相差 lookahead_days 天以内，同时备份下个月
+            let next_month = if current_month.month == 12 {
+                BackupMonth {
+                    year: current_month.year + 1,
+                    month: 1,
+                }
             } else {
-                // 只备份当月
-                result.push(current_month);
+                BackupMonth {
+                    year: current_month.year,
+                    month: current_month.month + 1,
+                }
+            };
+            let first_day_of_next_month =
+                NaiveDate::from_ymd_opt(next_month.year, next_month.month, 1).unwrap();
+            let days_left = first_day_of_next_month
+                .signed_duration_since(today)
+                .num_days();
+            if days_left <= thresholds.lookahead_days as i64 {
+                result.push(next_month);
+            }
+        }
+        BackupMode::Explicit | BackupMode::CatchUp | BackupMode::DateRange => {}
+        BackupMode::MonthsBack(count) => {
+            // 从当月开始逐月往前推，跨年时年份减一
+            let mut month = current_month;
+            for _ in 0..*count {
+                result.push(month);
+                month = if month.month == 1 {
+                    BackupMonth {
+                        year: month.year - 1,
+                        month: 12,
+                    }
+                } else {
+                    BackupMonth {
+                        year: month.year,
+                        month: month.month - 1,
+                    }
+                };
             }
         }
     }
@@ -70,3 +298,560 @@ pub fn determine_backup_months(mode: &BackupMode) -> Vec<BackupMonth> {
     result.sort();
     result
 }
+
+/// 根据模式确定使用 --weekly 时需要备份的 ISO 周列表
+///
+/// # Arguments
+///
+/// * `mode` - `PreviousMonth`、`CurrentMonth` 和 `Dynamic` 分别表示上一周、本周和动态模式；
+///   其他模式不能与 --weekly 一起使用，返回空列表
+/// * `threshold_days` - 动态模式下，本周的前几天内同时备份上一周，0 表示不备份上一周
+/// * `today` - 当前日期
+///
+/// # Returns
+///
+/// 按时间顺序排列的 ISO 周。
+pub fn determine_backup_weeks(
+    mode: &BackupMode,
+    threshold_days: u32,
+    today: NaiveDate,
+) -> Vec<BackupWeek> {
+    let current_week = BackupWeek::containing(today);
+    let previous_week = current_week.previous();
+    match mode {
+        BackupMode::PreviousMonth => vec![previous_week],
+        BackupMode::CurrentMonth => vec![current_week],
+        BackupMode::Dynamic => {
+            // 与按月的动态模式相同：今天与上一周的最后一天（周日）相差 threshold_days 天以内
+            let days_diff = today.weekday().number_from_monday();
+            if days_diff <= threshold_days {
+                vec![previous_week, current_week]
+            } else {
+                vec![current_week]
+            }
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// 确定从 `since` 所在的月份（本地时间）到 `today` 所在的月份的所有月份（--catch-up）
+///
+/// # Returns
+///
+/// 按时间顺序排列的月份，至少包含当月；`since` 晚于当月时只返回当月。
+pub fn months_between(since: DateTime<Utc>, today: NaiveDate) -> Vec<BackupMonth> {
+    let since = since.with_timezone(&Local);
+    let current_month = BackupMonth {
+        year: today.year(),
+        month: today.month(),
+    };
+    let mut month = BackupMonth {
+        year: since.year(),
+        month: since.month(),
+    };
+    let mut result = Vec::new();
+    while month < current_month {
+        result.push(month);
+        month = if month.month == 12 {
+            BackupMonth {
+                year: month.year + 1,
+                month: 1,
+            }
+        } else {
+            BackupMonth {
+                year: month.year,
+                month: month.month + 1,
+            }
+        };
+    }
+    result.push(current_month);
+    result
+}
+
+/// 解析 --month 参数，格式为 `YYYY-MM`
+///
+/// # Arguments
+///
+/// * `value` - 命令行中的值，例如 `2025-03`
+/// * `today` - 当前日期，晚于当月的月份被拒绝
+pub fn parse_backup_month(value: &str, today: NaiveDate) -> Result<BackupMonth, String> {
+    let invalid = || format!("invalid month '{}': expected YYYY-MM", value);
+    let (year, month) = value.split_once('-').ok_or_else(invalid)?;
+    let digits = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(year, 4) || !digits(month, 2) {
+        return Err(invalid());
+    }
+    let year: i32 = year.parse().map_err(|_| invalid())?;
+    let month: u32 = month.parse().map_err(|_| invalid())?;
+    if !(1..=12).contains(&month) {
+        return Err(invalid());
+    }
+    let result = BackupMonth { year, month };
+    let current_month = BackupMonth {
+        year: today.year(),
+        month: today.month(),
+    };
+    if result > current_month {
+        return Err(format!("month '{}' is in the future", value));
+    }
+    Ok(result)
+}
+
+/// 解析 --since/--until 参数：RFC 3339 时间，或 `YYYY-MM-DD` 表示该日的本地零点
+pub fn parse_date_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let invalid = || {
+        format!(
+            "invalid date '{}': expected YYYY-MM-DD or an RFC 3339 time",
+            value
+        )
+    };
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid())?;
+    // chrono 也接受不补零的月份和日期，例如 `2024-6-15`
+    if date.format("%Y-%m-%d").to_string() != value {
+        return Err(invalid());
+    }
+    Ok(start_of_day_utc(&Local, date))
+}
+
+/// `date` 在时区 `tz` 中的第一个时刻（UTC）
+///
+/// 有些时区（例如 America/Santiago）在零点切换夏令时：零点出现两次时取较早的一次，
+/// 零点不存在时取其后第一个存在的时刻。
+pub fn start_of_day_utc<Tz: TimeZone>(tz: &Tz, date: NaiveDate) -> DateTime<Utc> {
+    let mut naive = date.and_hms_opt(0, 0, 0).unwrap();
+    loop {
+        match tz.from_local_datetime(&naive) {
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => {
+                return time.with_timezone(&Utc);
+            }
+            // 夏令时跳过的时间不超过几个小时，逐分钟向后查找
+            LocalResult::None => naive += chrono::Duration::minutes(1),
+        }
+    }
+}
+
+/// 确定 --month 指定的备份月份：按时间顺序排列，重复的月份只备份一次
+pub fn explicit_backup_months(months: &[BackupMonth]) -> Vec<BackupMonth> {
+    let mut result = months.to_vec();
+    result.sort();
+    result.dedup();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn month(year: i32, month: u32) -> BackupMonth {
+        BackupMonth { year, month }
+    }
+
+    #[test]
+    fn parse_backup_month_accepts_past_and_current_months() {
+        let today = NaiveDate::from_ymd_opt(2025, 7, 15).unwrap();
+        assert_eq!(parse_backup_month("2025-03", today), Ok(month(2025, 3)));
+        assert_eq!(parse_backup_month("2024-12", today), Ok(month(2024, 12)));
+        assert_eq!(parse_backup_month("2025-07", today), Ok(month(2025, 7)));
+    }
+
+    #[test]
+    fn parse_backup_month_rejects_invalid_and_future_months() {
+        let today = NaiveDate::from_ymd_opt(2025, 7, 15).unwrap();
+        for value in [
+            "2025-3",
+            "+025-03",
+            "2025-13",
+            "2025-00",
+            "25-03",
+            "2025/03",
+            "2025-03-01",
+            "",
+        ] {
+            let error = parse_backup_month(value, today).unwrap_err();
+            assert!(error.contains("expected YYYY-MM"), "{}: {}", value, error);
+        }
+        let error = parse_backup_month("2025-08", today).unwrap_err();
+        assert!(error.contains("in the future"), "{}", error);
+        assert!(parse_backup_month("2026-01", today).is_err());
+    }
+
+    #[test]
+    fn explicit_backup_months_are_ordered_and_deduplicated() {
+        let months = [
+            month(2025, 3),
+            month(2024, 11),
+            month(2025, 1),
+            month(2025, 3),
+        ];
+        assert_eq!(
+            explicit_backup_months(&months),
+            [month(2024, 11), month(2025, 1), month(2025, 3)]
+        );
+        assert!(explicit_backup_months(&[]).is_empty());
+    }
+
+    #[test]
+    fn months_back_crosses_year_boundaries() {
+        let defaults = DynamicThresholds::default();
+        let today = NaiveDate::from_ymd_opt(2025, 2, 10).unwrap();
+        assert_eq!(
+            determine_backup_months(&BackupMode::MonthsBack(4), &defaults, today),
+            [
+                month(2024, 11),
+                month(2024, 12),
+                month(2025, 1),
+                month(2025, 2)
+            ]
+        );
+        let today = NaiveDate::from_ymd_opt(2025, 1, 31).unwrap();
+        assert_eq!(
+            determine_backup_months(&BackupMode::MonthsBack(14), &defaults, today).first(),
+            Some(&month(2023, 12))
+        );
+        assert!(determine_backup_months(&BackupMode::MonthsBack(0), &defaults, today).is_empty());
+    }
+
+    #[test]
+    fn one_month_back_is_the_current_month() {
+        let defaults = DynamicThresholds::default();
+        for today in [
+            NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2025, 7, 15).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 12, 31).unwrap(),
+        ] {
+            assert_eq!(
+                determine_backup_months(&BackupMode::MonthsBack(1), &defaults, today),
+                determine_backup_months(&BackupMode::CurrentMonth, &defaults, today)
+            );
+        }
+    }
+
+    fn dynamic_months(
+        threshold_days: u32,
+        lookahead_days: u32,
+        today: (i32, u32, u32),
+    ) -> Vec<BackupMonth> {
+        let thresholds = DynamicThresholds {
+            threshold_days,
+            lookahead_days,
+        };
+        let today = NaiveDate::from_ymd_opt(today.0, today.1, today.2).unwrap();
+        determine_backup_months(&BackupMode::Dynamic, &thresholds, today)
+    }
+
+    #[test]
+    fn dynamic_threshold_includes_previous_month_up_to_the_boundary() {
+        // 默认 7 天：7 日仍备份上个月，8 日起只备份当月
+        assert_eq!(
+            dynamic_months(7, 0, (2025, 3, 7)),
+            [month(2025, 2), month(2025, 3)]
+        );
+        assert_eq!(dynamic_months(7, 0, (2025, 3, 8)), [month(2025, 3)]);
+        assert_eq!(
+            dynamic_months(14, 0, (2025, 1, 14)),
+            [month(2024, 12), month(2025, 1)]
+        );
+        assert_eq!(dynamic_months(14, 0, (2025, 1, 15)), [month(2025, 1)]);
+        assert_eq!(dynamic_months(0, 0, (2025, 3, 1)), [month(2025, 3)]);
+    }
+
+    #[test]
+    fn dynamic_lookahead_includes_next_month_up_to_the_boundary() {
+        assert_eq!(
+            dynamic_months(7, 3, (2024, 12, 29)),
+            [month(2024, 12), month(2025, 1)]
+        );
+        assert_eq!(dynamic_months(7, 3, (2024, 12, 28)), [month(2024, 12)]);
+        assert_eq!(
+            dynamic_months(7, 1, (2025, 2, 28)),
+            [month(2025, 2), month(2025, 3)]
+        );
+        assert_eq!(dynamic_months(7, 1, (2024, 2, 28)), [month(2024, 2)]);
+        // 默认不备份下个月
+        assert_eq!(dynamic_months(7, 0, (2025, 3, 31)), [month(2025, 3)]);
+        // 两个窗口同时生效
+        assert_eq!(
+            dynamic_months(30, 30, (2025, 2, 10)),
+            [month(2025, 1), month(2025, 2), month(2025, 3)]
+        );
+    }
+
+    /// 零点切换夏令时的时区，类似 America/Santiago：2024-04-07 01:00（UTC-3）回拨到
+    /// 00:00（UTC-4），2024-09-08 00:00（UTC-4）跳到 01:00（UTC-3）
+    #[derive(Debug, Clone, Copy)]
+    struct MidnightDst;
+
+    impl MidnightDst {
+        fn offset_at(utc: &chrono::NaiveDateTime) -> chrono::FixedOffset {
+            let transition = |m, d| {
+                NaiveDate::from_ymd_opt(2024, m, d)
+                    .unwrap()
+                    .and_hms_opt(4, 0, 0)
+                    .unwrap()
+            };
+            let hours = if *utc >= transition(4, 7) && *utc < transition(9, 8) {
+                -4
+            } else {
+                -3
+            };
+            chrono::FixedOffset::east_opt(hours * 3600).unwrap()
+        }
+    }
+
+    impl TimeZone for MidnightDst {
+        type Offset = chrono::FixedOffset;
+
+        fn from_offset(_: &chrono::FixedOffset) -> Self {
+            MidnightDst
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<chrono::FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(chrono::NaiveTime::MIN))
+        }
+
+        fn offset_from_local_datetime(
+            &self,
+            local: &chrono::NaiveDateTime,
+        ) -> LocalResult<chrono::FixedOffset> {
+            // 按 UTC 时间从早到晚的顺序列出与本地时间一致的偏移
+            let offsets: Vec<chrono::FixedOffset> = [-3, -4]
+                .into_iter()
+                .map(|hours| chrono::FixedOffset::east_opt(hours * 3600).unwrap())
+                .filter(|offset| Self::offset_at(&(*local - *offset)) == *offset)
+                .collect();
+            match offsets[..] {
+                [] => LocalResult::None,
+                [offset] => LocalResult::Single(offset),
+                [earlier, later] => LocalResult::Ambiguous(earlier, later),
+                _ => unreachable!(),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> chrono::FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_time(chrono::NaiveTime::MIN))
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &chrono::NaiveDateTime) -> chrono::FixedOffset {
+            Self::offset_at(utc)
+        }
+    }
+
+    #[test]
+    fn start_of_day_handles_midnight_dst_transitions() {
+        let date = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        let utc = |m, d, h| Utc.with_ymd_and_hms(2024, m, d, h, 0, 0).unwrap();
+        // 普通的零点
+        assert_eq!(start_of_day_utc(&MidnightDst, date(1, 1)), utc(1, 1, 3));
+        assert_eq!(start_of_day_utc(&MidnightDst, date(6, 1)), utc(6, 1, 4));
+        // 零点出现两次，取较早的一次（UTC-3）
+        assert_eq!(start_of_day_utc(&MidnightDst, date(4, 7)), utc(4, 7, 3));
+        // 零点不存在，取 01:00（UTC-3）
+        assert_eq!(start_of_day_utc(&MidnightDst, date(9, 8)), utc(9, 8, 4));
+        assert_eq!(start_of_day_utc(&Utc, date(9, 8)), utc(9, 8, 0));
+    }
+
+    // 月中的本地时间，测试结果不受时区影响
+    fn local(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        Local
+            .with_ymd_and_hms(year, month, day, 12, 0, 0)
+            .unwrap()
+            .to_utc()
+    }
+
+    #[test]
+    fn months_between_spans_year_boundaries() {
+        let today = NaiveDate::from_ymd_opt(2025, 2, 10).unwrap();
+        assert_eq!(
+            months_between(local(2024, 11, 15), today),
+            [
+                month(2024, 11),
+                month(2024, 12),
+                month(2025, 1),
+                month(2025, 2)
+            ]
+        );
+        assert_eq!(months_between(local(2023, 2, 15), today).len(), 25);
+        assert_eq!(month(2025, 1).previous(), month(2024, 12));
+        assert_eq!(month(2025, 2).previous(), month(2025, 1));
+    }
+
+    #[test]
+    fn months_between_within_the_current_month_is_the_current_month() {
+        let today = NaiveDate::from_ymd_opt(2025, 2, 10).unwrap();
+        assert_eq!(months_between(local(2025, 2, 3), today), [month(2025, 2)]);
+        // 时钟回拨等原因导致上次运行晚于今天时同样只备份当月
+        assert_eq!(months_between(local(2025, 4, 3), today), [month(2025, 2)]);
+    }
+
+    fn week(year: i32, week: u32) -> BackupWeek {
+        BackupWeek { year, week }
+    }
+
+    #[test]
+    fn weeks_across_year_boundaries() {
+        use BackupMode::{CurrentMonth, Dynamic, PreviousMonth};
+        // 2025-01-01 是周三，属于 2025 年第 1 周；2024-12-30 是这一周的周一
+        let new_year = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+        assert_eq!(
+            determine_backup_weeks(&CurrentMonth, 2, new_year),
+            [week(2025, 1)]
+        );
+        assert_eq!(
+            determine_backup_weeks(&PreviousMonth, 2, new_year),
+            [week(2024, 52)]
+        );
+        assert_eq!(
+            week(2025, 1).first_day(),
+            NaiveDate::from_ymd_opt(2024, 12, 30).unwrap()
+        );
+        // 2020 年有 53 周
+        let monday = NaiveDate::from_ymd_opt(2021, 1, 4).unwrap();
+        assert_eq!(
+            determine_backup_weeks(&Dynamic, 2, monday),
+            [week(2020, 53), week(2021, 1)]
+        );
+        // 周二仍在 2 天内，周三起只备份本周
+        let tuesday = NaiveDate::from_ymd_opt(2025, 7, 29).unwrap();
+        assert_eq!(
+            determine_backup_weeks(&Dynamic, 2, tuesday),
+            [week(2025, 30), week(2025, 31)]
+        );
+        let wednesday = NaiveDate::from_ymd_opt(2025, 7, 30).unwrap();
+        assert_eq!(
+            determine_backup_weeks(&Dynamic, 2, wednesday),
+            [week(2025, 31)]
+        );
+        assert_eq!(determine_backup_weeks(&Dynamic, 0, monday), [week(2021, 1)]);
+        assert!(determine_backup_weeks(&BackupMode::CatchUp, 2, monday).is_empty());
+    }
+
+    #[test]
+    fn period_labels_and_ranges() {
+        let july = BackupPeriod::Month(month(2025, 7));
+        assert_eq!(july.label(), "2025-07");
+        assert_eq!(format!("{:?}", july), format!("{:?}", month(2025, 7)));
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(july.date_range(), (date(2025, 7, 1), date(2025, 8, 1)));
+        assert_eq!(
+            BackupPeriod::Month(month(2024, 12)).date_range(),
+            (date(2024, 12, 1), date(2025, 1, 1))
+        );
+        let week31 = BackupPeriod::IsoWeek(week(2025, 31));
+        assert_eq!(week31.label(), "2025-W31");
+        assert_eq!(week31.date_range(), (date(2025, 7, 28), date(2025, 8, 4)));
+
+        for period in [july, week31, BackupPeriod::IsoWeek(week(2020, 53))] {
+            assert_eq!(BackupPeriod::from_label(&period.label()), Some(period));
+        }
+        for label in [
+            "2025-13", "2025-W54", "2025-W00", "2025-W5", "2025-7", "25-07", "2025",
+        ] {
+            assert_eq!(BackupPeriod::from_label(label), None, "{}", label);
+        }
+    }
+
+    #[test]
+    fn explicit_mode_has_no_months_of_its_own() {
+        let today = NaiveDate::from_ymd_opt(2025, 7, 15).unwrap();
+        let defaults = DynamicThresholds::default();
+        assert!(determine_backup_months(&BackupMode::Explicit, &defaults, today).is_empty());
+        assert!(determine_backup_months(&BackupMode::CatchUp, &defaults, today).is_empty());
+        assert!(determine_backup_months(&BackupMode::DateRange, &defaults, today).is_empty());
+    }
+
+    #[test]
+    fn date_range_arguments_and_labels() {
+        let local_midnight = |y, m, d| {
+            Local
+                .with_ymd_and_hms(y, m, d, 0, 0, 0)
+                .earliest()
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        assert_eq!(
+            parse_date_time("2024-06-15"),
+            Ok(local_midnight(2024, 6, 15))
+        );
+        assert_eq!(
+            parse_date_time("2024-06-15T08:30:00+08:00"),
+            Ok(Utc.with_ymd_and_hms(2024, 6, 15, 0, 30, 0).unwrap())
+        );
+        assert_eq!(
+            parse_date_time("2024-06-15T00:30:00Z"),
+            Ok(Utc.with_ymd_and_hms(2024, 6, 15, 0, 30, 0).unwrap())
+        );
+        for value in [
+            "2024-6-15",
+            "2024-06-31",
+            "20240615",
+            "2024-06-15 08:30",
+            "",
+        ] {
+            assert!(parse_date_time(value).is_err(), "{}", value);
+        }
+
+        let range = BackupPeriod::Range(DateRange {
+            start: local_midnight(2024, 6, 15),
+            end: local_midnight(2024, 9, 1),
+        });
+        assert_eq!(range.label(), "2024-06-15_to_2024-09-01");
+        assert_eq!(BackupPeriod::from_label(&range.label()), None);
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(range.date_range(), (date(2024, 6, 15), date(2024, 9, 1)));
+        // 终点不在零点时，终点所在的日期也被覆盖
+        let range = BackupPeriod::Range(DateRange {
+            start: local_midnight(2024, 6, 15),
+            end: local_midnight(2024, 9, 1) + chrono::Duration::hours(12),
+        });
+        assert_eq!(range.label(), "2024-06-15_to_2024-09-01");
+        assert_eq!(range.date_range(), (date(2024, 6, 15), date(2024, 9, 2)));
+    }
+
+    #[test]
+    fn modes_across_month_and_year_boundaries() {
+        use BackupMode::{CurrentMonth, Dynamic, PreviousMonth};
+        // 模式、今天的日期和预期的月份
+        type Case = (BackupMode, (i32, u32, u32), &'static [(i32, u32)]);
+        let cases: &[Case] = &[
+            // 年初：上个月是去年 12 月
+            (PreviousMonth, (2025, 1, 1), &[(2024, 12)]),
+            (CurrentMonth, (2025, 1, 1), &[(2025, 1)]),
+            (Dynamic, (2025, 1, 1), &[(2024, 12), (2025, 1)]),
+            (Dynamic, (2025, 1, 7), &[(2024, 12), (2025, 1)]),
+            (Dynamic, (2025, 1, 8), &[(2025, 1)]),
+            // 年末
+            (PreviousMonth, (2024, 12, 31), &[(2024, 11)]),
+            (CurrentMonth, (2024, 12, 31), &[(2024, 12)]),
+            (Dynamic, (2024, 12, 31), &[(2024, 12)]),
+            // 平年和闰年的 2 月底
+            (PreviousMonth, (2025, 2, 28), &[(2025, 1)]),
+            (CurrentMonth, (2025, 2, 28), &[(2025, 2)]),
+            (Dynamic, (2025, 2, 28), &[(2025, 2)]),
+            (CurrentMonth, (2024, 2, 29), &[(2024, 2)]),
+            (Dynamic, (2024, 2, 29), &[(2024, 2)]),
+            // 2 月之后的 3 月初：上个月的最后一天是 28 日或 29 日
+            (PreviousMonth, (2025, 3, 1), &[(2025, 2)]),
+            (Dynamic, (2025, 3, 7), &[(2025, 2), (2025, 3)]),
+            (Dynamic, (2025, 3, 8), &[(2025, 3)]),
+            (Dynamic, (2024, 3, 7), &[(2024, 2), (2024, 3)]),
+            (Dynamic, (2024, 3, 8), &[(2024, 3)]),
+            // 月中
+            (PreviousMonth, (2025, 7, 15), &[(2025, 6)]),
+            (Dynamic, (2025, 7, 15), &[(2025, 7)]),
+        ];
+        for (mode, (year, mon, day), expected) in cases {
+            let today = NaiveDate::from_ymd_opt(*year, *mon, *day).unwrap();
+            let expected: Vec<BackupMonth> = expected.iter().map(|&(y, m)| month(y, m)).collect();
+            assert_eq!(
+                determine_backup_months(mode, &DynamicThresholds::default(), today),
+                expected,
+                "{:?} on {}",
+                mode,
+                today
+            );
+        }
+    }
+}
@@ -1,62 +1,1925 @@
-use chrono::{DateTime, TimeZone, Utc};
+use crate::backup_logic::ArchiveKind;
+use crate::error::{BackupError, cache_error};
+use chrono::{DateTime, Datelike, Local, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+mod legacy;
+mod rebuild;
+mod sqlite;
+
+pub use rebuild::records_from_archives;
+pub use sqlite::SqliteStore;
+
+/// `.cache` 目录中的备份记录文件（JSON 后端）
+pub const CACHE_FILE_NAME: &str = "backupEvents.json";
+
+/// `backupEvents.json` 的格式版本：1 为旧版本写入的记录数组，
+/// 2 起为 `{ "Version": 2, "Records": [...] }`
+pub const CACHE_FORMAT_VERSION: u64 = 2;
+
+/// `.cache` 目录中的去重索引文件（JSON 后端）
+pub const FILE_HASHES_FILE_NAME: &str = "fileHashes.json";
+
+/// `.cache` 目录中的变化检测索引文件（JSON 后端）
+pub const SCAN_INDEX_FILE_NAME: &str = "scanIndex.json";
+
+/// `.cache` 目录中 `verify --record` 写入的校验记录文件（JSON 后端）
+pub const VERIFICATIONS_FILE_NAME: &str = "verifyEvents.json";
 
 /// 定义单个备份事件的记录结构
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "PascalCase")]
 pub struct CacheRecord {
     pub start_time: DateTime<Utc>,
     pub end_time: DateTime<Utc>,
     pub backup_info: String,
+    /// 下次增量备份的截止时间：本次扫描开始的时间，此后修改的文件由下次备份归档。
+    /// 旧版本的记录没有该字段，此时使用 `start_time`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cutoff_time: Option<DateTime<Utc>>,
+    /// 本次运行创建的全量/增量归档；独立归档不记录
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub archives: Vec<ArchiveRecord>,
+    /// 使用 --split-by-top-dir 时本次运行归档的账号（顶层目录）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accounts: Vec<String>,
+    /// 本次运行备份的所有源目录 (--from)；旧版本的记录没有该字段
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_paths: Vec<String>,
+    /// 本次运行在目标目录中创建的所有归档文件名（包括分卷和独立归档）；旧版本的记录没有该字段
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub archive_files: Vec<String>,
+    /// 发现 `archive_files` 中的归档已全部被删除的时间
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archives_deleted_time: Option<DateTime<Utc>>,
+    /// 本次运行处理的每个月份的结果；旧版本的记录没有该字段，此时其截止时间适用于所有月份
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub months: Vec<MonthResult>,
+    /// 本次运行的整体结果；旧版本的记录没有该字段（旧版本只记录成功的运行）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<RunStatus>,
+    /// 本次运行归档的文件数；旧版本的记录没有该字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files_archived: Option<usize>,
+    /// 本次运行归档的文件的总大小（压缩前）；旧版本的记录没有该字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_archived: Option<u64>,
+    /// 写入该记录的程序版本；旧版本的记录没有该字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+    /// 本次运行划分月份使用的时区（--month-boundary-tz）；旧版本的记录没有该字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub month_boundary_tz: Option<String>,
+    /// 尚未归档的文件：本次运行中被占用或无法读取而跳过的文件，加上之前的运行中被跳过、
+    /// 本次未成功扫描其月份的文件。下次运行不论修改时间都会重新尝试它们
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_files: Vec<SkippedFileRecord>,
+    /// 本次运行备份的源：--source-label，或规范化后的 --from 路径（多个时以 `|` 连接）。
+    /// 旧版本的记录没有该字段，适用于所有源
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_id: Option<String>,
+    /// 本版本不认识的字段（由更新的版本写入），重新写入时原样保留
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl CacheRecord {
+    /// 本次运行的整体结果；旧版本只记录成功的运行，没有 `Status` 字段时视为 `Success`
+    pub fn run_status(&self) -> RunStatus {
+        self.status.unwrap_or(RunStatus::Success)
+    }
+
+    /// 记录是否属于指定的源；没有 `SourceId` 字段的旧记录属于所有源
+    pub fn applies_to_source(&self, source_id: &str) -> bool {
+        self.source_id.as_deref().is_none_or(|id| id == source_id)
+    }
+}
+
+/// 一次备份运行的整体结果
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    /// 所有归档都已创建，且没有跳过文件
+    Success,
+    /// 部分归档失败，或有被占用、无法读取的文件被跳过
+    Partial,
+    /// 没有成功创建任何归档
+    Failed,
+}
+
+/// 一次运行中单个月份的备份结果
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct MonthResult {
+    /// 备份的月份，例如 `2025-07`
+    pub month: String,
+    /// 成功时为本次扫描开始的时间，该月此后修改的文件由下次备份归档；
+    /// 失败时为本次扫描使用的（上次的）截止时间
+    pub cutoff: DateTime<Utc>,
+    /// 该月创建的归档文件名（包括分卷和独立归档）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub archive_files: Vec<String>,
+    pub status: MonthStatus,
+}
+
+/// 一次运行中被跳过、尚未归档的文件
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct SkippedFileRecord {
+    /// 文件所属的月份，例如 `2025-07`
+    pub month: String,
+    /// 归档内的相对路径（`/` 分隔）；有多个源目录时以源目录标签开头
+    pub path: String,
+    /// 跳过的原因
+    pub reason: String,
+}
+
+/// 单个月份的备份状态
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonthStatus {
+    /// 该月的所有归档都已创建并通过校验，或没有需要备份的文件
+    Succeeded,
+    /// 该月至少有一个归档失败，下次运行从上次的截止时间重新扫描
+    Failed,
+}
+
+/// 单个全量或增量归档的记录
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ArchiveRecord {
+    /// 备份的月份，例如 `2025-07`
+    pub month: String,
+    /// 按顶层目录拆分时的账号（顶层目录）名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub kind: ArchiveKind,
+    /// 归档文件名（位于目标目录中）
+    pub file_name: String,
+}
+
+/// `backupEvents.json` 的外层结构（格式版本 2 起）
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CacheFile<'r> {
+    version: u64,
+    records: &'r [CacheRecord],
+}
+
+/// 解析缓存文件的内容
+///
+/// # Returns
+/// 格式版本高于本版本支持的版本时返回 `Ok(Err(版本))`；无法解析时返回 `Err(原因)`。
+fn parse_cache_content(content: &str) -> Result<Result<Vec<CacheRecord>, u64>, String> {
+    let value: serde_json::Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    if let Some(version) = value.get("Version") {
+        let version = version
+            .as_u64()
+            .ok_or_else(|| format!("invalid format version {}", version))?;
+        if version > CACHE_FORMAT_VERSION {
+            return Ok(Err(version));
+        }
+        let records = value
+            .get("Records")
+            .cloned()
+            .ok_or_else(|| "missing Records".to_string())?;
+        return serde_json::from_value(records)
+            .map(Ok)
+            .map_err(|e| e.to_string());
+    }
+    // 格式版本 1：记录数组；否则尝试原 PowerShell 脚本的格式
+    serde_json::from_value(value)
+        .or_else(|e| legacy::parse_powershell_records(content).ok_or_else(|| e.to_string()))
+        .map(Ok)
 }
 
 /// 读取并解析缓存文件
 ///
+/// 同时接受格式版本 1（记录数组）和当前的格式；更新的版本写入的文件不会被覆盖，
+/// 而是返回错误提示升级。
+///
+/// 原 PowerShell 脚本写入的缓存文件（.NET 日期格式、旧的字段名）同样可以读取，
+/// 下次写入时改为当前的格式。
+///
+/// 无法解析的缓存文件（例如写入时断电）被改名为 `backupEvents.json.corrupt-<毫秒时间戳>` 保留，
+/// 并打印警告后按没有记录继续：截止时间退回 1970 年，下次备份只是比需要的更大。
+/// 只读时（--dry-run、status 等）不改名，只打印警告。
+///
 /// # Arguments
 /// * `cache_path` - `backupEvents.json` 文件的路径
+/// * `read_only` - 不修改缓存目录
 ///
 /// # Returns
-/// 成功时返回一个包含 `CacheRecord` 的向量，如果文件不存在、为空或解析失败，则返回错误。
-pub fn read_cache_records(cache_path: &Path) -> io::Result<Vec<CacheRecord>> {
+/// 成功时返回一个包含 `CacheRecord` 的向量；文件不存在、为空或已损坏时返回空记录，
+/// 读取或改名失败时返回 [`BackupError::CacheError`]。
+pub fn read_cache_records(
+    cache_path: &Path,
+    read_only: bool,
+) -> Result<Vec<CacheRecord>, BackupError> {
     // 检查文件是否存在
     if !cache_path.exists() {
         return Ok(Vec::new()); // 文件不存在，返回空记录
     }
 
-    let content = fs::read_to_string(cache_path)?;
-    if content.trim().is_empty() {
-        return Ok(Vec::new()); // 文件为空，返回空记录
+    let content = fs::read(cache_path).map_err(cache_error(cache_path))?;
+    let parsed = String::from_utf8(content)
+        .map_err(|e| e.to_string())
+        .and_then(|content| {
+            // PowerShell 5.1 写入的 UTF-8 文件带有 BOM
+            let content = content.trim_start_matches('\u{feff}');
+            if content.trim().is_empty() {
+                return Ok(Ok(Vec::new())); // 文件为空，返回空记录
+            }
+            parse_cache_content(content)
+        });
+    match parsed {
+        Ok(Ok(records)) => Ok(records),
+        Ok(Err(version)) => Err(cache_error(cache_path)(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "'{}' uses cache format version {}, but this version of dat-patch-rust only understands up to version {}; upgrade dat-patch-rust",
+                cache_path.display(),
+                version,
+                CACHE_FORMAT_VERSION
+            ),
+        ))),
+        Err(reason) if read_only => {
+            log::warn!(
+                target: crate::logging::ALERT_TARGET,
+                "The cache file '{}' is corrupt ({}). Continuing without backup history.",
+                cache_path.display(),
+                reason
+            );
+            Ok(Vec::new())
+        }
+        Err(reason) => {
+            // 同一毫秒内损坏多次时加上序号，不覆盖之前保留的文件
+            let stamp = Local::now().format("%Y%m%d%H%M%S%3f").to_string();
+            let mut corrupt_path =
+                crate::archiver::with_suffix(cache_path, &format!(".corrupt-{}", stamp));
+            let mut count = 1;
+            while corrupt_path.exists() {
+                count += 1;
+                corrupt_path = crate::archiver::with_suffix(
+                    cache_path,
+                    &format!(".corrupt-{}-{}", stamp, count),
+                );
+            }
+            fs::rename(cache_path, &corrupt_path).map_err(cache_error(cache_path))?;
+            log::warn!(
+                target: crate::logging::ALERT_TARGET,
+                "The cache file '{}' is corrupt ({}) and was moved to '{}'. \
+                 Continuing without backup history; this backup may include more files than needed.",
+                cache_path.display(),
+                reason,
+                corrupt_path.display()
+            );
+            Ok(Vec::new())
+        }
     }
+}
+
+/// 先写入同一目录中的 `<文件名>.tmp` 再改名覆盖原文件，写入中断时原文件保持完整
+fn write_json_atomically<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    use std::io::Write;
 
-    // 解析 JSON
-    serde_json::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    // 使用 to_string_pretty 来生成格式化、易读的 JSON 文件
+    let json_content = serde_json::to_string_pretty(value)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let tmp_path = crate::archiver::with_suffix(path, ".tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(json_content.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)
+}
+
+/// 记录的时间晚于当前时间超过该秒数时视为时钟错误
+const MAX_CLOCK_SKEW_SECONDS: i64 = 60 * 60;
+
+/// 检查记录的时间是否明显错误（系统时钟设置错误时写入）
+///
+/// 截止时间在未来的记录会让之后的每次运行都找不到需要备份的文件，因此不能使用。
+///
+/// # Arguments
+/// * `record` - 缓存记录
+/// * `now` - 当前时间
+///
+/// # Returns
+/// 时间明显错误时返回原因，否则返回 `None`。
+pub fn bogus_timestamp_reason(record: &CacheRecord, now: DateTime<Utc>) -> Option<&'static str> {
+    let limit = now + chrono::Duration::seconds(MAX_CLOCK_SKEW_SECONDS);
+    if record.end_time < record.start_time {
+        Some("EndTime is before StartTime")
+    } else if record.end_time > limit {
+        Some("EndTime is in the future")
+    } else if record.cutoff_time.is_some_and(|cutoff| cutoff > limit)
+        || record.months.iter().any(|m| m.cutoff > limit)
+    {
+        Some("the cutoff is in the future")
+    } else {
+        None
+    }
 }
 
-/// 从缓存记录中获取最后一次备份的结束时间
+/// 从缓存记录中获取指定月份增量备份的截止时间，该月只有此后修改的文件需要备份
+///
+/// 使用截止时间（扫描开始的时间）而不是 `EndTime`：
+/// 在上次备份运行期间被修改的文件修改时间早于 `EndTime`，否则会被永久遗漏。
+///
+/// 按 `EndTime` 从新到旧查找第一条成功备份了该月份的记录：某个月份失败或未被处理的运行
+/// 不会推进该月的截止时间，下次运行会从更早的截止时间重新扫描该月。
+/// 其他源的记录、`Status` 为 `Failed` 的记录以及时间明显错误的记录（见 `bogus_timestamp_reason`）被忽略。
+/// 旧版本的记录没有 `Months` 字段，其截止时间适用于所有月份。
 ///
 /// # Arguments
 /// * `records` - `CacheRecord` 的切片
+/// * `month` - 月份，例如 `2025-07`
+/// * `source_id` - 本次运行的源（见 `CacheRecord::source_id`）
+/// * `now` - 当前时间
 ///
 /// # Returns
-/// 返回最后一次备份的 `EndTime`。如果没有记录，则返回一个10年前的时间点。
-pub fn get_last_backup_time(records: &[CacheRecord]) -> DateTime<Utc> {
-    records.iter().max_by_key(|r| r.end_time).map_or_else(
-        || Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap(), // 如果没有记录，返回一个很早的时间
-        |r| r.end_time,
-    )
+/// 返回该月份的截止时间，旧记录没有 `CutoffTime` 时返回其 `StartTime`。
+/// 如果没有匹配的记录，则返回 1970-01-01。
+pub fn get_last_backup_time_for_month(
+    records: &[CacheRecord],
+    month: &str,
+    source_id: &str,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    effective_cutoff(records, month, source_id, now)
+        .map(|(_, cutoff)| cutoff)
+        .unwrap_or_else(|| Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap()) // 如果没有记录，返回一个很早的时间
+}
+
+/// 查找提供指定月份截止时间的记录（见 `get_last_backup_time_for_month`），返回其下标及截止时间
+fn effective_cutoff(
+    records: &[CacheRecord],
+    month: &str,
+    source_id: &str,
+    now: DateTime<Utc>,
+) -> Option<(usize, DateTime<Utc>)> {
+    let mut sorted: Vec<(usize, &CacheRecord)> = records.iter().enumerate().collect();
+    sorted.sort_by_key(|(_, r)| std::cmp::Reverse(r.end_time));
+    sorted
+        .into_iter()
+        .filter(|(_, r)| r.applies_to_source(source_id))
+        .filter(|(_, r)| r.run_status() != RunStatus::Failed)
+        .filter(|(_, r)| bogus_timestamp_reason(r, now).is_none())
+        .find_map(|(index, r)| {
+            if r.months.is_empty() {
+                return Some((index, r.cutoff_time.unwrap_or(r.start_time)));
+            }
+            r.months
+                .iter()
+                .find(|m| m.month == month && m.status == MonthStatus::Succeeded)
+                .map(|m| (index, m.cutoff))
+        })
+}
+
+/// 查找最近一次完全成功（`Status` 为 `Success`）的备份运行
+///
+/// # Arguments
+/// * `records` - `CacheRecord` 的切片
+/// * `source_id` - 只考虑适用于该源的记录；`None` 时考虑所有记录
+/// * `now` - 当前时间
+///
+/// # Returns
+/// 返回 `EndTime` 最晚的成功记录，时间明显错误的记录除外；没有成功记录时返回 `None`。
+pub fn last_successful_backup<'r>(
+    records: &'r [CacheRecord],
+    source_id: Option<&str>,
+    now: DateTime<Utc>,
+) -> Option<&'r CacheRecord> {
+    successful_backups(records, source_id, now).max_by_key(|r| r.end_time)
+}
+
+/// 查找最早一次完全成功的备份运行，条件同 [`last_successful_backup`]
+pub fn first_successful_backup<'r>(
+    records: &'r [CacheRecord],
+    source_id: Option<&str>,
+    now: DateTime<Utc>,
+) -> Option<&'r CacheRecord> {
+    successful_backups(records, source_id, now).min_by_key(|r| r.end_time)
+}
+
+/// 适用于 `source_id`、时间没有明显错误的成功记录
+fn successful_backups<'r>(
+    records: &'r [CacheRecord],
+    source_id: Option<&str>,
+    now: DateTime<Utc>,
+) -> impl Iterator<Item = &'r CacheRecord> {
+    records
+        .iter()
+        .filter(move |r| source_id.is_none_or(|id| r.applies_to_source(id)))
+        .filter(|r| r.run_status() == RunStatus::Success)
+        .filter(move |r| bogus_timestamp_reason(r, now).is_none())
+}
+
+/// 获取指定的源尚未归档的文件，即最近一条记录的 `SkippedFiles`
+///
+/// 每条记录都带上之前未解决的文件，因此只需查看最近的一条；时间明显错误的记录被忽略。
+///
+/// # Arguments
+/// * `records` - `CacheRecord` 的切片
+/// * `source_id` - 本次运行的源（见 `CacheRecord::source_id`）
+/// * `now` - 当前时间
+pub fn outstanding_skipped_files<'r>(
+    records: &'r [CacheRecord],
+    source_id: &str,
+    now: DateTime<Utc>,
+) -> &'r [SkippedFileRecord] {
+    records
+        .iter()
+        .filter(|r| r.applies_to_source(source_id))
+        .filter(|r| bogus_timestamp_reason(r, now).is_none())
+        .max_by_key(|r| r.end_time)
+        .map_or(&[], |r| r.skipped_files.as_slice())
+}
+
+/// 本次运行的归档文件名中的源标记（见 [`crate::archiver::source_tag`]）
+///
+/// 最早备份到该目标目录的源（第一条带 `SourceId` 的记录）的归档不带标记，因此只有一个源的
+/// 目标目录中的文件名与之前的版本相同；其他源的归档都带标记，按月份去重、GFS、追加模式和
+/// 恢复不会把不同源的归档当作同一系列。没有带 `SourceId` 的记录时返回 `None`。
+pub fn archive_source_tag(records: &[CacheRecord], source_id: &str) -> Option<String> {
+    let (_, owner) = records
+        .iter()
+        .filter_map(|r| Some((r.start_time, r.source_id.as_deref()?)))
+        .min_by_key(|(start_time, _)| *start_time)?;
+    (owner != source_id).then(|| crate::archiver::source_tag(source_id))
 }
 
-/// 将缓存记录列表写入到指定的 JSON 文件。
+/// 查找指定月份（及账号）最近一次创建的全量归档，作为增量归档的基础
+///
+/// # Arguments
+/// * `records` - `CacheRecord` 的切片
+/// * `month` - 月份，例如 `2025-07`
+/// * `group` - 按顶层目录拆分时的账号名，未拆分时为 `None`
+/// * `source_id` - 本次运行的源，其他源的全量归档不能作为基础
+///
+/// # Returns
+/// 返回该全量归档的文件名；从未创建过对应的全量归档时返回 `None`。
+pub fn latest_full_archive<'r>(
+    records: &'r [CacheRecord],
+    month: &str,
+    group: Option<&str>,
+    source_id: &str,
+) -> Option<&'r str> {
+    full_archive_record(records, month, group, source_id).map(|(_, name)| name)
+}
+
+/// 查找记录了 `latest_full_archive` 所返回的全量归档的记录，返回其下标及归档文件名
+fn full_archive_record<'r>(
+    records: &'r [CacheRecord],
+    month: &str,
+    group: Option<&str>,
+    source_id: &str,
+) -> Option<(usize, &'r str)> {
+    let is_base = |a: &ArchiveRecord| {
+        a.month == month && a.group.as_deref() == group && a.kind == ArchiveKind::Full
+    };
+    records
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.applies_to_source(source_id))
+        .filter(|(_, r)| r.archives.iter().any(is_base))
+        .max_by_key(|(_, r)| r.end_time)
+        .and_then(|(index, r)| {
+            r.archives
+                .iter()
+                .rfind(|a| is_base(a))
+                .map(|a| (index, a.file_name.as_str()))
+        })
+}
+
+/// 标记归档已全部不在目标目录中（例如已被 --keep-months 删除）的缓存记录
+///
+/// 只要还有一个归档存在，记录就保持不变；没有 `ArchiveFiles` 字段的旧记录不会被修改。
+/// 记录本身不会被删除，最近一次备份的截止时间仍然有效。
+///
+/// # Arguments
+/// * `records` - 缓存记录
+/// * `destination_path` - 归档所在的目标目录
+/// * `now` - 写入 `ArchivesDeletedTime` 的时间
+///
+/// # Returns
+/// 返回本次新标记的记录数。
+pub fn prune_records_for_deleted(
+    records: &mut [CacheRecord],
+    destination_path: &Path,
+    now: DateTime<Utc>,
+) -> usize {
+    let mut marked = 0;
+    for record in records {
+        if record.archive_files.is_empty() || record.archives_deleted_time.is_some() {
+            continue;
+        }
+        if !record
+            .archive_files
+            .iter()
+            .any(|name| destination_path.join(name).exists())
+        {
+            record.archives_deleted_time = Some(now);
+            marked += 1;
+        }
+    }
+    marked
+}
+
+/// 只保留最新的 `limit` 条缓存记录（--cache-history-limit），多出的记录从最旧的开始移出
+///
+/// 仍提供某个月份（某个源）截止时间的记录，以及记录了增量归档所基于的全量归档的记录
+/// 总会保留，因此保留的记录可能多于 `limit` 条。
+///
+/// # Arguments
+/// * `records` - 缓存记录，处理后按结束时间排序
+/// * `limit` - 保留的记录数
+/// * `now` - 当前时间，用于识别时间明显错误的记录
+///
+/// # Returns
+/// 返回被移出的记录（按结束时间排序），由调用方写入 `archive_history`。
+pub fn limit_history(
+    records: &mut Vec<CacheRecord>,
+    limit: usize,
+    now: DateTime<Utc>,
+) -> Vec<CacheRecord> {
+    // 记录按运行顺序追加，但仍按结束时间排序，保证移出的是最旧的记录
+    records.sort_by_key(|r| r.end_time);
+    let in_use = records_in_use(records, now);
+    let mut excess = records.len().saturating_sub(limit);
+    let mut overflow = Vec::new();
+    let mut kept = Vec::with_capacity(records.len() - excess);
+    for (index, record) in records.drain(..).enumerate() {
+        if excess > 0 && !in_use.contains(&index) {
+            overflow.push(record);
+            excess -= 1;
+        } else {
+            kept.push(record);
+        }
+    }
+    *records = kept;
+    overflow
+}
+
+/// 截止时间或增量归档仍然依赖的记录的下标
+fn records_in_use(records: &[CacheRecord], now: DateTime<Utc>) -> HashSet<usize> {
+    // 没有 `SourceId` 的旧记录适用于所有源，空字符串代表只有旧记录的源
+    let sources: BTreeSet<&str> = records
+        .iter()
+        .map(|r| r.source_id.as_deref().unwrap_or(""))
+        .collect();
+    // 空字符串代表记录中没有出现过的月份，旧记录的截止时间同样适用于它们
+    let months: BTreeSet<&str> = records
+        .iter()
+        .flat_map(|r| r.months.iter().map(|m| m.month.as_str()))
+        .chain([""])
+        .collect();
+    let bases: BTreeSet<(&str, Option<&str>)> = records
+        .iter()
+        .flat_map(|r| &r.archives)
+        .filter(|a| a.kind == ArchiveKind::Full)
+        .map(|a| (a.month.as_str(), a.group.as_deref()))
+        .collect();
+
+    let mut in_use = HashSet::new();
+    for source_id in &sources {
+        for month in &months {
+            in_use.extend(effective_cutoff(records, month, source_id, now).map(|(i, _)| i));
+        }
+        for (month, group) in &bases {
+            in_use.extend(full_archive_record(records, month, *group, source_id).map(|(i, _)| i));
+        }
+    }
+    in_use
+}
+
+/// 保存被 --cache-history-limit 移出的记录的文件名，按记录结束时间（本地时间）的年份分开
+pub fn history_archive_file_name(year: i32) -> String {
+    format!("backupEvents-archive-{}.json", year)
+}
+
+/// 把被 --cache-history-limit 移出的记录追加到 `.cache` 目录中按年份的归档文件
+///
+/// 归档文件与 `backupEvents.json` 的格式相同，不论使用哪种缓存后端。
+///
+/// # Arguments
+/// * `cache_folder` - `.cache` 目录
+/// * `records` - `limit_history` 移出的记录
+pub fn archive_history(cache_folder: &Path, records: &[CacheRecord]) -> Result<(), BackupError> {
+    let mut by_year: BTreeMap<i32, Vec<CacheRecord>> = BTreeMap::new();
+    for record in records {
+        let year = record.end_time.with_timezone(&Local).year();
+        by_year.entry(year).or_default().push(record.clone());
+    }
+    for (year, records) in by_year {
+        let path = cache_folder.join(history_archive_file_name(year));
+        let mut archived = read_cache_records(&path, false)?;
+        archived.extend(records);
+        write_cache_records(&path, &archived)?;
+    }
+    Ok(())
+}
+
+/// 将缓存记录列表以当前的格式版本写入到指定的 JSON 文件。
 ///
 /// # Arguments
 /// * `cache_path` - `backupEvents.json` 文件的路径。
 /// * `records` - 需要写入的完整记录切片。
-pub fn write_cache_records(cache_path: &Path, records: &[CacheRecord]) -> io::Result<()> {
-    // 使用 to_string_pretty 来生成格式化、易读的 JSON 文件
-    let json_content = serde_json::to_string_pretty(records)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    fs::write(cache_path, json_content)
+pub fn write_cache_records(cache_path: &Path, records: &[CacheRecord]) -> Result<(), BackupError> {
+    write_json_atomically(
+        cache_path,
+        &CacheFile {
+            version: CACHE_FORMAT_VERSION,
+            records,
+        },
+    )
+    .map_err(cache_error(cache_path))
+}
+
+/// 去重索引中单个文件的记录，对应该文件最近一次被归档时的状态
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct FileHashRecord {
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+    pub sha256: String,
+}
+
+/// 去重索引：归档内相对路径（`/` 分隔）-> 文件记录
+pub type FileHashIndex = BTreeMap<String, FileHashRecord>;
+
+/// 读取去重索引
+///
+/// # Arguments
+/// * `index_path` - `fileHashes.json` 文件的路径
+///
+/// # Returns
+/// 文件不存在或为空时返回空索引，读取或解析失败时返回 [`BackupError::CacheError`]。
+pub fn read_file_hashes(index_path: &Path) -> Result<FileHashIndex, BackupError> {
+    if !index_path.exists() {
+        return Ok(FileHashIndex::new());
+    }
+
+    let content = fs::read_to_string(index_path).map_err(cache_error(index_path))?;
+    if content.trim().is_empty() {
+        return Ok(FileHashIndex::new());
+    }
+
+    serde_json::from_str(&content)
+        .map_err(|e| cache_error(index_path)(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+/// 将去重索引写入到指定的 JSON 文件。
+///
+/// # Arguments
+/// * `index_path` - `fileHashes.json` 文件的路径。
+/// * `index` - 需要写入的完整索引。
+pub fn write_file_hashes(index_path: &Path, index: &FileHashIndex) -> Result<(), BackupError> {
+    write_json_atomically(index_path, index).map_err(cache_error(index_path))
+}
+
+/// 变化检测索引中单个文件的记录，对应该文件最近一次被归档时的状态
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ScanIndexRecord {
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+    /// 内容的 xxh3 哈希（小写十六进制），只在 `--detect-changes hash` 时记录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+/// 变化检测索引：归档内相对路径（`/` 分隔）-> 文件记录
+pub type ScanIndex = BTreeMap<String, ScanIndexRecord>;
+
+/// 读取变化检测索引
+///
+/// 索引只用于跳过未变化的文件，损坏时视为空索引：所有文件都会被当作新文件重新归档。
+///
+/// # Arguments
+/// * `index_path` - `scanIndex.json` 文件的路径
+///
+/// # Returns
+/// 文件不存在、为空或无法解析时返回空索引，读取失败时返回 [`BackupError::CacheError`]。
+pub fn read_scan_index(index_path: &Path) -> Result<ScanIndex, BackupError> {
+    if !index_path.exists() {
+        return Ok(ScanIndex::new());
+    }
+
+    let content = fs::read_to_string(index_path).map_err(cache_error(index_path))?;
+    Ok(serde_json::from_str(&content).unwrap_or_default())
+}
+
+/// 将变化检测索引写入到指定的 JSON 文件。
+///
+/// # Arguments
+/// * `index_path` - `scanIndex.json` 文件的路径。
+/// * `index` - 需要写入的完整索引。
+pub fn write_scan_index(index_path: &Path, index: &ScanIndex) -> Result<(), BackupError> {
+    write_json_atomically(index_path, index).map_err(cache_error(index_path))
+}
+
+/// 一次 `verify --record` 的结果
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct VerificationRecord {
+    pub time: DateTime<Utc>,
+    /// 只校验了 CRC，没有与清单核对 SHA-256（--quick）
+    pub quick: bool,
+    /// 校验的月份（--month）；为空时校验了所有归档
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub months: Vec<String>,
+    /// 校验的归档数，不含损坏的归档
+    pub archives_passed: usize,
+    /// 损坏或无法读取的归档
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed: Vec<FailedArchive>,
+}
+
+/// 校验失败的归档
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct FailedArchive {
+    /// 目标目录中的归档文件名
+    pub name: String,
+    pub reason: String,
+}
+
+/// 读取校验记录，按写入顺序排列
+///
+/// # Returns
+/// 文件不存在时返回空列表，读取或解析失败时返回 [`BackupError::CacheError`]。
+pub fn read_verification_records(path: &Path) -> Result<Vec<VerificationRecord>, BackupError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(path).map_err(cache_error(path))?;
+    serde_json::from_str(&content)
+        .map_err(|e| cache_error(path)(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+/// Where the backup records and file indexes in `.cache` are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CacheBackend {
+    /// backupEvents.json, fileHashes.json and scanIndex.json (default)
+    #[default]
+    Json,
+    /// A single cache.sqlite3 database; existing JSON files are imported once
+    Sqlite,
+}
+
+/// 备份记录、去重索引和变化检测索引的存储，使其余代码不依赖具体的后端
+///
+/// 所有方法失败时返回 [`BackupError::CacheError`]，其中的路径为出错的文件。
+pub trait CacheStore {
+    /// 存储的位置（文件路径），用于输出信息
+    fn location(&self) -> &Path;
+    /// 读取所有备份记录，按写入顺序排列
+    fn read_records(&self) -> Result<Vec<CacheRecord>, BackupError>;
+    /// 用给定的记录替换所有备份记录
+    fn write_records(&self, records: &[CacheRecord]) -> Result<(), BackupError>;
+    /// 读取去重索引（`--dedup`）
+    fn read_file_hashes(&self) -> Result<FileHashIndex, BackupError>;
+    /// 用给定的索引替换去重索引
+    fn write_file_hashes(&self, index: &FileHashIndex) -> Result<(), BackupError>;
+    /// 读取变化检测索引（`--detect-changes`）
+    fn read_scan_index(&self) -> Result<ScanIndex, BackupError>;
+    /// 用给定的索引替换变化检测索引
+    fn write_scan_index(&self, index: &ScanIndex) -> Result<(), BackupError>;
+    /// 读取所有校验记录（`verify --record`），按写入顺序排列
+    fn read_verifications(&self) -> Result<Vec<VerificationRecord>, BackupError>;
+    /// 追加一条校验记录
+    fn append_verification(&self, record: &VerificationRecord) -> Result<(), BackupError>;
+}
+
+/// 保存在 `.cache` 目录中各个 JSON 文件里的缓存（`--cache-backend json`）
+pub struct JsonStore {
+    /// 只读打开：损坏的缓存文件不会被改名
+    read_only: bool,
+    cache_file: PathBuf,
+    file_hashes_file: PathBuf,
+    scan_index_file: PathBuf,
+    verifications_file: PathBuf,
+}
+
+impl JsonStore {
+    pub fn new(cache_folder: &Path, read_only: bool) -> Self {
+        JsonStore {
+            read_only,
+            cache_file: cache_folder.join(CACHE_FILE_NAME),
+            file_hashes_file: cache_folder.join(FILE_HASHES_FILE_NAME),
+            scan_index_file: cache_folder.join(SCAN_INDEX_FILE_NAME),
+            verifications_file: cache_folder.join(VERIFICATIONS_FILE_NAME),
+        }
+    }
+}
+
+impl CacheStore for JsonStore {
+    fn location(&self) -> &Path {
+        &self.cache_file
+    }
+
+    fn read_records(&self) -> Result<Vec<CacheRecord>, BackupError> {
+        read_cache_records(&self.cache_file, self.read_only)
+    }
+
+    fn write_records(&self, records: &[CacheRecord]) -> Result<(), BackupError> {
+        write_cache_records(&self.cache_file, records)
+    }
+
+    fn read_file_hashes(&self) -> Result<FileHashIndex, BackupError> {
+        read_file_hashes(&self.file_hashes_file)
+    }
+
+    fn write_file_hashes(&self, index: &FileHashIndex) -> Result<(), BackupError> {
+        write_file_hashes(&self.file_hashes_file, index)
+    }
+
+    fn read_scan_index(&self) -> Result<ScanIndex, BackupError> {
+        read_scan_index(&self.scan_index_file)
+    }
+
+    fn write_scan_index(&self, index: &ScanIndex) -> Result<(), BackupError> {
+        write_scan_index(&self.scan_index_file, index)
+    }
+
+    fn read_verifications(&self) -> Result<Vec<VerificationRecord>, BackupError> {
+        read_verification_records(&self.verifications_file)
+    }
+
+    fn append_verification(&self, record: &VerificationRecord) -> Result<(), BackupError> {
+        let mut records = self.read_verifications()?;
+        records.push(record.clone());
+        write_json_atomically(&self.verifications_file, &records)
+            .map_err(cache_error(&self.verifications_file))
+    }
+}
+
+/// 打开 `.cache` 目录中指定后端的缓存存储
+///
+/// # Arguments
+/// * `backend` - `--cache-backend`
+/// * `cache_folder` - `.cache` 目录
+/// * `read_only` - 不创建或修改任何文件（`--dry-run`、`status`）
+///
+/// 数据库无法打开或版本过新时返回 [`BackupError::CacheError`]
+pub fn open_store(
+    backend: CacheBackend,
+    cache_folder: &Path,
+    read_only: bool,
+) -> Result<Box<dyn CacheStore>, BackupError> {
+    Ok(match backend {
+        CacheBackend::Json => Box::new(JsonStore::new(cache_folder, read_only)),
+        CacheBackend::Sqlite => Box::new(SqliteStore::open(cache_folder, read_only)?),
+    })
+}
+
+/// `.cache` 目录中防止多个实例同时运行的锁文件
+pub const LOCK_FILE_NAME: &str = "lock";
+
+/// 本次运行持有的独占锁，释放（drop）时解锁
+///
+/// 锁由操作系统绑定在打开的文件上，进程崩溃或被结束时会自动释放，
+/// 因此残留的锁文件不会导致之后的运行永远无法开始。
+#[derive(Debug)]
+pub struct RunLock {
+    file: fs::File,
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let _ = fs2::FileExt::unlock(&self.file);
+    }
+}
+
+/// 获取 `.cache/lock` 的独占锁，并在其中写入本进程的 PID 和开始时间
+///
+/// # Arguments
+/// * `cache_folder` - `.cache` 目录的路径
+/// * `wait` - 锁被占用时最多等待的时间，为零时立即返回；超出时钟能表示的范围时一直等待
+///
+/// # Returns
+/// 等待超时后返回 [`BackupError::AlreadyRunning`]，其中的路径为 `cache_folder`，
+/// 底层 `WouldBlock` 错误的消息中包含持有锁的进程的 PID 和开始时间（如果可以读取）；
+/// 无法打开或锁定锁文件时返回 [`BackupError::CacheError`]。
+pub fn acquire_run_lock(
+    cache_folder: &Path,
+    wait: std::time::Duration,
+) -> Result<RunLock, BackupError> {
+    use fs2::FileExt;
+    use std::io::Write;
+
+    let lock_path = cache_folder.join(LOCK_FILE_NAME);
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+        .map_err(cache_error(&lock_path))?;
+    let deadline = std::time::Instant::now().checked_add(wait);
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => break,
+            Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                if deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline) {
+                    // Windows 上被锁定的文件无法读取，此时不显示持有者
+                    let holder = fs::read_to_string(&lock_path)
+                        .ok()
+                        .map(|content| content.trim().to_string())
+                        .filter(|content| !content.is_empty())
+                        .map_or_else(String::new, |content| format!(" ({})", content));
+                    return Err(BackupError::AlreadyRunning {
+                        path: cache_folder.to_path_buf(),
+                        source: io::Error::new(
+                            io::ErrorKind::WouldBlock,
+                            format!("another backup is running{}", holder),
+                        ),
+                    });
+                }
+                std::thread::sleep(std::time::Duration::from_millis(200));
+            }
+            Err(e) => return Err(cache_error(&lock_path)(e)),
+        }
+    }
+
+    // 持有者信息只用于提示，写入失败不影响加锁
+    let _ = file.set_len(0).and_then(|_| {
+        write!(
+            file,
+            "PID {}, started at {}",
+            std::process::id(),
+            Local::now().format("%Y-%m-%d %H:%M:%S")
+        )
+    });
+    Ok(RunLock { file })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试中本次运行的源
+    const SOURCE: &str = "/home/user/WeChat Files";
+
+    #[test]
+    fn file_hashes_round_trip_and_default_to_empty() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let index_path = dir.join("fileHashes.json");
+        assert!(read_file_hashes(&index_path).unwrap().is_empty());
+
+        let mut index = FileHashIndex::new();
+        index.insert(
+            "Msg/Multi/msg0.db".to_string(),
+            FileHashRecord {
+                size: 42,
+                modified: Some(Utc.with_ymd_and_hms(2025, 7, 1, 8, 0, 0).unwrap()),
+                sha256: "ab".repeat(32),
+            },
+        );
+        write_file_hashes(&index_path, &index).unwrap();
+        assert_eq!(read_file_hashes(&index_path).unwrap(), index);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 辅助函数：两个后端共用的测试，写入、读回并覆盖所有类型的数据
+    fn check_store(store: &dyn CacheStore) {
+        assert!(store.read_records().unwrap().is_empty());
+        assert!(store.read_file_hashes().unwrap().is_empty());
+        assert!(store.read_scan_index().unwrap().is_empty());
+
+        let at = |day: u32| Utc.with_ymd_and_hms(2025, 7, day, 8, 0, 0).unwrap();
+        let legacy: CacheRecord = serde_json::from_str(
+            r#"{ "StartTime": "2025-07-01T08:00:00Z", "EndTime": "2025-07-01T09:00:00Z", "BackupInfo": "legacy" }"#,
+        )
+        .unwrap();
+        let full = CacheRecord {
+            start_time: at(2),
+            end_time: at(3),
+            backup_info: "Backup for 2025-07".to_string(),
+            cutoff_time: Some(at(2)),
+            archives: vec![ArchiveRecord {
+                month: "2025-07".to_string(),
+                group: Some("wxid_a".to_string()),
+                kind: ArchiveKind::Full,
+                file_name: "full.zip".to_string(),
+            }],
+            accounts: vec!["wxid_a".to_string()],
+            source_paths: vec!["C:\\WeChat Files".to_string()],
+            archive_files: vec!["full.zip".to_string()],
+            archives_deleted_time: Some(at(4)),
+            months: vec![MonthResult {
+                month: "2025-07".to_string(),
+                cutoff: at(2),
+                archive_files: vec!["full.zip".to_string()],
+                status: MonthStatus::Succeeded,
+            }],
+            status: Some(RunStatus::Partial),
+            files_archived: Some(3),
+            bytes_archived: Some(u32::MAX as u64 * 4),
+            tool_version: Some("0.1.0".to_string()),
+            month_boundary_tz: None,
+            source_id: Some(SOURCE.to_string()),
+            skipped_files: vec![SkippedFileRecord {
+                month: "2025-07".to_string(),
+                path: "Msg/Multi/MSG0.db".to_string(),
+                reason: "The process cannot access the file".to_string(),
+            }],
+            // 更新的版本写入的字段
+            extra: serde_json::json!({ "FutureField": { "Nested": [1, 2] } })
+                .as_object()
+                .unwrap()
+                .clone(),
+        };
+        let records = vec![full.clone(), legacy.clone()];
+        store.write_records(&records).unwrap();
+        assert_eq!(store.read_records().unwrap(), records);
+        // 写入替换所有记录，并保持顺序
+        store.write_records(std::slice::from_ref(&legacy)).unwrap();
+        assert_eq!(store.read_records().unwrap(), vec![legacy]);
+
+        let mut hashes = FileHashIndex::new();
+        for (path, modified) in [("Msg/a.db", Some(at(1))), ("Msg/b.db", None)] {
+            hashes.insert(
+                path.to_string(),
+                FileHashRecord {
+                    size: 42,
+                    modified,
+                    sha256: "ab".repeat(32),
+                },
+            );
+        }
+        store.write_file_hashes(&hashes).unwrap();
+        assert_eq!(store.read_file_hashes().unwrap(), hashes);
+        hashes.remove("Msg/a.db");
+        store.write_file_hashes(&hashes).unwrap();
+        assert_eq!(store.read_file_hashes().unwrap(), hashes);
+
+        let mut scan_index = ScanIndex::new();
+        for (path, hash) in [("Msg/a.db", Some("00ff".to_string())), ("Msg/b.db", None)] {
+            scan_index.insert(
+                path.to_string(),
+                ScanIndexRecord {
+                    size: 7,
+                    modified: at(1),
+                    hash,
+                },
+            );
+        }
+        store.write_scan_index(&scan_index).unwrap();
+        assert_eq!(store.read_scan_index().unwrap(), scan_index);
+    }
+
+    #[test]
+    fn json_store_round_trips_all_data() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = open_store(CacheBackend::Json, &dir, false).unwrap();
+        check_store(store.as_ref());
+        assert_eq!(store.location(), dir.join(CACHE_FILE_NAME));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sqlite_store_round_trips_all_data() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = open_store(CacheBackend::Sqlite, &dir, false).unwrap();
+        check_store(store.as_ref());
+        assert_eq!(store.location(), dir.join(sqlite::DATABASE_FILE_NAME));
+        // 重新打开后数据仍在
+        drop(store);
+        let store = open_store(CacheBackend::Sqlite, &dir, true).unwrap();
+        assert_eq!(store.read_records().unwrap().len(), 1);
+        assert_eq!(store.read_scan_index().unwrap().len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sqlite_store_imports_json_cache_once() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let json = JsonStore::new(&dir, false);
+        let record: CacheRecord = serde_json::from_str(
+            r#"{ "StartTime": "2025-07-01T08:00:00Z", "EndTime": "2025-07-01T09:00:00Z", "BackupInfo": "from json" }"#,
+        )
+        .unwrap();
+        json.write_records(std::slice::from_ref(&record)).unwrap();
+        let mut hashes = FileHashIndex::new();
+        hashes.insert(
+            "Msg/a.db".to_string(),
+            FileHashRecord {
+                size: 1,
+                modified: None,
+                sha256: "cd".repeat(32),
+            },
+        );
+        json.write_file_hashes(&hashes).unwrap();
+
+        // 只读打开不创建数据库，但同样能看到 JSON 中的记录
+        let store = SqliteStore::open(&dir, true).unwrap();
+        assert_eq!(store.read_records().unwrap(), vec![record.clone()]);
+        assert!(!dir.join(sqlite::DATABASE_FILE_NAME).exists());
+
+        let store = SqliteStore::open(&dir, false).unwrap();
+        assert_eq!(store.read_records().unwrap(), vec![record.clone()]);
+        assert_eq!(store.read_file_hashes().unwrap(), hashes);
+        store.write_records(&[]).unwrap();
+        drop(store);
+
+        // 已导入的数据库不会再次导入 JSON 文件，JSON 文件保持不变
+        let store = SqliteStore::open(&dir, false).unwrap();
+        assert!(store.read_records().unwrap().is_empty());
+        assert_eq!(json.read_records().unwrap(), vec![record]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_scan_index_is_treated_as_empty() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let index_path = dir.join("scanIndex.json");
+        assert!(read_scan_index(&index_path).unwrap().is_empty());
+
+        let mut index = ScanIndex::new();
+        index.insert(
+            "Msg/Multi/msg0.db".to_string(),
+            ScanIndexRecord {
+                size: 42,
+                modified: Utc.with_ymd_and_hms(2025, 7, 1, 8, 0, 0).unwrap(),
+                hash: Some("0123456789abcdef".to_string()),
+            },
+        );
+        write_scan_index(&index_path, &index).unwrap();
+        assert_eq!(read_scan_index(&index_path).unwrap(), index);
+
+        // 写到一半被中断的索引
+        let content = fs::read_to_string(&index_path).unwrap();
+        fs::write(&index_path, &content[..content.len() / 2]).unwrap();
+        assert!(read_scan_index(&index_path).unwrap().is_empty());
+        fs::write(&index_path, "").unwrap();
+        assert!(read_scan_index(&index_path).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_modified_during_previous_run_is_picked_up() {
+        use crate::backup_logic::{BackupMonth, BackupPeriod};
+        use chrono::{Datelike, Local};
+        use std::time::{Duration, SystemTime};
+
+        let source = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&source).unwrap();
+        let touched = source.join("touched.dat");
+        fs::write(&touched, "modified mid-run").unwrap();
+
+        // 上次运行：开始 -> 扫描开始 -> 文件被修改 -> 结束
+        let now = SystemTime::now();
+        let minutes_ago = |m: u64| DateTime::<Utc>::from(now - Duration::from_secs(m * 60));
+        let modified = now - Duration::from_secs(2 * 60);
+        filetime::set_file_mtime(&touched, filetime::FileTime::from_system_time(modified)).unwrap();
+        let record = CacheRecord {
+            start_time: minutes_ago(4),
+            end_time: minutes_ago(1),
+            backup_info: String::new(),
+            cutoff_time: Some(minutes_ago(3)),
+            archives: Vec::new(),
+            accounts: Vec::new(),
+            source_paths: Vec::new(),
+            archive_files: Vec::new(),
+            archives_deleted_time: None,
+            status: None,
+            files_archived: None,
+            bytes_archived: None,
+            tool_version: None,
+            month_boundary_tz: None,
+            source_id: None,
+            skipped_files: Vec::new(),
+            extra: serde_json::Map::new(),
+            months: Vec::new(),
+        };
+
+        let today = Local::now();
+        let month = BackupPeriod::Month(BackupMonth {
+            year: today.year(),
+            month: today.month(),
+        });
+        let label = month.label();
+        let cutoff = get_last_backup_time_for_month(
+            std::slice::from_ref(&record),
+            &label,
+            SOURCE,
+            Utc::now(),
+        );
+        assert_eq!(cutoff, minutes_ago(3));
+        let found = crate::file_scanner::find_files_to_backup(
+            &source,
+            &cutoff,
+            &month,
+            &[],
+            &crate::file_scanner::ScanFilters::default(),
+            &|_| {},
+        )
+        .unwrap()
+        .files;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, touched);
+
+        // 旧版本的记录没有 CutoffTime，退回使用开始时间
+        let old: CacheRecord = serde_json::from_str(
+            r#"{ "StartTime": "2025-07-01T08:00:00Z", "EndTime": "2025-07-01T09:00:00Z", "BackupInfo": "" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            get_last_backup_time_for_month(&[old], "2025-07", SOURCE, Utc::now()),
+            Utc.with_ymd_and_hms(2025, 7, 1, 8, 0, 0).unwrap()
+        );
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn records_round_trip_in_old_and_new_shapes() {
+        // 旧版本只写入这三个字段，重新写入时保持不变
+        let old_json = r#"{"StartTime":"2025-07-01T08:00:00Z","EndTime":"2025-07-01T09:00:00Z","BackupInfo":"Backup for 2025-07"}"#;
+        let old: CacheRecord = serde_json::from_str(old_json).unwrap();
+        assert_eq!(old.status, None);
+        assert_eq!(old.files_archived, None);
+        assert!(old.tool_version.is_none() && old.months.is_empty());
+        assert_eq!(serde_json::to_string(&old).unwrap(), old_json);
+
+        let new = CacheRecord {
+            status: Some(RunStatus::Partial),
+            files_archived: Some(12),
+            bytes_archived: Some(3456),
+            tool_version: Some("1.2.3".to_string()),
+            month_boundary_tz: None,
+            archive_files: vec!["2025-07_backup_20250701090000.zip".to_string()],
+            months: vec![MonthResult {
+                month: "2025-07".to_string(),
+                cutoff: Utc.with_ymd_and_hms(2025, 7, 1, 8, 0, 0).unwrap(),
+                archive_files: vec!["2025-07_backup_20250701090000.zip".to_string()],
+                status: MonthStatus::Succeeded,
+            }],
+            ..old.clone()
+        };
+        let value = serde_json::to_value(&new).unwrap();
+        assert_eq!(value["Status"], "Partial");
+        assert_eq!(value["FilesArchived"], 12);
+        assert_eq!(value["BytesArchived"], 3456);
+        assert_eq!(value["ToolVersion"], "1.2.3");
+        assert_eq!(value["Months"][0]["Status"], "Succeeded");
+        assert_eq!(serde_json::from_value::<CacheRecord>(value).unwrap(), new);
+
+        // 失败的运行不提供截止时间
+        let failed = CacheRecord {
+            status: Some(RunStatus::Failed),
+            start_time: Utc.with_ymd_and_hms(2025, 7, 2, 8, 0, 0).unwrap(),
+            end_time: Utc.with_ymd_and_hms(2025, 7, 2, 9, 0, 0).unwrap(),
+            months: Vec::new(),
+            ..new
+        };
+        let records = [old, failed];
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-07", SOURCE, Utc::now()),
+            Utc.with_ymd_and_hms(2025, 7, 1, 8, 0, 0).unwrap()
+        );
+        // 没有 Status 的旧记录视为成功
+        assert_eq!(
+            last_successful_backup(&records, None, Utc::now()),
+            Some(&records[0])
+        );
+        assert_eq!(
+            last_successful_backup(&records[1..], None, Utc::now()),
+            None
+        );
+    }
+
+    #[test]
+    fn failed_month_keeps_its_previous_cutoff() {
+        let at = |day: u32| Utc.with_ymd_and_hms(2025, 7, day, 8, 0, 0).unwrap();
+        let record = |day: u32, months: Vec<(&str, MonthStatus)>| CacheRecord {
+            start_time: at(day),
+            end_time: at(day) + chrono::Duration::hours(1),
+            backup_info: String::new(),
+            cutoff_time: Some(at(day)),
+            archives: Vec::new(),
+            accounts: Vec::new(),
+            source_paths: Vec::new(),
+            archive_files: Vec::new(),
+            archives_deleted_time: None,
+            status: None,
+            files_archived: None,
+            bytes_archived: None,
+            tool_version: None,
+            month_boundary_tz: None,
+            source_id: None,
+            skipped_files: Vec::new(),
+            extra: serde_json::Map::new(),
+            months: months
+                .into_iter()
+                .map(|(month, status)| MonthResult {
+                    month: month.to_string(),
+                    cutoff: at(day),
+                    archive_files: Vec::new(),
+                    status,
+                })
+                .collect(),
+        };
+        // 第 2 天的运行中六月成功、七月失败
+        let partial = record(
+            2,
+            vec![
+                ("2025-06", MonthStatus::Succeeded),
+                ("2025-07", MonthStatus::Failed),
+            ],
+        );
+        let epoch = Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap();
+        let records = vec![partial.clone()];
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-06", SOURCE, Utc::now()),
+            at(2)
+        );
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-07", SOURCE, Utc::now()),
+            epoch
+        );
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-05", SOURCE, Utc::now()),
+            epoch
+        );
+
+        // 旧版本的记录适用于所有月份，失败的月份退回到它的截止时间
+        let records = vec![record(1, Vec::new()), partial.clone()];
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-06", SOURCE, Utc::now()),
+            at(2)
+        );
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-07", SOURCE, Utc::now()),
+            at(1)
+        );
+
+        // 之后的运行中七月成功
+        let records = vec![
+            partial,
+            record(3, vec![("2025-07", MonthStatus::Succeeded)]),
+        ];
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-06", SOURCE, Utc::now()),
+            at(2)
+        );
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-07", SOURCE, Utc::now()),
+            at(3)
+        );
+    }
+
+    #[test]
+    fn records_of_other_sources_are_ignored() {
+        let at = |day: u32| Utc.with_ymd_and_hms(2025, 7, day, 8, 0, 0).unwrap();
+        let record = |day: u32, source_id: Option<&str>| CacheRecord {
+            start_time: at(day),
+            end_time: at(day),
+            backup_info: String::new(),
+            cutoff_time: Some(at(day)),
+            archives: vec![ArchiveRecord {
+                month: "2025-07".to_string(),
+                group: None,
+                kind: ArchiveKind::Full,
+                file_name: format!("full-{}.zip", day),
+            }],
+            accounts: Vec::new(),
+            source_paths: Vec::new(),
+            archive_files: Vec::new(),
+            archives_deleted_time: None,
+            status: None,
+            files_archived: None,
+            bytes_archived: None,
+            tool_version: None,
+            month_boundary_tz: None,
+            source_id: source_id.map(str::to_string),
+            skipped_files: Vec::new(),
+            extra: serde_json::Map::new(),
+            months: Vec::new(),
+        };
+        let now = at(10);
+        let records = vec![
+            record(1, None),
+            record(2, Some(SOURCE)),
+            record(3, Some("/mnt/other")),
+        ];
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-07", SOURCE, now),
+            at(2)
+        );
+        assert_eq!(
+            latest_full_archive(&records, "2025-07", None, SOURCE),
+            Some("full-2.zip")
+        );
+        // 旧版本的记录适用于所有源
+        assert_eq!(
+            get_last_backup_time_for_month(&records[..1], "2025-07", "/mnt/new", now),
+            at(1)
+        );
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-07", "/mnt/other", now),
+            at(3)
+        );
+        assert_eq!(
+            last_successful_backup(&records, Some(SOURCE), now),
+            Some(&records[1])
+        );
+        assert_eq!(
+            last_successful_backup(&records, None, now),
+            Some(&records[2])
+        );
+        // 只有最早备份到该目录的源的归档不带源标记
+        assert_eq!(archive_source_tag(&records, SOURCE), None);
+        assert_eq!(
+            archive_source_tag(&records, "/mnt/other"),
+            Some(crate::archiver::source_tag("/mnt/other"))
+        );
+        assert_eq!(archive_source_tag(&records[..1], "/mnt/other"), None);
+    }
+
+    #[test]
+    fn records_with_bogus_timestamps_are_ignored() {
+        let now = Utc.with_ymd_and_hms(2025, 7, 10, 12, 0, 0).unwrap();
+        let record =
+            |start: DateTime<Utc>, end: DateTime<Utc>, cutoff: DateTime<Utc>| CacheRecord {
+                start_time: start,
+                end_time: end,
+                backup_info: String::new(),
+                cutoff_time: Some(cutoff),
+                archives: Vec::new(),
+                accounts: Vec::new(),
+                source_paths: Vec::new(),
+                archive_files: Vec::new(),
+                archives_deleted_time: None,
+                status: None,
+                files_archived: None,
+                bytes_archived: None,
+                tool_version: None,
+                month_boundary_tz: None,
+                source_id: None,
+                skipped_files: Vec::new(),
+                extra: serde_json::Map::new(),
+                months: Vec::new(),
+            };
+        let hours = chrono::Duration::hours;
+        let sane = record(now - hours(48), now - hours(47), now - hours(48));
+        let newer_sane = record(now - hours(2), now - hours(1), now - hours(2));
+        let future = record(
+            now + hours(24 * 365),
+            now + hours(24 * 365),
+            now + hours(24 * 365),
+        );
+        let reversed = record(now - hours(3), now - hours(30), now - hours(3));
+        let future_cutoff = record(now - hours(1), now, now + hours(5));
+        let slight_skew = record(now, now + chrono::Duration::minutes(30), now);
+
+        assert_eq!(bogus_timestamp_reason(&sane, now), None);
+        assert_eq!(bogus_timestamp_reason(&slight_skew, now), None);
+        assert!(bogus_timestamp_reason(&future, now).is_some());
+        assert!(bogus_timestamp_reason(&reversed, now).is_some());
+        assert!(bogus_timestamp_reason(&future_cutoff, now).is_some());
+
+        // 错误的记录无论位置都被跳过，使用最近一条正常的记录
+        let records = vec![
+            sane.clone(),
+            future.clone(),
+            newer_sane.clone(),
+            reversed.clone(),
+            future_cutoff.clone(),
+        ];
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-07", SOURCE, now),
+            now - hours(2)
+        );
+        assert_eq!(
+            last_successful_backup(&records, None, now),
+            Some(&newer_sane)
+        );
+
+        // 只剩下错误的记录时退回到纪元时间，所有文件都会被备份
+        let records = vec![future, reversed, future_cutoff];
+        let epoch = Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2025-07", SOURCE, now),
+            epoch
+        );
+        assert_eq!(last_successful_backup(&records, None, now), None);
+    }
+
+    #[test]
+    fn latest_full_archive_is_found_per_month() {
+        let record =
+            |day: u32, archives: Vec<(&str, Option<&str>, ArchiveKind, &str)>| CacheRecord {
+                start_time: Utc.with_ymd_and_hms(2025, 7, day, 8, 0, 0).unwrap(),
+                end_time: Utc.with_ymd_and_hms(2025, 7, day, 9, 0, 0).unwrap(),
+                backup_info: String::new(),
+                cutoff_time: None,
+                archives: archives
+                    .into_iter()
+                    .map(|(month, group, kind, file_name)| ArchiveRecord {
+                        month: month.to_string(),
+                        group: group.map(str::to_string),
+                        kind,
+                        file_name: file_name.to_string(),
+                    })
+                    .collect(),
+                accounts: Vec::new(),
+                source_paths: Vec::new(),
+                archive_files: Vec::new(),
+                archives_deleted_time: None,
+                status: None,
+                files_archived: None,
+                bytes_archived: None,
+                tool_version: None,
+                month_boundary_tz: None,
+                source_id: None,
+                skipped_files: Vec::new(),
+                extra: serde_json::Map::new(),
+                months: Vec::new(),
+            };
+        let records = vec![
+            record(1, vec![("2025-07", None, ArchiveKind::Full, "full-1.zip")]),
+            record(
+                10,
+                vec![("2025-07", None, ArchiveKind::Full, "full-10.zip")],
+            ),
+            record(
+                20,
+                vec![
+                    ("2025-07", None, ArchiveKind::Incremental, "inc-20.zip"),
+                    ("2025-06", None, ArchiveKind::Full, "june.zip"),
+                    ("2025-07", Some("wxid_a"), ArchiveKind::Full, "wxid_a.zip"),
+                ],
+            ),
+            record(25, Vec::new()),
+        ];
+
+        assert_eq!(
+            latest_full_archive(&records, "2025-07", None, SOURCE),
+            Some("full-10.zip")
+        );
+        assert_eq!(
+            latest_full_archive(&records, "2025-07", Some("wxid_a"), SOURCE),
+            Some("wxid_a.zip")
+        );
+        assert_eq!(
+            latest_full_archive(&records, "2025-06", None, SOURCE),
+            Some("june.zip")
+        );
+        assert_eq!(latest_full_archive(&records, "2025-05", None, SOURCE), None);
+        assert_eq!(
+            latest_full_archive(&records, "2025-07", Some("wxid_b"), SOURCE),
+            None
+        );
+    }
+
+    #[test]
+    fn records_whose_archives_are_gone_are_marked() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("kept.part2.zip"), "").unwrap();
+        let record = |day: u32, archive_files: &[&str]| CacheRecord {
+            start_time: Utc.with_ymd_and_hms(2025, 7, day, 8, 0, 0).unwrap(),
+            end_time: Utc.with_ymd_and_hms(2025, 7, day, 9, 0, 0).unwrap(),
+            backup_info: String::new(),
+            cutoff_time: None,
+            archives: Vec::new(),
+            accounts: Vec::new(),
+            source_paths: Vec::new(),
+            archive_files: archive_files.iter().map(|s| s.to_string()).collect(),
+            archives_deleted_time: None,
+            status: None,
+            files_archived: None,
+            bytes_archived: None,
+            tool_version: None,
+            month_boundary_tz: None,
+            source_id: None,
+            skipped_files: Vec::new(),
+            extra: serde_json::Map::new(),
+            months: Vec::new(),
+        };
+        let mut records = vec![
+            // 旧版本的记录
+            record(1, &[]),
+            record(2, &["deleted.zip"]),
+            record(3, &["kept.part1.zip", "kept.part2.zip"]),
+        ];
+        let now = Utc.with_ymd_and_hms(2025, 8, 1, 0, 0, 0).unwrap();
+
+        assert_eq!(prune_records_for_deleted(&mut records, &dir, now), 1);
+        let deleted: Vec<_> = records.iter().map(|r| r.archives_deleted_time).collect();
+        assert_eq!(deleted, vec![None, Some(now), None]);
+        // 已标记的记录不会被重复标记
+        assert_eq!(prune_records_for_deleted(&mut records, &dir, now), 0);
+
+        let json = serde_json::to_value(&records).unwrap();
+        assert_eq!(
+            json[0].as_object().unwrap().keys().collect::<Vec<_>>(),
+            vec!["BackupInfo", "EndTime", "StartTime"]
+        );
+        assert_eq!(json[1]["ArchivesDeletedTime"], "2025-08-01T00:00:00Z");
+
+        assert!(limit_history(&mut records, 5, now).is_empty());
+        assert_eq!(limit_history(&mut records, 2, now).len(), 1);
+        assert_eq!(records[0].archive_files, vec!["deleted.zip"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn history_limit_keeps_records_in_use_and_archives_the_rest() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let at = |year: i32, day: u32| Utc.with_ymd_and_hms(year, 7, day, 8, 0, 0).unwrap();
+        let record = |time: DateTime<Utc>, months: &[&str], full: Option<&str>| CacheRecord {
+            start_time: time,
+            end_time: time,
+            backup_info: String::new(),
+            cutoff_time: Some(time),
+            archives: full
+                .into_iter()
+                .map(|file_name| ArchiveRecord {
+                    month: months[0].to_string(),
+                    group: None,
+                    kind: ArchiveKind::Full,
+                    file_name: file_name.to_string(),
+                })
+                .collect(),
+            accounts: Vec::new(),
+            source_paths: Vec::new(),
+            archive_files: Vec::new(),
+            archives_deleted_time: None,
+            status: None,
+            files_archived: None,
+            bytes_archived: None,
+            tool_version: None,
+            month_boundary_tz: None,
+            source_id: Some(SOURCE.to_string()),
+            skipped_files: Vec::new(),
+            extra: serde_json::Map::new(),
+            months: months
+                .iter()
+                .map(|month| MonthResult {
+                    month: month.to_string(),
+                    cutoff: time,
+                    archive_files: Vec::new(),
+                    status: MonthStatus::Succeeded,
+                })
+                .collect(),
+        };
+        let mut records = vec![
+            record(at(2024, 1), &["2024-06"], None),
+            // 六月最后一次备份，之后只备份了七月
+            record(at(2024, 2), &["2024-06"], None),
+            record(at(2025, 1), &["2025-07"], Some("full.zip")),
+            record(at(2025, 2), &["2025-07"], None),
+            record(at(2025, 3), &["2025-07"], None),
+        ];
+        let now = at(2025, 10);
+
+        // 超出 3 条，但提供六月截止时间的记录和全量归档的记录被保留，保留的记录多于限制
+        let overflow = limit_history(&mut records, 2, now);
+        let kept: Vec<_> = records.iter().map(|r| r.end_time).collect();
+        assert_eq!(kept, vec![at(2024, 2), at(2025, 1), at(2025, 3)]);
+        let moved: Vec<_> = overflow.iter().map(|r| r.end_time).collect();
+        assert_eq!(moved, vec![at(2024, 1), at(2025, 2)]);
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2024-06", SOURCE, now),
+            at(2024, 2)
+        );
+        assert_eq!(
+            latest_full_archive(&records, "2025-07", None, SOURCE),
+            Some("full.zip")
+        );
+
+        // 移出的记录按年份追加到归档文件
+        archive_history(&dir, &overflow).unwrap();
+        archive_history(&dir, &overflow[..1]).unwrap();
+        let archived =
+            |year: i32| read_cache_records(&dir.join(history_archive_file_name(year)), false);
+        assert_eq!(
+            archived(2024).unwrap(),
+            vec![overflow[0].clone(), overflow[0].clone()]
+        );
+        assert_eq!(archived(2025).unwrap(), vec![overflow[1].clone()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn second_run_lock_is_refused_until_released() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let lock = acquire_run_lock(&dir, std::time::Duration::ZERO).unwrap();
+        let err = acquire_run_lock(&dir, std::time::Duration::from_millis(300)).unwrap_err();
+        assert!(
+            matches!(err, BackupError::AlreadyRunning { .. }),
+            "{:?}",
+            err
+        );
+        assert_eq!(err.kind(), Some(io::ErrorKind::WouldBlock));
+        let message = err.to_string();
+        assert!(message.contains("another backup is running"), "{}", message);
+        assert!(
+            message.contains(&format!("PID {}", std::process::id())),
+            "{}",
+            message
+        );
+
+        drop(lock);
+        // 锁文件仍然存在，但已不再被锁定
+        assert!(dir.join(LOCK_FILE_NAME).exists());
+        acquire_run_lock(&dir, std::time::Duration::ZERO).unwrap();
+        // 等待时间过长时不会溢出
+        acquire_run_lock(&dir, std::time::Duration::from_secs(u64::MAX)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cache_format_versions_are_handled() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let cache_path = dir.join("backupEvents.json");
+
+        // 格式版本 1：记录数组，其中有本版本不认识的字段
+        fs::write(
+            &cache_path,
+            r#"[{ "StartTime": "2025-07-01T08:00:00Z", "EndTime": "2025-07-01T09:00:00Z", "BackupInfo": "v1", "FutureField": { "A": [1, 2] } }]"#,
+        )
+        .unwrap();
+        let records = read_cache_records(&cache_path, false).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].extra["FutureField"],
+            serde_json::json!({ "A": [1, 2] })
+        );
+
+        // 写入时升级为版本 2，未知字段原样保留
+        write_cache_records(&cache_path, &records).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&cache_path).unwrap()).unwrap();
+        assert_eq!(written["Version"], CACHE_FORMAT_VERSION);
+        assert_eq!(written["Records"][0]["BackupInfo"], "v1");
+        assert_eq!(written["Records"][0]["FutureField"]["A"][1], 2);
+        assert_eq!(read_cache_records(&cache_path, false).unwrap(), records);
+
+        // 更新的格式版本被拒绝，文件保持不变
+        let future = r#"{ "Version": 99, "Records": [] }"#;
+        fs::write(&cache_path, future).unwrap();
+        let error = read_cache_records(&cache_path, false).unwrap_err();
+        assert!(
+            matches!(error, BackupError::CacheError { .. }),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.kind(), Some(io::ErrorKind::InvalidData));
+        assert!(error.to_string().contains("version 99"), "{}", error);
+        assert!(error.to_string().contains("upgrade"), "{}", error);
+        assert_eq!(fs::read_to_string(&cache_path).unwrap(), future);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn powershell_cache_files_are_read() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/powershell");
+        let read_fixture = |name: &str| {
+            let cache_path = dir.join(name);
+            fs::copy(fixtures.join(name), &cache_path).unwrap();
+            let records = read_cache_records(&cache_path, false).unwrap();
+            // 没有被当作损坏的文件改名
+            assert!(cache_path.exists());
+            records
+        };
+        let utc = |text: &str| text.parse::<DateTime<Utc>>().unwrap();
+
+        let records = read_fixture("backupEvents-ps51.json");
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].start_time, utc("2024-05-01T00:13:04Z"));
+        assert_eq!(records[0].end_time, utc("2024-05-01T00:13:11.517Z"));
+        assert_eq!(records[1].backup_info, "Backup for 2024-05 2024-06");
+        assert_eq!(
+            get_last_backup_time_for_month(&records, "2024-06", SOURCE, Utc::now()),
+            utc("2024-06-01T00:12:00Z")
+        );
+
+        let records = read_fixture("backupEvents-ps51-single.json");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].start_time, utc("2024-05-01T00:13:04Z"));
+
+        let records = read_fixture("backupEvents-ps7.json");
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].start_time, utc("2024-05-01T00:13:04.1234567Z"));
+        assert_eq!(records[0].backup_info, "Backup for 2024-05");
+        // 不带时区的时间按本地时间解释
+        let local = Local.with_ymd_and_hms(2024, 6, 1, 8, 12, 0).unwrap();
+        assert_eq!(records[1].start_time, local.to_utc());
+
+        // DataContractJsonSerializer 写入的时区后缀不影响毫秒数
+        let records = legacy::parse_powershell_records(
+            r#"{ "startTime": "\/Date(1714522384000+0800)\/", "EndTime": "\/Date(1714522391517-0500)\/", "info": "x" }"#,
+        )
+        .unwrap();
+        assert_eq!(records[0].start_time, utc("2024-05-01T00:13:04Z"));
+        assert_eq!(records[0].end_time, utc("2024-05-01T00:13:11.517Z"));
+        assert_eq!(records[0].backup_info, "x");
+
+        // 下次写入时改为当前的格式
+        let cache_path = dir.join("backupEvents-ps51.json");
+        let records = read_cache_records(&cache_path, false).unwrap();
+        write_cache_records(&cache_path, &records).unwrap();
+        let rewritten = fs::read_to_string(&cache_path).unwrap();
+        assert!(rewritten.contains("\"Version\": 2"));
+        assert!(rewritten.contains("\"StartTime\": \"2024-05-01T00:13:04Z\""));
+        assert_eq!(read_cache_records(&cache_path, false).unwrap(), records);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_cache_file_is_set_aside() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cache-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let cache_path = dir.join("backupEvents.json");
+        let record = CacheRecord {
+            start_time: Utc.with_ymd_and_hms(2025, 7, 1, 8, 0, 0).unwrap(),
+            end_time: Utc.with_ymd_and_hms(2025, 7, 1, 9, 0, 0).unwrap(),
+            backup_info: "Backup for 2025-07".to_string(),
+            cutoff_time: None,
+            archives: Vec::new(),
+            accounts: Vec::new(),
+            source_paths: Vec::new(),
+            archive_files: Vec::new(),
+            archives_deleted_time: None,
+            status: None,
+            files_archived: None,
+            bytes_archived: None,
+            tool_version: None,
+            month_boundary_tz: None,
+            source_id: None,
+            skipped_files: Vec::new(),
+            extra: serde_json::Map::new(),
+            months: Vec::new(),
+        };
+        write_cache_records(&cache_path, std::slice::from_ref(&record)).unwrap();
+        assert!(!dir.join("backupEvents.json.tmp").exists());
+        assert_eq!(read_cache_records(&cache_path, false).unwrap().len(), 1);
+        let valid = fs::read(&cache_path).unwrap();
+
+        let truncated = valid[..valid.len() / 2].to_vec();
+        let invalid_utf8 = [&valid[..10], &[0xff, 0xfe][..], &valid[10..]].concat();
+        let object = br#"{ "StartTime": "2025-07-01T08:00:00Z" }"#.to_vec();
+        for content in [truncated, invalid_utf8, object] {
+            fs::write(&cache_path, &content).unwrap();
+            // 只读时不改名
+            assert!(read_cache_records(&cache_path, true).unwrap().is_empty());
+            assert_eq!(fs::read(&cache_path).unwrap(), content);
+            assert!(read_cache_records(&cache_path, false).unwrap().is_empty());
+            assert!(!cache_path.exists());
+            // 损坏的文件被保留下来
+            let corrupt: Vec<std::path::PathBuf> = fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| {
+                    path.to_string_lossy()
+                        .contains("backupEvents.json.corrupt-")
+                })
+                .collect();
+            assert_eq!(corrupt.len(), 1);
+            assert_eq!(fs::read(&corrupt[0]).unwrap(), content);
+            fs::remove_file(&corrupt[0]).unwrap();
+        }
+
+        // 连续损坏的文件各自保留，不互相覆盖
+        for _ in 0..2 {
+            fs::write(&cache_path, b"[").unwrap();
+            assert!(read_cache_records(&cache_path, false).unwrap().is_empty());
+        }
+        let corrupt: Vec<std::path::PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.to_string_lossy()
+                    .contains("backupEvents.json.corrupt-")
+            })
+            .collect();
+        assert_eq!(corrupt.len(), 2);
+        for path in corrupt {
+            fs::remove_file(path).unwrap();
+        }
+
+        // 改名后可以重新写入
+        write_cache_records(&cache_path, &[record]).unwrap();
+        assert_eq!(read_cache_records(&cache_path, false).unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
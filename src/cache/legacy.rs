@@ -0,0 +1,97 @@
+use super::CacheRecord;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use serde_json::{Map, Value};
+
+/// 原 PowerShell 脚本写入的字段名（不区分大小写）与当前字段名的对应关系
+const FIELD_ALIASES: &[(&str, &[&str])] = &[
+    ("StartTime", &["StartTime", "Start", "BackupStartTime"]),
+    (
+        "EndTime",
+        &["EndTime", "End", "BackupEndTime", "LastBackupTime"],
+    ),
+    ("BackupInfo", &["BackupInfo", "Info", "Description"]),
+];
+
+/// 保存时间的字段，需要从 .NET 的日期格式转换
+const TIME_FIELDS: &[&str] = &["StartTime", "EndTime", "CutoffTime", "ArchivesDeletedTime"];
+
+/// 解析原 PowerShell 脚本写入的 `backupEvents.json`
+///
+/// 与当前格式的差异：
+/// - Windows PowerShell 5.1 的 `ConvertTo-Json` 把时间写成 `"\/Date(1714522384000)\/"`，
+///   `Get-Date` 的结果则写成 `{ "value": "\/Date(...)\/", "DisplayHint": 2, "DateTime": "..." }`；
+///   PowerShell 7 写成不带时区的 `2024-05-01T08:13:04.1234567`，按本地时间解释
+/// - 只有一条记录时写成单个对象而不是数组
+/// - 字段名大小写不同或使用旧的名称（见 `FIELD_ALIASES`）
+///
+/// # Returns
+/// 能按旧格式解析时返回记录，否则返回 `None`。
+pub fn parse_powershell_records(content: &str) -> Option<Vec<CacheRecord>> {
+    let value: Value = serde_json::from_str(content).ok()?;
+    let items = match value {
+        Value::Array(items) => items,
+        object @ Value::Object(_) => vec![object],
+        _ => return None,
+    };
+    items
+        .into_iter()
+        .map(|item| match item {
+            Value::Object(fields) => {
+                serde_json::from_value(Value::Object(normalize_fields(fields)?)).ok()
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// 把字段名映射到当前的名称，并把时间转换为 RFC 3339
+fn normalize_fields(fields: Map<String, Value>) -> Option<Map<String, Value>> {
+    let mut normalized = Map::new();
+    for (name, value) in fields {
+        let name = FIELD_ALIASES
+            .iter()
+            .find(|(_, aliases)| {
+                aliases
+                    .iter()
+                    .any(|alias| alias.eq_ignore_ascii_case(&name))
+            })
+            .map_or(name, |(current, _)| current.to_string());
+        let value = if TIME_FIELDS.contains(&name.as_str()) && !value.is_null() {
+            Value::String(parse_time(&value)?.to_rfc3339())
+        } else {
+            value
+        };
+        normalized.insert(name, value);
+    }
+    Some(normalized)
+}
+
+/// 解析 PowerShell 写入的时间值
+fn parse_time(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::Object(fields) => fields
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("value"))
+            .and_then(|(_, value)| parse_time(value)),
+        Value::String(text) => parse_dotnet_date(text)
+            .or_else(|| DateTime::parse_from_rfc3339(text).ok().map(|t| t.to_utc()))
+            .or_else(|| {
+                let naive = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+                Local
+                    .from_local_datetime(&naive)
+                    .earliest()
+                    .map(|t| t.to_utc())
+            }),
+        _ => None,
+    }
+}
+
+/// 解析 .NET 的 `/Date(<毫秒>)/` 格式；时区后缀（例如 `+0800`）只用于显示，毫秒数本身就是 UTC
+fn parse_dotnet_date(text: &str) -> Option<DateTime<Utc>> {
+    let inner = text.strip_prefix("/Date(")?.strip_suffix(")/")?;
+    let millis = match inner.get(1..).and_then(|rest| rest.find(['+', '-'])) {
+        Some(offset) => &inner[..offset + 1],
+        None => inner,
+    };
+    DateTime::from_timestamp_millis(millis.parse().ok()?)
+}
@@ -0,0 +1,125 @@
+use super::{CacheRecord, MonthResult, MonthStatus, RunStatus};
+use crate::archiver::{self, ArchiveFormat, ArchiveNameInfo};
+use crate::cleaner::TIMESTAMP_FORMAT;
+use crate::error::{BackupError, cache_error};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// 从目标目录中已有的归档重建缓存记录（--rebuild-cache）
+///
+/// 每个归档（所有分卷合起来）对应一条记录，开始和结束时间都取文件名中的时间戳；
+/// ZIP 注释中有备份元数据时，该月的截止时间使用其中记录的扫描开始时间（旧版本的归档
+/// 没有该字段，使用最早的分卷创建时间），否则同样使用时间戳。截止时间不能晚于扫描开始，
+/// 否则扫描和压缩期间被修改的文件在下次备份中会被漏掉。
+/// 只扫描目标目录本身，被跳过文件列表和其他文件被忽略。
+/// 重建的记录不区分全量/增量归档，之后的 --incremental 运行会先创建新的全量归档；
+/// 也不记录源，适用于所有源。
+///
+/// # Arguments
+/// * `destination_path` - 归档所在的目标目录
+///
+/// # Returns
+/// 返回按开始时间排序的记录；无法列出目标目录时返回 [`BackupError::CacheError`]，
+/// 其中的路径为目标目录。
+pub fn records_from_archives(destination_path: &Path) -> Result<Vec<CacheRecord>, BackupError> {
+    // 同一归档的分卷按去掉分卷编号后的文件名归为一组
+    let mut archives: BTreeMap<String, (ArchiveNameInfo, Vec<String>)> = BTreeMap::new();
+    for entry in fs::read_dir(destination_path).map_err(cache_error(destination_path))? {
+        let entry = entry.map_err(cache_error(destination_path))?;
+        if !entry
+            .file_type()
+            .map_err(cache_error(destination_path))?
+            .is_file()
+        {
+            continue;
+        }
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let Some(info) = archiver::parse_archive_name(&file_name) else {
+            continue;
+        };
+        if info.skip_report {
+            continue;
+        }
+        let key = ArchiveNameInfo {
+            part: None,
+            ..info.clone()
+        }
+        .file_name();
+        let (_, files) = archives.entry(key).or_insert_with(|| (info, Vec::new()));
+        files.push(file_name);
+    }
+
+    let mut records: Vec<CacheRecord> = archives
+        .into_values()
+        .filter_map(|(info, mut files)| {
+            let naive = NaiveDateTime::parse_from_str(&info.timestamp, TIMESTAMP_FORMAT).ok()?;
+            let time = Local.from_local_datetime(&naive).earliest()?.to_utc();
+            files.sort();
+            Some(record_for_archive(destination_path, &info, files, time))
+        })
+        .collect();
+    records.sort_by_key(|r| r.start_time);
+    Ok(records)
+}
+
+/// 为一个归档的所有分卷生成缓存记录
+fn record_for_archive(
+    destination_path: &Path,
+    info: &ArchiveNameInfo,
+    files: Vec<String>,
+    time: DateTime<Utc>,
+) -> CacheRecord {
+    // 加密后的归档和 tar 归档没有可读的注释
+    let metadata: Vec<archiver::ArchiveInfo> =
+        if info.format == ArchiveFormat::Zip && !info.encrypted {
+            files
+                .iter()
+                .filter_map(|name| {
+                    archiver::read_archive_info(&destination_path.join(name))
+                        .ok()
+                        .flatten()
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+    let cutoff = metadata
+        .iter()
+        .map(|m| m.next_cutoff.unwrap_or(m.created_at))
+        .min()
+        .unwrap_or(time);
+    CacheRecord {
+        start_time: time,
+        end_time: time,
+        backup_info: format!("Rebuilt from {}", files[0]),
+        cutoff_time: Some(cutoff),
+        archives: Vec::new(),
+        accounts: info.group.iter().cloned().collect(),
+        source_paths: metadata
+            .first()
+            .map(|m| {
+                std::iter::once(m.source_path.clone())
+                    .chain(m.extra_source_paths.iter().cloned())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        archive_files: files.clone(),
+        archives_deleted_time: None,
+        months: vec![MonthResult {
+            month: info.month.clone(),
+            cutoff,
+            archive_files: files,
+            status: MonthStatus::Succeeded,
+        }],
+        status: Some(RunStatus::Success),
+        files_archived: (!metadata.is_empty()).then(|| metadata.iter().map(|m| m.file_count).sum()),
+        bytes_archived: None,
+        tool_version: None,
+        month_boundary_tz: None,
+        source_id: None,
+        skipped_files: Vec::new(),
+        extra: serde_json::Map::new(),
+    }
+}
@@ -0,0 +1,509 @@
+use super::{
+    CacheRecord, CacheStore, FileHashIndex, FileHashRecord, JsonStore, ScanIndex, ScanIndexRecord,
+    VerificationRecord, read_cache_records, read_file_hashes, read_scan_index,
+    read_verification_records,
+};
+use crate::error::{BackupError, cache_error};
+use rusqlite::{Connection, OpenFlags, Row, params};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// `.cache` 目录中 SQLite 数据库的文件名
+pub const DATABASE_FILE_NAME: &str = "cache.sqlite3";
+
+/// 当前的数据库结构版本，保存在 `PRAGMA user_version` 中；0 表示新建的数据库
+const SCHEMA_VERSION: i64 = 6;
+
+/// 另一个连接正在写入时等待的时间
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        start_time TEXT NOT NULL,
+        end_time TEXT NOT NULL,
+        backup_info TEXT NOT NULL,
+        cutoff_time TEXT,
+        archives TEXT NOT NULL,
+        accounts TEXT NOT NULL,
+        source_paths TEXT NOT NULL,
+        archive_files TEXT NOT NULL,
+        archives_deleted_time TEXT,
+        months TEXT NOT NULL,
+        status TEXT,
+        files_archived INTEGER,
+        bytes_archived INTEGER,
+        tool_version TEXT,
+        extra TEXT NOT NULL DEFAULT '{}',
+        source_id TEXT,
+        skipped_files TEXT NOT NULL DEFAULT '[]',
+        month_boundary_tz TEXT
+    );
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        modified TEXT,
+        sha256 TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS scan_index (
+        path TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        modified TEXT NOT NULL,
+        hash TEXT
+    );
+    CREATE TABLE IF NOT EXISTS verifications (
+        id INTEGER PRIMARY KEY,
+        time TEXT NOT NULL,
+        quick INTEGER NOT NULL,
+        months TEXT NOT NULL,
+        archives_passed INTEGER NOT NULL,
+        failed TEXT NOT NULL
+    );
+";
+
+/// 从结构版本 `N` 升级到 `N + 1` 的语句，第一项对应版本 1
+const MIGRATIONS: &[&str] = &[
+    // 1 -> 2：增加保存未知字段的 `extra` 列
+    "ALTER TABLE runs ADD COLUMN extra TEXT NOT NULL DEFAULT '{}';",
+    // 2 -> 3：增加区分源目录的 `source_id` 列
+    "ALTER TABLE runs ADD COLUMN source_id TEXT;",
+    // 3 -> 4：增加尚未归档文件的 `skipped_files` 列
+    "ALTER TABLE runs ADD COLUMN skipped_files TEXT NOT NULL DEFAULT '[]';",
+    // 4 -> 5：增加划分月份所用时区的 `month_boundary_tz` 列
+    "ALTER TABLE runs ADD COLUMN month_boundary_tz TEXT;",
+    // 5 -> 6：增加 verify --record 的 `verifications` 表
+    "CREATE TABLE verifications (
+        id INTEGER PRIMARY KEY,
+        time TEXT NOT NULL,
+        quick INTEGER NOT NULL,
+        months TEXT NOT NULL,
+        archives_passed INTEGER NOT NULL,
+        failed TEXT NOT NULL
+    );",
+];
+
+/// 保存在 `.cache/cache.sqlite3` 中的缓存（`--cache-backend sqlite`）
+///
+/// `runs` 表的每一行对应一条 `CacheRecord`，嵌套的列表及未知字段以 JSON 文本保存；
+/// `files` 表保存去重索引，`scan_index` 表保存变化检测索引，`verifications` 表保存校验记录。
+pub struct SqliteStore {
+    conn: Connection,
+    path: PathBuf,
+    /// 数据库的结构版本；只读打开的旧数据库不升级，读取时用默认值代替缺少的列
+    version: i64,
+}
+
+impl SqliteStore {
+    /// 打开（必要时创建）`.cache` 目录中的数据库
+    ///
+    /// 新建的数据库会一次性导入同一目录中已有的 `backupEvents.json`、`fileHashes.json`、
+    /// `scanIndex.json` 和 `verifyEvents.json`；JSON 文件保持不变，切换回 JSON 后端时仍可使用。
+    ///
+    /// # Arguments
+    /// * `cache_folder` - `.cache` 目录
+    /// * `read_only` - 只读打开（`--dry-run`）；数据库不存在时在内存中导入 JSON 文件，不创建任何文件
+    ///
+    /// 数据库或导入的 JSON 文件无法读取、数据库版本过新时返回 [`BackupError::CacheError`]
+    pub fn open(cache_folder: &Path, read_only: bool) -> Result<Self, BackupError> {
+        let path = cache_folder.join(DATABASE_FILE_NAME);
+        let mut store = Self::connect(&path, read_only).map_err(cache_error(&path))?;
+        if store.version == 0 {
+            store.migrate(cache_folder, read_only)?;
+            store.version = SCHEMA_VERSION;
+        }
+        Ok(store)
+    }
+
+    /// 连接数据库，并把旧版本的数据库结构升级到当前版本（只读时不升级）
+    fn connect(path: &Path, read_only: bool) -> io::Result<Self> {
+        let mut conn = if !read_only {
+            Connection::open(path)
+        } else if path.exists() {
+            Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        } else {
+            Connection::open_in_memory()
+        }
+        .map_err(sql_error)?;
+        conn.busy_timeout(BUSY_TIMEOUT).map_err(sql_error)?;
+        // 只读打开的数据库尚未完成导入时，同样在内存中导入
+        if read_only && schema_version(&conn)? == 0 {
+            conn = Connection::open_in_memory().map_err(sql_error)?;
+        }
+        let mut store = SqliteStore {
+            version: schema_version(&conn)?,
+            conn,
+            path: path.to_path_buf(),
+        };
+        match store.version {
+            version if version > 0 && version < SCHEMA_VERSION && !read_only => {
+                let tx = store.conn.unchecked_transaction().map_err(sql_error)?;
+                for migration in &MIGRATIONS[version as usize - 1..] {
+                    tx.execute_batch(migration).map_err(sql_error)?;
+                }
+                tx.pragma_update(None, "user_version", SCHEMA_VERSION)
+                    .map_err(sql_error)?;
+                tx.commit().map_err(sql_error)?;
+                store.version = SCHEMA_VERSION;
+            }
+            version if version > SCHEMA_VERSION => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "'{}' uses database schema version {}, but this version of dat-patch-rust only understands up to version {}; upgrade dat-patch-rust",
+                        store.path.display(),
+                        version,
+                        SCHEMA_VERSION
+                    ),
+                ));
+            }
+            _ => {}
+        }
+        Ok(store)
+    }
+
+    /// 创建数据库结构并导入已有的 JSON 缓存，整个过程在一个事务中完成
+    fn migrate(&self, cache_folder: &Path, read_only: bool) -> Result<(), BackupError> {
+        let json = JsonStore::new(cache_folder, read_only);
+        let records = read_cache_records(&json.cache_file, read_only)?;
+        let hashes = read_file_hashes(&json.file_hashes_file)?;
+        let scan_index = read_scan_index(&json.scan_index_file)?;
+        let verifications = read_verification_records(&json.verifications_file)?;
+        self.import(&records, &hashes, &scan_index, &verifications)
+            .map_err(cache_error(&self.path))
+    }
+
+    fn import(
+        &self,
+        records: &[CacheRecord],
+        hashes: &FileHashIndex,
+        scan_index: &ScanIndex,
+        verifications: &[VerificationRecord],
+    ) -> io::Result<()> {
+        let tx = self.conn.unchecked_transaction().map_err(sql_error)?;
+        tx.execute_batch(SCHEMA).map_err(sql_error)?;
+        self.insert_records(records)?;
+        self.insert_file_hashes(hashes)?;
+        self.insert_scan_index(scan_index)?;
+        for record in verifications {
+            self.insert_verification(record)?;
+        }
+        tx.pragma_update(None, "user_version", SCHEMA_VERSION)
+            .map_err(sql_error)?;
+        tx.commit().map_err(sql_error)
+    }
+
+    fn insert_records(&self, records: &[CacheRecord]) -> io::Result<()> {
+        let mut insert = self
+            .conn
+            .prepare_cached(
+                "INSERT INTO runs (start_time, end_time, backup_info, cutoff_time, archives,
+                    accounts, source_paths, archive_files, archives_deleted_time, months,
+                    status, files_archived, bytes_archived, tool_version, extra, source_id,
+                    skipped_files, month_boundary_tz)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                    ?17, ?18)",
+            )
+            .map_err(sql_error)?;
+        for record in records {
+            insert
+                .execute(params![
+                    record.start_time,
+                    record.end_time,
+                    record.backup_info,
+                    record.cutoff_time,
+                    to_json(&record.archives)?,
+                    to_json(&record.accounts)?,
+                    to_json(&record.source_paths)?,
+                    to_json(&record.archive_files)?,
+                    record.archives_deleted_time,
+                    to_json(&record.months)?,
+                    record.status.map(|status| to_text(&status)).transpose()?,
+                    record.files_archived,
+                    record.bytes_archived,
+                    record.tool_version,
+                    to_json(&record.extra)?,
+                    record.source_id,
+                    to_json(&record.skipped_files)?,
+                    record.month_boundary_tz,
+                ])
+                .map_err(sql_error)?;
+        }
+        Ok(())
+    }
+
+    fn insert_file_hashes(&self, index: &FileHashIndex) -> io::Result<()> {
+        let mut insert = self
+            .conn
+            .prepare_cached(
+                "INSERT INTO files (path, size, modified, sha256) VALUES (?1, ?2, ?3, ?4)",
+            )
+            .map_err(sql_error)?;
+        for (path, record) in index {
+            insert
+                .execute(params![path, record.size, record.modified, record.sha256])
+                .map_err(sql_error)?;
+        }
+        Ok(())
+    }
+
+    fn insert_scan_index(&self, index: &ScanIndex) -> io::Result<()> {
+        let mut insert = self
+            .conn
+            .prepare_cached(
+                "INSERT INTO scan_index (path, size, modified, hash) VALUES (?1, ?2, ?3, ?4)",
+            )
+            .map_err(sql_error)?;
+        for (path, record) in index {
+            insert
+                .execute(params![path, record.size, record.modified, record.hash])
+                .map_err(sql_error)?;
+        }
+        Ok(())
+    }
+
+    fn insert_verification(&self, record: &VerificationRecord) -> io::Result<()> {
+        let mut insert = self
+            .conn
+            .prepare_cached(
+                "INSERT INTO verifications (time, quick, months, archives_passed, failed)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )
+            .map_err(sql_error)?;
+        insert
+            .execute(params![
+                record.time,
+                record.quick,
+                to_json(&record.months)?,
+                record.archives_passed,
+                to_json(&record.failed)?,
+            ])
+            .map_err(sql_error)?;
+        Ok(())
+    }
+
+    /// 在一个事务中清空表并写入新的内容
+    fn replace(&self, table: &str, insert: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+        let tx = self.conn.unchecked_transaction().map_err(sql_error)?;
+        tx.execute(&format!("DELETE FROM {}", table), [])
+            .map_err(sql_error)?;
+        insert()?;
+        tx.commit().map_err(sql_error)
+    }
+}
+
+/// [`CacheStore`] 的实现，返回底层的 I/O 错误
+impl SqliteStore {
+    fn select_records(&self) -> io::Result<Vec<CacheRecord>> {
+        // 只读打开的旧数据库缺少的列用默认值代替
+        let extra = if self.version >= 2 { "extra" } else { "'{}'" };
+        let source_id = if self.version >= 3 {
+            "source_id"
+        } else {
+            "NULL"
+        };
+        let skipped_files = if self.version >= 4 {
+            "skipped_files"
+        } else {
+            "'[]'"
+        };
+        let month_boundary_tz = if self.version >= 5 {
+            "month_boundary_tz"
+        } else {
+            "NULL"
+        };
+        let mut select = self
+            .conn
+            .prepare(&format!(
+                "SELECT start_time, end_time, backup_info, cutoff_time, archives, accounts,
+                    source_paths, archive_files, archives_deleted_time, months, status,
+                    files_archived, bytes_archived, tool_version, {}, {}, {}, {}
+                 FROM runs ORDER BY id",
+                extra, source_id, skipped_files, month_boundary_tz
+            ))
+            .map_err(sql_error)?;
+        let rows = select.query_map([], read_record).map_err(sql_error)?;
+        rows.map(|row| row.map_err(sql_error)).collect()
+    }
+
+    fn replace_records(&self, records: &[CacheRecord]) -> io::Result<()> {
+        self.replace("runs", || self.insert_records(records))
+    }
+
+    fn select_file_hashes(&self) -> io::Result<FileHashIndex> {
+        let mut select = self
+            .conn
+            .prepare("SELECT path, size, modified, sha256 FROM files")
+            .map_err(sql_error)?;
+        let rows = select
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    FileHashRecord {
+                        size: row.get(1)?,
+                        modified: row.get(2)?,
+                        sha256: row.get(3)?,
+                    },
+                ))
+            })
+            .map_err(sql_error)?;
+        rows.map(|row| row.map_err(sql_error)).collect()
+    }
+
+    fn replace_file_hashes(&self, index: &FileHashIndex) -> io::Result<()> {
+        self.replace("files", || self.insert_file_hashes(index))
+    }
+
+    fn select_scan_index(&self) -> io::Result<ScanIndex> {
+        let mut select = self
+            .conn
+            .prepare("SELECT path, size, modified, hash FROM scan_index")
+            .map_err(sql_error)?;
+        let rows = select
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    ScanIndexRecord {
+                        size: row.get(1)?,
+                        modified: row.get(2)?,
+                        hash: row.get(3)?,
+                    },
+                ))
+            })
+            .map_err(sql_error)?;
+        rows.map(|row| row.map_err(sql_error)).collect()
+    }
+
+    fn replace_scan_index(&self, index: &ScanIndex) -> io::Result<()> {
+        self.replace("scan_index", || self.insert_scan_index(index))
+    }
+
+    fn select_verifications(&self) -> io::Result<Vec<VerificationRecord>> {
+        // 只读打开的旧数据库还没有该表
+        if self.version < 6 {
+            return Ok(Vec::new());
+        }
+        let mut select = self
+            .conn
+            .prepare(
+                "SELECT time, quick, months, archives_passed, failed FROM verifications ORDER BY id",
+            )
+            .map_err(sql_error)?;
+        let rows = select
+            .query_map([], |row| {
+                Ok(VerificationRecord {
+                    time: row.get(0)?,
+                    quick: row.get(1)?,
+                    months: from_json(row, 2)?,
+                    archives_passed: row.get(3)?,
+                    failed: from_json(row, 4)?,
+                })
+            })
+            .map_err(sql_error)?;
+        rows.map(|row| row.map_err(sql_error)).collect()
+    }
+}
+
+impl CacheStore for SqliteStore {
+    fn location(&self) -> &Path {
+        &self.path
+    }
+
+    fn read_records(&self) -> Result<Vec<CacheRecord>, BackupError> {
+        self.select_records().map_err(cache_error(&self.path))
+    }
+
+    fn write_records(&self, records: &[CacheRecord]) -> Result<(), BackupError> {
+        self.replace_records(records)
+            .map_err(cache_error(&self.path))
+    }
+
+    fn read_file_hashes(&self) -> Result<FileHashIndex, BackupError> {
+        self.select_file_hashes().map_err(cache_error(&self.path))
+    }
+
+    fn write_file_hashes(&self, index: &FileHashIndex) -> Result<(), BackupError> {
+        self.replace_file_hashes(index)
+            .map_err(cache_error(&self.path))
+    }
+
+    fn read_scan_index(&self) -> Result<ScanIndex, BackupError> {
+        self.select_scan_index().map_err(cache_error(&self.path))
+    }
+
+    fn write_scan_index(&self, index: &ScanIndex) -> Result<(), BackupError> {
+        self.replace_scan_index(index)
+            .map_err(cache_error(&self.path))
+    }
+
+    fn read_verifications(&self) -> Result<Vec<VerificationRecord>, BackupError> {
+        self.select_verifications().map_err(cache_error(&self.path))
+    }
+
+    fn append_verification(&self, record: &VerificationRecord) -> Result<(), BackupError> {
+        self.insert_verification(record)
+            .map_err(cache_error(&self.path))
+    }
+}
+
+fn schema_version(conn: &Connection) -> io::Result<i64> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(sql_error)
+}
+
+/// 将 `runs` 表的一行转换为 `CacheRecord`
+fn read_record(row: &Row) -> rusqlite::Result<CacheRecord> {
+    let status: Option<String> = row.get(10)?;
+    Ok(CacheRecord {
+        start_time: row.get(0)?,
+        end_time: row.get(1)?,
+        backup_info: row.get(2)?,
+        cutoff_time: row.get(3)?,
+        archives: from_json(row, 4)?,
+        accounts: from_json(row, 5)?,
+        source_paths: from_json(row, 6)?,
+        archive_files: from_json(row, 7)?,
+        archives_deleted_time: row.get(8)?,
+        months: from_json(row, 9)?,
+        status: status
+            .map(|status| serde_json::from_value(serde_json::Value::String(status)))
+            .transpose()
+            .map_err(|e| json_column_error(10, e))?,
+        files_archived: row.get(11)?,
+        bytes_archived: row.get(12)?,
+        tool_version: row.get(13)?,
+        month_boundary_tz: row.get(17)?,
+        extra: from_json(row, 14)?,
+        source_id: row.get(15)?,
+        skipped_files: from_json(row, 16)?,
+    })
+}
+
+/// 列表等嵌套字段以 JSON 文本保存
+fn to_json<T: Serialize>(value: &T) -> io::Result<String> {
+    serde_json::to_string(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// 枚举以其 JSON 字符串（例如 `Success`）保存，与 `backupEvents.json` 中的写法一致
+fn to_text<T: Serialize>(value: &T) -> io::Result<String> {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(text)) => Ok(text),
+        Ok(other) => Ok(other.to_string()),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    }
+}
+
+fn from_json<T: DeserializeOwned>(row: &Row, index: usize) -> rusqlite::Result<T> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text).map_err(|e| json_column_error(index, e))
+}
+
+fn json_column_error(index: usize, e: serde_json::Error) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e))
+}
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
@@ -1,75 +1,2010 @@
-use chrono::{Duration, Local, NaiveDateTime};
+use crate::archiver::{self, ArchiveFormat, parse_archive_name};
+use crate::backup_logic::{ArchiveKind, BackupWeek};
+use crate::error::{BackupError, cleanup_error};
+use chrono::{Datelike, Days, Local, Months, NaiveDate, NaiveDateTime};
+use indicatif::HumanBytes;
 use regex::Regex;
-use std::fs;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-/// Cleans up old backup archives based on the keep_months parameter.
+/// 目标目录中存放被删除归档的回收站目录（`--trash`）
+pub const TRASH_DIR: &str = ".trash";
+
+/// What the retention window of `--keep-months` is measured against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum RetentionBy {
+    /// The creation timestamp in the archive file name (default)
+    #[default]
+    Created,
+    /// The backed-up month (the leading `YYYY-MM`) in the archive file name
+    Month,
+}
+
+/// How the archives older than `--keep-months` are thinned out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum RetentionPolicy {
+    /// Delete every archive older than `--keep-months`
+    #[default]
+    Simple,
+    /// Grandfather-father-son: keep every archive of the last `--keep-months`
+    /// months, the newest of each month for `--gfs-monthly` months and the
+    /// newest of each year for `--gfs-yearly` years
+    Gfs,
+}
+
+/// Which backups [`cleanup_old_backups`] keeps.
+#[derive(Debug, Clone, Default)]
+pub struct RetentionOptions {
+    /// The number of months to keep backups; 0 disables the age-based pass.
+    pub keep_months: u32,
+    /// The number of weeks to keep weekly backups, which `keep_months` then does
+    /// not apply to; 0 treats them like the other backups.
+    pub keep_weeks: u32,
+    /// Whether the age of a backup is its creation time or its month.
+    pub retention_by: RetentionBy,
+    /// The number of newest archives the age-based pass never deletes.
+    pub keep_min: usize,
+    /// With [`RetentionPolicy::Gfs`], `keep_months` is the number of months of
+    /// which every archive is kept, and `retention_by` and `keep_min` are ignored.
+    pub policy: RetentionPolicy,
+    /// The number of months of which the newest archive is kept (GFS).
+    pub gfs_monthly: u32,
+    /// The number of years of which the newest archive is kept (GFS); `None` keeps one for every year.
+    pub gfs_yearly: Option<u32>,
+    /// The total size of the kept backups in bytes; `None` means no quota.
+    pub max_total_size: Option<u64>,
+    /// Keep only the newest archive of each month (and group), regardless of age.
+    pub dedupe_months: bool,
+    /// Move deleted backups into [`TRASH_DIR`] instead of deleting them.
+    pub trash: bool,
+    /// Really delete items that have been in [`TRASH_DIR`] for more than this many days.
+    pub purge_trash_days: Option<u32>,
+    /// Also look for backups in subdirectories (except [`TRASH_DIR`] and `.cache`)
+    /// and remove the subdirectories that become empty.
+    pub recursive: bool,
+    /// The password of AES-256 encrypted zip archives, used to read the manifests
+    /// that tie incremental archives to their full archive.
+    pub password: Option<String>,
+    /// Patterns for other archives to rotate, e.g. those of the old PowerShell
+    /// script. The capture `ts` is the creation time, the optional capture
+    /// `month` (`YYYY-MM`) the backed-up month; without it the month of `ts` is used.
+    pub extra_patterns: Vec<Regex>,
+    /// The chrono format of the `ts` capture; `None` means [`TIMESTAMP_FORMAT`].
+    pub extra_timestamp_format: Option<String>,
+}
+
+/// The format of the timestamps in the archive names this tool creates.
+pub const TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S";
+
+/// A file in the destination named like a backup.
+#[derive(Clone)]
+struct BackupFile {
+    /// The path relative to the destination; the file name without `recursive`
+    name: String,
+    /// The part of the name before `_backup_`: the month and the group, if any,
+    /// plus `@<source tag>` for the archives of a source sharing the destination
+    prefix: String,
+    /// The group, followed by `@<source tag>` if the archive has one
+    group: Option<String>,
+    created: NaiveDateTime,
+    /// The first day of the backed-up month, or the Monday of the backed-up week
+    month: NaiveDateTime,
+    /// Whether this is a weekly backup (`YYYY-Www`)
+    weekly: bool,
+    size: u64,
+    /// The format to read the manifest in, for unencrypted, unsplit archives
+    manifest_format: Option<ArchiveFormat>,
+}
+
+/// The outcome of [`cleanup_old_backups`].
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct CleanupReport {
+    /// Removed backups; in a dry run, the backups that would be removed.
+    pub deleted: Vec<PathBuf>,
+    /// How many of `deleted` were moved to [`TRASH_DIR`] rather than deleted.
+    pub trashed: usize,
+    /// Items deleted from [`TRASH_DIR`] because of `purge_trash_days`.
+    pub purged: Vec<PathBuf>,
+    /// Backups within the retention window or protected by `keep_min`.
+    pub kept: Vec<PathBuf>,
+    /// Files named like backups whose timestamp (or month) is not a valid date.
+    pub skipped_unparsable: Vec<PathBuf>,
+    /// The total size of the really deleted backups and trash items.
+    pub bytes_freed: u64,
+    /// How many backups were selected but kept because the deletion was not confirmed.
+    pub unconfirmed: usize,
+    /// Subdirectories removed because they became empty (`recursive`).
+    pub removed_dirs: Vec<PathBuf>,
+    /// Backups that could not be removed even after retrying, with the reason.
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// How often the removal of a backup is attempted before it counts as failed.
+const REMOVE_ATTEMPTS: u32 = 3;
+/// The pause between two attempts to remove a backup.
+const REMOVE_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// A message from the cleanup functions, passed to the caller's callback.
+///
+/// The cleaner does not print anything itself; the caller decides how to show
+/// the messages (or drops them when running silently).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CleanupEvent {
+    /// What is about to be removed, e.g. the retention deadline.
+    Info(String),
+    /// An item that could not be removed.
+    Warning(String),
+}
+
+/// A backup selected for removal, passed to the confirmation callback of
+/// [`cleanup_old_backups`] before anything is deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeletionCandidate {
+    pub path: PathBuf,
+    pub size: u64,
+    /// The creation time from the file name; for an expired item in the trash,
+    /// when it was moved there.
+    pub created: NaiveDateTime,
+    /// Whether this is an item in [`TRASH_DIR`] that is deleted for good
+    /// (`purge_trash_days`), rather than a backup to delete or move to the trash.
+    pub purge: bool,
+}
+
+/// Computes the creation deadline: archives created before it are old.
+pub fn created_deadline(now: NaiveDateTime, keep_months: u32) -> NaiveDateTime {
+    now.checked_sub_months(Months::new(keep_months))
+        .unwrap_or(NaiveDateTime::MIN)
+}
+
+/// Computes the first backed-up month to keep, as the first day of that month.
+///
+/// The month `keep_months` calendar months before `today` is still kept, e.g.
+/// with 6 months an archive of July data is kept until the end of January.
+pub fn month_deadline(today: NaiveDate, keep_months: u32) -> NaiveDateTime {
+    today
+        .checked_sub_months(Months::new(keep_months))
+        .and_then(|date| date.with_day(1))
+        .unwrap_or(NaiveDate::MIN)
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+}
+
+/// Computes the creation deadline of weekly backups: those created before it are old.
+pub fn created_week_deadline(now: NaiveDateTime, keep_weeks: u32) -> NaiveDateTime {
+    now.checked_sub_days(Days::new(7 * u64::from(keep_weeks)))
+        .unwrap_or(NaiveDateTime::MIN)
+}
+
+/// Computes the first backed-up week to keep, as the Monday of that week.
+///
+/// The week `keep_weeks` weeks before the week of `today` is still kept, like
+/// the months of [`month_deadline`].
+pub fn week_deadline(today: NaiveDate, keep_weeks: u32) -> NaiveDateTime {
+    today
+        .checked_sub_days(Days::new(7 * u64::from(keep_weeks)))
+        .map(|date| BackupWeek::containing(date).first_day())
+        .unwrap_or(NaiveDate::MIN)
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+}
+
+/// Selects the backups to delete: those with a timestamp before `deadline`,
+/// except the files belonging to the `keep_min` newest archives.
+///
+/// Files sharing a timestamp (the parts of a split archive and its skipped file
+/// list) count as one archive and are kept or deleted together.
+///
+/// # Arguments
+/// * `backups` - `(file name, timestamp embedded in the file name)` pairs.
+/// * `deadline` - Backups with an older timestamp may be deleted.
+/// * `keep_min` - The number of newest archives that are never deleted.
+///
+/// # Returns
+/// The file names to delete, in the order of `backups`.
+pub fn backups_to_delete(
+    backups: &[(String, NaiveDateTime)],
+    deadline: NaiveDateTime,
+    keep_min: usize,
+) -> Vec<&str> {
+    let mut timestamps: Vec<NaiveDateTime> = backups.iter().map(|(_, ts)| *ts).collect();
+    timestamps.sort_unstable_by(|a, b| b.cmp(a));
+    timestamps.dedup();
+    // 最新的 keep_min 个归档中最旧的时间戳，不早于它的归档都保留；
+    // 归档不足 keep_min 个时全部保留
+    let oldest_kept = match keep_min {
+        0 => None,
+        n => timestamps.get(n - 1).or(timestamps.last()).copied(),
+    };
+
+    backups
+        .iter()
+        .filter(|(_, ts)| *ts < deadline && oldest_kept.is_none_or(|kept| *ts < kept))
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+/// Selects the oldest backups to delete until the rest fit in `max_total_size` bytes.
+///
+/// Files sharing a timestamp count as one archive, like in [`backups_to_delete`].
+/// The newest archive is never deleted, even if it alone exceeds the quota.
+///
+/// # Arguments
+/// * `backups` - `(file name, timestamp embedded in the file name, size)` tuples.
+/// * `max_total_size` - The total size the kept backups must fit in.
+///
+/// # Returns
+/// The file names to delete, in the order of `backups`.
+pub fn backups_over_quota(
+    backups: &[(String, NaiveDateTime, u64)],
+    max_total_size: u64,
+) -> Vec<&str> {
+    // 每个归档（时间戳）的总大小，按从旧到新排列
+    let mut archives: BTreeMap<NaiveDateTime, u64> = BTreeMap::new();
+    for (_, timestamp, size) in backups {
+        *archives.entry(*timestamp).or_default() += size;
+    }
+    let Some(&newest) = archives.keys().next_back() else {
+        return Vec::new();
+    };
+
+    // 从最旧的归档开始删除，直到剩余的总大小不超过配额；最新的归档总是保留
+    let mut total: u64 = archives.values().sum();
+    let mut newest_deleted = None;
+    for (&timestamp, &size) in &archives {
+        if total <= max_total_size || timestamp == newest {
+            break;
+        }
+        total -= size;
+        newest_deleted = Some(timestamp);
+    }
+
+    backups
+        .iter()
+        .filter(|(_, timestamp, _)| newest_deleted.is_some_and(|deleted| *timestamp <= deleted))
+        .map(|(name, _, _)| name.as_str())
+        .collect()
+}
+
+/// Selects all but the newest archive of each month.
+///
+/// Archives are grouped by the part of the name before `_backup_`, so the
+/// archives of different accounts (`--split-by-top-dir`) of the same month are
+/// kept apart. Files sharing the newest timestamp of a group (the parts of a split
+/// archive and its skipped file list) are all kept.
+///
+/// # Arguments
+/// * `backups` - `(file name, month and group prefix, timestamp embedded in the file name)` tuples.
+///
+/// # Returns
+/// The file names to delete, in the order of `backups`.
+pub fn duplicate_month_backups(backups: &[(String, String, NaiveDateTime)]) -> Vec<&str> {
+    let mut newest: BTreeMap<&str, NaiveDateTime> = BTreeMap::new();
+    for (_, prefix, timestamp) in backups {
+        let entry = newest.entry(prefix.as_str()).or_insert(*timestamp);
+        *entry = (*entry).max(*timestamp);
+    }
+    backups
+        .iter()
+        .filter(|(_, prefix, timestamp)| *timestamp < newest[prefix.as_str()])
+        .map(|(name, _, _)| name.as_str())
+        .collect()
+}
+
+/// Selects the backups to delete under the grandfather-father-son policy.
+///
+/// Months are counted back from the month of `today`, which is the first
+/// month. Every archive of the first `keep_all_months` months is kept; of the
+/// first `monthly` months, the newest archive of each month; of the first
+/// `yearly` calendar years (all years if `None`), the newest archive of each
+/// year. Everything older is deleted. Buckets are per group, like in
+/// [`duplicate_month_backups`], and files sharing the newest timestamp of a
+/// bucket are all kept.
+///
+/// # Arguments
+/// * `backups` - `(file name, group, backed-up month, timestamp embedded in the file name)` tuples.
+/// * `today` - The current date.
+/// * `keep_all_months` - The number of months of which every archive is kept.
+/// * `monthly` - The number of months of which one archive per month is kept.
+/// * `yearly` - The number of years of which one archive per year is kept.
+///
+/// # Returns
+/// The file names to delete, in the order of `backups`.
+pub fn gfs_backups_to_delete(
+    backups: &[(String, Option<String>, NaiveDate, NaiveDateTime)],
+    today: NaiveDate,
+    keep_all_months: u32,
+    monthly: u32,
+    yearly: Option<u32>,
+) -> Vec<&str> {
+    /// 保留每个桶中最新的归档；桶为 (分组, 年, 月)，按年保留时月为 `None`
+    enum Bucket {
+        KeepAll,
+        Newest((Option<String>, i32, Option<u32>)),
+        Expired,
+    }
+    let month_index = |date: NaiveDate| i64::from(date.year()) * 12 + i64::from(date.month0());
+    let bucket = |group: &Option<String>, month: NaiveDate| {
+        let months_ago = month_index(today) - month_index(month);
+        let years_ago = i64::from(today.year()) - i64::from(month.year());
+        if months_ago < i64::from(keep_all_months) {
+            Bucket::KeepAll
+        } else if months_ago < i64::from(monthly) {
+            Bucket::Newest((group.clone(), month.year(), Some(month.month())))
+        } else if yearly.is_none_or(|years| years_ago < i64::from(years)) {
+            Bucket::Newest((group.clone(), month.year(), None))
+        } else {
+            Bucket::Expired
+        }
+    };
+
+    let mut newest: BTreeMap<(Option<String>, i32, Option<u32>), NaiveDateTime> = BTreeMap::new();
+    for (_, group, month, timestamp) in backups {
+        if let Bucket::Newest(key) = bucket(group, *month) {
+            let entry = newest.entry(key).or_insert(*timestamp);
+            *entry = (*entry).max(*timestamp);
+        }
+    }
+    backups
+        .iter()
+        .filter(|(_, group, month, timestamp)| match bucket(group, *month) {
+            Bucket::KeepAll => false,
+            Bucket::Newest(key) => *timestamp < newest[&key],
+            Bucket::Expired => true,
+        })
+        .map(|(name, _, _, _)| name.as_str())
+        .collect()
+}
+
+/// Runs `remove` until it succeeds, at most [`REMOVE_ATTEMPTS`] times.
+///
+/// Transient errors, e.g. on network shares or while a virus scanner holds the
+/// file, often clear up after a short pause. A missing file is not retried.
+fn remove_with_retry<T>(mut remove: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 1;
+    loop {
+        match remove() {
+            Err(e) if attempt < REMOVE_ATTEMPTS && e.kind() != io::ErrorKind::NotFound => {
+                std::thread::sleep(REMOVE_RETRY_DELAY);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Describes why a backup could not be removed, naming Windows sharing violations.
+fn removal_error_message(e: &io::Error) -> String {
+    // ERROR_SHARING_VIOLATION (32) 和 ERROR_LOCK_VIOLATION (33)：文件正被其他程序打开
+    if cfg!(windows) && matches!(e.raw_os_error(), Some(32 | 33)) {
+        format!("{} (the file is open in another program)", e)
+    } else {
+        e.to_string()
+    }
+}
+
+/// Lists the files in `dir` as paths relative to it; with `recursive` also the
+/// files in its subdirectories, except [`TRASH_DIR`] and `.cache`.
+///
+/// Symbolic links to directories are not followed.
+fn list_files(dir: &Path, recursive: bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative_dir) = pending.pop() {
+        for entry in fs::read_dir(dir.join(&relative_dir))? {
+            let entry = entry?;
+            let relative = relative_dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                let excluded = relative_dir.as_os_str().is_empty()
+                    && (entry.file_name() == TRASH_DIR || entry.file_name() == ".cache");
+                if recursive && !excluded {
+                    pending.push(relative);
+                }
+            } else if entry.path().is_file() {
+                files.push(relative);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Removes the empty directories from `dir` up to, but not including, `root`.
+fn remove_empty_parents(root: &Path, dir: &Path, report: &mut CleanupReport) {
+    let mut dir = dir;
+    while dir != root && dir.starts_with(root) {
+        let is_empty = fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none());
+        if !is_empty || fs::remove_dir(dir).is_err() {
+            return;
+        }
+        report.removed_dirs.push(dir.to_path_buf());
+        let Some(parent) = dir.parent() else {
+            return;
+        };
+        dir = parent;
+    }
+}
+
+/// Moves a backup into `trash_dir`, creating it on demand.
+///
+/// If the trash already holds a file with the same name, ` (1)`, ` (2)`, … is
+/// added before the extensions. Returns where the backup was moved to; see
+/// [`mark_trashed`] for its modification time.
+fn move_to_trash(path: &Path, trash_dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(trash_dir)?;
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, extensions) = file_name
+        .find('.')
+        .map_or((file_name.as_str(), ""), |index| file_name.split_at(index));
+    let mut target = trash_dir.join(&file_name);
+    let mut n = 1;
+    while target.exists() {
+        target = trash_dir.join(format!("{} ({}){}", stem, n, extensions));
+        n += 1;
+    }
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+/// Sets the modification time of a trashed backup to now, so that
+/// `purge_trash_days` counts from the time the backup was trashed.
+fn mark_trashed(target: &Path) -> io::Result<()> {
+    File::options()
+        .write(true)
+        .open(target)?
+        .set_modified(SystemTime::now())
+}
+
+/// Lists the items in the trash that were trashed more than `days` days ago.
+fn expired_trash(destination_path: &Path, days: u32) -> io::Result<Vec<DeletionCandidate>> {
+    let trash_dir = destination_path.join(TRASH_DIR);
+    if !trash_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let max_age = std::time::Duration::from_secs(u64::from(days) * 24 * 60 * 60);
+    let now = SystemTime::now();
+    let mut expired = Vec::new();
+    for entry in fs::read_dir(&trash_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let Some(trashed) = metadata
+            .modified()
+            .ok()
+            .filter(|modified| now.duration_since(*modified).is_ok_and(|age| age > max_age))
+        else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        expired.push(DeletionCandidate {
+            path: entry.path(),
+            size: metadata.len(),
+            created: chrono::DateTime::<Local>::from(trashed).naive_local(),
+            purge: true,
+        });
+    }
+    expired.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(expired)
+}
+
+/// Deletes the expired items in the trash found by [`expired_trash`].
+fn purge_trash(
+    expired: &[DeletionCandidate],
+    dry_run: bool,
+    report: &mut CleanupReport,
+) -> io::Result<()> {
+    for item in expired {
+        if !dry_run {
+            fs::remove_file(&item.path)?;
+        }
+        report.bytes_freed += item.size;
+        report.purged.push(item.path.clone());
+    }
+    Ok(())
+}
+
+/// Cleans up old backup archives based on the retention options.
+///
+/// The age-based pass (`keep_months`) and the per-month deduplication
+/// (`dedupe_months`) run first, then the size quota (`max_total_size`)
+/// applies to the backups that are left. With `trash` the selected backups
+/// are moved into [`TRASH_DIR`]; `purge_trash_days` empties it over time.
+///
+/// All selected backups and expired items in the trash are passed to `confirm`
+/// at once before any of them is removed; if it returns `false` they are all
+/// kept. It is not called in a dry run or when nothing is selected.
 ///
 /// # Arguments
 /// * `destination_path` - The directory where backup archives are stored.
-/// * `keep_months` - The number of months to keep backups.
-/// * `silent` - Suppress console output.
+/// * `retention` - Which backups to keep.
+/// * `dry_run` - Only report the archives that would be removed.
+/// * `on_event` - Receives the progress messages.
+/// * `confirm` - Asks whether the selected backups may be removed.
+///
+/// # Returns
+/// The removed, kept and unparsable backups; the caller prints them.
+/// Failing to list the destination is a [`BackupError::CleanupError`];
+/// backups that cannot be removed are listed in [`CleanupReport::failed`] instead.
 pub fn cleanup_old_backups(
     destination_path: &Path,
-    keep_months: u32,
-    silent: bool,
-) -> io::Result<()> {
-    if keep_months == 0 {
-        return Ok(());
+    retention: &RetentionOptions,
+    dry_run: bool,
+    on_event: &dyn Fn(CleanupEvent),
+    confirm: &dyn Fn(&[DeletionCandidate]) -> bool,
+) -> Result<CleanupReport, BackupError> {
+    remove_old_backups(destination_path, retention, dry_run, on_event, confirm)
+        .map_err(cleanup_error(destination_path))
+}
+
+/// The body of [`cleanup_old_backups`], returning the underlying I/O error.
+fn remove_old_backups(
+    destination_path: &Path,
+    retention: &RetentionOptions,
+    dry_run: bool,
+    on_event: &dyn Fn(CleanupEvent),
+    confirm: &dyn Fn(&[DeletionCandidate]) -> bool,
+) -> io::Result<CleanupReport> {
+    let mut report = CleanupReport::default();
+    let expired = match retention.purge_trash_days {
+        Some(days) => expired_trash(destination_path, days)?,
+        None => Vec::new(),
+    };
+    let gfs = retention.policy == RetentionPolicy::Gfs;
+    if retention.keep_months == 0
+        && retention.keep_weeks == 0
+        && !gfs
+        && retention.max_total_size.is_none()
+        && !retention.dedupe_months
+    {
+        if !dry_run && !expired.is_empty() && !confirm(&expired) {
+            report.unconfirmed = expired.len();
+        } else {
+            purge_trash(&expired, dry_run, &mut report)?;
+        }
+        return Ok(report);
     }
+    let action = if dry_run { "Looking for" } else { "Removing" };
 
-    // 计算删除的截止日期
-    let deadline = Local::now() - Duration::days(30 * keep_months as i64);
-    if !silent {
-        println!(
-            "\nRemoving backups older than {} months (before {})...",
-            keep_months,
-            deadline.format("%Y-%m-%d %H:%M:%S")
+    // 按日历月计算删除的截止日期
+    let now = Local::now().naive_local();
+    let deadline = match retention.retention_by {
+        RetentionBy::Created => created_deadline(now, retention.keep_months),
+        RetentionBy::Month => month_deadline(now.date(), retention.keep_months),
+    };
+    if gfs {
+        on_event(CleanupEvent::Info(format!(
+            "\n{} backups beyond all of the last {} months, one per month for {} months and one per year{}...",
+            action,
+            retention.keep_months,
+            retention.gfs_monthly,
+            retention
+                .gfs_yearly
+                .map_or_else(String::new, |years| format!(" for {} years", years))
+        )));
+    } else if retention.keep_months > 0 {
+        on_event(CleanupEvent::Info(match retention.retention_by {
+            RetentionBy::Created => format!(
+                "\n{} backups older than {} months (before {})...",
+                action,
+                retention.keep_months,
+                deadline.format("%Y-%m-%d %H:%M:%S")
+            ),
+            RetentionBy::Month => format!(
+                "\n{} backups of months before {} (keeping {} months)...",
+                action,
+                deadline.format("%Y-%m"),
+                retention.keep_months
+            ),
+        }));
+    }
+    let week_deadline = match retention.retention_by {
+        RetentionBy::Created => created_week_deadline(now, retention.keep_weeks),
+        RetentionBy::Month => week_deadline(now.date(), retention.keep_weeks),
+    };
+    if retention.keep_weeks > 0 {
+        on_event(CleanupEvent::Info(match retention.retention_by {
+            RetentionBy::Created => format!(
+                "\n{} weekly backups older than {} weeks (before {})...",
+                action,
+                retention.keep_weeks,
+                week_deadline.format("%Y-%m-%d %H:%M:%S")
+            ),
+            RetentionBy::Month => format!(
+                "\n{} backups of weeks before {} (keeping {} weeks)...",
+                action,
+                week_deadline.format("%G-W%V"),
+                retention.keep_weeks
+            ),
+        }));
+    }
+    if retention.dedupe_months {
+        on_event(CleanupEvent::Info(format!(
+            "\n{} all but the newest backup of each month...",
+            action
+        )));
+    }
+    if let Some(max_total_size) = retention.max_total_size {
+        on_event(CleanupEvent::Info(format!(
+            "\n{} the oldest backups beyond a total of {}...",
+            action,
+            HumanBytes(max_total_size)
+        )));
+    }
+
+    // 按文件名中的时间戳判断：分卷归档共享同一时间戳，因此会作为一个整体被保留或删除；
+    // 归档旁的被跳过文件列表随归档一起删除
+
+    let mut backups: Vec<BackupFile> = Vec::new();
+    for relative in list_files(destination_path, retention.recursive)? {
+        let path = destination_path.join(&relative);
+        let (Some(name), Some(file_name)) = (
+            relative.to_str(),
+            relative.file_name().and_then(|n| n.to_str()),
+        ) else {
+            continue;
+        };
+        // 尝试将时间戳和月份解析为日期时间对象
+        let parse_month = |month: &str| {
+            NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap())
+        };
+        let (created, month, prefix, group, weekly, manifest_format) = if let Some(info) =
+            parse_archive_name(file_name)
+        {
+            // --since/--until 的归档不属于任何月份，由用户自行管理
+            if info.is_date_range() {
+                continue;
+            }
+            // 每周的归档以该周的周一作为月份
+            let weekly = info.month.as_bytes().get(5) == Some(&b'W');
+            // 共享目标目录的各个源分别去重和按 GFS 保留
+            let group = match &info.source {
+                Some(source) => Some(format!(
+                    "{}@{}",
+                    info.group.as_deref().unwrap_or_default(),
+                    source
+                )),
+                None => info.group.clone(),
+            };
+            (
+                info.created(),
+                info.period_start()
+                    .map(|start| start.and_hms_opt(0, 0, 0).unwrap()),
+                info.series(),
+                group,
+                weekly,
+                (!info.encrypted && info.part.is_none() && !info.skip_report)
+                    .then_some(info.format),
+            )
+        } else if let Some(caps) = retention
+            .extra_patterns
+            .iter()
+            .find_map(|pattern| pattern.captures(file_name))
+        {
+            // 其他工具的归档：没有分组，按月份去重时与同月的归档一起比较
+            let format = retention
+                .extra_timestamp_format
+                .as_deref()
+                .unwrap_or(TIMESTAMP_FORMAT);
+            let created = NaiveDateTime::parse_from_str(&caps["ts"], format).ok();
+            let month = match caps.name("month") {
+                Some(month) => parse_month(month.as_str()),
+                None => {
+                    created.and_then(|created| created.date().with_day(1)?.and_hms_opt(0, 0, 0))
+                }
+            };
+            let prefix = month.map_or_else(String::new, |month| month.format("%Y-%m").to_string());
+            (created, month, prefix, None, false, None)
+        } else {
+            continue;
+        };
+        match (created, month) {
+            (Some(created), Some(month)) => backups.push(BackupFile {
+                name: name.to_string(),
+                prefix,
+                group,
+                created,
+                month,
+                weekly,
+                size: fs::metadata(&path).map_or(0, |m| m.len()),
+                manifest_format,
+            }),
+            // 例如 "2024-13_backup_…" 或 "…_backup_20249999999999.zip"，报告而不是静默忽略
+            _ => report.skipped_unparsable.push(path),
+        }
+    }
+    backups.sort_by(|a, b| a.name.cmp(&b.name));
+    report.skipped_unparsable.sort();
+
+    // 增量归档依赖其全量归档和之前的增量归档：同一条链作为一个整体参与下面的每一步，
+    // 其创建时间为链中最新的归档的创建时间，大小为所有归档之和
+    let chains = archive_chains(destination_path, &backups, retention.password.as_deref());
+    let chain_of = |backup: &BackupFile| chains.get(&backup.name).unwrap_or(&backup.name).clone();
+    let mut units: Vec<BackupFile> = Vec::new();
+    let mut unit_index: HashMap<String, usize> = HashMap::new();
+    for backup in &backups {
+        let chain = chain_of(backup);
+        match unit_index.get(&chain) {
+            Some(&index) => {
+                let unit = &mut units[index];
+                unit.created = unit.created.max(backup.created);
+                unit.size += backup.size;
+            }
+            None => {
+                unit_index.insert(chain.clone(), units.len());
+                units.push(BackupFile {
+                    name: chain,
+                    ..backup.clone()
+                });
+            }
+        }
+    }
+
+    // 先按时间删除并去除重复的月份，再对剩下的归档应用大小配额；
+    // 指定了 keep_weeks 时每周的归档单独按周数删除
+    let mut to_delete: BTreeSet<String> = BTreeSet::new();
+    let by_months = |backup: &&BackupFile| retention.keep_weeks == 0 || !backup.weekly;
+    if gfs {
+        let keyed: Vec<(String, Option<String>, NaiveDate, NaiveDateTime)> = units
+            .iter()
+            .filter(by_months)
+            .map(|backup| {
+                (
+                    backup.name.clone(),
+                    backup.group.clone(),
+                    backup.month.date(),
+                    backup.created,
+                )
+            })
+            .collect();
+        to_delete.extend(
+            gfs_backups_to_delete(
+                &keyed,
+                now.date(),
+                retention.keep_months,
+                retention.gfs_monthly,
+                retention.gfs_yearly,
+            )
+            .into_iter()
+            .map(str::to_string),
+        );
+    } else if retention.keep_months > 0 {
+        let keyed: Vec<(String, NaiveDateTime)> = units
+            .iter()
+            .filter(by_months)
+            .map(|backup| match retention.retention_by {
+                RetentionBy::Created => (backup.name.clone(), backup.created),
+                RetentionBy::Month => (backup.name.clone(), backup.month),
+            })
+            .collect();
+        to_delete.extend(
+            backups_to_delete(&keyed, deadline, retention.keep_min)
+                .into_iter()
+                .map(str::to_string),
+        );
+    }
+    if retention.keep_weeks > 0 {
+        let keyed: Vec<(String, NaiveDateTime)> = units
+            .iter()
+            .filter(|backup| backup.weekly)
+            .map(|backup| match retention.retention_by {
+                RetentionBy::Created => (backup.name.clone(), backup.created),
+                RetentionBy::Month => (backup.name.clone(), backup.month),
+            })
+            .collect();
+        to_delete.extend(
+            backups_to_delete(&keyed, week_deadline, retention.keep_min)
+                .into_iter()
+                .map(str::to_string),
+        );
+    }
+    if retention.dedupe_months {
+        let keyed: Vec<(String, String, NaiveDateTime)> = units
+            .iter()
+            .map(|backup| (backup.name.clone(), backup.prefix.clone(), backup.created))
+            .collect();
+        to_delete.extend(
+            duplicate_month_backups(&keyed)
+                .into_iter()
+                .map(str::to_string),
+        );
+    }
+    if let Some(max_total_size) = retention.max_total_size {
+        let remaining: Vec<(String, NaiveDateTime, u64)> = units
+            .iter()
+            .filter(|backup| !to_delete.contains(&backup.name))
+            .map(|backup| (backup.name.clone(), backup.created, backup.size))
+            .collect();
+        to_delete.extend(
+            backups_over_quota(&remaining, max_total_size)
+                .into_iter()
+                .map(str::to_string),
         );
     }
 
-    // 正则表达式，用于匹配文件名并捕获时间戳
-    // 例如: "2024-12_backup_20250101123045.zip"
-    let re = Regex::new(r"^\d{4}-\d{2}_backup_(\d{14})\.zip$").unwrap();
+    let to_delete: BTreeSet<String> = backups
+        .iter()
+        .filter(|backup| to_delete.contains(&chain_of(backup)))
+        .map(|backup| backup.name.clone())
+        .collect();
 
-    for entry in fs::read_dir(destination_path)? {
-        let entry = entry?;
-        let path = entry.path();
+    // 先列出全部待删除的归档和要从回收站清除的文件，确认后再删除
+    let mut candidates: Vec<DeletionCandidate> = backups
+        .iter()
+        .filter(|backup| to_delete.contains(&backup.name))
+        .map(|backup| DeletionCandidate {
+            path: destination_path.join(&backup.name),
+            size: backup.size,
+            created: backup.created,
+            purge: false,
+        })
+        .collect();
+    candidates.extend(expired.iter().cloned());
+    let (to_delete, expired) = if !dry_run && !candidates.is_empty() && !confirm(&candidates) {
+        report.unconfirmed = candidates.len();
+        (BTreeSet::new(), Vec::new())
+    } else {
+        (to_delete, expired)
+    };
+    purge_trash(&expired, dry_run, &mut report)?;
 
-        if path.is_file() {
-            if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-                if let Some(caps) = re.captures(file_name) {
-                    if let Some(ts_match) = caps.get(1) {
-                        let ts_str = ts_match.as_str();
-                        // 尝试将时间戳字符串解析为日期时间对象
-                        if let Ok(file_timestamp_naive) =
-                            NaiveDateTime::parse_from_str(ts_str, "%Y%m%d%H%M%S")
-                        {
-                            let file_timestamp =
-                                file_timestamp_naive.and_local_timezone(Local).unwrap();
-
-                            // 如果文件的时间戳早于截止日期，则删除
-                            if file_timestamp < deadline {
-                                match fs::remove_file(&path) {
-                                    Ok(_) => {
-                                        if !silent {
-                                            println!("Removed old backup: {}", file_name)
-                                        }
-                                    }
-                                    Err(e) => {
-                                        if !silent {
-                                            eprintln!("Failed to remove {}: {}", file_name, e)
-                                        }
-                                    }
-                                }
-                            }
-                        }
+    for BackupFile {
+        name: file_name,
+        size,
+        ..
+    } in &backups
+    {
+        let path = destination_path.join(file_name);
+        if !to_delete.contains(file_name) {
+            report.kept.push(path);
+            continue;
+        }
+        if dry_run {
+            if retention.trash {
+                report.trashed += 1;
+            } else {
+                report.bytes_freed += size;
+            }
+            report.deleted.push(path);
+            continue;
+        }
+        // 只重试移动本身：移动成功后再次重试会因源文件已不存在而失败
+        let result = if retention.trash {
+            remove_with_retry(|| move_to_trash(&path, &destination_path.join(TRASH_DIR))).map(
+                |target| {
+                    if let Err(e) = mark_trashed(&target) {
+                        on_event(CleanupEvent::Warning(format!(
+                            "Moved {} to the trash, but could not update its modification time; \
+                             it may be purged earlier than expected: {}",
+                            file_name, e
+                        )));
                     }
+                },
+            )
+        } else {
+            remove_with_retry(|| fs::remove_file(&path))
+        };
+        match result {
+            Ok(_) => {
+                if retention.trash {
+                    report.trashed += 1;
+                } else {
+                    report.bytes_freed += size;
                 }
+                report.deleted.push(path);
             }
+            Err(e) => {
+                let message = removal_error_message(&e);
+                on_event(CleanupEvent::Warning(format!(
+                    "Failed to remove {}: {}",
+                    file_name, message
+                )));
+                report.failed.push((path, message));
+            }
+        }
+    }
+
+    // 删除因清理而变空的子目录
+    if retention.recursive && !dry_run {
+        let parents: BTreeSet<PathBuf> = report
+            .deleted
+            .iter()
+            .filter_map(|path| path.parent().map(Path::to_path_buf))
+            .collect();
+        // 从最深的目录开始，使父目录在子目录删除后也能被删除
+        for dir in parents.iter().rev() {
+            remove_empty_parents(destination_path, dir, &mut report);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Maps each archive of an incremental chain to the name of the chain's full
+/// archive, the `BaseArchive` in the manifests of its incremental archives.
+///
+/// Only series with more than one archive are read; archives whose manifest
+/// cannot be read (age encrypted, or a zip without the right password) stand alone.
+fn archive_chains(
+    destination_path: &Path,
+    backups: &[BackupFile],
+    password: Option<&str>,
+) -> HashMap<String, String> {
+    let mut series: HashMap<&str, usize> = HashMap::new();
+    for backup in backups {
+        *series.entry(&backup.prefix).or_default() += 1;
+    }
+    let mut chains = HashMap::new();
+    for backup in backups {
+        let Some(format) = backup.manifest_format else {
+            continue;
+        };
+        if series[backup.prefix.as_str()] < 2 {
+            continue;
+        }
+        let Ok(manifest) =
+            archiver::read_manifest(&destination_path.join(&backup.name), format, password)
+        else {
+            continue;
+        };
+        if manifest.kind != Some(ArchiveKind::Incremental) {
+            continue;
+        }
+        // 全量归档与增量归档位于同一目录
+        if let Some(base) = manifest.base_archive
+            && let Some(base) = Path::new(&backup.name).with_file_name(base).to_str()
+        {
+            chains.insert(backup.name.clone(), base.to_string());
+            chains.insert(base.to_string(), base.to_string());
+        }
+    }
+    // 被跳过文件列表随其归档所在的链一起保留或删除
+    for backup in backups {
+        if let Some(archive) = backup.name.strip_suffix(".skipped_files.txt")
+            && let Some(chain) = chains.get(archive).cloned()
+        {
+            chains.insert(backup.name.clone(), chain);
         }
     }
+    chains
+}
+
+/// Removes leftover `*.partial` files from interrupted runs.
+///
+/// Only files last modified more than `max_age` ago are removed, so an archive
+/// that another instance is still writing is left alone.
+///
+/// # Arguments
+/// * `destination_path` - The directory where backup archives are stored.
+/// * `max_age` - Minimum age of a `.partial` file before it is considered stale.
+/// * `on_event` - Receives the removed and failed files.
+pub fn remove_stale_partials(
+    destination_path: &Path,
+    max_age: std::time::Duration,
+    on_event: &dyn Fn(CleanupEvent),
+) -> Result<(), BackupError> {
+    remove_partials_older_than(destination_path, max_age, on_event)
+        .map_err(cleanup_error(destination_path))
+}
+
+/// The body of [`remove_stale_partials`], returning the underlying I/O error.
+fn remove_partials_older_than(
+    destination_path: &Path,
+    max_age: std::time::Duration,
+    on_event: &dyn Fn(CleanupEvent),
+) -> io::Result<()> {
+    let now = SystemTime::now();
+
+    for entry in fs::read_dir(destination_path)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if !file_name.ends_with(crate::archiver::PARTIAL_SUFFIX) || !path.is_file() {
+            continue;
+        }
+        // 修改时间无法读取或晚于当前时间时视为仍在写入
+        let age = entry
+            .metadata()?
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok());
+        if age.is_none_or(|age| age < max_age) {
+            continue;
+        }
+
+        on_event(match fs::remove_file(&path) {
+            Ok(_) => CleanupEvent::Info(format!("Removed stale partial archive: {}", file_name)),
+            Err(e) => CleanupEvent::Warning(format!("Failed to remove {}: {}", file_name, e)),
+        });
+    }
+
+    Ok(())
+}
+
+/// Removes UUID-named temporary directories left in the destination by
+/// crashed runs of older versions, which staged files there before zipping.
+///
+/// Like [`remove_stale_partials`], only directories last modified more than
+/// `max_age` ago are removed.
+///
+/// # Arguments
+/// * `destination_path` - The directory where backup archives are stored.
+/// * `max_age` - Minimum age of a directory before it is considered stale.
+/// * `on_event` - Receives the removed and failed directories.
+pub fn remove_stale_temp_dirs(
+    destination_path: &Path,
+    max_age: std::time::Duration,
+    on_event: &dyn Fn(CleanupEvent),
+) -> Result<(), BackupError> {
+    remove_temp_dirs_older_than(destination_path, max_age, on_event)
+        .map_err(cleanup_error(destination_path))
+}
+
+/// The body of [`remove_stale_temp_dirs`], returning the underlying I/O error.
+fn remove_temp_dirs_older_than(
+    destination_path: &Path,
+    max_age: std::time::Duration,
+    on_event: &dyn Fn(CleanupEvent),
+) -> io::Result<()> {
+    let now = SystemTime::now();
+    for entry in fs::read_dir(destination_path)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let Some(dir_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        // 旧版本使用 `Uuid::new_v4().to_string()` 作为临时目录名（小写、带连字符）
+        if !uuid::Uuid::try_parse(dir_name).is_ok_and(|id| id.hyphenated().to_string() == dir_name)
+        {
+            continue;
+        }
+        let age = entry
+            .metadata()?
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok());
+        if age.is_none_or(|age| age < max_age) {
+            continue;
+        }
+
+        on_event(match fs::remove_dir_all(&path) {
+            Ok(_) => CleanupEvent::Info(format!("Removed stale temporary directory: {}", dir_name)),
+            Err(e) => CleanupEvent::Warning(format!("Failed to remove {}: {}", dir_name, e)),
+        });
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn only_stale_partial_files_are_removed() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let stale = dir.join("2025-07_backup_20250801000000.zip.partial");
+        let fresh = dir.join("2025-08_backup_20250901000000.zip.partial");
+        let archive = dir.join("2025-06_backup_20250701000000.zip");
+        for path in [&stale, &fresh, &archive] {
+            fs::write(path, b"data").unwrap();
+        }
+        let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+        for path in [&stale, &archive] {
+            filetime::set_file_mtime(path, filetime::FileTime::from_system_time(two_days_ago))
+                .unwrap();
+        }
+
+        remove_stale_partials(&dir, Duration::from_secs(24 * 60 * 60), &|_| {}).unwrap();
+
+        assert!(!stale.exists());
+        assert!(fresh.exists());
+        assert!(archive.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn timestamp(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M%S").unwrap()
+    }
+
+    fn retention(keep_months: u32, retention_by: RetentionBy) -> RetentionOptions {
+        RetentionOptions {
+            keep_months,
+            retention_by,
+            ..Default::default()
+        }
+    }
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn created_deadline_uses_calendar_months() {
+        assert_eq!(
+            created_deadline(timestamp("20250715120000"), 6),
+            timestamp("20250115120000")
+        );
+        // 跨年
+        assert_eq!(
+            created_deadline(timestamp("20250115000000"), 1),
+            timestamp("20241215000000")
+        );
+        // 目标月份没有这一天时取该月最后一天
+        assert_eq!(
+            created_deadline(timestamp("20250331235959"), 1),
+            timestamp("20250228235959")
+        );
+        assert_eq!(
+            created_deadline(timestamp("20240330000000"), 1),
+            timestamp("20240229000000")
+        );
+        assert_eq!(
+            created_deadline(timestamp("20250831000000"), 6),
+            timestamp("20250228000000")
+        );
+        // 闰年的 2 月有 29 天；8 月 31 日运行时 3 月 2 日的归档仍在 6 个月内
+        assert_eq!(
+            created_deadline(timestamp("20240831000000"), 6),
+            timestamp("20240229000000")
+        );
+        let backups = vec![("march-2nd.zip".to_string(), timestamp("20250302000000"))];
+        assert!(
+            backups_to_delete(
+                &backups,
+                created_deadline(timestamp("20250831000000"), 6),
+                0
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn month_deadline_is_the_first_day_of_the_oldest_kept_month() {
+        let first_day = |s: &str| date(s).and_hms_opt(0, 0, 0).unwrap();
+        assert_eq!(
+            month_deadline(date("2025-07-15"), 6),
+            first_day("2025-01-01")
+        );
+        assert_eq!(
+            month_deadline(date("2025-07-01"), 6),
+            first_day("2025-01-01")
+        );
+        assert_eq!(
+            month_deadline(date("2025-07-31"), 6),
+            first_day("2025-01-01")
+        );
+        assert_eq!(
+            month_deadline(date("2025-08-01"), 6),
+            first_day("2025-02-01")
+        );
+        assert_eq!(
+            month_deadline(date("2025-08-31"), 6),
+            first_day("2025-02-01")
+        );
+        assert_eq!(
+            month_deadline(date("2025-03-31"), 1),
+            first_day("2025-02-01")
+        );
+        assert_eq!(
+            month_deadline(date("2024-03-31"), 1),
+            first_day("2024-02-01")
+        );
+        assert_eq!(
+            month_deadline(date("2025-02-28"), 2),
+            first_day("2024-12-01")
+        );
+        assert_eq!(
+            month_deadline(date("2025-07-15"), 0),
+            first_day("2025-07-01")
+        );
+    }
+
+    #[test]
+    fn week_deadline_is_the_monday_of_the_oldest_kept_week() {
+        let monday = |s: &str| date(s).and_hms_opt(0, 0, 0).unwrap();
+        // 2025-07-30 是周三，所在的周从 2025-07-28 开始
+        assert_eq!(week_deadline(date("2025-07-30"), 0), monday("2025-07-28"));
+        assert_eq!(week_deadline(date("2025-07-30"), 2), monday("2025-07-14"));
+        assert_eq!(week_deadline(date("2025-07-28"), 2), monday("2025-07-14"));
+        assert_eq!(week_deadline(date("2025-08-03"), 2), monday("2025-07-14"));
+        // 跨年
+        assert_eq!(week_deadline(date("2025-01-08"), 2), monday("2024-12-23"));
+        assert_eq!(
+            created_week_deadline(timestamp("20250730120000"), 2),
+            timestamp("20250716120000")
+        );
+    }
+
+    #[test]
+    fn weekly_backups_are_aged_out_by_keep_weeks() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = Local::now().naive_local();
+        let created = |days: u64| (now - Days::new(days)).format(TIMESTAMP_FORMAT).to_string();
+        let names = [
+            format!("2025-W01_backup_{}.zip", created(21)),
+            format!("2025-W02_backup_{}.zip", created(1)),
+            format!("2025-01_backup_{}.zip", created(21)),
+        ];
+        for name in &names {
+            fs::write(dir.join(name), b"data").unwrap();
+        }
+
+        // 每周的归档按周数删除，每月的归档仍按月数保留
+        let options = RetentionOptions {
+            keep_weeks: 2,
+            ..retention(6, RetentionBy::Created)
+        };
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        assert_eq!(report.deleted, [dir.join(&names[0])]);
+        assert!(dir.join(&names[1]).exists());
+        assert!(dir.join(&names[2]).exists());
+
+        // 不指定 keep_weeks 时每周的归档同样按月数保留
+        let report = cleanup_old_backups(
+            &dir,
+            &retention(6, RetentionBy::Created),
+            true,
+            &|_| {},
+            &|_| true,
+        )
+        .unwrap();
+        assert!(report.deleted.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn date_range_backups_are_never_deleted() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("2020-06-15_to_2020-09-01_backup_20200901000000.zip");
+        let monthly = "2020-06_backup_20200701000000.zip";
+        for path in [&old, &dir.join(monthly)] {
+            fs::write(path, b"data").unwrap();
+        }
+
+        for by in [RetentionBy::Created, RetentionBy::Month] {
+            let report =
+                cleanup_old_backups(&dir, &retention(1, by), true, &|_| {}, &|_| true).unwrap();
+            assert_eq!(report.deleted, [dir.join(monthly)]);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn month_retention_ignores_the_creation_time() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        // 刚刚创建的旧月份归档，以及很久以前创建的本月归档
+        let now = Local::now();
+        let old_month = (now.date_naive() - Months::new(12)).format("%Y-%m");
+        let recent = dir.join(format!(
+            "{}_backup_{}.zip",
+            old_month,
+            now.format("%Y%m%d%H%M%S")
+        ));
+        let current = dir.join(format!("{}_backup_20000101000000.zip", now.format("%Y-%m")));
+        for path in [&recent, &current] {
+            fs::write(path, b"data").unwrap();
+        }
+
+        cleanup_old_backups(
+            &dir,
+            &retention(6, RetentionBy::Month),
+            false,
+            &|_| {},
+            &|_| true,
+        )
+        .unwrap();
+        assert!(!recent.exists());
+        assert!(current.exists());
+        cleanup_old_backups(
+            &dir,
+            &retention(6, RetentionBy::Created),
+            false,
+            &|_| {},
+            &|_| true,
+        )
+        .unwrap();
+        assert!(!current.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn newest_archives_are_kept_even_if_all_are_old() {
+        let backups: Vec<(String, NaiveDateTime)> = [
+            "2024-01_backup_20240201000000.zip",
+            "2024-02_backup_20240301000000.part1.zip",
+            "2024-02_backup_20240301000000.part2.zip",
+            "2024-03_backup_20240401000000.zip",
+            "2024-03_backup_20240401000000.zip.skipped_files.txt",
+        ]
+        .iter()
+        .map(|name| {
+            let ts = name.split("_backup_").nth(1).unwrap();
+            (name.to_string(), timestamp(&ts[..14]))
+        })
+        .collect();
+        let deadline = timestamp("20250101000000");
+
+        // 所有归档都早于截止日期：只删除最新的 keep_min 个归档之外的，
+        // 同一时间戳的分卷和被跳过文件列表作为一个归档整体保留
+        assert_eq!(
+            backups_to_delete(&backups, deadline, 1),
+            vec![
+                "2024-01_backup_20240201000000.zip",
+                "2024-02_backup_20240301000000.part1.zip",
+                "2024-02_backup_20240301000000.part2.zip",
+            ]
+        );
+        assert_eq!(
+            backups_to_delete(&backups, deadline, 2),
+            vec!["2024-01_backup_20240201000000.zip"]
+        );
+        assert!(backups_to_delete(&backups, deadline, 5).is_empty());
+        assert_eq!(backups_to_delete(&backups, deadline, 0).len(), 5);
+
+        // 截止日期之后的归档本来就不会删除，也计入保留的数量
+        let deadline = timestamp("20240315000000");
+        assert_eq!(
+            backups_to_delete(&backups, deadline, 1),
+            vec![
+                "2024-01_backup_20240201000000.zip",
+                "2024-02_backup_20240301000000.part1.zip",
+                "2024-02_backup_20240301000000.part2.zip",
+            ]
+        );
+        assert_eq!(
+            backups_to_delete(&backups, deadline, 2),
+            vec!["2024-01_backup_20240201000000.zip"]
+        );
+        assert!(backups_to_delete(&[], deadline, 1).is_empty());
+    }
+
+    fn sized(backups: &[(&str, u64)]) -> Vec<(String, NaiveDateTime, u64)> {
+        backups
+            .iter()
+            .map(|(name, size)| {
+                let ts = name.split("_backup_").nth(1).unwrap();
+                (name.to_string(), timestamp(&ts[..14]), *size)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn oldest_archives_are_deleted_until_the_quota_fits() {
+        let backups = sized(&[
+            ("2025-01_backup_20250201000000.zip", 40),
+            ("2025-02_backup_20250301000000.part1.zip", 30),
+            ("2025-02_backup_20250301000000.part2.zip", 30),
+            ("2025-03_backup_20250401000000.zip", 50),
+        ]);
+        // 总共 150 字节，已在配额之内
+        assert!(backups_over_quota(&backups, 150).is_empty());
+        assert_eq!(
+            backups_over_quota(&backups, 149),
+            vec!["2025-01_backup_20250201000000.zip"]
+        );
+        assert_eq!(
+            backups_over_quota(&backups, 110),
+            vec!["2025-01_backup_20250201000000.zip"]
+        );
+        // 分卷作为一个归档整体删除
+        assert_eq!(
+            backups_over_quota(&backups, 109),
+            vec![
+                "2025-01_backup_20250201000000.zip",
+                "2025-02_backup_20250301000000.part1.zip",
+                "2025-02_backup_20250301000000.part2.zip",
+            ]
+        );
+        assert_eq!(backups_over_quota(&backups, 50).len(), 3);
+        assert!(backups_over_quota(&[], 0).is_empty());
+    }
+
+    #[test]
+    fn newest_archive_is_kept_even_if_it_exceeds_the_quota() {
+        let backups = sized(&[
+            ("2025-01_backup_20250201000000.zip", 40),
+            ("2025-02_backup_20250301000000.zip", 500),
+        ]);
+        assert_eq!(
+            backups_over_quota(&backups, 100),
+            vec!["2025-01_backup_20250201000000.zip"]
+        );
+        assert_eq!(backups_over_quota(&backups, 0).len(), 1);
+        let single = sized(&[("2025-02_backup_20250301000000.zip", 500)]);
+        assert!(backups_over_quota(&single, 0).is_empty());
+    }
+
+    #[test]
+    fn quota_applies_after_the_age_based_pass() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = Local::now();
+        let name = |months_ago: u32| {
+            let time = now - Months::new(months_ago);
+            dir.join(format!(
+                "{}_backup_{}.zip",
+                time.format("%Y-%m"),
+                time.format("%Y%m%d%H%M%S")
+            ))
+        };
+        let (expired, older, newer, newest) = (name(12), name(3), name(2), name(1));
+        for path in [&expired, &older, &newer, &newest] {
+            fs::write(path, [0u8; 100]).unwrap();
+        }
+
+        let options = RetentionOptions {
+            max_total_size: Some(250),
+            ..retention(6, RetentionBy::Created)
+        };
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        // 过期的归档先按时间删除，剩下 300 字节，再删除最旧的一个
+        assert_eq!(report.deleted, {
+            let mut deleted = vec![expired.clone(), older.clone()];
+            deleted.sort();
+            deleted
+        });
+        assert_eq!(report.bytes_freed, 200);
+        assert!(newer.exists() && newest.exists());
+
+        // 只设置配额时不按时间删除
+        let options = RetentionOptions {
+            max_total_size: Some(100),
+            ..Default::default()
+        };
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        assert_eq!(report.deleted, vec![newer]);
+        assert!(newest.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_the_newest_archive_of_each_month_is_kept() {
+        let backups: Vec<(String, String, NaiveDateTime)> = [
+            "2025-06_backup_20250630120000.zip",
+            "2025-07_backup_20250710000000.zip",
+            "2025-07_backup_20250720000000.part1.zip",
+            "2025-07_backup_20250720000000.part2.zip",
+            "2025-07_backup_20250731000000.part1.zip",
+            "2025-07_backup_20250731000000.part2.zip",
+            "2025-07_backup_20250731000000.part3.zip",
+            "2025-07_backup_20250731000000.part1.zip.skipped_files.txt",
+            // 不同账号的同月归档分别保留最新的一个
+            "2025-07_wxid_a_backup_20250701000000.zip",
+        ]
+        .iter()
+        .map(|name| {
+            let (prefix, rest) = name.split_once("_backup_").unwrap();
+            (name.to_string(), prefix.to_string(), timestamp(&rest[..14]))
+        })
+        .collect();
+
+        assert_eq!(
+            duplicate_month_backups(&backups),
+            vec![
+                "2025-07_backup_20250710000000.zip",
+                "2025-07_backup_20250720000000.part1.zip",
+                "2025-07_backup_20250720000000.part2.zip",
+            ]
+        );
+        assert!(duplicate_month_backups(&backups[..1]).is_empty());
+        assert!(duplicate_month_backups(&[]).is_empty());
+    }
+
+    #[test]
+    fn gfs_keeps_recent_monthly_and_yearly_archives() {
+        let backups: Vec<(String, Option<String>, NaiveDate, NaiveDateTime)> = [
+            // 最近两个月全部保留
+            "2026-10_backup_20261001000000.zip",
+            "2026-10_backup_20261010000000.zip",
+            "2026-09_backup_20260905000000.zip",
+            "2026-09_backup_20260920000000.zip",
+            // 最近 12 个月每月保留最新的一个
+            "2026-08_backup_20260815000000.zip",
+            "2026-08_backup_20260901000000.zip",
+            "2025-11_backup_20251115000000.zip",
+            "2025-11_backup_20251201000000.part1.zip",
+            "2025-11_backup_20251201000000.part2.zip",
+            // 更早的每年保留最新的一个
+            "2025-03_backup_20250401000000.zip",
+            "2025-10_backup_20251101000000.zip",
+            "2024-06_backup_20240701000000.zip",
+            "2024-12_backup_20250101000000.zip",
+            "2023-05_backup_20230601000000.zip",
+            // 不同账号分别保留
+            "2023-02_wxid_a_backup_20230301000000.zip",
+        ]
+        .iter()
+        .map(|name| {
+            let info = parse_archive_name(name).unwrap();
+            (
+                name.to_string(),
+                info.group,
+                date(&format!("{}-01", info.month)),
+                timestamp(&info.timestamp),
+            )
+        })
+        .collect();
+        let today = date("2026-10-14");
+
+        assert_eq!(
+            gfs_backups_to_delete(&backups, today, 2, 12, None),
+            vec![
+                "2026-08_backup_20260815000000.zip",
+                "2025-11_backup_20251115000000.zip",
+                "2025-03_backup_20250401000000.zip",
+                "2024-06_backup_20240701000000.zip",
+            ]
+        );
+        assert_eq!(
+            gfs_backups_to_delete(&backups, today, 2, 12, Some(3)),
+            vec![
+                "2026-08_backup_20260815000000.zip",
+                "2025-11_backup_20251115000000.zip",
+                "2025-03_backup_20250401000000.zip",
+                "2024-06_backup_20240701000000.zip",
+                "2023-05_backup_20230601000000.zip",
+                "2023-02_wxid_a_backup_20230301000000.zip",
+            ]
+        );
+        // 不按月保留时 2026-09 和 2025-11 的分卷归档分别是当年最新的归档
+        assert_eq!(
+            gfs_backups_to_delete(&backups, today, 1, 0, Some(2)),
+            vec![
+                "2026-09_backup_20260905000000.zip",
+                "2026-08_backup_20260815000000.zip",
+                "2026-08_backup_20260901000000.zip",
+                "2025-11_backup_20251115000000.zip",
+                "2025-03_backup_20250401000000.zip",
+                "2025-10_backup_20251101000000.zip",
+                "2024-06_backup_20240701000000.zip",
+                "2024-12_backup_20250101000000.zip",
+                "2023-05_backup_20230601000000.zip",
+                "2023-02_wxid_a_backup_20230301000000.zip",
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_months_are_removed_regardless_of_age() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = Local::now();
+        let month = now.format("%Y-%m");
+        let names = [
+            format!("{}_backup_20000101000000.zip", month),
+            format!("{}_backup_20000102000000.zip", month),
+            format!("{}_backup_{}.zip", month, now.format("%Y%m%d%H%M%S")),
+            "2020-01_backup_20200201000000.zip".to_string(),
+        ];
+        for name in &names {
+            fs::write(dir.join(name), b"data").unwrap();
+        }
+
+        let options = RetentionOptions {
+            dedupe_months: true,
+            ..Default::default()
+        };
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        let mut deleted = vec![dir.join(&names[0]), dir.join(&names[1])];
+        deleted.sort();
+        assert_eq!(report.deleted, deleted);
+        assert!(dir.join(&names[2]).exists());
+        // 另一个月份唯一的归档即使很旧也保留
+        assert!(dir.join(&names[3]).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn incremental_chains_are_kept_or_removed_as_a_whole() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let older = "2025-07_backup_20250701000000.zip";
+        let full = "2025-07_backup_20250702000000.zip";
+        let incremental = "2025-07_backup_20250703000000.zip";
+        fs::write(dir.join(older), b"data").unwrap();
+        for (name, manifest) in [
+            (
+                full,
+                archiver::Manifest {
+                    kind: Some(ArchiveKind::Full),
+                    ..Default::default()
+                },
+            ),
+            (
+                incremental,
+                archiver::Manifest {
+                    kind: Some(ArchiveKind::Incremental),
+                    base_archive: Some(full.to_string()),
+                    ..Default::default()
+                },
+            ),
+        ] {
+            let mut zip = zip::ZipWriter::new(File::create(dir.join(name)).unwrap());
+            zip.start_file(
+                archiver::MANIFEST_NAME,
+                zip::write::SimpleFileOptions::default(),
+            )
+            .unwrap();
+            serde_json::to_writer(&mut zip, &manifest).unwrap();
+            zip.finish().unwrap();
+        }
+
+        // 全量归档不是该月最新的归档，但之后的增量归档依赖它
+        let options = RetentionOptions {
+            dedupe_months: true,
+            ..Default::default()
+        };
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        assert_eq!(report.deleted, [dir.join(older)]);
+        assert!(dir.join(full).exists());
+
+        // 超出配额时整条链一起删除
+        fs::write(dir.join("2025-08_backup_20250801000000.zip"), b"data").unwrap();
+        let options = RetentionOptions {
+            max_total_size: Some(4),
+            ..Default::default()
+        };
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        assert_eq!(report.deleted, [dir.join(full), dir.join(incremental)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn year_subdirectories_are_cleaned_recursively() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        let now = Local::now();
+        let recent = format!(
+            "{}/{}_backup_{}.zip",
+            now.format("%Y"),
+            now.format("%Y-%m"),
+            now.format("%Y%m%d%H%M%S")
+        );
+        let files = [
+            "2023-12_backup_20240101000000.zip",
+            "2024/2024-01_backup_20240201000000.zip",
+            "2024/2024-02_backup_20240301000000.part1.zip",
+            "2025/2025-01_backup_20250201000000.zip",
+            "2025/notes.txt",
+            ".trash/2019-01_backup_20190201000000.zip",
+            ".cache/2019-02_backup_20190301000000.zip",
+            &recent,
+        ];
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, b"data").unwrap();
+        }
+        let mut options = retention(6, RetentionBy::Created);
+
+        let report = cleanup_old_backups(&dir, &options, true, &|_| {}, &|_| true).unwrap();
+        assert_eq!(
+            report.deleted,
+            vec![dir.join("2023-12_backup_20240101000000.zip")]
+        );
+
+        options.recursive = true;
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        assert_eq!(
+            report.deleted,
+            [
+                "2023-12_backup_20240101000000.zip",
+                "2024/2024-01_backup_20240201000000.zip",
+                "2024/2024-02_backup_20240301000000.part1.zip",
+                "2025/2025-01_backup_20250201000000.zip",
+            ]
+            .map(|file| dir.join(file))
+        );
+        assert_eq!(report.kept, vec![dir.join(&recent)]);
+        // 只删除变空的目录
+        assert_eq!(report.removed_dirs, vec![dir.join("2024")]);
+        assert!(dir.join("2025").join("notes.txt").exists());
+        assert!(
+            dir.join(".trash/2019-01_backup_20190201000000.zip")
+                .exists()
+        );
+        assert!(
+            dir.join(".cache/2019-02_backup_20190301000000.zip")
+                .exists()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn archives_matching_extra_patterns_are_rotated() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = Local::now();
+        let old = dir.join("WeChatBackup-2024-11-20241130_235901.zip");
+        let recent = dir.join(format!(
+            "WeChatBackup-{}.zip",
+            now.format("%Y-%m-%Y%m%d_%H%M%S")
+        ));
+        let bad_timestamp = dir.join("WeChatBackup-2024-11-20241399_235901.zip");
+        let unrelated = dir.join("WeChatBackup-notes.zip");
+        for path in [&old, &recent, &bad_timestamp, &unrelated] {
+            fs::write(path, b"data").unwrap();
+        }
+        let options = RetentionOptions {
+            extra_patterns: vec![
+                Regex::new(r"^WeChatBackup-(?P<month>\d{4}-\d{2})-(?P<ts>\d{8}_\d{6})\.zip$")
+                    .unwrap(),
+            ],
+            extra_timestamp_format: Some("%Y%m%d_%H%M%S".to_string()),
+            ..retention(6, RetentionBy::Month)
+        };
+
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        assert_eq!(report.deleted, vec![old.clone()]);
+        assert_eq!(report.kept, vec![recent.clone()]);
+        assert_eq!(report.skipped_unparsable, vec![bad_timestamp.clone()]);
+        assert!(unrelated.exists());
+
+        // 没有 month 捕获组时使用时间戳的月份
+        let options = RetentionOptions {
+            extra_patterns: vec![
+                Regex::new(r"^WeChatBackup-.*-(?P<ts>\d{8}_\d{6})\.zip$").unwrap(),
+            ],
+            extra_timestamp_format: Some("%Y%m%d_%H%M%S".to_string()),
+            dedupe_months: true,
+            ..retention(0, RetentionBy::Month)
+        };
+        let legacy = dir.join("WeChatBackup-2024-11-20241201_000000.zip");
+        let native = dir.join("2024-12_backup_20241230000000.zip");
+        fs::write(&legacy, b"data").unwrap();
+        fs::write(&native, b"data").unwrap();
+        let report = cleanup_old_backups(&dir, &options, true, &|_| {}, &|_| true).unwrap();
+        assert_eq!(report.deleted, vec![legacy]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_removals_are_retried_and_reported() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("2020-01_backup_20200201000000.zip");
+        fs::write(&old, b"data").unwrap();
+        // 回收站的位置被一个文件占用，移入回收站总是失败
+        fs::write(dir.join(TRASH_DIR), b"").unwrap();
+        let options = RetentionOptions {
+            trash: true,
+            ..retention(6, RetentionBy::Created)
+        };
+
+        let started = std::time::Instant::now();
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        assert!(started.elapsed() >= REMOVE_RETRY_DELAY * (REMOVE_ATTEMPTS - 1));
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, old);
+        assert!(report.deleted.is_empty() && report.kept.is_empty());
+        assert!(old.exists());
+
+        let mut attempts = 0;
+        let result = remove_with_retry(|| {
+            attempts += 1;
+            if attempts < 2 {
+                Err(io::Error::other("transient"))
+            } else {
+                Ok(())
+            }
+        });
+        assert!(result.is_ok());
+        assert_eq!(attempts, 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn trashed_backups_are_moved_and_purged_later() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        let trash = dir.join(TRASH_DIR);
+        fs::create_dir_all(&trash).unwrap();
+        let name = "2020-01_backup_20200201000000.part1.zip";
+        fs::write(dir.join(name), b"new").unwrap();
+        // 回收站中已有同名文件，以及一个很久以前移入的文件
+        fs::write(trash.join(name), b"older").unwrap();
+        let expired = trash.join("2019-01_backup_20190201000000.zip");
+        fs::write(&expired, b"expired").unwrap();
+        let long_ago = SystemTime::now() - Duration::from_secs(40 * 24 * 60 * 60);
+        filetime::set_file_mtime(&expired, filetime::FileTime::from_system_time(long_ago)).unwrap();
+
+        let options = RetentionOptions {
+            trash: true,
+            purge_trash_days: Some(30),
+            ..retention(6, RetentionBy::Created)
+        };
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        assert_eq!(report.deleted, vec![dir.join(name)]);
+        assert_eq!(report.trashed, 1);
+        assert_eq!(report.purged, vec![expired.clone()]);
+        // 只有真正删除的回收站文件释放了空间
+        assert_eq!(report.bytes_freed, 7);
+        assert!(!dir.join(name).exists());
+        assert!(!expired.exists());
+        assert_eq!(fs::read(trash.join(name)).unwrap(), b"older");
+        let renamed = trash.join("2020-01_backup_20200201000000 (1).part1.zip");
+        assert_eq!(fs::read(&renamed).unwrap(), b"new");
+
+        // 移入回收站的时间从现在算起，不会被立即清除
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        assert!(report.purged.is_empty());
+        assert!(renamed.exists());
+
+        // 清除回收站同样需要确认
+        filetime::set_file_mtime(&renamed, filetime::FileTime::from_system_time(long_ago)).unwrap();
+        let options = RetentionOptions {
+            purge_trash_days: Some(30),
+            ..Default::default()
+        };
+        let asked = std::cell::RefCell::new(Vec::new());
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|candidates| {
+            asked.borrow_mut().extend_from_slice(candidates);
+            false
+        })
+        .unwrap();
+        let asked = asked.into_inner();
+        assert_eq!(asked.len(), 1);
+        assert_eq!(asked[0].path, renamed);
+        assert!(asked[0].purge);
+        assert_eq!(report.unconfirmed, 1);
+        assert!(report.purged.is_empty());
+        assert!(renamed.exists());
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|_| true).unwrap();
+        assert_eq!(report.purged, vec![renamed.clone()]);
+        assert!(!renamed.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn old_backups_are_kept_in_dry_run() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("2020-01_backup_20200201000000.zip");
+        fs::write(&old, b"data").unwrap();
+
+        let expected = CleanupReport {
+            deleted: vec![old.clone()],
+            kept: Vec::new(),
+            skipped_unparsable: Vec::new(),
+            bytes_freed: 4,
+            ..Default::default()
+        };
+        let report = cleanup_old_backups(
+            &dir,
+            &retention(6, RetentionBy::Created),
+            true,
+            &|_| {},
+            &|_| true,
+        )
+        .unwrap();
+        assert_eq!(report, expected);
+        assert!(old.exists());
+        let report = cleanup_old_backups(
+            &dir,
+            &retention(6, RetentionBy::Created),
+            false,
+            &|_| {},
+            &|_| true,
+        )
+        .unwrap();
+        assert_eq!(report, expected);
+        assert!(!old.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nothing_is_removed_unless_confirmed() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("2020-01_backup_20200201000000.zip");
+        let older = dir.join("2019-12_backup_20200101000000.zip");
+        fs::write(&old, b"data").unwrap();
+        fs::write(&older, b"older data").unwrap();
+        let options = retention(6, RetentionBy::Created);
+
+        let asked = std::cell::RefCell::new(Vec::new());
+        let report = cleanup_old_backups(&dir, &options, false, &|_| {}, &|candidates| {
+            asked.borrow_mut().extend_from_slice(candidates);
+            false
+        })
+        .unwrap();
+        assert_eq!(
+            asked.into_inner(),
+            vec![
+                DeletionCandidate {
+                    path: older.clone(),
+                    size: 10,
+                    created: timestamp("20200101000000"),
+                    purge: false,
+                },
+                DeletionCandidate {
+                    path: old.clone(),
+                    size: 4,
+                    created: timestamp("20200201000000"),
+                    purge: false,
+                },
+            ]
+        );
+        assert_eq!(report.unconfirmed, 2);
+        assert_eq!(report.kept, vec![older.clone(), old.clone()]);
+        assert!(report.deleted.is_empty());
+        assert!(old.exists() && older.exists());
+
+        // 演练时不询问
+        let report =
+            cleanup_old_backups(&dir, &options, true, &|_| {}, &|_| unreachable!()).unwrap();
+        assert_eq!(report.deleted.len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn kept_and_unparsable_backups_are_reported() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("2020-01_backup_20200201000000.zip");
+        let recent = dir.join(format!(
+            "{}_backup_{}.zip",
+            Local::now().format("%Y-%m"),
+            Local::now().format("%Y%m%d%H%M%S")
+        ));
+        let bad_timestamp = dir.join("2020-01_backup_20201399000000.zip");
+        let bad_month = dir.join("2020-13_backup_20200201000000.zip");
+        for path in [&old, &recent, &bad_timestamp, &bad_month] {
+            fs::write(path, b"data").unwrap();
+        }
+
+        // 时间戳和月份都必须有效，否则无论按哪种方式保留都不会删除
+        for retention_by in [RetentionBy::Created, RetentionBy::Month] {
+            let report =
+                cleanup_old_backups(&dir, &retention(6, retention_by), true, &|_| {}, &|_| true)
+                    .unwrap();
+            assert_eq!(report.deleted, vec![old.clone()]);
+            assert_eq!(report.kept, vec![recent.clone()]);
+            assert_eq!(
+                report.skipped_unparsable,
+                vec![bad_timestamp.clone(), bad_month.clone()]
+            );
+            assert_eq!(report.bytes_freed, 4);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn uuid_named_directories_are_removed() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-cleaner-{}", uuid::Uuid::new_v4()));
+        let stale = dir.join(uuid::Uuid::new_v4().to_string());
+        let cache = dir.join(".cache");
+        fs::create_dir_all(stale.join("Msg")).unwrap();
+        fs::write(stale.join("Msg").join("partial.dat"), b"data").unwrap();
+        fs::create_dir_all(&cache).unwrap();
+        // 名称形如 UUID 的普通文件不受影响
+        let file = dir.join(uuid::Uuid::new_v4().to_string());
+        fs::write(&file, b"data").unwrap();
+
+        remove_stale_temp_dirs(&dir, Duration::ZERO, &|_| {}).unwrap();
+
+        assert!(!stale.exists());
+        assert!(cache.exists());
+        assert!(file.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
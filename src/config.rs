@@ -0,0 +1,685 @@
+//! 配置文件（--config）：从 TOML 文件读取命令行选项的值，命令行上给出的选项优先
+//!
+//! 文件按 [`SECTIONS`] 分节，键与命令行的长选项同名，例如：
+//!
+//! ```toml
+//! [paths]
+//! from = ["D:\\WeChat Files"]
+//! to = "E:\\Backups"
+//!
+//! [mode]
+//! mode = "dynamic"
+//!
+//! [retention]
+//! keep-months = 12
+//! ```
+//!
+//! 文件中的值被转换为命令行参数，放在实际的命令行参数之前交给 clap 解析，
+//! 因此得到的是同一个参数结构，校验和默认值与命令行完全相同。
+//! 优先级为：默认值 < 配置文件 < 命令行。
+
+use crate::error::BackupError;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 未指定 --config 时查找的文件名，依次位于可执行文件所在目录和用户配置目录中
+pub const CONFIG_FILE_NAME: &str = "dat-patch.toml";
+
+/// 配置文件的节和每节中可用的键
+///
+/// `[mode]` 中的 `mode` 是 `"previous"`、`"current"` 或 `"dynamic"`，对应 -p、-n、-d；
+/// `[run]` 中的 `silent` 对应 -s。其他键与长选项同名。
+pub const SECTIONS: &[(&str, &[&str])] = &[
+    (
+        "paths",
+        &[
+            "from",
+            "from-label",
+            "source-label",
+            "to",
+            "subdir",
+            "cache-dir",
+            "log-file",
+        ],
+    ),
+    (
+        "mode",
+        &[
+            "mode",
+            "month",
+            "months-back",
+            "catch-up",
+            "since",
+            "until",
+            "as-of",
+            "weekly",
+            "dynamic-threshold-days",
+            "dynamic-lookahead-days",
+            "dynamic-week-threshold-days",
+            "month-boundary-tz",
+            "month-source",
+            "month-pattern",
+            "full",
+            "incremental",
+        ],
+    ),
+    (
+        "retention",
+        &[
+            "keep-months",
+            "retention-by",
+            "keep-min",
+            "keep-weeks",
+            "retention-policy",
+            "gfs-monthly",
+            "gfs-yearly",
+            "max-destination-size",
+            "dedupe-months",
+            "trash",
+            "purge-trash-days",
+            "cleanup-recursive",
+            "cleanup-extra-pattern",
+            "cleanup-extra-format",
+            "ignore-cleanup-errors",
+            "yes",
+            "stale-grace-hours",
+        ],
+    ),
+    (
+        "excludes",
+        &[
+            "exclude",
+            "include",
+            "ext",
+            "no-default-excludes",
+            "include-own-artifacts",
+            "no-backupignore",
+            "max-depth",
+            "min-file-size",
+            "max-file-size",
+            "symlinks",
+            "settle-seconds",
+            "future-skew-seconds",
+            "include-future-mtimes",
+            "allow-nested-destination",
+            "max-files",
+            "max-total-size",
+        ],
+    ),
+    (
+        "archive",
+        &[
+            "archive-format",
+            "compression-level",
+            "split-size",
+            "store-extensions",
+            "no-store-heuristic",
+            "no-verify",
+            "password-file",
+            "encrypt-to",
+            "split-by-top-dir",
+            "dedup",
+            "detect-changes",
+            "append",
+            "reproducible",
+            "max-parallel-bytes",
+            "min-free-space",
+            "expected-compression-ratio",
+            "locked-file-retries",
+            "retry-delay-ms",
+            "overlap-seconds",
+            "fail-on-skip",
+            "write-skip-report",
+        ],
+    ),
+    (
+        "run",
+        &[
+            "silent",
+            "verbose",
+            "output",
+            "lock-wait-seconds",
+            "cache-backend",
+            "cache-history-limit",
+            "scan-threads",
+            "force",
+        ],
+    ),
+];
+
+/// `[mode]` 中 `mode` 的取值和对应的参数
+const MODES: &[(&str, &str)] = &[("previous", "p"), ("current", "n"), ("dynamic", "d")];
+
+/// 读取并检查过的配置文件
+#[derive(Debug, Clone)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    /// 已知的键和值，按节的顺序
+    values: Vec<(&'static str, toml::Value)>,
+    /// 未知的节和键，不影响运行
+    pub warnings: Vec<String>,
+}
+
+impl ConfigFile {
+    /// 读取配置文件；文件不存在或不是有效的 TOML 时返回 [`BackupError::ConfigError`]
+    pub fn load(path: &Path) -> Result<Self, BackupError> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            BackupError::ConfigError(format!(
+                "Failed to read the config file '{}': {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Self::parse(path, &text)
+    }
+
+    /// 解析配置文件的内容；`path` 只用于消息
+    pub fn parse(path: &Path, text: &str) -> Result<Self, BackupError> {
+        let invalid = |message: String| {
+            BackupError::ConfigError(format!(
+                "Invalid config file '{}': {}",
+                path.display(),
+                message
+            ))
+        };
+        let table: toml::value::Table = toml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+        let mut config = ConfigFile {
+            path: path.to_path_buf(),
+            values: Vec::new(),
+            warnings: Vec::new(),
+        };
+        for (section, value) in table {
+            let Some(&(_, keys)) = SECTIONS.iter().find(|(name, _)| *name == section) else {
+                config.warnings.push(format!(
+                    "Unknown section [{}] in '{}' ignored.",
+                    section,
+                    path.display()
+                ));
+                continue;
+            };
+            let toml::Value::Table(entries) = value else {
+                return Err(invalid(format!("[{}] must be a table.", section)));
+            };
+            for (key, value) in entries {
+                match keys.iter().find(|known| **known == key) {
+                    Some(known) => config.values.push((known, value)),
+                    None => {
+                        // 放错节的键提示正确的位置
+                        let hint = SECTIONS
+                            .iter()
+                            .find(|(_, keys)| keys.contains(&key.as_str()))
+                            .map(|(name, _)| format!(" (it belongs in [{}])", name))
+                            .unwrap_or_default();
+                        config.warnings.push(format!(
+                            "Unknown key '{}' in [{}] of '{}' ignored{}.",
+                            key,
+                            section,
+                            path.display(),
+                            hint
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// 未指定 --config 时依次查找的位置：可执行文件所在目录，然后是用户配置目录
+/// （Windows 上为 `%APPDATA%`，其他系统为 `$XDG_CONFIG_HOME` 或 `~/.config`）
+pub fn default_locations() -> Vec<PathBuf> {
+    let mut locations = Vec::new();
+    if let Ok(exe) = std::env::current_exe()
+        && let Some(dir) = exe.parent()
+    {
+        locations.push(dir.join(CONFIG_FILE_NAME));
+    }
+    let config_dir = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    if let Some(dir) = config_dir {
+        locations.push(dir.join(CONFIG_FILE_NAME));
+    }
+    locations
+}
+
+/// 在解析之前从命令行参数中找出 `--config <PATH>` 或 `--config=<PATH>`
+pub fn config_arg(argv: &[OsString]) -> Option<PathBuf> {
+    let mut args = argv.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// 合并了配置文件的命令行参数
+#[derive(Debug, Clone)]
+pub struct MergedArgs {
+    /// 交给 clap 解析的参数，第一个是程序名
+    pub args: Vec<OsString>,
+    /// 所用的配置文件
+    pub config_path: Option<PathBuf>,
+    /// 取自配置文件的参数 id
+    pub from_file: BTreeSet<String>,
+}
+
+/// 配置文件的键对应的参数 id
+fn arg_id(key: &str) -> String {
+    match key {
+        "silent" => "s".to_string(),
+        _ => key.replace('-', "_"),
+    }
+}
+
+/// 把配置文件中的值转换为参数，放在命令行参数之前；命令行上已经给出的选项，
+/// 以及与命令行上的选项冲突（或属于同一个互斥组）的选项，忽略文件中的值。
+/// `command` 中没有对应参数的键同样忽略，例如 `clean` 子命令不使用 `[paths]` 中的 `from`
+///
+/// # Arguments
+/// * `command` - 命令行参数的定义
+/// * `argv` - 实际的命令行参数，第一个是程序名
+/// * `config` - 配置文件；为 `None` 时原样返回 `argv`
+pub fn merge_args(
+    command: &Command,
+    argv: &[OsString],
+    config: Option<&ConfigFile>,
+) -> Result<MergedArgs, BackupError> {
+    let mut merged = MergedArgs {
+        args: argv.to_vec(),
+        config_path: config.map(|config| config.path.clone()),
+        from_file: BTreeSet::new(),
+    };
+    let Some(config) = config else {
+        return Ok(merged);
+    };
+    let mut command = command.clone();
+    command.build();
+    // 只用于判断哪些选项出现在命令行上；缺少必需的参数（可能在配置文件中）不算错误。
+    // --help、--version 之类由之后的正式解析处理
+    let Ok(cli) = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(argv)
+    else {
+        return Ok(merged);
+    };
+    let on_cli = |id: &str| cli.value_source(id) == Some(ValueSource::CommandLine);
+
+    let invalid = |key: &str, message: &str| {
+        BackupError::ConfigError(format!(
+            "Invalid value for '{}' in '{}': {}",
+            key,
+            config.path.display(),
+            message
+        ))
+    };
+    let mut tokens: Vec<OsString> = Vec::new();
+    for (key, value) in &config.values {
+        let id = if *key == "mode" {
+            let mode = value.as_str().unwrap_or_default();
+            match MODES.iter().find(|(name, _)| *name == mode) {
+                Some((_, id)) => id.to_string(),
+                None => {
+                    return Err(invalid(
+                        key,
+                        "expected \"previous\", \"current\" or \"dynamic\"",
+                    ));
+                }
+            }
+        } else {
+            arg_id(key)
+        };
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str())
+        else {
+            continue;
+        };
+        // 冲突只声明在其中一方，两个方向都要检查
+        let conflicts = |a: &clap::Arg, b: &clap::Arg| {
+            command
+                .get_arg_conflicts_with(a)
+                .iter()
+                .any(|other| other.get_id() == b.get_id())
+        };
+        let overridden = on_cli(&id)
+            || command
+                .get_arguments()
+                .filter(|other| on_cli(other.get_id().as_str()))
+                .any(|other| conflicts(arg, other) || conflicts(other, arg))
+            || command.get_groups().any(|group| {
+                !group.clone().is_multiple()
+                    && group.get_args().any(|member| member == arg.get_id())
+                    && group.get_args().any(|member| on_cli(member.as_str()))
+            });
+        if overridden {
+            continue;
+        }
+        let long = format!("--{}", arg.get_long().unwrap_or(id.as_str()));
+        match arg.get_action() {
+            ArgAction::SetTrue if *key == "mode" => tokens.push(long.into()),
+            ArgAction::SetTrue => match value {
+                toml::Value::Boolean(true) => tokens.push(long.into()),
+                toml::Value::Boolean(false) => continue,
+                _ => return Err(invalid(key, "expected true or false")),
+            },
+            ArgAction::Count => match value.as_integer() {
+                Some(count) if count >= 0 => {
+                    tokens.extend((0..count).map(|_| OsString::from(&long)))
+                }
+                _ => return Err(invalid(key, "expected a number")),
+            },
+            action => {
+                let values = match value {
+                    toml::Value::Array(values) if action.takes_values() => values.clone(),
+                    value => vec![value.clone()],
+                };
+                if values.len() > 1 && !matches!(action, ArgAction::Append) {
+                    return Err(invalid(key, "expected a single value"));
+                }
+                for value in values {
+                    let value = scalar_string(&value).ok_or_else(|| {
+                        invalid(key, "expected a string, number, boolean or date")
+                    })?;
+                    tokens.push(format!("{}={}", long, value).into());
+                }
+            }
+        }
+        merged.from_file.insert(id);
+    }
+    merged.args.splice(1..1, tokens);
+    Ok(merged)
+}
+
+/// 单个 TOML 值在命令行上的写法
+fn scalar_string(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Datetime(value) => Some(value.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => None,
+    }
+}
+
+/// 合并后实际生效的配置，格式与配置文件相同，每个值后注明来源（默认值、配置文件或命令行）；
+/// 没有值的选项不列出
+pub fn effective_config(command: &Command, matches: &ArgMatches, merged: &MergedArgs) -> String {
+    let mut output = match &merged.config_path {
+        Some(path) => format!("# Effective configuration, using '{}'\n", path.display()),
+        None => "# Effective configuration, no config file\n".to_string(),
+    };
+    let source = |id: &str| match matches.value_source(id) {
+        Some(ValueSource::CommandLine) if merged.from_file.contains(id) => "config file",
+        Some(ValueSource::CommandLine) => "command line",
+        Some(ValueSource::EnvVariable) => "environment",
+        _ => "default",
+    };
+    for (section, keys) in SECTIONS {
+        output.push_str(&format!("\n[{}]\n", section));
+        for key in *keys {
+            let (value, source) = if *key == "mode" {
+                let Some((name, id)) = MODES.iter().find(|(_, id)| matches.get_flag(id)) else {
+                    continue;
+                };
+                (toml::Value::String(name.to_string()), source(id))
+            } else {
+                let id = arg_id(key);
+                let Some(arg) = command
+                    .get_arguments()
+                    .find(|arg| arg.get_id() == id.as_str())
+                else {
+                    continue;
+                };
+                let value = match arg.get_action() {
+                    ArgAction::SetTrue => toml::Value::Boolean(matches.get_flag(&id)),
+                    ArgAction::Count => toml::Value::Integer(matches.get_count(&id).into()),
+                    action => {
+                        let Some(raw) = matches.get_raw(&id) else {
+                            continue;
+                        };
+                        let values: Vec<toml::Value> = raw
+                            .map(|value| toml_value(&value.to_string_lossy()))
+                            .collect();
+                        match action {
+                            ArgAction::Append => toml::Value::Array(values),
+                            _ => values
+                                .into_iter()
+                                .next()
+                                .unwrap_or(toml::Value::Array(Vec::new())),
+                        }
+                    }
+                };
+                (value, source(&id))
+            };
+            let mut entry = toml::value::Table::new();
+            entry.insert(key.to_string(), value);
+            let line = toml::to_string(&entry).unwrap_or_default();
+            output.push_str(&format!("{}  # {}\n", line.trim_end(), source));
+        }
+    }
+    output
+}
+
+/// 命令行上的值在配置文件中的写法：数字写成数字，其他写成字符串
+fn toml_value(value: &str) -> toml::Value {
+    if let Ok(number) = value.parse::<i64>() {
+        toml::Value::Integer(number)
+    } else if let Ok(number) = value.parse::<f64>()
+        && number.is_finite()
+        && value.contains('.')
+    {
+        toml::Value::Float(number)
+    } else {
+        toml::Value::String(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches, Parser};
+
+    /// 与命令行工具同名的一部分选项
+    #[derive(Parser, Debug)]
+    struct TestArgs {
+        #[arg(long)]
+        from: Vec<PathBuf>,
+        #[arg(long, required = true)]
+        to: Option<PathBuf>,
+        #[arg(short, long, group = "mode")]
+        p: bool,
+        #[arg(short, long, group = "mode")]
+        n: bool,
+        #[arg(short, long, group = "mode")]
+        d: bool,
+        #[arg(long, group = "mode")]
+        months_back: Option<u32>,
+        #[arg(long, default_value_t = 6)]
+        keep_months: u32,
+        #[arg(long)]
+        exclude: Vec<String>,
+        #[arg(short, long)]
+        s: bool,
+        #[arg(short, long, action = ArgAction::Count, conflicts_with = "s")]
+        verbose: u8,
+        #[arg(long)]
+        full: bool,
+        #[arg(long, conflicts_with = "full")]
+        incremental: bool,
+        #[arg(long)]
+        config: Option<PathBuf>,
+    }
+
+    const CONFIG: &str = r#"
+[paths]
+from = ["/data/a", "/data/b"]
+to = "/backups"
+
+[mode]
+mode = "dynamic"
+
+[retention]
+keep-months = 12
+
+[excludes]
+exclude = ["*.tmp"]
+
+[run]
+verbose = 2
+"#;
+
+    fn parse(argv: &[&str], config: Option<&str>) -> (TestArgs, MergedArgs) {
+        let argv: Vec<OsString> = std::iter::once("dat-patch-rust")
+            .chain(argv.iter().copied())
+            .map(OsString::from)
+            .collect();
+        let config =
+            config.map(|text| ConfigFile::parse(Path::new("dat-patch.toml"), text).unwrap());
+        let merged = merge_args(&TestArgs::command(), &argv, config.as_ref()).unwrap();
+        let matches = TestArgs::command()
+            .try_get_matches_from(&merged.args)
+            .unwrap();
+        (TestArgs::from_arg_matches(&matches).unwrap(), merged)
+    }
+
+    #[test]
+    fn defaults_are_overridden_by_the_file() {
+        let (args, _) = parse(&["--to", "/elsewhere"], None);
+        assert_eq!(args.keep_months, 6);
+        assert!(args.exclude.is_empty());
+
+        let (args, merged) = parse(&[], Some(CONFIG));
+        assert_eq!(
+            args.from,
+            [PathBuf::from("/data/a"), PathBuf::from("/data/b")]
+        );
+        assert_eq!(args.to, Some(PathBuf::from("/backups")));
+        assert!(args.d);
+        assert_eq!(args.keep_months, 12);
+        assert_eq!(args.exclude, ["*.tmp"]);
+        assert_eq!(args.verbose, 2);
+        assert!(merged.from_file.contains("keep_months"));
+    }
+
+    #[test]
+    fn command_line_overrides_the_file() {
+        let (args, merged) = parse(
+            &["--keep-months", "3", "--exclude", "*.log", "--to", "/other"],
+            Some(CONFIG),
+        );
+        assert_eq!(args.keep_months, 3);
+        // 列表同样整体替换，而不是与文件中的值合并
+        assert_eq!(args.exclude, ["*.log"]);
+        assert_eq!(args.to, Some(PathBuf::from("/other")));
+        assert_eq!(args.from.len(), 2);
+        assert!(!merged.from_file.contains("keep_months"));
+
+        // 命令行上的模式替换文件中的模式，而不是与之冲突
+        let (args, _) = parse(&["-p"], Some(CONFIG));
+        assert!(args.p);
+        assert!(!args.d);
+        let (args, _) = parse(&["--months-back", "2"], Some(CONFIG));
+        assert_eq!(args.months_back, Some(2));
+        assert!(!args.d);
+
+        // 与命令行上的选项冲突的值同样忽略
+        let (args, _) = parse(&["-s"], Some(CONFIG));
+        assert!(args.s);
+        assert_eq!(args.verbose, 0);
+        let (args, _) = parse(
+            &["--incremental"],
+            Some("[mode]\nfull = true\n[paths]\nto = \"/b\""),
+        );
+        assert!(args.incremental);
+        assert!(!args.full);
+    }
+
+    #[test]
+    fn unknown_keys_are_warnings() {
+        let config = ConfigFile::parse(
+            Path::new("dat-patch.toml"),
+            "[retention]\nkeep-months = 2\nexclude = [\"a\"]\ncolour = true\n\n[extras]\nx = 1\n",
+        )
+        .unwrap();
+        assert_eq!(config.values.len(), 1);
+        assert_eq!(config.warnings.len(), 3, "{:?}", config.warnings);
+        assert!(
+            config
+                .warnings
+                .iter()
+                .any(|w| w.contains("'exclude'") && w.contains("[excludes]")),
+            "{:?}",
+            config.warnings
+        );
+        assert!(config.warnings.iter().any(|w| w.contains("[extras]")));
+
+        // 语法错误和无效的值是错误
+        assert!(ConfigFile::parse(Path::new("dat-patch.toml"), "[paths\n").is_err());
+        let config =
+            ConfigFile::parse(Path::new("dat-patch.toml"), "[mode]\nmode = \"weekly\"\n").unwrap();
+        let argv = [OsString::from("dat-patch-rust")];
+        assert!(matches!(
+            merge_args(&TestArgs::command(), &argv, Some(&config)),
+            Err(BackupError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn effective_config_names_the_source_of_each_value() {
+        let (_, merged) = parse(
+            &["--keep-months", "3"],
+            Some("[paths]\nto = \"/backups\"\n"),
+        );
+        let matches = TestArgs::command()
+            .try_get_matches_from(&merged.args)
+            .unwrap();
+        let output = effective_config(&TestArgs::command(), &matches, &merged);
+        assert!(
+            output.contains("keep-months = 3  # command line"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("to = \"/backups\"  # config file"),
+            "{}",
+            output
+        );
+        assert!(output.contains("silent = false  # default"), "{}", output);
+        // 生成的内容本身是有效的配置文件
+        let config = ConfigFile::parse(Path::new("effective.toml"), &output).unwrap();
+        assert!(config.warnings.is_empty(), "{:?}", config.warnings);
+    }
+
+    #[test]
+    fn config_option_is_found_before_parsing() {
+        let argv = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            config_arg(&argv(&["prog", "-n", "--config", "a.toml"])),
+            Some(PathBuf::from("a.toml"))
+        );
+        assert_eq!(
+            config_arg(&argv(&["prog", "--config=b.toml"])),
+            Some(PathBuf::from("b.toml"))
+        );
+        assert_eq!(config_arg(&argv(&["prog", "-n"])), None);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::archiver::{self, ManifestEntry};
+use crate::file_scanner::{self, FileEntry};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 与已有的归档相比，一个待备份文件的变化
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileChange {
+    /// 不在该月份（及分组）的任何归档中
+    New,
+    /// 在归档中，但大小或内容不同
+    Modified,
+    /// 内容与归档中的记录相同，只是被重新写入
+    Retouched,
+    /// 修改时间与归档中的记录相同，没有变化；diff 不列出这样的文件
+    Unchanged,
+}
+
+/// 一个月份（及分组）已有的归档中记录的文件
+#[derive(Debug, Default)]
+pub struct ArchivedFiles {
+    /// 条目名 -> 最后归档该文件的清单记录
+    pub entries: HashMap<String, ManifestEntry>,
+    /// 读取了清单的归档文件，从新到旧
+    pub archives: Vec<PathBuf>,
+    /// 无法读取清单的归档及原因，例如 age 加密或已损坏的归档
+    pub unreadable: Vec<(PathBuf, String)>,
+}
+
+/// 读取目标目录中属于 `label`（归档名中 `_backup_` 之前的部分）且源标记为 `source`
+/// 的所有归档的清单
+///
+/// 从最新的归档读起，同一文件只保留最新的记录，因此增量归档链中的每个文件
+/// 对应最后一次归档的版本。目标目录不存在时没有任何归档。
+pub fn archived_files(
+    destination: &Path,
+    label: &str,
+    source: Option<&str>,
+    password: Option<&str>,
+) -> io::Result<ArchivedFiles> {
+    let mut archived = ArchivedFiles::default();
+    if !destination.is_dir() {
+        return Ok(archived);
+    }
+    let mut archives = archiver::find_archives(destination)?;
+    archives.retain(|(_, info)| info.prefix() == label && info.source.as_deref() == source);
+    for (path, info) in archives.into_iter().rev() {
+        if info.encrypted {
+            archived
+                .unreadable
+                .push((path, "encrypted with age".to_string()));
+            continue;
+        }
+        match archiver::read_manifest(&path, info.format, password) {
+            Ok(manifest) => {
+                for entry in manifest.entries {
+                    archived.entries.entry(entry.path.clone()).or_insert(entry);
+                }
+                archived.archives.push(path);
+            }
+            Err(e) => archived.unreadable.push((path, e.to_string())),
+        }
+    }
+    Ok(archived)
+}
+
+/// 按已有归档的清单判断文件的变化
+///
+/// 大小和修改时间都与清单中的记录相同的文件没有变化；只有大小相同、修改时间不同的文件
+/// 才需要计算哈希，与清单中的 SHA-256 比较；无法读取的文件归为 [`FileChange::Modified`]。
+pub fn classify(file: &FileEntry, archived: &ArchivedFiles) -> FileChange {
+    let name = archiver::entry_name(&file.archive_path());
+    let Some(entry) = archived.entries.get(&name) else {
+        return FileChange::New;
+    };
+    if entry.size != file.size {
+        return FileChange::Modified;
+    }
+    if entry.modified == Some(file.modified) {
+        FileChange::Unchanged
+    } else if file_scanner::file_sha256(&file.path).is_ok_and(|digest| digest == entry.sha256) {
+        FileChange::Retouched
+    } else {
+        FileChange::Modified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use sha2::{Digest, Sha256};
+    use std::fs;
+
+    fn sha256(content: &str) -> String {
+        Sha256::digest(content.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    #[test]
+    fn files_are_classified_against_the_manifests() {
+        let root = std::env::temp_dir().join(format!("dat-patch-differ-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let file = |name: &str, content: &str| {
+            fs::write(root.join(name), content).unwrap();
+            FileEntry {
+                path: root.join(name),
+                relative_path: PathBuf::from(name),
+                size: content.len() as u64,
+                modified: Utc::now(),
+                source_label: None,
+            }
+        };
+        let new = file("new.db", "new");
+        let resized = file("resized.db", "longer content");
+        let edited = file("edited.db", "edited");
+        let touched = file("touched.db", "same");
+
+        let mut archived = ArchivedFiles::default();
+        for (name, content) in [
+            ("resized.db", "short"),
+            ("edited.db", "before"),
+            ("touched.db", "same"),
+        ] {
+            archived.entries.insert(
+                name.to_string(),
+                ManifestEntry {
+                    path: name.to_string(),
+                    size: content.len() as u64,
+                    modified: None,
+                    sha256: sha256(content),
+                },
+            );
+        }
+
+        assert_eq!(classify(&new, &archived), FileChange::New);
+        assert_eq!(classify(&resized, &archived), FileChange::Modified);
+        assert_eq!(classify(&edited, &archived), FileChange::Modified);
+        assert_eq!(classify(&touched, &archived), FileChange::Retouched);
+
+        // 修改时间与清单中的相同时没有变化，不读取文件
+        let gone = file("gone.db", "same");
+        fs::remove_file(&gone.path).unwrap();
+        let entry = |modified| ManifestEntry {
+            path: "gone.db".to_string(),
+            size: 4,
+            modified,
+            sha256: sha256("same"),
+        };
+        archived
+            .entries
+            .insert("gone.db".to_string(), entry(Some(gone.modified)));
+        assert_eq!(classify(&gone, &archived), FileChange::Unchanged);
+        archived.entries.insert("gone.db".to_string(), entry(None));
+        assert_eq!(classify(&gone, &archived), FileChange::Modified);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
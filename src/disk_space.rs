@@ -0,0 +1,131 @@
+use crate::error::{BackupError, archive_error};
+use crate::file_scanner::FileEntry;
+use std::io;
+use std::path::Path;
+
+/// 查询目标文件系统剩余空间的接口，测试中可替换为固定值
+pub trait FreeSpaceProvider {
+    /// 返回 `path` 所在文件系统对当前用户可用的字节数
+    fn available_space(&self, path: &Path) -> io::Result<u64>;
+}
+
+/// 通过操作系统查询剩余空间（Windows 使用 `GetDiskFreeSpaceExW`，Unix 使用 `statvfs`）
+pub struct SystemFreeSpace;
+
+impl FreeSpaceProvider for SystemFreeSpace {
+    fn available_space(&self, path: &Path) -> io::Result<u64> {
+        fs2::available_space(path)
+    }
+}
+
+/// 按预期压缩率估算归档所需的空间
+///
+/// `compression_ratio` 为压缩后与压缩前大小之比，1.0 表示按不压缩估算（最保守）。
+pub fn estimate_required_bytes(files: &[FileEntry], compression_ratio: f64) -> u64 {
+    let total: u64 = files.iter().map(|f| f.size).sum();
+    (total as f64 * compression_ratio).ceil() as u64
+}
+
+/// 检查目标目录是否有足够的空间容纳即将创建的归档
+///
+/// # Arguments
+/// * `provider` - 剩余空间的查询方式
+/// * `destination_path` - 备份文件存放的目标目录
+/// * `required_bytes` - 预计需要写入的字节数
+/// * `min_free_bytes` - 归档完成后至少需要保留的剩余空间
+///
+/// # Returns
+/// 空间不足时返回 [`BackupError::ArchiveError`]，其中的 `StorageFull` 错误列出所需和可用的字节数
+pub fn check_free_space(
+    provider: &dyn FreeSpaceProvider,
+    destination_path: &Path,
+    required_bytes: u64,
+    min_free_bytes: u64,
+) -> Result<(), BackupError> {
+    let available = provider
+        .available_space(destination_path)
+        .map_err(archive_error(destination_path))?;
+
+    if required_bytes > available {
+        return Err(archive_error(destination_path)(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "Not enough free space: {} bytes required, {} bytes available",
+                required_bytes, available
+            ),
+        )));
+    }
+    if available - required_bytes < min_free_bytes {
+        return Err(archive_error(destination_path)(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "Backup would leave {} bytes free, less than the required minimum of {} bytes \
+                 ({} bytes required, {} bytes available)",
+                available - required_bytes,
+                min_free_bytes,
+                required_bytes,
+                available
+            ),
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// 返回固定剩余空间的测试实现
+    struct FixedFreeSpace(u64);
+
+    impl FreeSpaceProvider for FixedFreeSpace {
+        fn available_space(&self, _path: &Path) -> io::Result<u64> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn estimate_applies_compression_ratio() {
+        let entry = |name: &str, size| FileEntry {
+            path: PathBuf::from(name),
+            relative_path: PathBuf::from(name),
+            size,
+            modified: chrono::Utc::now(),
+            source_label: None,
+        };
+        let files = vec![entry("a", 600), entry("b", 400)];
+        assert_eq!(estimate_required_bytes(&files, 1.0), 1000);
+        assert_eq!(estimate_required_bytes(&files, 0.25), 250);
+        assert_eq!(estimate_required_bytes(&[], 1.0), 0);
+    }
+
+    #[test]
+    fn insufficient_space_is_reported_with_sizes() {
+        let err = check_free_space(&FixedFreeSpace(100), Path::new("dest"), 150, 0).unwrap_err();
+        assert!(
+            matches!(&err, BackupError::ArchiveError { path, .. } if path == Path::new("dest"))
+        );
+        assert_eq!(err.kind(), Some(io::ErrorKind::StorageFull));
+        let message = err.to_string();
+        assert!(message.contains("150 bytes required"));
+        assert!(message.contains("100 bytes available"));
+    }
+
+    #[test]
+    fn min_free_space_is_enforced() {
+        let provider = FixedFreeSpace(1000);
+        assert!(check_free_space(&provider, Path::new("dest"), 800, 200).is_ok());
+        let err = check_free_space(&provider, Path::new("dest"), 800, 201).unwrap_err();
+        assert_eq!(err.kind(), Some(io::ErrorKind::StorageFull));
+        assert!(err.to_string().contains("leave 200 bytes free"));
+    }
+
+    #[test]
+    fn system_provider_reports_space_for_existing_directory() {
+        let available = SystemFreeSpace
+            .available_space(&std::env::temp_dir())
+            .unwrap();
+        assert!(available > 0);
+    }
+}
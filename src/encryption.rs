@@ -0,0 +1,88 @@
+use crate::archiver::{PARTIAL_SUFFIX, with_suffix};
+use crate::error::{BackupError, archive_error};
+use age::x25519;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// 加密后的归档在原文件名后追加的后缀，例如 `2025-07_backup_20250801000000.zip.age`
+pub const ENCRYPTED_SUFFIX: &str = ".age";
+
+/// 使用 age 将归档加密给指定的接收者，生成 `<archive>.age` 并删除明文归档
+///
+/// 密文先写入 `.age.partial`，写完后再重命名，中断时不会留下不完整的 `.age` 文件；
+/// 加密失败时保留明文归档。
+///
+/// # Arguments
+/// * `archive_path` - 已创建并通过校验的归档
+/// * `recipients` - age X25519 公钥接收者，至少一个
+///
+/// # Returns
+/// 返回加密后归档的路径；失败时返回 [`BackupError::ArchiveError`]
+pub fn encrypt_archive(
+    archive_path: &Path,
+    recipients: &[x25519::Recipient],
+) -> Result<PathBuf, BackupError> {
+    write_encrypted(archive_path, recipients).map_err(archive_error(archive_path))
+}
+
+/// [`encrypt_archive`] 的实现，返回底层的 I/O 错误
+fn write_encrypted(archive_path: &Path, recipients: &[x25519::Recipient]) -> io::Result<PathBuf> {
+    let encrypted_path = with_suffix(archive_path, ENCRYPTED_SUFFIX);
+    let partial_path = with_suffix(&encrypted_path, PARTIAL_SUFFIX);
+
+    let result = (|| {
+        let encryptor =
+            age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+                .map_err(io::Error::other)?;
+        let mut output = BufWriter::new(File::create(&partial_path)?);
+        let mut writer = encryptor.wrap_output(&mut output)?;
+        io::copy(&mut BufReader::new(File::open(archive_path)?), &mut writer)?;
+        writer.finish()?;
+        output.into_inner().map_err(|e| e.into_error())?.sync_all()
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial_path);
+        return Err(e);
+    }
+
+    fs::rename(&partial_path, &encrypted_path)?;
+    fs::remove_file(archive_path)?;
+    Ok(encrypted_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn encrypted_archive_decrypts_to_original_bytes() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-age-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive_path = dir.join("2025-07_backup_20250801000000.zip");
+        let original: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        fs::write(&archive_path, &original).unwrap();
+
+        let identity = x25519::Identity::generate();
+        let other = x25519::Identity::generate();
+        let encrypted_path =
+            encrypt_archive(&archive_path, &[other.to_public(), identity.to_public()]).unwrap();
+
+        assert_eq!(
+            encrypted_path,
+            dir.join("2025-07_backup_20250801000000.zip.age")
+        );
+        assert!(!archive_path.exists());
+        let decryptor =
+            age::Decryptor::new(BufReader::new(File::open(&encrypted_path).unwrap())).unwrap();
+        let mut reader = decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .unwrap();
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, original);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+//! 备份过程中的错误类型
+//!
+//! 各模块的入口函数返回 [`BackupError`]，调用方可以区分"源目录消失"、"目标磁盘已满"、
+//! "缓存损坏"等情况；底层的 [`io::Error`] 保留为 [`std::error::Error::source`]。
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::io;
+use std::path::PathBuf;
+
+/// 扫描、归档、读写缓存或清理旧备份时的错误
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    /// 扫描源目录失败，例如源目录不存在或无法读取
+    #[error("Failed to scan '{}': {source}", .path.display())]
+    ScanError {
+        /// 被扫描的源目录
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// 创建、校验或加密归档失败，例如目标磁盘已满
+    #[error("Failed to write '{}': {source}", .path.display())]
+    ArchiveError {
+        /// 归档文件或目标目录
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// 读写 `.cache` 中的记录、索引或锁文件失败，例如缓存文件损坏
+    #[error("Failed to use the cache '{}': {source}", .path.display())]
+    CacheError {
+        /// 缓存文件或缓存目录
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// 滚动删除旧备份失败
+    #[error("Failed to clean up '{}': {source}", .path.display())]
+    CleanupError {
+        /// 被清理的目标目录
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// 选项无效或相互矛盾
+    #[error("{0}")]
+    ConfigError(String),
+    /// 另一个实例正在向同一目标目录备份
+    #[error("{source} for '{}'; not starting a second one.", .path.display())]
+    AlreadyRunning {
+        /// 目标目录
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl BackupError {
+    /// 底层的 I/O 错误；[`BackupError::ConfigError`] 没有
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            BackupError::ScanError { source, .. }
+            | BackupError::ArchiveError { source, .. }
+            | BackupError::CacheError { source, .. }
+            | BackupError::CleanupError { source, .. }
+            | BackupError::AlreadyRunning { source, .. } => Some(source),
+            BackupError::ConfigError(_) => None,
+        }
+    }
+
+    /// 底层 I/O 错误的类型，用于区分例如 [`io::ErrorKind::NotFound`] 和 [`io::ErrorKind::StorageFull`]
+    pub fn kind(&self) -> Option<io::ErrorKind> {
+        self.io_error().map(io::Error::kind)
+    }
+}
+
+/// 序列化为 `{"Kind": "ScanError", "Path": ..., "IoKind": "NotFound", "Message": ...}`，
+/// 没有路径或底层 I/O 错误时对应的字段为 `null`
+impl Serialize for BackupError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (kind, path) = match self {
+            BackupError::ScanError { path, .. } => ("ScanError", Some(path)),
+            BackupError::ArchiveError { path, .. } => ("ArchiveError", Some(path)),
+            BackupError::CacheError { path, .. } => ("CacheError", Some(path)),
+            BackupError::CleanupError { path, .. } => ("CleanupError", Some(path)),
+            BackupError::ConfigError(_) => ("ConfigError", None),
+            BackupError::AlreadyRunning { path, .. } => ("AlreadyRunning", Some(path)),
+        };
+        let mut state = serializer.serialize_struct("BackupError", 4)?;
+        state.serialize_field("Kind", kind)?;
+        state.serialize_field("Path", &path)?;
+        state.serialize_field("IoKind", &self.kind().map(|kind| format!("{:?}", kind)))?;
+        state.serialize_field("Message", &self.to_string())?;
+        state.end()
+    }
+}
+
+/// 把模块内部的 I/O 错误包装为指定的变体，例如 `.map_err(scan_error(path))`
+pub(crate) fn scan_error(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> BackupError {
+    let path = path.into();
+    move |source| BackupError::ScanError { path, source }
+}
+
+pub(crate) fn archive_error(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> BackupError {
+    let path = path.into();
+    move |source| BackupError::ArchiveError { path, source }
+}
+
+pub(crate) fn cache_error(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> BackupError {
+    let path = path.into();
+    move |source| BackupError::CacheError { path, source }
+}
+
+pub(crate) fn cleanup_error(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> BackupError {
+    let path = path.into();
+    move |source| BackupError::CleanupError { path, source }
+}
@@ -1,66 +1,2160 @@
-use crate::backup_logic::BackupMonth;
-use chrono::{DateTime, Datelike, Local, TimeZone, Utc};
-use std::io;
+use crate::archiver::{PARTIAL_SUFFIX, SkippedFile, parse_archive_name};
+use crate::backup_logic::{BackupMonth, BackupPeriod, DateRange};
+use crate::cache::{FileHashIndex, ScanIndex, ScanIndexRecord};
+use crate::error::{BackupError, scan_error};
+use crate::timezone::MonthBoundaryTz;
+use chrono::{DateTime, Utc};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use rayon::prelude::*;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-/// 获取指定年月的起止时间（UTC）
-fn get_month_range_utc(month: &BackupMonth) -> (DateTime<Utc>, DateTime<Utc>) {
-    let start_naive = chrono::NaiveDate::from_ymd_opt(month.year, month.month, 1)
-        .unwrap()
-        .and_hms_opt(0, 0, 0)
-        .unwrap();
+/// 扫描得到的待备份文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    /// 文件的绝对路径
+    pub path: PathBuf,
+    /// 相对源目录的路径
+    pub relative_path: PathBuf,
+    /// 扫描时的文件大小（字节），用于计算进度总量
+    pub size: u64,
+    /// 扫描时的修改时间
+    pub modified: DateTime<Utc>,
+    /// 有多个源目录时文件所属源目录的标签（例如 `source0`），归档时作为顶层目录
+    pub source_label: Option<String>,
+}
 
-    let end_naive = if month.month == 12 {
-        chrono::NaiveDate::from_ymd_opt(month.year + 1, 1, 1)
-            .unwrap()
-            .and_hms_opt(0, 0, 0)
-            .unwrap()
-    } else {
-        chrono::NaiveDate::from_ymd_opt(month.year, month.month + 1, 1)
-            .unwrap()
-            .and_hms_opt(0, 0, 0)
-            .unwrap()
-    };
+impl FileEntry {
+    /// 文件在归档中的路径：相对路径，有源目录标签时位于以标签命名的顶层目录下
+    pub fn archive_path(&self) -> Cow<'_, Path> {
+        match &self.source_label {
+            Some(label) => Cow::Owned(Path::new(label).join(&self.relative_path)),
+            None => Cow::Borrowed(&self.relative_path),
+        }
+    }
+}
 
-    // 将本地时间的起止转换为 UTC
-    let start_utc = Local
-        .from_local_datetime(&start_naive)
-        .unwrap()
-        .with_timezone(&Utc);
-    let end_utc = Local
-        .from_local_datetime(&end_naive)
-        .unwrap()
-        .with_timezone(&Utc);
+/// 一次扫描的结果
+#[derive(Debug, Clone, Default)]
+pub struct ScanResult {
+    /// 需要备份的文件
+    pub files: Vec<FileEntry>,
+    /// 无法读取元数据而被跳过的文件，以及无法进入而被整体跳过的目录
+    pub skipped: Vec<SkippedFile>,
+    /// 修改时间符合条件、但匹配排除模式而未备份的文件数
+    pub excluded_files: usize,
+    /// 匹配排除模式而未进入的目录
+    pub pruned_dirs: Vec<PathBuf>,
+    /// 修改时间符合条件、但匹配 [`DEFAULT_EXCLUDES`] 而未备份的文件数
+    pub default_excluded_files: usize,
+    /// 匹配 [`DEFAULT_EXCLUDES`] 而未进入的目录
+    pub default_pruned_dirs: Vec<PathBuf>,
+    /// 其他条件都满足、但大小超出 `--min-file-size`/`--max-file-size` 限制的文件
+    pub size_filtered: Vec<FileEntry>,
+    /// `--symlinks skip` 时跳过的符号链接
+    pub skipped_symlinks: Vec<PathBuf>,
+    /// `--month-source path` 时修改时间在本月份内且不早于截止时间、但路径中是其他月份的文件，
+    /// 及其路径中的月份；只有扫描路径中的月份时才会被备份
+    pub other_path_months: Vec<(PathBuf, BackupMonth)>,
+    /// 修改时间太近、可能仍在写入而推迟到下次备份的文件数
+    pub deferred_files: usize,
+    /// 修改时间晚于 [`ScanFilters::future_cutoff`] 的文件（无论属于哪个月份）
+    pub future_mtimes: Vec<PathBuf>,
+    /// 源目录中本工具之前生成的归档和 `.cache/backupEvents.json`（无论属于哪个月份）
+    pub own_artifacts: Vec<PathBuf>,
+    /// 扫描结束时的目录数和文件数
+    pub progress: ScanProgress,
+}
+
+/// 扫描过程中的进度快照
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanProgress {
+    /// 已遍历的目录数（包括遍历起点）
+    pub dirs_visited: usize,
+    /// 已检查的文件数
+    pub files_considered: usize,
+    /// 需要备份的文件数
+    pub files_matched: usize,
+}
+
+impl std::ops::Add for ScanProgress {
+    type Output = ScanProgress;
+
+    fn add(self, other: ScanProgress) -> ScanProgress {
+        ScanProgress {
+            dirs_visited: self.dirs_visited + other.dirs_visited,
+            files_considered: self.files_considered + other.files_considered,
+            files_matched: self.files_matched + other.files_matched,
+        }
+    }
+}
+
+/// 两次进度报告之间的最小间隔
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+/// 每检查这么多个条目才查看一次是否需要报告进度
+const PROGRESS_ENTRIES: usize = 500;
+
+/// 并行扫描的各个目录共用的进度计数
+#[derive(Debug)]
+struct ScanCounters {
+    dirs_visited: AtomicUsize,
+    files_considered: AtomicUsize,
+    files_matched: AtomicUsize,
+    last_report: Mutex<Instant>,
+}
+
+impl ScanCounters {
+    fn snapshot(&self) -> ScanProgress {
+        ScanProgress {
+            dirs_visited: self.dirs_visited.load(Ordering::Relaxed),
+            files_considered: self.files_considered.load(Ordering::Relaxed),
+            files_matched: self.files_matched.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl ScanResult {
+    /// 合并两个目录（或两个源目录）的扫描结果
+    pub fn merge(mut self, other: ScanResult) -> ScanResult {
+        self.files.extend(other.files);
+        self.skipped.extend(other.skipped);
+        self.excluded_files += other.excluded_files;
+        self.pruned_dirs.extend(other.pruned_dirs);
+        self.default_excluded_files += other.default_excluded_files;
+        self.default_pruned_dirs.extend(other.default_pruned_dirs);
+        self.size_filtered.extend(other.size_filtered);
+        self.skipped_symlinks.extend(other.skipped_symlinks);
+        self.other_path_months.extend(other.other_path_months);
+        self.deferred_files += other.deferred_files;
+        self.future_mtimes.extend(other.future_mtimes);
+        self.own_artifacts.extend(other.own_artifacts);
+        self.progress = self.progress + other.progress;
+        self
+    }
+}
+
+/// 扫描和归档时对符号链接（包括 Windows 的目录联接）的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SymlinkPolicy {
+    /// Skip symbolic links and report them (default)
+    #[default]
+    Skip,
+    /// Follow symbolic links and archive the files or directories they point to
+    Follow,
+    /// Archive the link itself: a symlink entry in tar, a text file holding the
+    /// link target in zip
+    Preserve,
+}
+
+/// 扫描时按路径过滤文件的规则（`--exclude`、`--include`、`--ext`）
+///
+/// 模式匹配相对源目录的路径，以 `/` 作为分隔符，模式中的 `\` 也视为分隔符，
+/// 因此 `FileStorage/Cache/**` 与 `FileStorage\Cache\**` 等价。
+/// `*` 可以跨越目录，`*.tmp` 匹配任意层级的 `.tmp` 文件。
+///
+/// 排除优先于包含：匹配排除模式的文件即使也匹配包含模式也不会备份。
+/// 存在任何包含模式或扩展名时，只备份至少匹配其中一项的文件；
+/// 包含模式只作用于文件，目录总会被遍历。
+///
+/// 源目录下的 `.backupignore`（gitignore 语法）与 `--exclude` 一起生效，
+/// 其中的否定模式（`!important/**`）不能恢复被 `--exclude` 排除的文件。
+/// 启用 [`DEFAULT_EXCLUDES`] 时它们先于上述规则应用，单独计数。
+/// 启用 [`ScanFilters::skip_own_artifacts`] 时本工具生成的文件也不会备份。
+#[derive(Debug, Clone, Default)]
+pub struct ScanFilters {
+    /// [`DEFAULT_EXCLUDES`]，未启用时为空
+    default_exclude: GlobSet,
+    exclude: GlobSet,
+    /// 从 `.backupignore` 读取的规则
+    ignore_file: Option<Gitignore>,
+    include: GlobSet,
+    /// 小写、不带 `.` 的扩展名
+    extensions: Vec<String>,
+    /// 是否跳过本工具生成的归档
+    own_artifacts: bool,
+    /// 文件大小下限（字节），`None` 表示不限制
+    pub min_file_size: Option<u64>,
+    /// 文件大小上限（字节），`None` 表示不限制
+    pub max_file_size: Option<u64>,
+    /// 符号链接的处理方式
+    pub symlinks: SymlinkPolicy,
+    /// `--month-source path` 时从相对路径中提取月份的正则表达式，
+    /// 第一、二个捕获组分别为年份和月份；为 `None` 时按修改时间确定月份
+    pub month_pattern: Option<Regex>,
+    /// 在此时间及之后修改的文件可能仍在写入（例如正在下载的视频），推迟到下次备份；
+    /// `None` 表示不推迟
+    pub settle_cutoff: Option<DateTime<Utc>>,
+    /// 修改时间晚于此时间的文件视为来自时钟错误的机器，不属于修改时间所在的月份；
+    /// `None` 表示不检查
+    pub future_cutoff: Option<DateTime<Utc>>,
+    /// 修改时间在未来的文件归入的月份或周（--include-future-mtimes）；`None` 时不备份这些文件
+    pub future_month: Option<BackupPeriod>,
+    /// 月份（或周）的边界所在的时区，默认为本地时区
+    pub month_boundary_tz: MonthBoundaryTz,
+    /// 之前的运行中被跳过、尚未归档的文件（相对路径）；在所扫描的月份中时不论修改时间都会被选中
+    pub retry_paths: HashSet<PathBuf>,
+    /// 最大遍历深度：遍历起点中的条目深度为 1，更深的文件和目录不会被扫描；
+    /// `None` 表示不限制
+    pub max_depth: Option<usize>,
+    /// 只扫描源目录下的这些子目录（相对路径）；为空时扫描整个源目录。
+    /// 深度从每个子目录起算，归档中的相对路径仍然相对于源目录
+    pub subdirs: Vec<PathBuf>,
+}
 
-    (start_utc, end_utc)
+/// 确定文件所属月份的依据
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MonthSource {
+    /// The modification time of the file (default)
+    #[default]
+    Mtime,
+    /// A `YYYY-MM` component of the path (e.g. `FileStorage/Image/2025-07/...`); files
+    /// without one still use their modification time
+    Path,
+}
+
+/// 判断文件自上次备份以来是否变化的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ChangeDetection {
+    /// Modified after the cutoff of the last backup (default)
+    #[default]
+    Mtime,
+    /// The size or modification time differs from the change detection index
+    #[value(name = "size+mtime")]
+    SizeMtime,
+    /// The content hash differs from the change detection index; reads every candidate
+    Hash,
+}
+
+/// `--month-source path` 默认使用的月份模式
+pub const DEFAULT_MONTH_PATTERN: &str = r"(\d{4})-(\d{2})";
+
+/// 源目录下自动读取的忽略文件名
+pub const BACKUP_IGNORE_FILE: &str = ".backupignore";
+
+/// 默认排除的 WeChat 临时文件：SQLite 的预写日志、共享内存和回滚日志在备份时与数据库本身
+/// 不一致，锁文件和临时目录恢复后没有用处。在 `--exclude` 之前应用，`--no-default-excludes` 关闭
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    "*.db-wal",
+    "*.db-shm",
+    "*.db-journal",
+    "*.lock",
+    "*.lck",
+    "**/Temp/**",
+    "**/temp/**",
+];
+
+/// 将 glob 模式编译为 `GlobSet`
+fn build_glob_set(patterns: &[impl AsRef<str>]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref().replace('\\', "/");
+        // `dir/**` 只匹配目录中的内容，同时匹配目录本身才能在遍历时跳过整个目录
+        if let Some(dir) = pattern.strip_suffix("/**") {
+            builder.add(Glob::new(dir)?);
+        }
+        builder.add(Glob::new(&pattern)?);
+    }
+    builder.build()
+}
+
+/// 将相对路径转换为以 `/` 分隔的字符串，用于 glob 匹配
+fn glob_path(relative_path: &Path) -> String {
+    let components: Vec<_> = relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+impl ScanFilters {
+    /// # Arguments
+    /// * `exclude` - 排除的 glob 模式
+    /// * `include` - 包含的 glob 模式
+    /// * `extensions` - 包含的扩展名，可带前导 `.`，不区分大小写
+    pub fn new(
+        exclude: &[String],
+        include: &[String],
+        extensions: &[String],
+    ) -> Result<Self, globset::Error> {
+        Ok(Self {
+            default_exclude: GlobSet::empty(),
+            exclude: build_glob_set(exclude)?,
+            ignore_file: None,
+            include: build_glob_set(include)?,
+            extensions: extensions
+                .iter()
+                .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+                .filter(|e| !e.is_empty())
+                .collect(),
+            own_artifacts: false,
+            min_file_size: None,
+            max_file_size: None,
+            symlinks: SymlinkPolicy::default(),
+            month_pattern: None,
+            settle_cutoff: None,
+            future_cutoff: None,
+            future_month: None,
+            month_boundary_tz: MonthBoundaryTz::default(),
+            retry_paths: HashSet::new(),
+            max_depth: None,
+            subdirs: Vec::new(),
+        })
+    }
+
+    /// 启用 [`DEFAULT_EXCLUDES`]
+    pub fn use_default_excludes(&mut self) {
+        self.default_exclude =
+            build_glob_set(DEFAULT_EXCLUDES).expect("default exclude patterns are valid");
+    }
+
+    /// 跳过本工具之前生成的归档（包括未完成的 `.partial` 文件）和 `.cache/backupEvents.json`
+    ///
+    /// 目标目录曾经位于源目录中（例如经由符号链接），或者用户把旧归档放进了源目录时，
+    /// 这些文件会在每次备份时被再次打包。
+    pub fn skip_own_artifacts(&mut self) {
+        self.own_artifacts = true;
+    }
+
+    /// 判断相对源目录的文件路径是否为本工具生成的文件
+    fn is_own_artifact(&self, relative_path: &Path) -> bool {
+        if !self.own_artifacts {
+            return false;
+        }
+        let Some(file_name) = relative_path.file_name().and_then(|n| n.to_str()) else {
+            return false;
+        };
+        let in_cache_dir = relative_path
+            .parent()
+            .and_then(Path::file_name)
+            .is_some_and(|dir| dir == ".cache");
+        (in_cache_dir && file_name == "backupEvents.json")
+            || parse_archive_name(file_name.strip_suffix(PARTIAL_SUFFIX).unwrap_or(file_name))
+                .is_some()
+    }
+
+    /// 读取 gitignore 语法的忽略文件，其中的模式相对于 `source_path` 匹配
+    ///
+    /// # Returns
+    /// 文件不存在时返回 `Ok(false)`，不改变过滤规则
+    pub fn load_ignore_file(
+        &mut self,
+        source_path: &Path,
+        ignore_path: &Path,
+    ) -> Result<bool, ignore::Error> {
+        if !ignore_path.is_file() {
+            return Ok(false);
+        }
+        let mut builder = GitignoreBuilder::new(source_path);
+        if let Some(e) = builder.add(ignore_path) {
+            return Err(e);
+        }
+        self.ignore_file = Some(builder.build()?);
+        Ok(true)
+    }
+
+    /// 从相对源目录的路径中提取月份，使用路径中第一个有效的匹配
+    ///
+    /// # Returns
+    /// 未启用 `--month-source path` 或路径中没有有效月份时返回 `None`
+    fn path_month(&self, relative_path: &Path) -> Option<BackupMonth> {
+        let pattern = self.month_pattern.as_ref()?;
+        pattern
+            .captures_iter(&glob_path(relative_path))
+            .find_map(|caps| {
+                let year = caps.get(1)?.as_str().parse().ok()?;
+                let month = caps.get(2)?.as_str().parse().ok()?;
+                (1..=12)
+                    .contains(&month)
+                    .then_some(BackupMonth { year, month })
+            })
+    }
+
+    /// 判断文件大小是否在限制范围内
+    fn size_allowed(&self, size: u64) -> bool {
+        self.min_file_size.is_none_or(|min| size >= min)
+            && self.max_file_size.is_none_or(|max| size <= max)
+    }
+
+    /// 判断相对源目录的路径是否匹配默认排除的模式
+    fn is_default_excluded(&self, relative_path: &Path) -> bool {
+        !self.default_exclude.is_empty() && self.default_exclude.is_match(glob_path(relative_path))
+    }
+
+    /// 判断相对源目录的路径是否被排除
+    fn is_excluded(&self, relative_path: &Path, is_dir: bool) -> bool {
+        if !self.exclude.is_empty() && self.exclude.is_match(glob_path(relative_path)) {
+            return true;
+        }
+        self.ignore_file
+            .as_ref()
+            .is_some_and(|ignore| ignore.matched(relative_path, is_dir).is_ignore())
+    }
+
+    /// 判断相对源目录的文件路径是否满足包含条件；没有包含条件时总是满足
+    fn is_included(&self, relative_path: &Path) -> bool {
+        if self.include.is_empty() && self.extensions.is_empty() {
+            return true;
+        }
+        let extension_matches = relative_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.extensions.contains(&ext.to_lowercase()));
+        extension_matches || self.include.is_match(glob_path(relative_path))
+    }
+}
+
+/// 获取指定年月（或 ISO 周、时间范围）在时区 `tz` 中的起止时间（UTC）；
+/// 时间范围本身就是确定的时刻，不受 `tz` 影响
+fn get_month_range_utc(
+    period: &BackupPeriod,
+    tz: &MonthBoundaryTz,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    if let BackupPeriod::Range(range) = period {
+        return (range.start, range.end);
+    }
+    // 将时区中的起止转换为 UTC；零点切换夏令时的时区同样有确定的起止时刻
+    let (start, end) = period.date_range();
+    (tz.start_of_day_utc(start), tz.start_of_day_utc(end))
+}
+
+/// 为 Windows 路径添加 `\\?\` 扩展长度前缀，使超过 260 个字符（MAX_PATH）的路径也能访问
+///
+/// 相对路径会先转换为绝对路径；已带前缀的路径和设备路径保持不变。
+/// 其他平台没有该限制，原样返回。
+#[cfg(windows)]
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    use std::path::{Component, Prefix};
+
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let prefixed = match (absolute.components().next(), absolute.to_str()) {
+        (Some(Component::Prefix(prefix)), Some(absolute_str)) => match prefix.kind() {
+            // C:\foo -> \\?\C:\foo
+            Prefix::Disk(_) => Some(format!(r"\\?\{}", absolute_str)),
+            // \\server\share\foo -> \\?\UNC\server\share\foo
+            Prefix::UNC(_, _) => Some(format!(r"\\?\UNC{}", &absolute_str[1..])),
+            // 已经是扩展长度路径或设备路径
+            _ => None,
+        },
+        _ => None,
+    };
+    prefixed.map(PathBuf::from).unwrap_or(absolute)
+}
+
+#[cfg(not(windows))]
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
 }
 
 /// 查找需要备份的文件
 ///
-/// 遍历源目录，找到所有在 `last_backup_time` 之后修改过，
-/// 且修改时间在指定月份范围内的文件。
+/// 遍历源目录，找到所有在 `last_backup_time` 及之后修改过，
+/// 且修改时间在指定月份（或周）范围内的文件，同时记录每个文件的大小。
+/// 修改时间恰好等于截止时间的文件也会被备份：宁可在两次备份中重复，也不能遗漏。
+/// `excluded_dirs` 中的目录（以 `source_path` 为前缀的路径）及其内容不会被扫描，
+/// 不满足 `filters` 的文件会被跳过，匹配排除模式的目录不会进入。
+///
+/// 无法读取元数据的文件和无法进入的目录不会中断扫描，而是记录在
+/// [`ScanResult::skipped`] 中；只有源目录本身无法读取时才返回 [`BackupError::ScanError`]。
+///
+/// 子目录在当前的 rayon 线程池中并行扫描，返回的文件按路径排序。
+/// 扫描期间最多每 200 毫秒通过 `on_progress` 报告一次进度，结束时的计数记录在
+/// [`ScanResult::progress`] 中。
+/// 设置了 [`ScanFilters::subdirs`] 时只遍历这些子目录，位于其他所列子目录内部的子目录
+/// 不会重复扫描；子目录不存在时返回错误。
 pub fn find_files_to_backup(
     source_path: &Path,
     last_backup_time: &DateTime<Utc>,
-    month_to_scan: &BackupMonth,
-) -> io::Result<Vec<PathBuf>> {
-    let mut files_to_backup = Vec::new();
-    let (month_start, month_end) = get_month_range_utc(month_to_scan);
-
-    for entry in WalkDir::new(source_path).into_iter().filter_map(|e| e.ok()) {
-        if entry.file_type().is_file() {
-            let metadata = entry.metadata()?;
-            let modified_time: DateTime<Utc> = metadata.modified()?.into();
-
-            if modified_time > *last_backup_time
-                && modified_time >= month_start
-                && modified_time < month_end
+    month_to_scan: &BackupPeriod,
+    excluded_dirs: &[PathBuf],
+    filters: &ScanFilters,
+    on_progress: &(dyn Fn(ScanProgress) + Sync),
+) -> Result<ScanResult, BackupError> {
+    let (month_start, month_end) = get_month_range_utc(month_to_scan, &filters.month_boundary_tz);
+    // 以扩展长度路径遍历，返回的路径再换回以 `source_path` 为前缀的形式，
+    // 保证调用方的 `strip_prefix` 得到正确的相对路径
+    let walk_root = extended_length_path(source_path);
+    let context = ScanContext {
+        source_path,
+        walk_root: &walk_root,
+        last_backup_time: *last_backup_time,
+        month_to_scan,
+        month_start,
+        month_end,
+        excluded_dirs,
+        filters,
+        counters: ScanCounters {
+            dirs_visited: AtomicUsize::new(0),
+            files_considered: AtomicUsize::new(0),
+            files_matched: AtomicUsize::new(0),
+            last_report: Mutex::new(Instant::now()),
+        },
+        on_progress,
+    };
+
+    let walk_roots: Vec<PathBuf> = if filters.subdirs.is_empty() {
+        vec![walk_root.clone()]
+    } else {
+        let mut subdirs: Vec<&PathBuf> = filters.subdirs.iter().collect();
+        subdirs.sort();
+        subdirs.dedup();
+        subdirs
+            .iter()
+            .filter(|subdir| {
+                !subdirs
+                    .iter()
+                    .any(|other| other != *subdir && subdir.starts_with(other))
+            })
+            .map(|subdir| walk_root.join(subdir))
+            .collect()
+    };
+
+    let mut result = ScanResult::default();
+    for root in &walk_roots {
+        // 跟随符号链接时记录每个目录的规范路径及其所有上级目录，用于发现链接循环
+        let error_path = || match context.relative_path(root) {
+            relative if relative.as_os_str().is_empty() => source_path.to_path_buf(),
+            relative => source_path.join(relative),
+        };
+        let ancestors = match filters.symlinks {
+            SymlinkPolicy::Follow => {
+                vec![fs::canonicalize(root).map_err(scan_error(error_path()))?]
+            }
+            _ => Vec::new(),
+        };
+        // 源目录（或指定的子目录）本身无法读取时整个扫描没有意义
+        let entries = fs::read_dir(root).map_err(scan_error(error_path()))?;
+        if *root != walk_root && context.is_excluded_dir(root, &mut result) {
+            continue;
+        }
+        if context.within_depth(0) {
+            result = result.merge(context.scan_dir(root, 0, entries, &ancestors));
+        }
+    }
+    // 并行扫描的结果顺序不确定，排序后归档中的文件顺序才是确定的
+    result.files.sort_by(|a, b| a.path.cmp(&b.path));
+    result.size_filtered.sort_by(|a, b| a.path.cmp(&b.path));
+    result.skipped.sort_by(|a, b| a.path.cmp(&b.path));
+    result.pruned_dirs.sort();
+    result.default_pruned_dirs.sort();
+    result.skipped_symlinks.sort();
+    result.future_mtimes.sort();
+    result.own_artifacts.sort();
+    result.progress = context.counters.snapshot();
+    Ok(result)
+}
+
+/// 查找修改时间在 `from` 及之后、`to` 之前的所有文件（--since/--until）
+///
+/// 与 [`find_files_to_backup`] 的遍历和筛选相同，只是时间范围不按月份划分，
+/// 也不考虑上次备份的截止时间。
+pub fn find_files_in_range(
+    source_path: &Path,
+    from: &DateTime<Utc>,
+    to: &DateTime<Utc>,
+    excluded_dirs: &[PathBuf],
+    filters: &ScanFilters,
+    on_progress: &(dyn Fn(ScanProgress) + Sync),
+) -> Result<ScanResult, BackupError> {
+    let range = BackupPeriod::Range(DateRange {
+        start: *from,
+        end: *to,
+    });
+    find_files_to_backup(
+        source_path,
+        &DateTime::UNIX_EPOCH,
+        &range,
+        excluded_dirs,
+        filters,
+        on_progress,
+    )
+}
+
+/// 一次扫描中所有目录共用的参数
+struct ScanContext<'a> {
+    source_path: &'a Path,
+    walk_root: &'a Path,
+    last_backup_time: DateTime<Utc>,
+    month_to_scan: &'a BackupPeriod,
+    month_start: DateTime<Utc>,
+    month_end: DateTime<Utc>,
+    excluded_dirs: &'a [PathBuf],
+    filters: &'a ScanFilters,
+    counters: ScanCounters,
+    on_progress: &'a (dyn Fn(ScanProgress) + Sync),
+}
+
+impl ScanContext<'_> {
+    /// 遍历路径相对源目录的部分
+    fn relative_path<'p>(&self, path: &'p Path) -> &'p Path {
+        path.strip_prefix(self.walk_root).unwrap_or(path)
+    }
+
+    /// 将遍历路径换回以 `source_path` 为前缀的形式
+    fn logical_path(&self, path: &Path) -> PathBuf {
+        self.source_path.join(self.relative_path(path))
+    }
+
+    /// 距离上次报告超过 [`PROGRESS_INTERVAL`] 时报告进度；其他线程正在报告时直接跳过
+    fn report_progress(&self) {
+        if let Ok(mut last_report) = self.counters.last_report.try_lock()
+            && last_report.elapsed() >= PROGRESS_INTERVAL
+        {
+            *last_report = Instant::now();
+            (self.on_progress)(self.counters.snapshot());
+        }
+    }
+
+    /// 深度为 `depth` 的目录中的条目是否在最大遍历深度之内（遍历起点的深度为 0）
+    fn within_depth(&self, depth: usize) -> bool {
+        self.filters.max_depth.is_none_or(|max| depth < max)
+    }
+
+    /// 扫描一个目录中的文件，子目录在当前的 rayon 线程池中并行扫描
+    ///
+    /// `depth` 是该目录相对遍历起点的深度；
+    /// `ancestors` 是该目录及其所有上级目录的规范路径，只在跟随符号链接时记录。
+    fn scan_dir(
+        &self,
+        dir: &Path,
+        depth: usize,
+        entries: fs::ReadDir,
+        ancestors: &[PathBuf],
+    ) -> ScanResult {
+        let mut result = ScanResult::default();
+        let skip = |result: &mut ScanResult, path: &Path, e: io::Error| {
+            result.skipped.push(SkippedFile {
+                path: self.logical_path(path),
+                reason: e.to_string(),
+            })
+        };
+
+        self.counters.dirs_visited.fetch_add(1, Ordering::Relaxed);
+        let mut subdirs = Vec::new();
+        for (index, entry) in entries.enumerate() {
+            if index % PROGRESS_ENTRIES == PROGRESS_ENTRIES - 1 {
+                self.report_progress();
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    skip(&mut result, dir, e);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            // 不跟随符号链接
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(e) => {
+                    skip(&mut result, &path, e);
+                    continue;
+                }
+            };
+            if file_type.is_symlink() {
+                match self.filters.symlinks {
+                    SymlinkPolicy::Skip => result.skipped_symlinks.push(self.logical_path(&path)),
+                    // 链接本身作为一个文件条目，由归档器写入链接目标
+                    SymlinkPolicy::Preserve => {
+                        self.scan_file(&path, || entry.metadata(), &mut result)
+                    }
+                    SymlinkPolicy::Follow => match fs::metadata(&path) {
+                        Ok(metadata) if metadata.is_dir() => {
+                            if self.is_excluded_dir(&path, &mut result) {
+                                continue;
+                            }
+                            match fs::canonicalize(&path) {
+                                Ok(target) if ancestors.contains(&target) => skip(
+                                    &mut result,
+                                    &path,
+                                    io::Error::other(format!(
+                                        "symlink loop to '{}'",
+                                        target.display()
+                                    )),
+                                ),
+                                Ok(target) => {
+                                    subdirs.push((path, [ancestors, &[target]].concat()));
+                                }
+                                Err(e) => skip(&mut result, &path, e),
+                            }
+                        }
+                        Ok(metadata) if metadata.is_file() => {
+                            self.scan_file(&path, || Ok(metadata), &mut result)
+                        }
+                        Ok(_) => {}
+                        // 指向不存在的目标
+                        Err(e) => skip(&mut result, &path, e),
+                    },
+                }
+            } else if file_type.is_dir() {
+                if self.is_excluded_dir(&path, &mut result) {
+                    continue;
+                }
+                let nested_ancestors = match ancestors.last() {
+                    Some(parent) => [ancestors, &[parent.join(entry.file_name())]].concat(),
+                    None => Vec::new(),
+                };
+                subdirs.push((path, nested_ancestors));
+            } else if file_type.is_file() {
+                self.scan_file(&path, || entry.metadata(), &mut result);
+            }
+        }
+
+        self.report_progress();
+        // 超出最大遍历深度的子目录不再进入
+        if !self.within_depth(depth + 1) {
+            subdirs.clear();
+        }
+        let nested = subdirs
+            .into_par_iter()
+            .map(|(subdir, ancestors)| {
+                let mut nested = ScanResult::default();
+                match fs::read_dir(&subdir) {
+                    Ok(entries) => nested = self.scan_dir(&subdir, depth + 1, entries, &ancestors),
+                    Err(e) => skip(&mut nested, &subdir, e),
+                }
+                nested
+            })
+            .reduce(ScanResult::default, ScanResult::merge);
+        result.merge(nested)
+    }
+
+    /// 判断目录是否被排除而不需要进入；匹配排除模式的目录记录在结果中
+    fn is_excluded_dir(&self, path: &Path, result: &mut ScanResult) -> bool {
+        let logical = self.logical_path(path);
+        if self.excluded_dirs.contains(&logical) {
+            return true;
+        }
+        if self.filters.is_default_excluded(self.relative_path(path)) {
+            result.default_pruned_dirs.push(logical);
+            return true;
+        }
+        if self.filters.is_excluded(self.relative_path(path), true) {
+            result.pruned_dirs.push(logical);
+            return true;
+        }
+        false
+    }
+
+    /// 判断单个文件是否需要备份，并记录到对应的结果中
+    ///
+    /// 元数据只在文件满足路径过滤条件时才通过 `metadata` 读取。
+    fn scan_file(
+        &self,
+        entry_path: &Path,
+        metadata: impl FnOnce() -> io::Result<fs::Metadata>,
+        result: &mut ScanResult,
+    ) {
+        self.counters
+            .files_considered
+            .fetch_add(1, Ordering::Relaxed);
+        let relative = self.relative_path(entry_path);
+        let path = self.source_path.join(relative);
+        // 先应用默认排除和排除模式，再应用包含条件
+        let default_excluded = self.filters.is_default_excluded(relative);
+        let excluded = default_excluded || self.filters.is_excluded(relative, false);
+        if !excluded && self.filters.is_own_artifact(relative) {
+            result.own_artifacts.push(path);
+            return;
+        }
+        if !excluded && !self.filters.is_included(relative) {
+            return;
+        }
+        let read_metadata = || -> io::Result<(u64, DateTime<Utc>)> {
+            let metadata = metadata()?;
+            Ok((metadata.len(), metadata.modified()?.into()))
+        };
+        let (size, modified_time) = match read_metadata() {
+            Ok(metadata) => metadata,
+            Err(_) if excluded => return,
+            Err(e) => {
+                result.skipped.push(SkippedFile {
+                    path,
+                    reason: e.to_string(),
+                });
+                return;
+            }
+        };
+
+        // 修改时间在未来的文件不会落在任何要备份的月份中，单独报告
+        let future = self
+            .filters
+            .future_cutoff
+            .is_some_and(|cutoff| modified_time > cutoff);
+        if future && !excluded {
+            result.future_mtimes.push(path.clone());
+        }
+
+        // 截止时间总是按修改时间判断，月份可以取自路径
+        let in_mtime_month = modified_time >= self.month_start && modified_time < self.month_end;
+        let in_month = match self.filters.path_month(relative) {
+            Some(month) if BackupPeriod::Month(month) == *self.month_to_scan => true,
+            Some(month) => {
+                // 按修改时间本应在本月份备份的文件，调用方需要确认其路径中的月份也被扫描
+                if in_mtime_month && !excluded && modified_time >= self.last_backup_time {
+                    result.other_path_months.push((path.clone(), month));
+                }
+                false
+            }
+            None if future => self.filters.future_month == Some(*self.month_to_scan),
+            None => in_mtime_month,
+        };
+        let retry = self.filters.retry_paths.contains(relative);
+        if (modified_time >= self.last_backup_time || retry) && in_month {
+            if default_excluded {
+                result.default_excluded_files += 1;
+            } else if excluded {
+                result.excluded_files += 1;
+            } else if !future
+                && self
+                    .filters
+                    .settle_cutoff
+                    .is_some_and(|settle| modified_time >= settle)
             {
-                files_to_backup.push(entry.into_path());
+                result.deferred_files += 1;
+            } else if relative.to_str().is_none() {
+                // 归档条目名必须是 UTF-8，有损转换会让不同文件得到相同的条目名
+                result.skipped.push(SkippedFile {
+                    path,
+                    reason: "file name is not valid Unicode".to_string(),
+                });
+            } else {
+                let entry = FileEntry {
+                    path,
+                    relative_path: relative.to_path_buf(),
+                    size,
+                    modified: modified_time,
+                    source_label: None,
+                };
+                if self.filters.size_allowed(size) {
+                    self.counters.files_matched.fetch_add(1, Ordering::Relaxed);
+                    result.files.push(entry);
+                } else {
+                    result.size_filtered.push(entry);
+                }
             }
         }
     }
+}
+
+/// 直接位于源目录下（不属于任何顶层目录）的文件所在的分组名
+pub const ROOT_GROUP: &str = "_root";
+
+/// 按源目录下的第一级路径（例如微信账号目录 `wxid_*`）对文件分组
+///
+/// 直接位于源目录下的文件归入 [`ROOT_GROUP`]。
+pub fn group_by_top_dir(files: Vec<FileEntry>) -> BTreeMap<String, Vec<FileEntry>> {
+    let mut groups: BTreeMap<String, Vec<FileEntry>> = BTreeMap::new();
+    for file in files {
+        let mut components = file.relative_path.components();
+        let group = match (components.next(), components.next()) {
+            (Some(top), Some(_)) => top.as_os_str().to_string_lossy().into_owned(),
+            _ => ROOT_GROUP.to_string(),
+        };
+        groups.entry(group).or_default().push(file);
+    }
+    groups
+}
+
+/// 只保留扫描时在 `since` 及之后修改过的文件，以及 `retry` 中（以归档内的路径表示）的文件
+pub fn modified_after(
+    files: Vec<FileEntry>,
+    since: &DateTime<Utc>,
+    retry: &HashSet<String>,
+) -> Vec<FileEntry> {
+    files
+        .into_iter()
+        .filter(|file| {
+            file.modified >= *since
+                || retry.contains(&crate::archiver::entry_name(&file.archive_path()))
+        })
+        .collect()
+}
+
+/// 计算文件内容的 SHA-256（小写十六进制）
+pub(crate) fn file_sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(extended_length_path(path))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// 计算文件内容的 xxh3 哈希（小写十六进制），比 SHA-256 快得多，只用于变化检测
+fn file_xxh3(path: &Path) -> io::Result<String> {
+    let mut file = File::open(extended_length_path(path))?;
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(format!("{:032x}", hasher.digest128()))
+}
+
+/// 生成文件在变化检测索引中的记录，`hash` 模式下会读取文件计算哈希
+fn scan_index_record(file: &FileEntry, mode: ChangeDetection) -> io::Result<ScanIndexRecord> {
+    let hash = match mode {
+        ChangeDetection::Hash => Some(file_xxh3(&file.path)?),
+        ChangeDetection::Mtime | ChangeDetection::SizeMtime => None,
+    };
+    Ok(ScanIndexRecord {
+        size: file.size,
+        modified: file.modified,
+        hash,
+    })
+}
+
+/// 为文件生成变化检测索引记录，归档成功后写入索引
+///
+/// 无法读取的文件没有记录，交由归档时处理。
+pub fn scan_index_records(files: &[FileEntry], mode: ChangeDetection) -> ScanIndex {
+    files
+        .par_iter()
+        .filter_map(|file| {
+            let record = scan_index_record(file, mode).ok()?;
+            Some((crate::archiver::entry_name(&file.archive_path()), record))
+        })
+        .collect()
+}
 
-    Ok(files_to_backup)
+/// 按 `--detect-changes` 剔除与变化检测索引中记录相同的文件
+///
+/// `size+mtime` 只比较扫描时的大小和修改时间；`hash` 比较内容哈希，修改时间被同步工具
+/// 重置过的文件也能被识别。索引中没有记录的文件视为新文件，无法读取的文件保留下来，
+/// 交由归档时处理。
+///
+/// # Returns
+/// 返回 (需要归档的文件, 这些文件归档成功后应写入索引的记录)
+pub fn detect_changes(
+    files: Vec<FileEntry>,
+    index: &ScanIndex,
+    mode: ChangeDetection,
+) -> (Vec<FileEntry>, ScanIndex) {
+    let checked: Vec<(FileEntry, Option<(String, ScanIndexRecord)>)> = files
+        .into_par_iter()
+        .filter_map(|file| {
+            let name = crate::archiver::entry_name(&file.archive_path());
+            let Ok(record) = scan_index_record(&file, mode) else {
+                return Some((file, None));
+            };
+            let unchanged = index.get(&name).is_some_and(|previous| match mode {
+                ChangeDetection::Hash => previous.hash.is_some() && previous.hash == record.hash,
+                ChangeDetection::Mtime | ChangeDetection::SizeMtime => {
+                    previous.size == record.size && previous.modified == record.modified
+                }
+            });
+            (!unchanged).then_some((file, Some((name, record))))
+        })
+        .collect();
+
+    let mut records = ScanIndex::new();
+    let mut changed = Vec::with_capacity(checked.len());
+    for (file, record) in checked {
+        records.extend(record);
+        changed.push(file);
+    }
+    (changed, records)
+}
+
+/// 去重：剔除内容与去重索引中记录完全相同的文件（即使修改时间已经变化）
+///
+/// 先与同一路径的记录比较；路径没有记录（例如被重命名或移动）时，与任何路径下内容相同的
+/// 记录比较。只有大小与某条记录一致的文件才需要计算哈希；无法读取的文件保留下来，
+/// 交由归档时处理。
+///
+/// # Returns
+/// 返回 (需要归档的文件, 因内容未变化而跳过的文件数)
+pub fn skip_unchanged_files(
+    files: Vec<FileEntry>,
+    index: &FileHashIndex,
+) -> (Vec<FileEntry>, usize) {
+    // 大小 -> 该大小的所有已记录哈希
+    let mut digests: HashMap<u64, HashSet<&str>> = HashMap::new();
+    for record in index.values() {
+        digests
+            .entry(record.size)
+            .or_default()
+            .insert(record.sha256.as_str());
+    }
+    let total = files.len();
+    let changed: Vec<FileEntry> = files
+        .into_iter()
+        .filter(|file| {
+            let name = crate::archiver::entry_name(&file.archive_path());
+            if let Some(record) = index.get(&name) {
+                return record.size != file.size
+                    || file_sha256(&file.path).map_or(true, |digest| digest != record.sha256);
+            }
+            let Some(known) = digests.get(&file.size) else {
+                return true;
+            };
+            !file_sha256(&file.path).is_ok_and(|digest| known.contains(digest.as_str()))
+        })
+        .collect();
+    let skipped = total - changed.len();
+    (changed, skipped)
+}
+
+/// 检查目标目录是否位于源目录内部（包括两者相同）
+///
+/// 两个路径都会先规范化以识别符号链接和相对路径。位于内部时返回目标目录在
+/// 遍历源目录时呈现的路径（即 `source_path` 加上相对路径），可直接作为
+/// [`find_files_to_backup`] 的排除目录。
+pub fn nested_destination(
+    source_path: &Path,
+    destination_path: &Path,
+) -> io::Result<Option<PathBuf>> {
+    let source = fs::canonicalize(source_path)?;
+    let destination = fs::canonicalize(destination_path)?;
+    Ok(destination
+        .strip_prefix(&source)
+        .ok()
+        .map(|relative| source_path.join(relative)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    #[cfg(windows)]
+    #[test]
+    fn extended_length_prefix_is_added_on_windows() {
+        assert_eq!(
+            extended_length_path(Path::new(r"C:\WeChat Files\Msg")),
+            PathBuf::from(r"\\?\C:\WeChat Files\Msg")
+        );
+        assert_eq!(
+            extended_length_path(Path::new(r"\\nas\backup\WeChat")),
+            PathBuf::from(r"\\?\UNC\nas\backup\WeChat")
+        );
+        // 已带前缀的路径保持不变
+        let verbatim = PathBuf::from(r"\\?\C:\WeChat Files");
+        assert_eq!(extended_length_path(&verbatim), verbatim);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn relative_paths_exclude_the_extended_length_prefix() {
+        // 扫描时源目录被转换为 \\?\C:\... 形式，相对路径和返回的路径都不应带前缀
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let nested = source.join("wxid_a").join("Msg");
+        fs::create_dir_all(&nested).unwrap();
+        let month = file_modified_at(&nested.join("msg0.db"), Utc::now());
+
+        let scanned = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &ScanFilters::default(),
+            &|_| {},
+        )
+        .unwrap();
+        assert_eq!(scanned.files.len(), 1);
+        assert_eq!(
+            scanned.files[0].relative_path,
+            PathBuf::from(r"wxid_a\Msg\msg0.db")
+        );
+        assert_eq!(
+            scanned.files[0].path,
+            PathBuf::from(format!(r"{}\wxid_a\Msg\msg0.db", source.display()))
+        );
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn paths_are_unchanged_on_other_platforms() {
+        let path = Path::new("relative/WeChat Files");
+        assert_eq!(extended_length_path(path), path);
+    }
+    /// 创建一个修改时间为 `modified` 的文件，返回其所在月份
+    fn file_modified_at(path: &Path, modified: DateTime<Utc>) -> BackupPeriod {
+        use chrono::Datelike;
+
+        fs::write(path, "content").unwrap();
+        filetime::set_file_mtime(
+            path,
+            filetime::FileTime::from_unix_time(
+                modified.timestamp(),
+                modified.timestamp_subsec_nanos(),
+            ),
+        )
+        .unwrap();
+        let local = modified.with_timezone(&Local);
+        BackupPeriod::Month(BackupMonth {
+            year: local.year(),
+            month: local.month(),
+        })
+    }
+
+    #[test]
+    fn file_modified_exactly_at_cutoff_is_included() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&source).unwrap();
+        let cutoff = Utc::now() - chrono::Duration::minutes(5);
+        let month = file_modified_at(&source.join("same.dat"), cutoff);
+        file_modified_at(
+            &source.join("before.dat"),
+            cutoff - chrono::Duration::nanoseconds(1000),
+        );
+
+        let found = find_files_to_backup(
+            &source,
+            &cutoff,
+            &month,
+            &[],
+            &ScanFilters::default(),
+            &|_| {},
+        )
+        .unwrap()
+        .files;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, source.join("same.dat"));
+        assert_eq!(modified_after(found, &cutoff, &HashSet::new()).len(), 1);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn weekly_scan_covers_monday_to_sunday() {
+        use crate::backup_logic::BackupWeek;
+
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&source).unwrap();
+        let week = BackupWeek {
+            year: 2025,
+            week: 31,
+        };
+        let local = |day: u32, hour: u32| {
+            Local
+                .with_ymd_and_hms(2025, 7, day, hour, 0, 0)
+                .unwrap()
+                .to_utc()
+        };
+        // 2025 年第 31 周为 7 月 28 日（周一）到 8 月 3 日（周日）
+        file_modified_at(&source.join("sunday-before.dat"), local(27, 23));
+        file_modified_at(&source.join("monday.dat"), local(28, 0));
+        file_modified_at(&source.join("thursday.dat"), local(31, 12));
+        let sunday = Local
+            .with_ymd_and_hms(2025, 8, 3, 23, 0, 0)
+            .unwrap()
+            .to_utc();
+        file_modified_at(&source.join("sunday.dat"), sunday);
+        let next_monday = Local
+            .with_ymd_and_hms(2025, 8, 4, 0, 0, 0)
+            .unwrap()
+            .to_utc();
+        file_modified_at(&source.join("next-monday.dat"), next_monday);
+
+        let found: Vec<PathBuf> = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &BackupPeriod::IsoWeek(week),
+            &[],
+            &ScanFilters::default(),
+            &|_| {},
+        )
+        .unwrap()
+        .files
+        .into_iter()
+        .map(|f| f.path)
+        .collect();
+        let expected: Vec<PathBuf> = ["monday.dat", "sunday.dat", "thursday.dat"]
+            .iter()
+            .map(|name| source.join(name))
+            .collect();
+        assert_eq!(found, expected);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn range_scan_includes_the_start_and_excludes_the_end() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&source).unwrap();
+        let from = Utc.with_ymd_and_hms(2024, 6, 15, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2024, 9, 1, 0, 0, 0).unwrap();
+        // 跨越多个月份，不受月份边界影响
+        file_modified_at(
+            &source.join("before.dat"),
+            from - chrono::Duration::seconds(1),
+        );
+        file_modified_at(&source.join("start.dat"), from);
+        file_modified_at(
+            &source.join("july.dat"),
+            Utc.with_ymd_and_hms(2024, 7, 31, 12, 0, 0).unwrap(),
+        );
+        file_modified_at(&source.join("last.dat"), to - chrono::Duration::seconds(1));
+        file_modified_at(&source.join("end.dat"), to);
+
+        let found: Vec<PathBuf> =
+            find_files_in_range(&source, &from, &to, &[], &ScanFilters::default(), &|_| {})
+                .unwrap()
+                .files
+                .into_iter()
+                .map(|f| f.path)
+                .collect();
+        let expected: Vec<PathBuf> = ["july.dat", "last.dat", "start.dat"]
+            .iter()
+            .map(|name| source.join(name))
+            .collect();
+        assert_eq!(found, expected);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn backupignore_rules_apply_relative_to_the_source_root() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        for dir in [
+            "wxid_a/FileStorage/Cache",
+            "wxid_a/Msg",
+            "wxid_b",
+            "important",
+        ] {
+            fs::create_dir_all(source.join(dir)).unwrap();
+        }
+        let now = Utc::now();
+        let month = file_modified_at(&source.join("wxid_a/FileStorage/Cache/x.dat"), now);
+        for file in [
+            "wxid_a/Msg/msg0.db",
+            "wxid_a/Msg/upload.tmp",
+            "wxid_a/top.log",
+            "wxid_b/Cache",
+            "important/keep.tmp",
+            "important/drop.dat",
+            "top.log",
+        ] {
+            file_modified_at(&source.join(file), now);
+        }
+        let ignore_path = source.join(BACKUP_IGNORE_FILE);
+        fs::write(
+            &ignore_path,
+            "# 缓存目录\nCache/\n*.tmp\n!important/**\n/top.log\n",
+        )
+        .unwrap();
+
+        let mut filters = ScanFilters::new(&["important/drop.dat".to_string()], &[], &[]).unwrap();
+        assert!(filters.load_ignore_file(&source, &ignore_path).unwrap());
+        let scanned = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &filters,
+            &|_| {},
+        )
+        .unwrap();
+
+        let found: Vec<_> = scanned.files.iter().map(|f| f.path.clone()).collect();
+        assert_eq!(
+            found,
+            vec![
+                source.join(BACKUP_IGNORE_FILE),
+                // 否定模式恢复了被 `*.tmp` 忽略的文件
+                source.join("important/keep.tmp"),
+                // `/top.log` 只匹配源目录下的文件
+                source.join("wxid_a/Msg/msg0.db"),
+                source.join("wxid_a/top.log"),
+                // `Cache/` 只匹配目录
+                source.join("wxid_b/Cache"),
+            ]
+        );
+        assert_eq!(
+            scanned.pruned_dirs,
+            vec![source.join("wxid_a/FileStorage/Cache")]
+        );
+        // upload.tmp、top.log，以及被 --exclude 排除、否定模式无法恢复的 drop.dat
+        assert_eq!(scanned.excluded_files, 3);
+
+        let mut missing = ScanFilters::default();
+        assert!(
+            !missing
+                .load_ignore_file(&source, &source.join("missing"))
+                .unwrap()
+        );
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn default_excludes_skip_wechat_temp_files() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let msg = source.join("wxid_a").join("Msg");
+        let temp = source.join("wxid_a").join("Temp");
+        fs::create_dir_all(&msg).unwrap();
+        fs::create_dir_all(&temp).unwrap();
+        let now = Utc::now();
+        let month = file_modified_at(&msg.join("MicroMsg.db"), now);
+        for name in ["MicroMsg.db-wal", "MicroMsg.db-shm", "MicroMsg.db.lock"] {
+            file_modified_at(&msg.join(name), now);
+        }
+        file_modified_at(&temp.join("upload.jpg"), now);
+
+        let scan = |filters: &ScanFilters| {
+            find_files_to_backup(
+                &source,
+                &DateTime::UNIX_EPOCH,
+                &month,
+                &[],
+                filters,
+                &|_| {},
+            )
+            .unwrap()
+        };
+        let mut filters = ScanFilters::new(&["*.db".to_string()], &[], &[]).unwrap();
+        filters.use_default_excludes();
+        let scanned = scan(&filters);
+        assert!(scanned.files.is_empty());
+        // 默认排除在 --exclude 之前应用，分别计数
+        assert_eq!(scanned.default_excluded_files, 3);
+        assert_eq!(scanned.excluded_files, 1);
+        assert_eq!(scanned.default_pruned_dirs, vec![temp.clone()]);
+
+        let mut filters = ScanFilters::default();
+        filters.use_default_excludes();
+        let found: Vec<PathBuf> = scan(&filters).files.into_iter().map(|f| f.path).collect();
+        assert_eq!(found, vec![msg.join("MicroMsg.db")]);
+        assert_eq!(scan(&ScanFilters::default()).files.len(), 5);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_unicode_file_names_are_skipped() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&source).unwrap();
+        let now = Utc::now();
+        let month = file_modified_at(&source.join("聊天记录（重要）.dat"), now);
+        // GBK 编码的“中文.dat”，不是合法的 UTF-8
+        let invalid = source.join(OsStr::from_bytes(b"\xd6\xd0\xce\xc4.dat"));
+        file_modified_at(&invalid, now);
+
+        let scanned = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &ScanFilters::default(),
+            &|_| {},
+        )
+        .unwrap();
+        let found: Vec<PathBuf> = scanned.files.into_iter().map(|f| f.path).collect();
+        assert_eq!(found, vec![source.join("聊天记录（重要）.dat")]);
+        assert_eq!(scanned.skipped.len(), 1);
+        assert_eq!(scanned.skipped[0].path, invalid);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn own_archives_and_cache_are_skipped() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let msg = source.join("wxid_a").join("Msg");
+        let old_backups = source.join("old backups");
+        fs::create_dir_all(&msg).unwrap();
+        fs::create_dir_all(old_backups.join(".cache")).unwrap();
+        let now = Utc::now();
+        let month = file_modified_at(&msg.join("MicroMsg.db"), now);
+        let artifacts = vec![
+            old_backups.join(".cache").join("backupEvents.json"),
+            old_backups.join("2025-06_backup_20250701000000.zip"),
+            old_backups.join("2025-06_wxid_a_backup_20250701000000.part1.zip.age"),
+            old_backups.join("2025-07_backup_20250801000000.tar.zst.partial"),
+        ];
+        for artifact in &artifacts {
+            file_modified_at(artifact, now);
+        }
+        // 名字相近但不是本工具生成的文件照常备份
+        file_modified_at(&old_backups.join("photos_backup.zip"), now);
+        file_modified_at(&msg.join("backupEvents.json"), now);
+
+        let scan = |filters: &ScanFilters| {
+            find_files_to_backup(
+                &source,
+                &DateTime::UNIX_EPOCH,
+                &month,
+                &[],
+                filters,
+                &|_| {},
+            )
+            .unwrap()
+        };
+        let mut filters = ScanFilters::default();
+        filters.skip_own_artifacts();
+        let scanned = scan(&filters);
+        let found: Vec<PathBuf> = scanned.files.into_iter().map(|f| f.path).collect();
+        assert_eq!(
+            found,
+            vec![
+                old_backups.join("photos_backup.zip"),
+                msg.join("MicroMsg.db"),
+                msg.join("backupEvents.json"),
+            ]
+        );
+        let mut expected = artifacts.clone();
+        expected.sort();
+        assert_eq!(scanned.own_artifacts, expected);
+
+        // 排除的文件不会被报告
+        let mut filters = ScanFilters::new(&["old backups/**".to_string()], &[], &[]).unwrap();
+        filters.skip_own_artifacts();
+        assert!(scan(&filters).own_artifacts.is_empty());
+
+        assert_eq!(scan(&ScanFilters::default()).files.len(), 7);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn include_filters_keep_only_matching_files() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let msg = source.join("wxid_a").join("Msg");
+        let image = source.join("wxid_a").join("FileStorage").join("Image");
+        fs::create_dir_all(&msg).unwrap();
+        fs::create_dir_all(&image).unwrap();
+        let now = Utc::now();
+        let month = file_modified_at(&msg.join("msg0.db"), now);
+        file_modified_at(&msg.join("msg0.db-journal"), now);
+        file_modified_at(&msg.join("excluded.db"), now);
+        file_modified_at(&msg.join("notes.txt"), now);
+        file_modified_at(&image.join("photo.JPG"), now);
+        file_modified_at(&image.join("thumb.dat"), now);
+
+        // 目录本身不匹配任何包含条件，仍然会进入其中查找文件
+        let filters = ScanFilters::new(
+            &["**/excluded.db".to_string()],
+            &["**/Msg/*.db".to_string()],
+            &[".jpg".to_string(), "DAT".to_string()],
+        )
+        .unwrap();
+        let scanned = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &filters,
+            &|_| {},
+        )
+        .unwrap();
+
+        let mut found: Vec<_> = scanned.files.into_iter().map(|f| f.path).collect();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                image.join("photo.JPG"),
+                image.join("thumb.dat"),
+                msg.join("msg0.db")
+            ]
+        );
+        // 排除优先于包含
+        assert_eq!(scanned.excluded_files, 1);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn files_outside_size_limits_are_reported() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&source).unwrap();
+        let now = Utc::now();
+        let month = file_modified_at(&source.join("tiny.dat"), now);
+        for (name, size) in [("small.dat", 10), ("exact.dat", 100), ("video.mp4", 101)] {
+            file_modified_at(&source.join(name), now);
+            fs::File::options()
+                .write(true)
+                .open(source.join(name))
+                .unwrap()
+                .set_len(size)
+                .unwrap();
+        }
+
+        let filters = ScanFilters {
+            min_file_size: Some(8),
+            max_file_size: Some(100),
+            ..Default::default()
+        };
+        let scanned = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &filters,
+            &|_| {},
+        )
+        .unwrap();
+
+        let mut found: Vec<_> = scanned.files.into_iter().map(|f| f.path).collect();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![source.join("exact.dat"), source.join("small.dat")]
+        );
+        let mut filtered: Vec<_> = scanned.size_filtered.into_iter().map(|f| f.path).collect();
+        filtered.sort();
+        assert_eq!(
+            filtered,
+            vec![source.join("tiny.dat"), source.join("video.mp4")]
+        );
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn excluded_directories_are_pruned_and_files_skipped() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let cache = source.join("wxid_a").join("FileStorage").join("Cache");
+        let now = Utc::now();
+        // 体积很大的缓存目录：如果没有被整体跳过，其中的每个文件都会计入排除数
+        for dir in 0..20 {
+            let nested = cache.join(format!("{:02}", dir));
+            fs::create_dir_all(&nested).unwrap();
+            for file in 0..50 {
+                file_modified_at(&nested.join(format!("{}.dat", file)), now);
+            }
+        }
+        fs::create_dir_all(source.join("wxid_a").join("Msg")).unwrap();
+        let month = file_modified_at(&source.join("wxid_a").join("Msg").join("msg0.db"), now);
+        file_modified_at(&source.join("wxid_a").join("Msg").join("msg0.db.tmp"), now);
+        file_modified_at(&source.join("upload.tmp"), now);
+
+        let filters = ScanFilters::new(
+            &[
+                "*.tmp".to_string(),
+                r"wxid_*\FileStorage\Cache\**".to_string(),
+            ],
+            &[],
+            &[],
+        )
+        .unwrap();
+        let scanned = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &filters,
+            &|_| {},
+        )
+        .unwrap();
+
+        assert_eq!(
+            scanned.files,
+            vec![FileEntry {
+                path: source.join("wxid_a").join("Msg").join("msg0.db"),
+                relative_path: Path::new("wxid_a").join("Msg").join("msg0.db"),
+                size: 7,
+                modified: now,
+                source_label: None,
+            }]
+        );
+        assert_eq!(scanned.excluded_files, 2);
+        assert_eq!(scanned.pruned_dirs, vec![cache]);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_skipped_followed_or_preserved() {
+        use std::os::unix::fs::symlink;
+
+        let root = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let source = root.join("source");
+        let outside = root.join("outside");
+        fs::create_dir_all(source.join("Msg")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        let month = file_modified_at(&source.join("Msg").join("msg0.db"), Utc::now());
+        file_modified_at(&outside.join("photo.jpg"), Utc::now());
+        symlink(source.join("Msg").join("msg0.db"), source.join("file-link")).unwrap();
+        symlink(&outside, source.join("dir-link")).unwrap();
+        // 指向上级目录的链接，跟随时会无限循环
+        symlink(&source, source.join("Msg").join("loop")).unwrap();
+
+        let scan = |symlinks| {
+            let filters = ScanFilters {
+                symlinks,
+                ..Default::default()
+            };
+            find_files_to_backup(
+                &source,
+                &DateTime::UNIX_EPOCH,
+                &month,
+                &[],
+                &filters,
+                &|_| {},
+            )
+            .unwrap()
+        };
+        let paths = |scanned: &ScanResult| -> Vec<PathBuf> {
+            scanned.files.iter().map(|f| f.path.clone()).collect()
+        };
+
+        let skipped = scan(SymlinkPolicy::Skip);
+        assert_eq!(paths(&skipped), vec![source.join("Msg").join("msg0.db")]);
+        assert_eq!(
+            skipped.skipped_symlinks,
+            vec![
+                source.join("Msg").join("loop"),
+                source.join("dir-link"),
+                source.join("file-link")
+            ]
+        );
+
+        let followed = scan(SymlinkPolicy::Follow);
+        assert_eq!(
+            paths(&followed),
+            vec![
+                source.join("Msg").join("msg0.db"),
+                source.join("dir-link").join("photo.jpg"),
+                source.join("file-link")
+            ]
+        );
+        assert_eq!(followed.skipped.len(), 1);
+        assert_eq!(followed.skipped[0].path, source.join("Msg").join("loop"));
+
+        // 链接本身作为文件条目，不进入链接指向的目录
+        let preserved = scan(SymlinkPolicy::Preserve);
+        assert_eq!(
+            paths(&preserved),
+            vec![
+                source.join("Msg").join("loop"),
+                source.join("Msg").join("msg0.db"),
+                source.join("dir-link"),
+                source.join("file-link")
+            ]
+        );
+        assert!(preserved.skipped.is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn month_can_be_taken_from_the_path() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let image = source.join("wxid_a").join("FileStorage").join("Image");
+        let msg = source.join("wxid_a").join("Msg");
+        for dir in ["2025-03", "2025-07", "2025-13"] {
+            fs::create_dir_all(image.join(dir)).unwrap();
+        }
+        fs::create_dir_all(&msg).unwrap();
+        // 三月的图片在七月被重新下载，修改时间落在七月
+        let july = Local
+            .with_ymd_and_hms(2025, 7, 15, 12, 0, 0)
+            .unwrap()
+            .to_utc();
+        let march = Local
+            .with_ymd_and_hms(2025, 3, 15, 12, 0, 0)
+            .unwrap()
+            .to_utc();
+        file_modified_at(&image.join("2025-03").join("redownloaded.dat"), july);
+        file_modified_at(&image.join("2025-07").join("new.dat"), july);
+        // 路径中没有月份、或月份无效时按修改时间确定
+        file_modified_at(&msg.join("msg0.db"), july);
+        file_modified_at(&image.join("2025-13").join("odd.dat"), march);
+        let month = |month| BackupPeriod::Month(BackupMonth { year: 2025, month });
+        let scan = |filters: &ScanFilters, month: &BackupPeriod| -> Vec<PathBuf> {
+            let scanned =
+                find_files_to_backup(&source, &DateTime::UNIX_EPOCH, month, &[], filters, &|_| {})
+                    .unwrap();
+            scanned.files.into_iter().map(|f| f.path).collect()
+        };
+
+        let by_mtime = ScanFilters::default();
+        assert_eq!(
+            scan(&by_mtime, &month(7)),
+            vec![
+                image.join("2025-03").join("redownloaded.dat"),
+                image.join("2025-07").join("new.dat"),
+                msg.join("msg0.db")
+            ]
+        );
+        assert_eq!(
+            scan(&by_mtime, &month(3)),
+            vec![image.join("2025-13").join("odd.dat")]
+        );
+
+        let by_path = ScanFilters {
+            month_pattern: Some(Regex::new(DEFAULT_MONTH_PATTERN).unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            scan(&by_path, &month(7)),
+            vec![image.join("2025-07").join("new.dat"), msg.join("msg0.db")]
+        );
+        // 修改时间在七月的三月图片留给调用方记录
+        let scanned = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month(7),
+            &[],
+            &by_path,
+            &|_| {},
+        )
+        .unwrap();
+        assert_eq!(
+            scanned.other_path_months,
+            vec![(
+                image.join("2025-03").join("redownloaded.dat"),
+                BackupMonth {
+                    year: 2025,
+                    month: 3
+                }
+            )]
+        );
+        assert_eq!(
+            scan(&by_path, &month(3)),
+            vec![
+                image.join("2025-03").join("redownloaded.dat"),
+                image.join("2025-13").join("odd.dat")
+            ]
+        );
+
+        // 截止时间仍按修改时间判断
+        let after_july = july + chrono::Duration::days(1);
+        let scanned =
+            find_files_to_backup(&source, &after_july, &month(3), &[], &by_path, &|_| {}).unwrap();
+        assert!(scanned.files.is_empty());
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn scan_progress_counts_directories_and_files() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let now = Utc::now();
+        let mut month = None;
+        for dir in 0..3 {
+            let nested = source.join(format!("{}", dir));
+            fs::create_dir_all(&nested).unwrap();
+            for file in 0..400 {
+                month = Some(file_modified_at(&nested.join(format!("{}.dat", file)), now));
+            }
+        }
+        file_modified_at(&source.join("old.dat"), now - chrono::Duration::days(400));
+        let month = month.unwrap();
+
+        let reports = Mutex::new(Vec::new());
+        let scanned = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &ScanFilters::default(),
+            &|progress| reports.lock().unwrap().push(progress),
+        )
+        .unwrap();
+        let expected = ScanProgress {
+            dirs_visited: 4,
+            files_considered: 1201,
+            files_matched: 1200,
+        };
+        assert_eq!(scanned.progress, expected);
+        // 报告的进度是扫描过程中的快照，不会超过最终计数
+        for report in reports.into_inner().unwrap() {
+            assert!(report.dirs_visited <= expected.dirs_visited);
+            assert!(report.files_considered <= expected.files_considered);
+            assert!(report.files_matched <= report.files_considered);
+        }
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn missing_source_directory_is_an_error() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let month = BackupPeriod::Month(BackupMonth {
+            year: 2025,
+            month: 7,
+        });
+        let error = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &ScanFilters::default(),
+            &|_| {},
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&error, BackupError::ScanError { path, .. } if *path == source),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.kind(), Some(io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn scan_can_be_limited_to_subdirectories_and_depth() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let now = Utc::now();
+        let relative_files = [
+            "config.ini",
+            "Msg/a.db",
+            "Msg/Multi/b.db",
+            "Msg/Multi/deep/c.db",
+            "FileStorage/Cache/z.dat",
+            "FileStorage/MsgAttach/x/y.dat",
+        ];
+        let mut month = None;
+        for relative in relative_files {
+            let path = source.join(relative);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            month = Some(file_modified_at(&path, now));
+        }
+        let month = month.unwrap();
+        let scan = |subdirs: &[&str], max_depth: Option<usize>| {
+            let filters = ScanFilters {
+                subdirs: subdirs.iter().map(PathBuf::from).collect(),
+                max_depth,
+                ..Default::default()
+            };
+            find_files_to_backup(
+                &source,
+                &DateTime::UNIX_EPOCH,
+                &month,
+                &[],
+                &filters,
+                &|_| {},
+            )
+            .map(|scanned| {
+                let mut names: Vec<String> = scanned
+                    .files
+                    .iter()
+                    .map(|f| crate::archiver::entry_name(&f.relative_path))
+                    .collect();
+                names.sort();
+                names
+            })
+        };
+
+        // 相对路径仍然相对于源目录；位于其他所列子目录内部的子目录不会重复扫描
+        assert_eq!(
+            scan(&["Msg", "FileStorage/MsgAttach", "Msg/Multi"], None).unwrap(),
+            vec![
+                "FileStorage/MsgAttach/x/y.dat",
+                "Msg/Multi/b.db",
+                "Msg/Multi/deep/c.db",
+                "Msg/a.db",
+            ]
+        );
+        assert_eq!(
+            scan(&["Msg", "FileStorage/MsgAttach"], Some(2)).unwrap(),
+            vec![
+                "FileStorage/MsgAttach/x/y.dat",
+                "Msg/Multi/b.db",
+                "Msg/a.db"
+            ]
+        );
+        assert_eq!(scan(&[], Some(1)).unwrap(), vec!["config.ini"]);
+        assert!(scan(&["Missing"], None).is_err());
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_directory_is_skipped_and_reported() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        let locked = source.join("wxid_locked");
+        fs::create_dir_all(&locked).unwrap();
+        let month = file_modified_at(&source.join("visible.dat"), Utc::now());
+        file_modified_at(&locked.join("hidden.dat"), Utc::now());
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // root 用户不受目录权限限制，无法模拟读取失败
+        if fs::read_dir(&locked).is_ok() {
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+            fs::remove_dir_all(&source).unwrap();
+            return;
+        }
+
+        let scanned = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &ScanFilters::default(),
+            &|_| {},
+        )
+        .unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(scanned.files.len(), 1);
+        assert_eq!(scanned.files[0].path, source.join("visible.dat"));
+        assert_eq!(scanned.skipped.len(), 1);
+        assert_eq!(scanned.skipped[0].path, locked);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn recently_modified_files_are_deferred() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&source).unwrap();
+        let scan_start = Utc::now();
+        let settle_cutoff = scan_start - chrono::Duration::seconds(30);
+        let month = file_modified_at(
+            &source.join("settled.mp4"),
+            settle_cutoff - chrono::Duration::milliseconds(1),
+        );
+        file_modified_at(&source.join("boundary.mp4"), settle_cutoff);
+        file_modified_at(
+            &source.join("downloading.mp4"),
+            scan_start - chrono::Duration::seconds(1),
+        );
+
+        let filters = ScanFilters {
+            settle_cutoff: Some(settle_cutoff),
+            ..Default::default()
+        };
+        let scanned = find_files_to_backup(
+            &source,
+            &DateTime::UNIX_EPOCH,
+            &month,
+            &[],
+            &filters,
+            &|_| {},
+        )
+        .unwrap();
+        let found: Vec<&PathBuf> = scanned.files.iter().map(|f| &f.path).collect();
+        assert_eq!(found, vec![&source.join("settled.mp4")]);
+        assert_eq!(scanned.deferred_files, 2);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn future_mtimes_are_reported_and_optionally_included() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&source).unwrap();
+        let now = Utc::now();
+        let month = file_modified_at(&source.join("today.dat"), now);
+        // 时钟错误的机器写入的文件
+        file_modified_at(
+            &source.join("clock.dat"),
+            Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap(),
+        );
+        file_modified_at(&source.join("skew.dat"), now + chrono::Duration::seconds(5));
+
+        let mut filters = ScanFilters {
+            future_cutoff: Some(now + chrono::Duration::days(1)),
+            settle_cutoff: Some(now - chrono::Duration::seconds(30)),
+            ..Default::default()
+        };
+        let scan = |filters: &ScanFilters| {
+            find_files_to_backup(
+                &source,
+                &DateTime::UNIX_EPOCH,
+                &month,
+                &[],
+                filters,
+                &|_| {},
+            )
+            .unwrap()
+        };
+        let scanned = scan(&filters);
+        assert!(scanned.files.is_empty());
+        assert_eq!(scanned.future_mtimes, vec![source.join("clock.dat")]);
+        // skew.dat 在允许的时钟偏差之内，与 today.dat 一样被推迟
+        assert_eq!(scanned.deferred_files, 2);
+
+        filters.future_month = Some(month);
+        let scanned = scan(&filters);
+        let found: Vec<&PathBuf> = scanned.files.iter().map(|f| &f.path).collect();
+        assert_eq!(found, vec![&source.join("clock.dat")]);
+        assert_eq!(scanned.future_mtimes, vec![source.join("clock.dat")]);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn overlap_window_covers_timestamps_truncated_to_seconds() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&source).unwrap();
+        // 截止时间带有小数秒，而写入文件的工具把修改时间截断到了整秒
+        let cutoff = DateTime::from_timestamp(Utc::now().timestamp() - 300, 700_000_000).unwrap();
+        let truncated = DateTime::from_timestamp(cutoff.timestamp(), 0).unwrap();
+        let month = file_modified_at(&source.join("truncated.dat"), truncated);
+
+        assert!(
+            find_files_to_backup(
+                &source,
+                &cutoff,
+                &month,
+                &[],
+                &ScanFilters::default(),
+                &|_| {}
+            )
+            .unwrap()
+            .files
+            .is_empty()
+        );
+        let overlapped = cutoff - chrono::Duration::seconds(2);
+        assert_eq!(
+            find_files_to_backup(
+                &source,
+                &overlapped,
+                &month,
+                &[],
+                &ScanFilters::default(),
+                &|_| {}
+            )
+            .unwrap()
+            .files
+            .len(),
+            1
+        );
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn files_are_grouped_by_top_level_directory() {
+        let source = PathBuf::from("WeChat Files");
+        let modified = Utc::now();
+        let entry = |relative: &str| FileEntry {
+            path: source.join(relative),
+            relative_path: PathBuf::from(relative),
+            size: 1,
+            modified,
+            source_label: None,
+        };
+        let files = vec![
+            entry("wxid_a/Msg/a.dat"),
+            entry("config.ini"),
+            entry("wxid_b/b.dat"),
+            entry("wxid_a/a.dat"),
+        ];
+
+        let groups = group_by_top_dir(files);
+        let names: Vec<&str> = groups.keys().map(String::as_str).collect();
+        assert_eq!(names, vec![ROOT_GROUP, "wxid_a", "wxid_b"]);
+        assert_eq!(
+            groups["wxid_a"],
+            vec![entry("wxid_a/Msg/a.dat"), entry("wxid_a/a.dat")]
+        );
+        assert_eq!(groups[ROOT_GROUP], vec![entry("config.ini")]);
+    }
+
+    #[test]
+    fn changes_are_detected_by_size_and_mtime_or_hash() {
+        let source = std::env::temp_dir().join(format!("dat-patch-scan-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(source.join("Msg")).unwrap();
+        // 同步工具把修改时间重置成了很早以前的固定值
+        let reset = Utc.with_ymd_and_hms(2025, 7, 1, 8, 0, 0).unwrap();
+        let month = file_modified_at(&source.join("Msg").join("same.dat"), reset);
+        file_modified_at(&source.join("Msg").join("edited.dat"), reset);
+        let scan = || {
+            find_files_to_backup(
+                &source,
+                &DateTime::UNIX_EPOCH,
+                &month,
+                &[],
+                &ScanFilters::default(),
+                &|_| {},
+            )
+            .unwrap()
+            .files
+        };
+        let hash_index = scan_index_records(&scan(), ChangeDetection::Hash);
+        let size_mtime_index = scan_index_records(&scan(), ChangeDetection::SizeMtime);
+        assert_eq!(hash_index.len(), 2);
+        assert!(size_mtime_index.values().all(|r| r.hash.is_none()));
+
+        // 内容变化但大小和修改时间不变，以及一个新文件
+        fs::write(source.join("Msg").join("edited.dat"), "CONTENT").unwrap();
+        filetime::set_file_mtime(
+            source.join("Msg").join("edited.dat"),
+            filetime::FileTime::from_unix_time(reset.timestamp(), 0),
+        )
+        .unwrap();
+        file_modified_at(&source.join("Msg").join("new.dat"), reset);
+
+        let names = |files: &[FileEntry]| -> Vec<String> {
+            files
+                .iter()
+                .map(|f| crate::archiver::entry_name(&f.relative_path))
+                .collect()
+        };
+        let (changed, records) = detect_changes(scan(), &hash_index, ChangeDetection::Hash);
+        assert_eq!(names(&changed), vec!["Msg/edited.dat", "Msg/new.dat"]);
+        assert_eq!(
+            records.keys().collect::<Vec<_>>(),
+            vec!["Msg/edited.dat", "Msg/new.dat"]
+        );
+        assert_ne!(records["Msg/edited.dat"], hash_index["Msg/edited.dat"]);
+
+        let (changed, _) = detect_changes(scan(), &size_mtime_index, ChangeDetection::SizeMtime);
+        assert_eq!(names(&changed), vec!["Msg/new.dat"]);
+        // 没有哈希的旧记录在 hash 模式下视为已变化
+        let (changed, _) = detect_changes(scan(), &size_mtime_index, ChangeDetection::Hash);
+        assert_eq!(changed.len(), 3);
+        fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn files_with_unchanged_content_are_skipped() {
+        let source = std::env::temp_dir().join(format!("dat-patch-dedup-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(source.join("Msg")).unwrap();
+        let unchanged = source.join("Msg").join("same.dat");
+        let edited = source.join("Msg").join("edited.dat");
+        let new = source.join("Msg").join("new.dat");
+        fs::write(&unchanged, b"same content").unwrap();
+        fs::write(&edited, b"new content!").unwrap();
+        fs::write(&new, b"brand new").unwrap();
+        // 从 old.dat 重命名而来
+        let renamed = source.join("Msg").join("renamed.dat");
+        fs::write(&renamed, b"moved around").unwrap();
+
+        // 记录的哈希与 same.dat 一致；edited.dat 大小相同但内容不同
+        let mut index = FileHashIndex::new();
+        for (name, content) in [
+            ("Msg/same.dat", "same content"),
+            ("Msg/edited.dat", "old content!"),
+            ("Msg/old.dat", "moved around"),
+        ] {
+            index.insert(
+                name.to_string(),
+                crate::cache::FileHashRecord {
+                    size: content.len() as u64,
+                    modified: None,
+                    sha256: Sha256::digest(content)
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect(),
+                },
+            );
+        }
+        let files: Vec<FileEntry> = [&unchanged, &edited, &new, &renamed]
+            .into_iter()
+            .map(|path| {
+                let metadata = fs::metadata(path).unwrap();
+                FileEntry {
+                    path: path.clone(),
+                    relative_path: path.strip_prefix(&source).unwrap().to_path_buf(),
+                    size: metadata.len(),
+                    modified: metadata.modified().unwrap().into(),
+                    source_label: None,
+                }
+            })
+            .collect();
+
+        let (remaining, skipped) = skip_unchanged_files(files.clone(), &index);
+        assert_eq!(skipped, 2);
+        let remaining: Vec<&PathBuf> = remaining.iter().map(|f| &f.path).collect();
+        assert_eq!(remaining, vec![&edited, &new]);
+
+        // 同一路径的记录优先：内容变回另一路径记录过的内容也视为已变化
+        fs::write(&edited, b"same content").unwrap();
+        let (remaining, _) = skip_unchanged_files(files[1..2].to_vec(), &index);
+        assert_eq!(remaining.len(), 1);
+        fs::remove_dir_all(&source).unwrap();
+    }
 }
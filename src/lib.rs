@@ -0,0 +1,23 @@
+//! 微信数据的增量备份：按月份扫描新增和修改的文件，归档到目标目录并滚动删除旧备份。
+//!
+//! 命令行工具 `dat-patch-rust` 只是 [`run_backup`] 的一层包装；
+//! 其他程序可以直接调用它，通过 [`BackupOptions::on_event`] 接收进度和消息。
+
+pub mod archiver;
+pub mod backup;
+pub mod backup_logic;
+pub mod cache;
+pub mod cleaner;
+pub mod config;
+pub mod differ;
+pub mod disk_space;
+pub mod encryption;
+pub mod error;
+pub mod file_scanner;
+pub mod logging;
+pub mod restorer;
+pub mod timezone;
+pub mod verifier;
+
+pub use backup::{BackupEvent, BackupOptions, BackupReport, run_backup};
+pub use error::BackupError;
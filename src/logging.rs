@@ -0,0 +1,304 @@
+//! 日志：通过 [`log`] 门面输出到控制台，并追加到按大小轮转的日志文件
+//!
+//! 库本身只调用 `log` 的宏（以及通过 [`crate::BackupOptions::on_event`] 报告事件），
+//! 是否输出、输出到哪里由调用方安装的 [`Logger`] 决定。
+
+use chrono::Local;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// 静默运行时日志文件的默认文件名，位于缓存目录中
+pub const DEFAULT_LOG_FILE_NAME: &str = "backup.log";
+
+/// 日志文件超过该大小时轮转
+pub const DEFAULT_MAX_LOG_SIZE: u64 = 5 * 1024 * 1024;
+
+/// 轮转时保留的旧日志文件数：`backup.log.1`（最新）到 `backup.log.3`
+pub const KEPT_LOG_FILES: usize = 3;
+
+/// 使用该 target 的警告即使在静默模式下也显示在控制台，
+/// 例如缓存损坏、文件被跳过等需要用户处理的情况
+pub const ALERT_TARGET: &str = "alert";
+
+/// 使用该 target 的记录只写入日志文件，不显示在控制台
+pub const FILE_TARGET: &str = "log_file";
+
+/// 追加写入的日志文件，超过 `max_size` 时改名为 `<name>.1` 并开始新文件
+pub struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    /// 该目录存在后才创建日志文件（及其上级目录），之前的行暂存在内存中；
+    /// 用于位于目标目录中的默认日志文件，选项无效时不会因为写日志创建目标目录
+    wait_for: Option<PathBuf>,
+    file: Option<File>,
+    size: u64,
+    pending: Vec<String>,
+}
+
+impl LogFile {
+    /// # Arguments
+    /// * `path` - 日志文件，上级目录不存在时自动创建
+    /// * `max_size` - 轮转的大小
+    /// * `wait_for` - 该目录存在之前不创建任何文件
+    pub fn new(path: impl Into<PathBuf>, max_size: u64, wait_for: Option<PathBuf>) -> Self {
+        LogFile {
+            path: path.into(),
+            max_size,
+            wait_for,
+            file: None,
+            size: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// 日志文件的路径
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 追加一行（不含换行符）
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.file.is_none() {
+            if self.wait_for.as_ref().is_some_and(|dir| !dir.is_dir()) {
+                self.pending.push(line.to_string());
+                return Ok(());
+            }
+            self.open()?;
+            for pending in std::mem::take(&mut self.pending) {
+                self.append(&pending)?;
+            }
+        }
+        self.append(line)
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        let bytes = line.len() as u64 + 1;
+        if self.size > 0 && self.size + bytes > self.max_size {
+            self.rotate()?;
+        }
+        let file = self.file.as_mut().expect("log file is open");
+        writeln!(file, "{}", line)?;
+        self.size += bytes;
+        Ok(())
+    }
+
+    /// `<name>.2` -> `<name>.3`，`<name>.1` -> `<name>.2`，`<name>` -> `<name>.1`，再创建新文件
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        let numbered = |n: usize| crate::archiver::with_suffix(&self.path, &format!(".{}", n));
+        // Windows 上改名不会覆盖已有的文件
+        if numbered(KEPT_LOG_FILES).exists() {
+            fs::remove_file(numbered(KEPT_LOG_FILES))?;
+        }
+        for n in (1..KEPT_LOG_FILES).rev() {
+            if numbered(n).exists() {
+                fs::rename(numbered(n), numbered(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, numbered(1))?;
+        self.open()
+    }
+}
+
+/// 把日志显示在控制台的函数，由调用方决定输出方式（例如先隐藏进度条）
+pub type ConsoleWriter = Box<dyn Fn(Level, &str) + Send + Sync>;
+
+/// 同时输出到控制台和日志文件的 [`Log`] 实现
+pub struct Logger {
+    console_level: LevelFilter,
+    console: ConsoleWriter,
+    file_level: LevelFilter,
+    /// 无法写入时关闭，之后只输出到控制台
+    file: Mutex<Option<LogFile>>,
+}
+
+impl Logger {
+    /// # Arguments
+    /// * `console_level` - 控制台显示的最低级别；[`ALERT_TARGET`] 的警告不受限制
+    /// * `console` - 显示一条记录
+    /// * `file` - 日志文件及写入的最低级别
+    pub fn new(
+        console_level: LevelFilter,
+        console: ConsoleWriter,
+        file: Option<(LogFile, LevelFilter)>,
+    ) -> Self {
+        let (file, file_level) = match file {
+            Some((file, level)) => (Some(file), level),
+            None => (None, LevelFilter::Off),
+        };
+        Logger {
+            console_level,
+            console,
+            file_level,
+            file: Mutex::new(file),
+        }
+    }
+
+    /// 安装为全局的日志实现；只能调用一次
+    pub fn install(self) -> Result<(), log::SetLoggerError> {
+        let max_level = self
+            .console_level
+            .max(self.file_level)
+            .max(LevelFilter::Warn);
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    fn shows_on_console(&self, record: &Record) -> bool {
+        match record.target() {
+            FILE_TARGET => false,
+            ALERT_TARGET => record.level() <= Level::Warn,
+            _ => record.level() <= self.console_level,
+        }
+    }
+}
+
+/// 日志文件中的一行或多行：每行以本地时间和级别开头，多行消息的每一行都带前缀，便于 grep
+fn format_lines(level: Level, message: &str) -> Vec<String> {
+    let prefix = format!(
+        "{} [{:<5}]",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        level
+    );
+    message
+        .trim_start_matches('\n')
+        .lines()
+        .map(|line| format!("{} {}", prefix, line))
+        .collect()
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.console_level.max(self.file_level)
+            || (metadata.target() == ALERT_TARGET && metadata.level() <= Level::Warn)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        if self.shows_on_console(record) {
+            (self.console)(record.level(), &message);
+        }
+        if record.level() > self.file_level {
+            return;
+        }
+        let mut file = self.file.lock().unwrap();
+        if let Some(log_file) = file.as_mut()
+            && let Err(e) = format_lines(record.level(), &message)
+                .iter()
+                .try_for_each(|line| log_file.write_line(line))
+        {
+            // 日志文件无法写入时提示一次，之后只输出到控制台
+            (self.console)(
+                Level::Warn,
+                &format!(
+                    "Failed to write the log file '{}': {}",
+                    log_file.path().display(),
+                    e
+                ),
+            );
+            *file = None;
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(LogFile {
+            file: Some(file), ..
+        }) = &mut *self.file.lock().unwrap()
+        {
+            let _ = file.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_file_rotates_and_keeps_a_few_old_files() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-log-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("nested").join(DEFAULT_LOG_FILE_NAME);
+        let mut log_file = LogFile::new(&path, 20, None);
+        for n in 0..6 {
+            // 每行 10 字节（含换行符），两行一个文件
+            log_file.write_line(&format!("line {:04}", n)).unwrap();
+        }
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "line 0004\nline 0005\n");
+        assert_eq!(
+            read(&dir.join("nested/backup.log.1")),
+            "line 0002\nline 0003\n"
+        );
+        assert_eq!(
+            read(&dir.join("nested/backup.log.2")),
+            "line 0000\nline 0001\n"
+        );
+
+        for n in 6..12 {
+            log_file.write_line(&format!("line {:04}", n)).unwrap();
+        }
+        assert_eq!(
+            read(&dir.join("nested/backup.log.3")),
+            "line 0004\nline 0005\n"
+        );
+        assert!(!dir.join("nested/backup.log.4").exists());
+
+        // 重新打开时继续追加并计入已有的大小
+        let mut log_file = LogFile::new(&path, 20, None);
+        log_file.write_line("line 0012").unwrap();
+        assert_eq!(read(&path), "line 0012\n");
+        assert_eq!(
+            read(&dir.join("nested/backup.log.1")),
+            "line 0010\nline 0011\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lines_wait_until_the_directory_exists() {
+        let dir = std::env::temp_dir().join(format!("dat-patch-log-{}", uuid::Uuid::new_v4()));
+        let path = dir.join(".cache").join(DEFAULT_LOG_FILE_NAME);
+        let mut log_file = LogFile::new(&path, DEFAULT_MAX_LOG_SIZE, Some(dir.clone()));
+        log_file.write_line("first").unwrap();
+        assert!(!dir.exists());
+
+        fs::create_dir_all(&dir).unwrap();
+        log_file.write_line("second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn every_line_of_a_message_is_prefixed() {
+        let lines = format_lines(Level::Error, "\nSkipped 2 files:\n  a.dat\n  b.dat");
+        assert_eq!(lines.len(), 3);
+        assert!(
+            lines.iter().all(|line| line.contains(" [ERROR] ")),
+            "{:?}",
+            lines
+        );
+        assert!(lines[2].ends_with("   b.dat"), "{:?}", lines);
+    }
+}
@@ -1,242 +1,2483 @@
-use chrono::Utc;
-use clap::Parser;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use dat_patch_rust::archiver::{self, ArchiveFormat};
+use dat_patch_rust::backup::{self, BackupEvent, BackupOptions, BackupReport};
+use dat_patch_rust::backup_logic::{self, BackupMode, BackupMonth, BackupPeriod};
+use dat_patch_rust::cache::{self, CacheBackend, CacheStore};
+use dat_patch_rust::cleaner::{self, RetentionBy, RetentionPolicy};
+use dat_patch_rust::config::{self, ConfigFile};
+use dat_patch_rust::differ::{self, FileChange};
+use dat_patch_rust::error::BackupError;
+use dat_patch_rust::file_scanner::{self, ChangeDetection, MonthSource, SymlinkPolicy};
+use dat_patch_rust::logging::{self, LogFile, Logger};
+use dat_patch_rust::restorer;
+use dat_patch_rust::timezone::MonthBoundaryTz;
+use dat_patch_rust::verifier;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use log::{Level, LevelFilter, debug, error, info, warn};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::path::{Component, Path, PathBuf};
 use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-mod archiver;
-mod backup_logic;
-mod cache;
-mod cleaner;
-mod file_scanner;
+/// 部分归档未能创建或滚动删除失败时的退出码；全部失败时退出码为 1
+const EXIT_PARTIAL_FAILURE: i32 = 2;
 
-use backup_logic::{BackupMode, determine_backup_months};
+/// 另一个实例正在向同一目标目录备份时的退出码
+const EXIT_ALREADY_RUNNING: i32 = 3;
+
+/// 选项无效或相互矛盾时的退出码
+const EXIT_CONFIG_ERROR: i32 = 4;
+
+/// 源目录不存在或无法扫描时的退出码
+const EXIT_SCAN_ERROR: i32 = 5;
+
+/// 无法写入目标目录时的退出码
+const EXIT_ARCHIVE_ERROR: i32 = 6;
+
+/// 无法读写缓存时的退出码，例如缓存文件的格式版本过新
+const EXIT_CACHE_ERROR: i32 = 7;
+
+/// 无法清理目标目录时的退出码
+const EXIT_CLEANUP_ERROR: i32 = 8;
+
+/// `--help` 末尾列出的退出码
+const EXIT_STATUS_HELP: &str = "Exit status:
+  0  Success
+  1  No archive could be created
+  2  Some archives or the cleanup failed
+  3  Another backup into the same --to is running
+  4  Invalid options
+  5  A source path is missing or cannot be scanned
+  6  The destination cannot be written
+  7  The cache cannot be read or written
+  8  The destination cannot be cleaned up";
 
 /// Incremental backup script for WeChat data, rewritten in Rust.
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// The source path (WeChat root directory) to back up.
+#[command(
+    version,
+    about,
+    long_about = None,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true,
+    after_help = EXIT_STATUS_HELP
+)]
+struct Cli {
+    /// Run a command; without one the options below run a backup, like `backup`.
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    backup: BackupArgs,
+}
+
+/// 备份的选项：直接写在命令行上（兼容旧的用法），或写在 `backup` 子命令之后
+#[derive(clap::Args, Debug, Clone)]
+struct BackupArgs {
+    /// Read option values from this TOML file, with sections [paths], [mode], [retention],
+    /// [excludes], [archive] and [run] whose keys are the long option names
+    /// (e.g. keep-months = 12). Options given on the command line take precedence.
+    /// Without it, dat-patch.toml next to the executable or in the user config folder
+    /// is used if it exists.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Print the effective configuration (defaults, config file and command line merged)
+    /// as TOML, noting where each value comes from, and exit.
+    #[arg(long)]
+    print_config: bool,
+
+    /// The source path (WeChat root directory) to back up. May be given multiple times;
+    /// each source then goes into its own top-level folder of the archive (source0/, source1/, ...).
+    #[arg(long, required_unless_present_any = ["show_info", "restore", "print_config"])]
+    from: Vec<PathBuf>,
+
+    /// Name of the top-level archive folder for the corresponding --from, in the same
+    /// order. Must be given once per --from; also works with a single --from.
+    #[arg(long, value_name = "LABEL")]
+    from_label: Vec<String>,
+
+    /// Identify this source in the backup records of --to by this name instead of the
+    /// canonical --from path(s), e.g. to keep a source's history after moving it.
+    /// Each source backed up into the same --to gets its own cutoffs.
+    #[arg(long, value_name = "LABEL")]
+    source_label: Option<String>,
+
+    /// The destination path for storing backup .zip and .cache (see --cache-dir).
+    #[arg(long, required_unless_present_any = ["show_info", "restore", "print_config"])]
+    to: Option<PathBuf>,
+
+    /// Allow the destination to live inside the source; it is then excluded from the scan.
+    #[arg(long)]
+    allow_nested_destination: bool,
+
+    /// Remove `.partial` files and temporary directories left in --to by interrupted
+    /// runs once they have not been modified for this many hours.
+    #[arg(long, value_name = "HOURS", default_value_t = 24)]
+    stale_grace_hours: u64,
+
+    /// Skip files and directories whose path relative to --from matches this glob,
+    /// e.g. `*.tmp` or `FileStorage/Cache/**`. May be given multiple times.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Do not skip the WeChat temp and lock files excluded by default
+    /// (*.db-wal, *.db-shm, *.db-journal, *.lock, *.lck and Temp/ directories).
+    #[arg(long)]
+    no_default_excludes: bool,
+
+    /// Also back up backup archives made by this tool (`*_backup_*.zip` etc.) and
+    /// `.cache/backupEvents.json` found inside --from. They are skipped by default.
+    #[arg(long)]
+    include_own_artifacts: bool,
+
+    /// Only scan this subdirectory of --from, e.g. `Msg` or `FileStorage/MsgAttach`.
+    /// May be given multiple times. Archive paths stay relative to --from.
+    #[arg(long, value_name = "RELATIVE", value_parser = parse_subdir)]
+    subdir: Vec<PathBuf>,
+
+    /// Do not descend more than N levels below the scanned directory (--from, or each
+    /// --subdir); files directly in it are at depth 1.
+    #[arg(long, value_name = "N")]
+    max_depth: Option<NonZeroUsize>,
+
+    /// Where a file's month comes from: its modification time, or a YYYY-MM
+    /// component of its path (falling back to the modification time).
+    #[arg(long, value_enum, default_value_t = MonthSource::Mtime)]
+    month_source: MonthSource,
+
+    /// Regex extracting the year and month (capture groups 1 and 2) from the path
+    /// relative to --from, used with --month-source path.
+    #[arg(long, value_name = "REGEX", default_value = file_scanner::DEFAULT_MONTH_PATTERN)]
+    month_pattern: String,
+
+    /// Defer files modified within this many seconds before the scan starts to the
+    /// next run, so files that are still being written are not archived truncated. 0 disables it.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    settle_seconds: u32,
+
+    /// Files modified more than this many seconds in the future were written by a machine
+    /// with a wrong clock; they belong to no month and are reported instead of backed up.
+    #[arg(long, value_name = "N", default_value_t = 24 * 60 * 60)]
+    future_skew_seconds: u32,
+
+    /// Archive files with modification times in the future (see --future-skew-seconds)
+    /// with the current month. They are archived again on every run until their mtime is fixed.
+    #[arg(long)]
+    include_future_mtimes: bool,
+
+    /// Ignore the .backupignore file (gitignore syntax) in the root of --from.
+    #[arg(long)]
+    no_backupignore: bool,
+
+    /// Only back up files whose path relative to --from matches this glob, e.g. `**/Msg/**`.
+    /// May be given multiple times. Excludes take precedence.
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Only back up files with these comma-separated extensions (case-insensitive),
+    /// e.g. `db,dat,jpg`. Combined with --include, a file matching either is kept.
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    ext: Vec<String>,
+
+    /// Skip files smaller than this size (e.g. 1KB). 0 means no limit.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, allow_hyphen_values = true)]
+    min_file_size: Option<u64>,
+
+    /// Skip files larger than this size (e.g. 2GB). 0 means no limit.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, allow_hyphen_values = true)]
+    max_file_size: Option<u64>,
+
+    /// Print the backup metadata stored in a zip archive's comment and exit.
+    #[arg(long, value_name = "ARCHIVE", exclusive = true)]
+    show_info: Option<PathBuf>,
+
+    /// Restore the backup state represented by this zip archive into --restore-to and exit.
+    /// For an incremental archive its full archive and earlier incrementals are replayed first.
+    #[arg(long, value_name = "ARCHIVE", requires = "restore_to")]
+    restore: Option<PathBuf>,
+
+    /// The directory to restore into (used with --restore).
+    #[arg(long, value_name = "DIR", requires = "restore")]
+    restore_to: Option<PathBuf>,
+
+    /// Backup the previous month.
+    #[arg(short, long, group = "mode")]
+    p: bool,
+
+    /// Backup the current month.
+    #[arg(short, long, group = "mode")]
+    n: bool,
+
+    /// Dynamic mode: backup based on date proximity to the end of the month.
+    #[arg(short, long, group = "mode")]
+    d: bool,
+
+    /// In dynamic mode, also backup the previous month until this many days into
+    /// the current month (0 disables).
+    #[arg(long, value_name = "N", default_value_t = 7)]
+    dynamic_threshold_days: u32,
+
+    /// In dynamic mode, also backup the next month during the last N days of the
+    /// current month (0 disables).
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    dynamic_lookahead_days: u32,
+
+    /// Backup ISO weeks instead of months: -p, -n and -d select the previous week, the
+    /// current week, and both during the first days of a week. Archives are named like
+    /// 2025-W31_backup_<timestamp>.zip.
+    #[arg(long, requires = "mode", conflicts_with_all = ["month", "months_back", "catch_up", "since"])]
+    weekly: bool,
+
+    /// With --weekly -d, also backup the previous week until this many days into the
+    /// current week (0 disables).
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    dynamic_week_threshold_days: u32,
+
+    /// Backup the given month (YYYY-MM, not in the future); repeat for several months.
+    /// All files of the month are archived regardless of the cache cutoff.
+    #[arg(long, value_name = "YYYY-MM", group = "mode", value_parser = parse_month)]
+    month: Vec<BackupMonth>,
+
+    /// Backup the last N calendar months (at most 1200), including the current one.
+    #[arg(long, value_name = "N", group = "mode", value_parser = clap::value_parser!(u32).range(1..=1200))]
+    months_back: Option<u32>,
+
+    /// Backup every month from the one of the last successful run through the current
+    /// month. Without a previous successful run only the current month is backed up.
+    #[arg(long, group = "mode")]
+    catch_up: bool,
+
+    /// Backup every file modified since this date, regardless of calendar months and the
+    /// cache cutoff. Accepts YYYY-MM-DD (local midnight) or an RFC 3339 time. Archives are
+    /// named like 2024-06-15_to_2024-09-01_backup_<timestamp>.zip and never cleaned up.
+    #[arg(long, value_name = "DATE", group = "mode", value_parser = backup_logic::parse_date_time)]
+    since: Option<DateTime<Utc>>,
+
+    /// With --since, only backup files modified before this date (exclusive). Defaults to now.
+    #[arg(long, value_name = "DATE", requires = "since", value_parser = backup_logic::parse_date_time)]
+    until: Option<DateTime<Utc>>,
+
+    /// Determine the months to backup as if today were this date (YYYY-MM-DD).
+    #[arg(long, value_name = "YYYY-MM-DD", hide = true)]
+    as_of: Option<chrono::NaiveDate>,
+
+    /// The time zone whose midnight starts each month (or week): local, utc, or an IANA
+    /// name such as Asia/Shanghai. Use the zone of the machine that writes the data when
+    /// backing it up from a machine in another zone.
+    #[arg(long, value_name = "TZ", default_value = "local", value_parser = MonthBoundaryTz::parse)]
+    month_boundary_tz: MonthBoundaryTz,
+
+    /// Archive every file of the selected months, ignoring the cutoff of previous backups.
+    #[arg(long)]
+    ignore_cache_cutoff: bool,
+
+    /// Silent mode: only print errors and warnings that need attention. The full output
+    /// is still written to --log-file.
+    #[arg(short, long, visible_short_alias = 'q', visible_alias = "quiet")]
+    s: bool,
+
+    /// Verbose mode: also list files skipped by --min-file-size/--max-file-size and show
+    /// debug messages such as the parsed arguments; -vv also shows every archived file.
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "s")]
+    verbose: u8,
+
+    /// Append timestamped log lines to this file, whatever the console shows. In silent
+    /// mode this defaults to backup.log in the cache folder. The file is rotated at 5 MB,
+    /// keeping 3 old files (backup.log.1 and so on).
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// How the run is reported on stdout. json prints nothing while running and a single
+    /// JSON document at the end with the mode, each month's archives and skipped files,
+    /// the cleanup, the cache update, errors and the overall status. Like -s, it writes
+    /// the text output to backup.log in the cache folder unless --log-file is given.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Scan and print what would be backed up for each month (with -v, every file) and
+    /// which old archives would be deleted, without writing or deleting anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    retention: RetentionArgs,
+
+    /// Back up as usual, but only print the old archives --keep-months would delete.
+    #[arg(long)]
+    cleanup_dry_run: bool,
+
+    /// If another backup into the same --to is running, wait up to N seconds for it to
+    /// finish instead of exiting immediately (with status 3).
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    lock_wait_seconds: u64,
+
+    /// Keep only the newest N records in the cache and move older ones into
+    /// .cache/backupEvents-archive-<year>.json. Records that still hold a month's cutoff
+    /// or the full archive of incremental backups are always kept. 0 keeps every record.
+    #[arg(long, value_name = "N", default_value_t = 500)]
+    cache_history_limit: u64,
+
+    /// Replace the backup records in .cache with one record per archive found in --to,
+    /// e.g. after the .cache folder was lost, before backing up as usual. The cutoff of
+    /// each month comes from the archive's metadata, or else from its file name.
+    #[arg(long)]
+    rebuild_cache: bool,
+
+    /// Keep the backup records, file indexes and the run lock in this directory instead of
+    /// <to>/.cache, e.g. next to the source when --to is one of several rotated drives.
+    /// It is created if needed and not backed up if it lies inside --from.
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Where the backup records and file indexes in .cache are stored. Switching to
+    /// sqlite imports the existing JSON files once and leaves them in place.
+    #[arg(long, value_enum, default_value_t = CacheBackend::Json)]
+    cache_backend: CacheBackend,
+
+    /// Compression level for the archive (0-9). 0 stores files without compression.
+    /// For tar-zst the level is mapped onto zstd levels 1-19.
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(0..=9))]
+    compression_level: u32,
+
+    /// Encrypt archive entries with AES-256 using this password.
+    #[arg(long, conflicts_with = "password_file")]
+    password: Option<String>,
+
+    /// Read the archive password from the first line of this file.
+    #[arg(long)]
+    password_file: Option<PathBuf>,
+
+    /// How to handle symbolic links (and Windows junctions) in the source:
+    /// skip them, follow them to their targets, or store the links themselves
+    /// (as symlink entries in tar, or `<name>.symlink` text files in zip).
+    #[arg(long, value_enum, default_value_t = SymlinkPolicy::Skip)]
+    symlinks: SymlinkPolicy,
+
+    /// The archive format to produce.
+    #[arg(long, value_enum, default_value_t = ArchiveFormat::Zip)]
+    archive_format: ArchiveFormat,
+
+    /// Split archives into parts no larger than this size (e.g. 2GB, 500MB).
+    #[arg(long, value_parser = parse_size)]
+    split_size: Option<u64>,
+
+    /// Comma-separated extensions written without compression
+    /// (defaults to common media types such as dat, mp4, jpg, silk).
+    #[arg(long, value_delimiter = ',', conflicts_with = "no_store_heuristic")]
+    store_extensions: Option<Vec<String>>,
+
+    /// Compress every file, even already-compressed media.
+    #[arg(long)]
+    no_store_heuristic: bool,
+
+    /// Skip the integrity check of newly created archives.
+    #[arg(long)]
+    no_verify: bool,
+
+    /// How many times to retry a file that is locked by another program while it is opened
+    /// or read; each retry reads the file again from the start.
+    #[arg(long, default_value_t = 3)]
+    locked_file_retries: u32,
+
+    /// Seconds subtracted from the previous backup's cutoff, so files whose timestamps
+    /// are truncated to whole seconds are not missed. Such files may be archived twice.
+    #[arg(long, default_value_t = 2)]
+    overlap_seconds: u32,
+
+    /// Fail the month instead of skipping files that stay locked or cannot be read.
+    #[arg(long)]
+    fail_on_skip: bool,
+
+    /// Write the list of skipped files to <archive>.skipped_files.txt next to the archive.
+    #[arg(long)]
+    write_skip_report: bool,
+
+    /// Base delay in milliseconds between retries of a locked file.
+    #[arg(long, default_value_t = 500)]
+    retry_delay_ms: u64,
+
+    /// Merge new files into the newest existing archive of the same month
+    /// instead of creating another archive (zip only). Not allowed with --encrypt-to,
+    /// whose encrypted archives cannot be merged into.
+    #[arg(long, conflicts_with_all = ["split_size", "encrypt_to"])]
+    append: bool,
+
+    /// Produce byte-identical archives for identical source content. The archive
+    /// name uses the newest file mtime (in UTC) plus a content digest instead of the current
+    /// time.
+    #[arg(long, conflicts_with_all = ["password", "password_file", "split_size", "append"])]
+    reproducible: bool,
+
+    /// Create full archives containing every file of the month, regardless of the last backup time.
+    #[arg(long, conflicts_with_all = ["incremental", "split_size", "append", "dedupe_months"])]
+    full: bool,
+
+    /// Create incremental archives that build on the month's latest full archive.
+    /// A month without a full archive gets a full one first.
+    #[arg(long, conflicts_with_all = ["split_size", "append", "dedupe_months"])]
+    incremental: bool,
+
+    /// Encrypt each verified archive to this age public key (age1...), producing
+    /// *.zip.age and deleting the plaintext archive. May be given multiple times.
+    #[arg(long, value_name = "AGE_RECIPIENT", value_parser = parse_recipient)]
+    encrypt_to: Vec<age::x25519::Recipient>,
+
+    /// Create one archive per top-level directory of --from (e.g. each wxid_* account)
+    /// per month, with the directory name in the archive name. Files directly in the
+    /// source root go into a `_root` archive.
+    #[arg(long)]
+    split_by_top_dir: bool,
+
+    /// Skip files whose content hash matches the one recorded when they were last
+    /// archived, even if their modification time changed. A renamed or moved file is
+    /// skipped when its content was archived under another path. Hashes are kept in
+    /// .cache/fileHashes.json.
+    #[arg(long)]
+    dedup: bool,
+
+    /// How to decide whether a file changed since the last run. `mtime` (default) compares
+    /// modification times with the last backup and reads nothing. `size+mtime` compares each
+    /// file's size and mtime with .cache/scanIndex.json, which catches mtimes reset to an older
+    /// value at the cost of checking every file of the month. `hash` reads every file of the
+    /// month to compare an xxh3 hash with the index: the slowest, but independent of mtimes.
+    #[arg(long, value_name = "MODE", default_value = "mtime")]
+    detect_changes: ChangeDetection,
+
+    /// Upper bound on the total size of files compressed in parallel at once
+    /// (e.g. 256MB). Larger files are streamed; 0 disables parallel compression.
+    #[arg(long, default_value = "256MB", value_parser = parse_size)]
+    max_parallel_bytes: u64,
+
+    /// Number of threads scanning the source directory (defaults to the number of CPUs).
+    #[arg(long, value_name = "N")]
+    scan_threads: Option<NonZeroUsize>,
+
+    /// Abort if a month's scan finds more than this many files, e.g. because --from
+    /// points at the wrong directory. See --force.
+    #[arg(long, value_name = "N")]
+    max_files: Option<usize>,
+
+    /// Abort if the files found by a month's scan add up to more than this size
+    /// (e.g. 200GB). See --force.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_total_size: Option<u64>,
+
+    /// Only warn instead of aborting when --max-files or --max-total-size is exceeded.
+    #[arg(long)]
+    force: bool,
+
+    /// Abort if the backup would leave less than this much free space on the
+    /// destination (e.g. 10GB).
+    #[arg(long, value_parser = parse_size)]
+    min_free_space: Option<u64>,
+
+    /// Expected compressed/uncompressed size ratio used to estimate the space an
+    /// archive needs (0 < ratio <= 1). The default of 1.0 assumes no compression.
+    #[arg(long, default_value_t = 1.0, value_parser = parse_ratio)]
+    expected_compression_ratio: f64,
+}
+
+/// 滚动删除旧备份的选项，`backup` 和 `clean` 共用
+#[derive(clap::Args, Debug, Clone)]
+struct RetentionArgs {
+    /// The number of months to keep backups. Counted in calendar months, not 30-day
+    /// periods: with 6, an archive created on March 31st is kept until September 30th.
+    #[arg(long, default_value_t = 6)]
+    keep_months: u32,
+
+    /// Whether --keep-months counts from the time an archive was created or from the
+    /// month of data it contains.
+    #[arg(long, value_enum, default_value = "created")]
+    retention_by: RetentionBy,
+
+    /// Never delete the newest N archives, even if they are older than --keep-months,
+    /// e.g. after the backup has not run for a long time.
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    keep_min: usize,
+
+    /// The number of weeks to keep weekly (--weekly) archives, counted like --keep-months.
+    /// 0 applies --keep-months to them as well.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    keep_weeks: u32,
+
+    /// How to thin out old archives. With gfs, every archive of the last --keep-months
+    /// months is kept, then the newest of each month for --gfs-monthly months and the
+    /// newest of each year for --gfs-yearly years. Not allowed with --full/--incremental.
+    #[arg(long, value_enum, default_value = "simple")]
+    retention_policy: RetentionPolicy,
+
+    /// With --retention-policy gfs, keep the newest archive of each of the last N months.
+    #[arg(long, value_name = "N", default_value_t = 12)]
+    gfs_monthly: u32,
+
+    /// With --retention-policy gfs, keep the newest archive of each of the last N years
+    /// (the current year included). Default: every year.
+    #[arg(long, value_name = "N")]
+    gfs_yearly: Option<u32>,
+
+    /// Delete the oldest archives in --to until all archives add up to at most this size
+    /// (e.g. 200GB). Applied after --keep-months; the newest archive is never deleted.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_destination_size: Option<u64>,
+
+    /// Delete all but the newest archive of each month (per account with
+    /// --split-by-top-dir), regardless of --keep-months. Useful with --append.
+    /// Not allowed with --full/--incremental, whose archives build on each other.
+    #[arg(long)]
+    dedupe_months: bool,
+
+    /// Move old archives into <to>/.trash instead of deleting them. Not allowed with
+    /// --max-destination-size: the trash is inside --to, so it would free no space.
+    #[arg(long, conflicts_with = "max_destination_size")]
+    trash: bool,
+
+    /// Really delete items that have been in <to>/.trash for more than N days.
+    #[arg(long, value_name = "N")]
+    purge_trash_days: Option<u32>,
+
+    /// Also rotate archives in subdirectories of --to (e.g. year folders), except
+    /// .cache and .trash, and remove the subdirectories that become empty.
+    #[arg(long)]
+    cleanup_recursive: bool,
+
+    /// Exit with status 0 even if old backups could not be removed.
+    #[arg(long)]
+    ignore_cleanup_errors: bool,
+
+    /// Also rotate other archives (e.g. those of the old PowerShell script) whose file
+    /// name matches this regex. It must capture the creation time as `ts`, and may
+    /// capture the backed-up month (YYYY-MM) as `month`. May be given multiple times.
+    #[arg(long, value_name = "REGEX", value_parser = parse_cleanup_pattern)]
+    cleanup_extra_pattern: Vec<Regex>,
+
+    /// The format of the `ts` capture of --cleanup-extra-pattern, e.g. %Y%m%d_%H%M%S.
+    /// Default: %Y%m%d%H%M%S.
+    #[arg(long, value_name = "FORMAT", requires = "cleanup_extra_pattern", value_parser = parse_timestamp_format)]
+    cleanup_extra_format: Option<String>,
+
+    /// Remove old backups without asking. Without it the old backups are listed and
+    /// confirmed interactively, or kept with a warning when stdout is not a terminal.
+    #[arg(short, long)]
+    yes: bool,
+}
+
+impl RetentionArgs {
+    /// 把滚动删除的选项写入 [`BackupOptions`]
+    fn apply(&self, options: &mut BackupOptions) {
+        options.keep_months = self.keep_months;
+        options.retention_by = self.retention_by;
+        options.keep_min = self.keep_min;
+        options.keep_weeks = self.keep_weeks;
+        options.retention_policy = self.retention_policy;
+        options.gfs_monthly = self.gfs_monthly;
+        options.gfs_yearly = self.gfs_yearly;
+        options.max_destination_size = self.max_destination_size;
+        options.dedupe_months = self.dedupe_months;
+        options.trash = self.trash;
+        options.purge_trash_days = self.purge_trash_days;
+        options.cleanup_recursive = self.cleanup_recursive;
+        options.ignore_cleanup_errors = self.ignore_cleanup_errors;
+        options.cleanup_extra_pattern = self.cleanup_extra_pattern.clone();
+        options.cleanup_extra_format = self.cleanup_extra_format.clone();
+        options.yes = self.yes;
+    }
+}
+
+/// 备份运行的输出格式（--output）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+enum OutputFormat {
+    /// Messages and progress bars while running, and a summary at the end (default)
+    #[default]
+    Text,
+    /// A single JSON document on stdout at the end of the run
+    Json,
+}
+
+/// --output json 的文档格式版本，字段含义改变时递增
+const JSON_OUTPUT_VERSION: u32 = 1;
+
+/// --output json 在运行结束时输出到 stdout 的文档；备份结果的字段直接来自 [`BackupReport`]
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct JsonOutput<'a> {
+    version: u32,
+    status: RunStatus,
+    /// 进程的退出码，与 --help 中的说明一致
+    exit_code: i32,
+    dry_run: bool,
+    /// 无法开始备份时为 `None`
+    #[serde(flatten)]
+    report: Option<&'a BackupReport>,
+    /// 无法开始备份的原因
+    error: Option<&'a BackupError>,
+    /// 运行过程中的警告，例如缓存损坏或文件被跳过
+    warnings: Vec<String>,
+}
+
+/// 整个运行的结果
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum RunStatus {
+    Succeeded,
+    /// 部分归档未能创建或滚动删除失败
+    PartiallyFailed,
+    /// 没有成功创建任何归档
+    Failed,
+    /// 无法开始备份，见 `Error`
+    Error,
+}
+
+impl JsonOutput<'_> {
+    fn print(&self) {
+        match serde_json::to_string_pretty(self) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error: Failed to format the run report: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Back up the selected months into --to and remove old backups. This is what runs
+    /// when no command is given.
+    #[command(after_help = EXIT_STATUS_HELP)]
+    Backup(Box<BackupArgs>),
+
+    /// Only remove old backups from --to according to the retention options.
+    Clean(CleanArgs),
+
+    /// Print the backup history recorded in .cache/backupEvents.json, newest first.
+    #[command(alias = "history")]
+    Status(StatusArgs),
+
+    /// Check every archive in --to by decompressing all of its entries and comparing
+    /// their SHA-256 digests with the archive's manifest.
+    Verify(VerifyArgs),
+
+    /// Restore the backup state represented by an archive into a directory.
+    Restore(RestoreArgs),
+
+    /// List the archives in --to with their month, creation time, size and number of
+    /// entries, oldest first.
+    List(ListArgs),
+
+    /// Show which files the next backup would archive and how they differ from the
+    /// archives already in --to: new, modified, or only retouched (same content with a
+    /// newer modification time). Files whose modification time matches the archives are
+    /// unchanged and not listed. Takes the same options as backup and writes nothing.
+    Diff(Box<DiffArgs>),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct CleanArgs {
+    /// Read option values from this TOML file, like `backup --config`; the [retention]
+    /// keys, `to` and `silent` apply, the other keys are ignored. Without it the default
+    /// dat-patch.toml is used if it exists.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// The backup destination (the --to of the backups) whose old archives are removed.
     #[arg(long)]
-    from: PathBuf,
+    to: PathBuf,
 
-    /// The destination path for storing backup .zip and .cache.
+    /// Only print the old archives that would be removed, without deleting anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Silent mode: only print errors and warnings that need attention.
+    #[arg(short, long, visible_short_alias = 'q', visible_alias = "quiet")]
+    s: bool,
+
+    #[command(flatten)]
+    retention: RetentionArgs,
+
+    /// If a backup into the same --to is running, wait up to N seconds for it to
+    /// finish instead of exiting immediately (with status 3).
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    lock_wait_seconds: u64,
+
+    /// The --cache-dir the backups were run with, instead of <to>/.cache.
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// The cache backend the backups were run with.
+    #[arg(long, value_enum, default_value_t = CacheBackend::Json)]
+    cache_backend: CacheBackend,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct VerifyArgs {
+    /// The backup destination (the --to of the backups) whose archives are checked.
     #[arg(long)]
     to: PathBuf,
 
-    /// Backup the previous month.
-    #[arg(short, long, group = "mode")]
-    p: bool,
+    /// Only check the archives of this month (YYYY-MM). May be given multiple times.
+    #[arg(long, value_name = "YYYY-MM", value_parser = parse_month)]
+    month: Vec<BackupMonth>,
+
+    /// Only check the CRCs of the entries, without recomputing the SHA-256 digests
+    /// recorded in each archive's MANIFEST.json.
+    #[arg(long)]
+    quick: bool,
+
+    /// Append the result to the cache of --to as a verification record
+    /// (.cache/verifyEvents.json, or the verifications table with sqlite).
+    #[arg(long)]
+    record: bool,
+
+    /// The --cache-dir the backups were run with, instead of <to>/.cache.
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// The cache backend the backups were run with.
+    #[arg(long, value_enum, default_value_t = CacheBackend::Json)]
+    cache_backend: CacheBackend,
+
+    /// The password of AES-256 encrypted zip archives.
+    #[arg(long, conflicts_with = "password_file")]
+    password: Option<String>,
+
+    /// Read the archive password from the first line of this file.
+    #[arg(long)]
+    password_file: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct RestoreArgs {
+    /// Restore the backup state represented by this zip archive. For an incremental
+    /// archive its full archive and earlier incrementals are replayed first.
+    #[arg(
+        long,
+        value_name = "ARCHIVE",
+        required_unless_present_any = ["month", "all"],
+        conflicts_with_all = ["to", "month", "all", "file"]
+    )]
+    archive: Option<PathBuf>,
+
+    /// The backup destination (the --to of the backups) to restore --month or --all from.
+    #[arg(long)]
+    to: Option<PathBuf>,
+
+    /// Restore every archive of this month (YYYY-MM) in --to, oldest first, so later
+    /// archives overwrite earlier versions of a file. May be given multiple times.
+    #[arg(long, value_name = "YYYY-MM", requires = "to", value_parser = parse_month)]
+    month: Vec<BackupMonth>,
+
+    /// Restore every archive in --to, oldest first.
+    #[arg(long, requires = "to", conflicts_with = "month")]
+    all: bool,
+
+    /// Only restore this file, given by its path in the archives (relative to --from).
+    #[arg(long, value_name = "RELATIVE_PATH")]
+    file: Option<PathBuf>,
+
+    /// When several sources are backed up into --to, restore the archives of this one:
+    /// its --source-label, or its --from directory.
+    #[arg(long, value_name = "LABEL_OR_DIR", requires = "to")]
+    source: Option<String>,
+
+    /// The directory to restore into.
+    #[arg(long, value_name = "DIR")]
+    to_dir: PathBuf,
+
+    /// Restore into a --to-dir that is not empty, overwriting files with the same path.
+    #[arg(long)]
+    force: bool,
+
+    /// The password of an AES-256 encrypted archive.
+    #[arg(long, conflicts_with = "password_file")]
+    password: Option<String>,
+
+    /// Read the archive password from the first line of this file.
+    #[arg(long)]
+    password_file: Option<PathBuf>,
+
+    /// Silent mode: only print errors.
+    #[arg(short, long, visible_short_alias = 'q', visible_alias = "quiet")]
+    s: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct StatusArgs {
+    /// The backup destination (the --to of the backups) whose history is printed.
+    #[arg(long, required_unless_present = "cache_dir")]
+    to: Option<PathBuf>,
+
+    /// The --cache-dir the backups were run with, instead of <to>/.cache.
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Only print the newest N runs.
+    #[arg(long, value_name = "N")]
+    last: Option<usize>,
+
+    /// Print the history as JSON instead of a table.
+    #[arg(long)]
+    json: bool,
+
+    /// Warn when the last successful backup is older than this many days.
+    #[arg(long, value_name = "DAYS", default_value_t = 7)]
+    stale_after_days: u64,
+
+    /// The cache backend the backups were run with.
+    #[arg(long, value_enum, default_value_t = CacheBackend::Json)]
+    cache_backend: CacheBackend,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct ListArgs {
+    /// The backup destination (the --to of the backups) whose archives are listed.
+    #[arg(long)]
+    to: PathBuf,
+
+    /// Only list the archives of this month (YYYY-MM). May be given multiple times.
+    #[arg(long, value_name = "YYYY-MM", value_parser = parse_month)]
+    month: Vec<BackupMonth>,
+
+    /// Print the archives as JSON instead of a table.
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct DiffArgs {
+    #[command(flatten)]
+    backup: BackupArgs,
+
+    /// Print the changes as JSON instead of a grouped listing.
+    #[arg(long)]
+    json: bool,
+}
+
+/// diff 输出中的一个文件
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DiffFile {
+    /// 文件所属的月份（及分组），即归档名中 `_backup_` 之前的部分
+    month: String,
+    /// 归档内的路径
+    path: String,
+    change: FileChange,
+    size: u64,
+    modified: DateTime<Utc>,
+}
+
+/// list --json 中的一个归档；分卷归档的各个分卷合为一项
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ArchiveListing {
+    month: String,
+    group: Option<String>,
+    /// 文件名中的时间戳不是有效的日期时间时为 `None`
+    created: Option<NaiveDateTime>,
+    files: Vec<String>,
+    format: &'static str,
+    /// 是否为 age 加密的归档
+    encrypted: bool,
+    /// 各个分卷的大小之和
+    size: u64,
+    /// 只有 ZIP 归档从中央目录读取条目数，其他归档为 `None`
+    entries: Option<usize>,
+    /// 同一月份（及分组）是否有更新的归档
+    superseded: bool,
+    /// 无法读取条目数的原因
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// 创建归档进度条，按已写入的字节数推进
+fn new_progress_bar() -> ProgressBar {
+    let progress_bar = ProgressBar::new(0);
+    progress_bar.set_style(
+        ProgressStyle::with_template(
+            "{spinner} [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ({eta}) {msg}",
+        )
+        .unwrap()
+        .progress_chars("=> "),
+    );
+    progress_bar
+}
+
+/// 创建扫描时显示进度的状态行
+fn new_scan_spinner() -> ProgressBar {
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::with_template("{spinner} [{elapsed_precise}] Scanning: {msg}").unwrap(),
+    );
+    spinner.enable_steady_tick(Duration::from_millis(200));
+    spinner
+}
+
+/// 打印滚动删除的结果：删除（或试运行时将要删除）的归档、保留的数量，以及无法解析日期的文件
+fn print_cleanup_report(report: &cleaner::CleanupReport, dry_run: bool) {
+    let file_name = |path: &PathBuf| {
+        path.file_name().map_or_else(
+            || path.display().to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        )
+    };
+    // 使用 --trash 时所有删除的归档都移入了回收站
+    let trashed = report.trashed > 0;
+    for path in &report.deleted {
+        match (dry_run, trashed) {
+            (true, false) => info!("Would remove old backup: {}", file_name(path)),
+            (true, true) => info!("Would move old backup to trash: {}", file_name(path)),
+            (false, false) => info!("Removed old backup: {}", file_name(path)),
+            (false, true) => info!("Moved old backup to trash: {}", file_name(path)),
+        }
+    }
+    for path in &report.purged {
+        if dry_run {
+            info!("Would purge from trash: {}", file_name(path));
+        } else {
+            info!("Purged from trash: {}", file_name(path));
+        }
+    }
+    if !report.failed.is_empty() {
+        error!(
+            "Failed to remove {} old backups, see above.",
+            report.failed.len()
+        );
+    }
+    for path in &report.removed_dirs {
+        info!("Removed empty directory: {}", path.display());
+    }
+    for path in &report.skipped_unparsable {
+        warn!(
+            "Not removing '{}': the date in its name is invalid.",
+            path.display()
+        );
+    }
+    info!(
+        "{} {} old backups{}, kept {}.",
+        if dry_run { "Would remove" } else { "Removed" },
+        report.deleted.len(),
+        cleanup_details(report),
+        report.kept.len()
+    );
+}
+
+/// 滚动删除前的确认：在终端中列出待删除的归档并询问
+fn confirm_cleanup(candidates: &[cleaner::DeletionCandidate], trash: bool) -> bool {
+    println!(
+        "\nThe following {} old backups will be {}:",
+        candidates.len(),
+        if trash { "moved to trash" } else { "deleted" }
+    );
+    for candidate in candidates {
+        if candidate.purge {
+            println!(
+                "  {}  {}  trashed {}, deleted for good",
+                candidate.path.display(),
+                HumanBytes(candidate.size),
+                candidate.created.format("%Y-%m-%d %H:%M:%S")
+            );
+        } else {
+            println!(
+                "  {}  {}  created {}",
+                candidate.path.display(),
+                HumanBytes(candidate.size),
+                candidate.created.format("%Y-%m-%d %H:%M:%S")
+            );
+        }
+    }
+    print!("Proceed? [y/N] ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    let confirmed = matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes");
+    if !confirmed {
+        println!("Keeping the old backups.");
+    }
+    confirmed
+}
+
+/// 滚动删除结果中移入回收站、从回收站清除的数量以及释放的空间
+fn cleanup_details(report: &cleaner::CleanupReport) -> String {
+    let mut details = Vec::new();
+    if report.trashed > 0 {
+        details.push(format!("{} moved to trash", report.trashed));
+    }
+    if !report.purged.is_empty() {
+        details.push(format!("{} purged from trash", report.purged.len()));
+    }
+    details.push(format!("{} freed", HumanBytes(report.bytes_freed)));
+    format!(" ({})", details.join(", "))
+}
+
+/// 打印本次运行每个月份的归档统计及合计
+fn print_summary(report: &BackupReport, cleanup_dry_run: bool) {
+    let run_stats: Vec<_> = report.archives().collect();
+    let filtered = &report.filtered;
+    // 使用 --encrypt-to 时额外显示加密后的大小
+    let encrypted = run_stats.iter().any(|(_, s)| s.encrypted_bytes.is_some());
+    info!("\nBackup summary:");
+    info!(
+        "{:<8} {:>8} {:>14} {:>14}{} {:>8} {:>10}",
+        "Month",
+        "Files",
+        "Uncompressed",
+        "Compressed",
+        if encrypted {
+            format!(" {:>14}", "Encrypted")
+        } else {
+            String::new()
+        },
+        "Skipped",
+        "Elapsed"
+    );
+    let print_row = |label: &str,
+                     files: usize,
+                     uncompressed: u64,
+                     compressed: u64,
+                     encrypted_bytes: Option<u64>,
+                     skipped: usize,
+                     elapsed: Duration| {
+        info!(
+            "{:<8} {:>8} {:>14} {:>14}{} {:>8} {:>9.1}s",
+            label,
+            files,
+            HumanBytes(uncompressed).to_string(),
+            HumanBytes(compressed).to_string(),
+            if encrypted {
+                format!(
+                    " {:>14}",
+                    HumanBytes(encrypted_bytes.unwrap_or(0)).to_string()
+                )
+            } else {
+                String::new()
+            },
+            skipped,
+            elapsed.as_secs_f64()
+        );
+    };
+    for (month, stats) in &run_stats {
+        print_row(
+            month,
+            stats.archived_files(),
+            stats.uncompressed_bytes,
+            stats.compressed_bytes,
+            stats.encrypted_bytes,
+            stats.skipped_count(),
+            stats.elapsed,
+        );
+    }
+    if run_stats.len() > 1 {
+        print_row(
+            "Total",
+            run_stats.iter().map(|(_, s)| s.archived_files()).sum(),
+            run_stats.iter().map(|(_, s)| s.uncompressed_bytes).sum(),
+            run_stats.iter().map(|(_, s)| s.compressed_bytes).sum(),
+            run_stats.iter().map(|(_, s)| s.encrypted_bytes).sum(),
+            run_stats.iter().map(|(_, s)| s.skipped_count()).sum(),
+            run_stats.iter().map(|(_, s)| s.elapsed).sum(),
+        );
+    }
+    if filtered.deduplicated > 0 {
+        info!(
+            "Skipped {} unchanged files (--dedup).",
+            filtered.deduplicated
+        );
+    }
+    if filtered.unchanged > 0 {
+        info!(
+            "Skipped {} unchanged files (--detect-changes).",
+            filtered.unchanged
+        );
+    }
+    if filtered.default_excluded > 0 || !filtered.default_excluded_dirs.is_empty() {
+        info!(
+            "Skipped {} temp/lock files and {} temp directories (disable with --no-default-excludes).",
+            filtered.default_excluded,
+            filtered.default_excluded_dirs.len()
+        );
+    }
+    if filtered.excluded > 0 || !filtered.excluded_dirs.is_empty() {
+        info!(
+            "Excluded {} files and {} directories (--exclude).",
+            filtered.excluded,
+            filtered.excluded_dirs.len()
+        );
+    }
+    if filtered.outside_size_limits > 0 {
+        info!(
+            "Skipped {} files outside the size limits (--min-file-size/--max-file-size).",
+            filtered.outside_size_limits
+        );
+    }
+    if !filtered.skipped_symlinks.is_empty() {
+        info!(
+            "Skipped {} symbolic links (--symlinks skip).",
+            filtered.skipped_symlinks.len()
+        );
+    }
+    if !filtered.future_mtimes.is_empty() {
+        info!(
+            "Found {} files with modification times in the future; the clock of the machine that wrote them is probably wrong.",
+            filtered.future_mtimes.len()
+        );
+    }
+    if !filtered.own_artifacts.is_empty() {
+        info!(
+            "Skipped {} backup archives and cache files found in the source (--include-own-artifacts).",
+            filtered.own_artifacts.len()
+        );
+    }
+    if let Some(cleanup) = &report.cleanup
+        && (!cleanup.deleted.is_empty() || !cleanup.purged.is_empty())
+    {
+        info!(
+            "{} {} old backups{}.",
+            if cleanup_dry_run {
+                "Would remove"
+            } else {
+                "Removed"
+            },
+            cleanup.deleted.len(),
+            cleanup_details(cleanup)
+        );
+    }
+}
+
+/// 有归档未能创建或滚动删除失败时结束进程：部分成功时退出码为 2，全部失败时为 1
+fn exit_on_failure(report: &BackupReport) {
+    let code = report_exit_code(report);
+    if code != 0 {
+        process::exit(code);
+    }
+}
+
+/// 备份结束后的退出码：全部成功时为 0
+fn report_exit_code(report: &BackupReport) -> i32 {
+    if report.failed_archives == 0 {
+        if report.cleanup_failed {
+            return EXIT_PARTIAL_FAILURE;
+        }
+        return 0;
+    }
+    if report.succeeded_archives > 0 {
+        EXIT_PARTIAL_FAILURE
+    } else {
+        1
+    }
+}
+
+/// 以 JSON 报告备份的结果，并以相应的退出码结束
+fn exit_with_json(report: &BackupReport, dry_run: bool, warnings: Vec<String>) -> ! {
+    let exit_code = report_exit_code(report);
+    JsonOutput {
+        version: JSON_OUTPUT_VERSION,
+        status: match exit_code {
+            0 => RunStatus::Succeeded,
+            EXIT_PARTIAL_FAILURE => RunStatus::PartiallyFailed,
+            _ => RunStatus::Failed,
+        },
+        exit_code,
+        dry_run,
+        report: Some(report),
+        error: None,
+        warnings,
+    }
+    .print();
+    process::exit(exit_code);
+}
+
+/// 以 JSON 报告无法开始备份的错误，并以相应的退出码结束
+fn exit_with_json_error(error: &BackupError, dry_run: bool, warnings: Vec<String>) -> ! {
+    let exit_code = exit_code(error);
+    JsonOutput {
+        version: JSON_OUTPUT_VERSION,
+        status: RunStatus::Error,
+        exit_code,
+        dry_run,
+        report: None,
+        error: Some(error),
+        warnings,
+    }
+    .print();
+    process::exit(exit_code);
+}
+
+/// 无法开始备份时的退出码，每种错误各不相同
+fn exit_code(error: &BackupError) -> i32 {
+    match error {
+        BackupError::ConfigError(_) => EXIT_CONFIG_ERROR,
+        BackupError::ScanError { .. } => EXIT_SCAN_ERROR,
+        BackupError::ArchiveError { .. } => EXIT_ARCHIVE_ERROR,
+        BackupError::CacheError { .. } => EXIT_CACHE_ERROR,
+        BackupError::CleanupError { .. } => EXIT_CLEANUP_ERROR,
+        BackupError::AlreadyRunning { .. } => EXIT_ALREADY_RUNNING,
+    }
+}
+
+/// 无法开始备份时显示的错误消息，附带可能的解决办法
+fn error_message(error: &BackupError) -> String {
+    let hint = match error {
+        BackupError::ScanError { source, .. } if source.kind() == io::ErrorKind::NotFound => {
+            Some("Check --from and --subdir.")
+        }
+        BackupError::ScanError { .. } => Some("Check that the source path can be read."),
+        BackupError::ArchiveError { source, .. } if source.kind() == io::ErrorKind::StorageFull => {
+            Some("Free up space on the destination or choose another --to.")
+        }
+        BackupError::ArchiveError { .. } => Some("Check that --to can be written."),
+        BackupError::CacheError { .. } => {
+            Some("Nothing was backed up; the cache was left as it was.")
+        }
+        BackupError::ConfigError(_)
+        | BackupError::CleanupError { .. }
+        | BackupError::AlreadyRunning { .. } => None,
+    };
+    match hint {
+        Some(hint) => format!("{}\n{}", error, hint),
+        None => error.to_string(),
+    }
+}
+
+/// 解析 --subdir：必须是不含 `..` 的相对路径
+fn parse_subdir(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    let mut components = path.components().peekable();
+    if components.peek().is_none() {
+        return Err("must not be empty".to_string());
+    }
+    if !components.all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(format!("'{}' must be a path relative to --from", value));
+    }
+    Ok(path)
+}
+
+/// 解析 --month 的值，不接受晚于当月的月份
+fn parse_month(value: &str) -> Result<BackupMonth, String> {
+    backup_logic::parse_backup_month(value, chrono::Local::now().date_naive())
+}
+
+/// 解析带单位的大小字符串，例如 `500MB`、`2GB`、`1024`（以 1024 为进制）
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    if value.starts_with('-') {
+        return Err(format!("invalid size '{}': must not be negative", value));
+    }
+    let split_at = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split_at);
+    let number: f64 = number.parse().map_err(|_| {
+        format!(
+            "invalid size '{}': expected a number with an optional unit",
+            value
+        )
+    })?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        other => {
+            return Err(format!(
+                "invalid size unit '{}' (use B, KB, MB, GB or TB)",
+                other
+            ));
+        }
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// 打印归档注释中的备份元数据，读取失败或没有元数据时以状态码 1 退出
+fn show_archive_info(archive_path: &Path) {
+    match archiver::read_archive_info(archive_path) {
+        Ok(Some(info)) => match serde_json::to_string_pretty(&info) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error: Failed to format archive info: {}", e);
+                process::exit(1);
+            }
+        },
+        Ok(None) => {
+            eprintln!(
+                "Error: '{}' does not contain backup metadata.",
+                archive_path.display()
+            );
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: Failed to read '{}': {}", archive_path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// 打开 `.cache` 目录中的缓存存储，失败时以 [`EXIT_CACHE_ERROR`] 结束
+fn open_cache_store(
+    backend: CacheBackend,
+    cache_folder: &Path,
+    read_only: bool,
+) -> Box<dyn CacheStore> {
+    match cache::open_store(backend, cache_folder, read_only) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(exit_code(&e));
+        }
+    }
+}
 
-    /// Backup the current month.
-    #[arg(short, long, group = "mode")]
-    n: bool,
+/// 打印目标目录缓存中记录的备份历史（从新到旧）及最近一次成功的备份
+///
+/// 同时列出各个源尚未归档的（之前被跳过的）文件。
+/// 最近一次成功的备份早于 --stale-after-days 或从未成功过时打印警告（终端中为红色）。
+fn show_status(status: &StatusArgs) {
+    let cache_folder = match (&status.cache_dir, &status.to) {
+        (Some(cache_dir), _) => cache_dir.clone(),
+        (None, Some(to)) => to.join(".cache"),
+        (None, None) => unreachable!("--to is required without --cache-dir"),
+    };
+    let store = open_cache_store(status.cache_backend, &cache_folder, true);
+    let mut records = match store.read_records() {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(exit_code(&e));
+        }
+    };
+    let last_success =
+        cache::last_successful_backup(&records, None, Utc::now()).map(|r| r.end_time);
+    let source_ids: BTreeSet<&str> = records
+        .iter()
+        .filter_map(|r| r.source_id.as_deref())
+        .collect();
+    let outstanding: Vec<cache::SkippedFileRecord> = source_ids
+        .into_iter()
+        .flat_map(|id| cache::outstanding_skipped_files(&records, id, Utc::now()))
+        .cloned()
+        .collect();
+    // 天数超出 chrono 能表示的范围时，任何成功的备份都不算过期
+    let stale_after = i64::try_from(status.stale_after_days)
+        .ok()
+        .and_then(chrono::Duration::try_days);
+    let stale = last_success
+        .is_none_or(|time| stale_after.is_some_and(|stale_after| Utc::now() - time > stale_after));
+    records.sort_by_key(|r| std::cmp::Reverse(r.end_time));
+    records.truncate(status.last.unwrap_or(records.len()));
 
-    /// Dynamic mode: backup based on date proximity to the end of the month.
-    #[arg(short, long, group = "mode")]
-    d: bool,
+    if status.json {
+        let output = serde_json::json!({
+            "LastSuccessfulBackup": last_success,
+            "Stale": stale,
+            "OutstandingSkippedFiles": outstanding,
+            "Records": records,
+        });
+        match serde_json::to_string_pretty(&output) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error: Failed to format backup history: {}", e);
+                process::exit(1);
+            }
+        }
+    } else {
+        println!(
+            "{:<19} {:>9} {:<8} {:<16} {:>8} Archives",
+            "Started", "Duration", "Status", "Months", "Files"
+        );
+        for record in &records {
+            let seconds = (record.end_time - record.start_time).num_seconds().max(0);
+            let months: Vec<&str> = record.months.iter().map(|m| m.month.as_str()).collect();
+            println!(
+                "{:<19} {:>9} {:<8} {:<16} {:>8} {}",
+                record
+                    .start_time
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                format!(
+                    "{}:{:02}:{:02}",
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60
+                ),
+                format!("{:?}", record.run_status()),
+                if months.is_empty() {
+                    "-".to_string()
+                } else {
+                    months.join(",")
+                },
+                record
+                    .files_archived
+                    .map_or_else(|| "-".to_string(), |files| files.to_string()),
+                if record.archive_files.is_empty() {
+                    "-".to_string()
+                } else {
+                    record.archive_files.join(", ")
+                }
+            );
+        }
+        match last_success {
+            Some(time) => println!(
+                "\nLast successful backup: {}",
+                time.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+            ),
+            None => println!(
+                "\nNo successful backup recorded in '{}'.",
+                store.location().display()
+            ),
+        }
+        if !outstanding.is_empty() {
+            println!(
+                "\n{} skipped files have not been backed up yet:",
+                outstanding.len()
+            );
+            for skipped in &outstanding {
+                println!(
+                    "  {} [{}] ({})",
+                    skipped.path, skipped.month, skipped.reason
+                );
+            }
+        }
+    }
 
-    /// Silent mode: suppress console output.
-    #[arg(short, long)]
-    s: bool,
+    if stale {
+        let message = format!(
+            "Warning: No successful backup in the last {} days.",
+            status.stale_after_days
+        );
+        if io::stderr().is_terminal() {
+            eprintln!("\x1b[31m{}\x1b[0m", message);
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+}
 
-    /// The number of months to keep backups.
-    #[arg(long, default_value_t = 6)]
-    keep_months: u32,
+/// 恢复归档到指定目录，失败时以退出码 1 结束
+fn restore_archive(archive_path: &Path, target_dir: &Path, password: Option<&str>, silent: bool) {
+    match restorer::restore_archive(archive_path, target_dir, password) {
+        Ok(chain) => {
+            if !silent {
+                for path in &chain {
+                    println!("Restored: {}", path.display());
+                }
+                println!("Restore completed into '{}'.", target_dir.display());
+            }
+        }
+        Err(e) => {
+            eprintln!(
+                "Error: Failed to restore '{}': {}",
+                archive_path.display(),
+                e
+            );
+            process::exit(1);
+        }
+    }
 }
 
-fn main() {
-    let args = Args::parse();
-    let script_start_time = Utc::now(); // 1. 记录脚本开始时间
+/// 列出目标目录中的归档：每个归档（分卷合为一项）的月份、创建时间、大小、条目数，
+/// 以及是否已被同一系列中更新的归档取代；--json 时以 JSON 输出
+///
+/// 无法读取条目数的归档照常列出并给出警告；无法读取目标目录时以退出码 1 结束。
+fn list_destination(list: &ListArgs) {
+    let archives = archiver::find_archives(&list.to).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to read '{}': {}", list.to.display(), e);
+        process::exit(1);
+    });
+    let sets = archiver::group_archive_sets(archives);
+    let months: Vec<String> = list
+        .month
+        .iter()
+        .map(|month| BackupPeriod::Month(*month).label())
+        .collect();
+    let listings: Vec<ArchiveListing> = sets
+        .iter()
+        .filter(|set| months.is_empty() || months.contains(&set.info.month))
+        .map(|set| {
+            let (entries, error) = match set.entry_count() {
+                Ok(entries) => (entries, None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            ArchiveListing {
+                month: set.info.month.clone(),
+                group: set.info.group.clone(),
+                created: set.info.created(),
+                files: set
+                    .paths
+                    .iter()
+                    .map(|path| {
+                        path.file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .into_owned()
+                    })
+                    .collect(),
+                format: set.info.format.extension(),
+                encrypted: set.info.encrypted,
+                size: set.size,
+                entries,
+                superseded: sets.iter().any(|other| {
+                    other.info.series() == set.info.series()
+                        && other.info.timestamp > set.info.timestamp
+                }),
+                error,
+            }
+        })
+        .collect();
 
-    // 0. 预检查
-    if !args.from.exists() {
-        // 关键错误信息即使在静默模式下也应该显示
-        eprintln!(
-            "Error: The source path '{}' does not exist.",
-            args.from.display()
+    if list.json {
+        match serde_json::to_string_pretty(&listings) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error: Failed to format the archive list: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    println!(
+        "{:<10} {:<19} {:>10} {:>8} {:<10} Archive",
+        "Month", "Created", "Size", "Entries", "Superseded"
+    );
+    for listing in &listings {
+        let mut archive = listing.files[0].clone();
+        if listing.files.len() > 1 {
+            archive.push_str(&format!(" (+{} parts)", listing.files.len() - 1));
+        }
+        println!(
+            "{:<10} {:<19} {:>10} {:>8} {:<10} {}",
+            listing.month,
+            listing.created.map_or_else(
+                || "-".to_string(),
+                |created| created.format("%Y-%m-%d %H:%M:%S").to_string()
+            ),
+            HumanBytes(listing.size).to_string(),
+            match (&listing.entries, &listing.error) {
+                (Some(entries), _) => entries.to_string(),
+                (None, Some(_)) => "?".to_string(),
+                (None, None) => "-".to_string(),
+            },
+            if listing.superseded { "yes" } else { "no" },
+            archive
         );
-        process::exit(1);
     }
-    if !args.to.exists() {
-        if !args.s {
-            println!(
-                "Warning: The destination path '{}' does not exist. Creating...",
-                args.to.display()
+    println!(
+        "\n{} archives, {} in total.",
+        listings.len(),
+        HumanBytes(listings.iter().map(|listing| listing.size).sum())
+    );
+    for listing in &listings {
+        if let Some(error) = &listing.error {
+            eprintln!(
+                "Warning: Failed to read the entries of '{}': {}",
+                listing.files[0], error
             );
         }
-        if let Err(e) = fs::create_dir_all(&args.to) {
-            eprintln!("Error: Failed to create destination directory: {}", e);
-            process::exit(1);
-        }
     }
+}
 
-    // 1. 根据参数确定备份模式
-    let mode = if args.p {
-        BackupMode::PreviousMonth
-    } else if args.n {
-        BackupMode::CurrentMonth
-    } else if args.d {
-        BackupMode::Dynamic
-    } else {
-        // Clap 的 group 设置应该能防止这种情况，但作为安全措施我们还是处理一下
-        eprintln!("Error: You must specify exactly one of -p, -n, or -d.");
+/// 像试运行的备份一样扫描，再将找到的文件与目标目录中已有归档的清单比较，按变化分组列出
+fn diff_command(diff: &DiffArgs) {
+    let args = &diff.backup;
+    let password = resolve_password(args).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
+    let Some(destination_path) = args.to.clone() else {
+        eprintln!("Error: diff needs --from and --to.");
         process::exit(1);
     };
+    let mut options = backup_options(
+        args,
+        destination_path.clone(),
+        backup_mode(args),
+        password.clone(),
+    );
+    // 内容没有变化的文件由下面的比较识别，不在扫描后剔除
+    options.dry_run = true;
+    options.dedup = false;
+    // 每个归档本应包含的文件：(归档名中 `_backup_` 之前的部分, 源标记, 文件)
+    let candidates = Arc::new(Mutex::new(Vec::new()));
+    let collected = Arc::clone(&candidates);
+    options.on_event = Some(Box::new(move |event| match event {
+        BackupEvent::DryRunFiles {
+            label,
+            source,
+            files,
+        } => collected.lock().unwrap().push((
+            label.to_string(),
+            source.map(str::to_string),
+            files.to_vec(),
+        )),
+        BackupEvent::Warning(message) | BackupEvent::Alert(message) => {
+            eprintln!("Warning: {}", message)
+        }
+        BackupEvent::Error(message) => eprintln!("Error: {}", message),
+        _ => {}
+    }));
+    if let Err(e) = backup::run_backup(options) {
+        eprintln!("Error: {}", error_message(&e));
+        process::exit(exit_code(&e));
+    }
 
-    // 2. 计算需要备份的月份
-    let months_to_backup = determine_backup_months(&mode);
+    let mut changes: Vec<DiffFile> = Vec::new();
+    for (label, source, files) in candidates.lock().unwrap().iter() {
+        let archived = differ::archived_files(
+            &destination_path,
+            label,
+            source.as_deref(),
+            password.as_deref(),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!(
+                "Error: Failed to read '{}': {}",
+                destination_path.display(),
+                e
+            );
+            process::exit(1);
+        });
+        for (path, reason) in &archived.unreadable {
+            eprintln!(
+                "Warning: Could not read the manifest of '{}' ({}); its files are not compared.",
+                path.display(),
+                reason
+            );
+        }
+        // 没有变化的文件（例如重叠窗口内重新扫描到的已归档文件）不列出
+        changes.extend(files.iter().filter_map(|file| {
+            let change = differ::classify(file, &archived);
+            (change != FileChange::Unchanged).then(|| DiffFile {
+                month: label.clone(),
+                path: archiver::entry_name(&file.archive_path()),
+                change,
+                size: file.size,
+                modified: file.modified,
+            })
+        }));
+    }
+    changes.sort_by(|a, b| (a.change, &a.month, &a.path).cmp(&(b.change, &b.month, &b.path)));
+    let count = |change: FileChange| changes.iter().filter(|file| file.change == change).count();
 
-    if months_to_backup.is_empty() {
-        if !args.s {
-            println!("No months to backup based on the selected mode. Exiting.");
+    if diff.json {
+        let output = serde_json::json!({
+            "New": count(FileChange::New),
+            "Modified": count(FileChange::Modified),
+            "Retouched": count(FileChange::Retouched),
+            "Files": changes,
+        });
+        match serde_json::to_string_pretty(&output) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error: Failed to format the changes: {}", e);
+                process::exit(1);
+            }
         }
         return;
     }
+    for (change, heading) in [
+        (FileChange::New, "New files"),
+        (FileChange::Modified, "Modified files"),
+        (
+            FileChange::Retouched,
+            "Retouched files (same content, newer modification time)",
+        ),
+    ] {
+        let files: Vec<&DiffFile> = changes
+            .iter()
+            .filter(|file| file.change == change)
+            .collect();
+        if files.is_empty() {
+            continue;
+        }
+        println!("{} ({}):", heading, files.len());
+        for file in files {
+            println!(
+                "  {}  {} ({})",
+                file.month,
+                file.path,
+                HumanBytes(file.size)
+            );
+        }
+        println!();
+    }
+    println!(
+        "{} new, {} modified and {} retouched files would be backed up.",
+        count(FileChange::New),
+        count(FileChange::Modified),
+        count(FileChange::Retouched)
+    );
+}
+
+/// 校验目标目录中的归档，有归档损坏或无法读取时列出这些归档并以退出码 1 结束
+///
+/// 没有 --quick 时同时与清单核对 SHA-256。age 加密的归档无法在不解密的情况下校验，只列出不校验。
+fn verify_destination(verify: &VerifyArgs) {
+    let password = password_or_exit(verify.password.as_deref(), verify.password_file.as_deref());
+    let archives = archiver::find_archives(&verify.to).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to read '{}': {}", verify.to.display(), e);
+        process::exit(1);
+    });
+    let months: Vec<String> = verify
+        .month
+        .iter()
+        .map(|month| BackupPeriod::Month(*month).label())
+        .collect();
+    let mut passed = 0;
+    let mut failed = Vec::new();
+    for (path, info) in &archives {
+        if !(months.is_empty() || months.contains(&info.month)) {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        if info.encrypted {
+            println!("SKIPPED {} (encrypted with age)", name);
+            continue;
+        }
+        let result = if verify.quick {
+            verifier::verify_archive(path, info.format, password.as_deref())
+                .map(|entries| format!("{} entries", entries))
+        } else {
+            verifier::verify_archive_digests(path, info.format, password.as_deref()).map(|check| {
+                match check.digests_checked {
+                    Some(digests) => format!("{} entries, {} digests", check.entries, digests),
+                    None => format!("{} entries, no manifest", check.entries),
+                }
+            })
+        };
+        match result {
+            Ok(details) => {
+                passed += 1;
+                println!("OK      {} ({})", name, details);
+            }
+            Err(e) => {
+                println!("FAILED  {}: {}", name, e);
+                failed.push(cache::FailedArchive {
+                    name,
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+    println!(
+        "\nVerified {} archives in '{}', {} failed.",
+        passed + failed.len(),
+        verify.to.display(),
+        failed.len()
+    );
 
-    // 3. 读取 .cache 并获取上次备份时间
-    let cache_folder = args.to.join(".cache");
-    if !cache_folder.exists() {
-        if let Err(e) = fs::create_dir_all(&cache_folder) {
-            eprintln!("Error: Failed to create .cache directory: {}", e);
-            process::exit(1);
+    if verify.record {
+        let cache_folder = verify
+            .cache_dir
+            .clone()
+            .unwrap_or_else(|| verify.to.join(".cache"));
+        let record = cache::VerificationRecord {
+            time: Utc::now(),
+            quick: verify.quick,
+            months,
+            archives_passed: passed,
+            failed: failed.clone(),
+        };
+        if let Err(e) = fs::create_dir_all(&cache_folder)
+            .map_err(|e| BackupError::CacheError {
+                path: cache_folder.clone(),
+                source: e,
+            })
+            .and_then(|()| {
+                open_cache_store(verify.cache_backend, &cache_folder, false)
+                    .append_verification(&record)
+            })
+        {
+            eprintln!("Error: {}", e);
+            process::exit(exit_code(&e));
         }
     }
-    let cache_file = cache_folder.join("backupEvents.json");
 
-    let mut cache_records = match cache::read_cache_records(&cache_file) {
-        // 声明为可变
-        Ok(records) => records,
-        Err(e) => {
-            eprintln!("Error reading cache file '{}': {}", cache_file.display(), e);
-            process::exit(1);
+    if !failed.is_empty() {
+        eprintln!(
+            "Error: {} archives are damaged or unreadable:",
+            failed.len()
+        );
+        for archive in &failed {
+            eprintln!("  {}: {}", archive.name, archive.reason);
         }
+        process::exit(1);
+    }
+}
+
+/// 只滚动删除目标目录中的旧备份，不备份任何内容
+fn clean_old_backups(clean: &CleanArgs) {
+    let progress = Arc::new(Mutex::new(None));
+    let console_progress = Arc::clone(&progress);
+    let logger = Logger::new(
+        if clean.s {
+            LevelFilter::Error
+        } else {
+            LevelFilter::Info
+        },
+        Box::new(move |level, message| write_console(&console_progress, level, message)),
+        None,
+    );
+    if let Err(e) = logger.install() {
+        eprintln!("Warning: Failed to set up logging: {}", e);
+    }
+
+    let mut options = BackupOptions::new(Vec::new(), &clean.to, BackupMode::CurrentMonth);
+    clean.retention.apply(&mut options);
+    options.dry_run = clean.dry_run;
+    options.lock_wait_seconds = clean.lock_wait_seconds;
+    options.cache_dir = clean.cache_dir.clone();
+    options.cache_backend = clean.cache_backend;
+    let console = Console {
+        silent: clean.s,
+        progress,
     };
+    options.on_event = Some(Box::new(move |event| console.handle(event)));
+    if !clean.s && io::stdout().is_terminal() {
+        let trash = clean.retention.trash;
+        options.confirm_cleanup = Some(Box::new(move |candidates| {
+            confirm_cleanup(candidates, trash)
+        }));
+    }
 
-    let last_backup_time = cache::get_last_backup_time(&cache_records);
+    match backup::run_cleanup(options) {
+        Ok(report) => exit_on_failure(&report),
+        Err(e) => {
+            error!("{}", error_message(&e));
+            process::exit(exit_code(&e));
+        }
+    }
+}
 
-    if !args.s {
-        println!("Arguments parsed successfully:");
-        println!("{:#?}", args);
-        println!("\nSelected backup mode: {:?}", mode);
-        println!("Months to be backed up: {:?}", months_to_backup);
-        println!(
-            "Last backup time from cache: {}",
-            last_backup_time.with_timezone(&chrono::Local)
+/// 恢复一个归档或若干月份的归档；目标目录不为空且没有 --force 时不恢复，以退出码 1 结束
+fn restore_command(restore: &RestoreArgs) {
+    // 只恢复一个文件时，只有该文件已存在才算覆盖
+    let occupied = match &restore.file {
+        Some(file) => restore.to_dir.join(file).exists(),
+        None => fs::read_dir(&restore.to_dir).is_ok_and(|mut entries| entries.next().is_some()),
+    };
+    if occupied && !restore.force {
+        eprintln!(
+            "Error: '{}' is not empty; pass --force to overwrite its files.",
+            restore.to_dir.display()
+        );
+        process::exit(1);
+    }
+    let password = password_or_exit(
+        restore.password.as_deref(),
+        restore.password_file.as_deref(),
+    );
+    let Some(destination) = &restore.to else {
+        let Some(archive_path) = &restore.archive else {
+            unreachable!("--archive is required without --month or --all");
+        };
+        restore_archive(
+            archive_path,
+            &restore.to_dir,
+            password.as_deref(),
+            restore.s,
         );
-        println!("\nStarting file scan...");
+        return;
+    };
+
+    let months: Vec<String> = restore
+        .month
+        .iter()
+        .map(|month| BackupPeriod::Month(*month).label())
+        .collect();
+    // 与备份时相同的源标识：--from 目录规范化后的路径，否则为 --source-label
+    let source = restore.source.as_deref().map(|source| {
+        let path = Path::new(source);
+        let source_id = if path.is_dir() {
+            backup::source_id(&[path.to_path_buf()], None)
+        } else {
+            backup::source_id(&[], Some(source))
+        };
+        archiver::source_tag(&source_id)
+    });
+    match restorer::restore_months(
+        destination,
+        &restore.to_dir,
+        &months,
+        restore.file.as_deref(),
+        source.as_deref(),
+        password.as_deref(),
+    ) {
+        Ok(summary) => {
+            if !restore.s {
+                for path in &summary.archives {
+                    println!("Restored: {}", path.display());
+                }
+                println!(
+                    "Restored {} files from {} archives into '{}'; {} files were replaced by newer versions.",
+                    summary.files_written,
+                    summary.archives.len(),
+                    restore.to_dir.display(),
+                    summary.conflicts_resolved
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!(
+                "Error: Failed to restore from '{}': {}",
+                destination.display(),
+                e
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// 解析 age X25519 公钥接收者（`age1...`）
+fn parse_recipient(value: &str) -> Result<age::x25519::Recipient, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid age recipient '{}': {}", value, e))
+}
+
+/// 解析 --cleanup-extra-pattern，正则必须包含命名捕获组 `ts`
+fn parse_cleanup_pattern(value: &str) -> Result<Regex, String> {
+    let pattern = Regex::new(value).map_err(|e| format!("invalid regex '{}': {}", value, e))?;
+    if !pattern.capture_names().any(|name| name == Some("ts")) {
+        return Err(format!(
+            "'{}' has no timestamp capture; name it ts, e.g. (?P<ts>\\d{{14}})",
+            value
+        ));
     }
+    Ok(pattern)
+}
 
-    // 用于跟踪本次运行是否真的创建了备份
-    let mut archives_created_this_run = false;
+/// 解析 --cleanup-extra-format，拒绝 chrono 无法识别的格式
+fn parse_timestamp_format(value: &str) -> Result<String, String> {
+    use chrono::format::{Item, StrftimeItems};
+    if StrftimeItems::new(value).any(|item| item == Item::Error) {
+        return Err(format!("invalid timestamp format '{}'", value));
+    }
+    Ok(value.to_string())
+}
 
-    // 4. 遍历每个待备份月份，查找文件并归档
-    for month in &months_to_backup {
-        if !args.s {
-            println!(
-                "Scanning for new/updated files for month: {:04}-{:02}...",
-                month.year, month.month
-            );
+/// 解析预期压缩率，必须在 (0, 1] 范围内
+fn parse_ratio(value: &str) -> Result<f64, String> {
+    let ratio: f64 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid ratio '{}': expected a number", value))?;
+    if ratio > 0.0 && ratio <= 1.0 {
+        Ok(ratio)
+    } else {
+        Err(format!("invalid ratio '{}': must be in (0, 1]", value))
+    }
+}
+
+/// 从 --password 或 --password-file 中读取归档密码
+fn read_password(
+    password: Option<&str>,
+    password_file: Option<&Path>,
+) -> Result<Option<String>, String> {
+    let password = match (password, password_file) {
+        (Some(password), _) => password.to_string(),
+        (None, Some(path)) => {
+            let content = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read password file '{}': {}", path.display(), e))?;
+            content.lines().next().unwrap_or("").trim().to_string()
         }
+        (None, None) => return Ok(None),
+    };
 
-        match file_scanner::find_files_to_backup(&args.from, &last_backup_time, month) {
-            Ok(files) => {
-                if files.is_empty() {
-                    if !args.s {
-                        println!(
-                            "No new or updated files found for {:04}-{:02}. Skipping.",
-                            month.year, month.month
-                        );
-                    }
-                } else {
-                    if !args.s {
-                        println!(
-                            "Found {} files to backup for {:04}-{:02}. Archiving...",
-                            files.len(),
-                            month.year,
-                            month.month
-                        );
-                    }
-
-                    match archiver::create_archive(&args.from, &files, &args.to, month) {
-                        Ok(zip_path) => {
-                            if !args.s {
-                                println!("Successfully created archive: {}", zip_path.display());
-                            }
-                            archives_created_this_run = true; // 标记已成功创建归档
-                        }
-                        Err(e) => {
-                            if !args.s {
-                                eprintln!(
-                                    "Error creating archive for {:04}-{:02}: {}",
-                                    month.year, month.month, e
-                                );
-                            }
-                        }
-                    }
+    if password.is_empty() {
+        return Err("The archive password must not be empty.".to_string());
+    }
+    Ok(Some(password))
+}
+
+/// 备份时使用的归档密码：只有 zip 归档支持密码
+fn resolve_password(args: &BackupArgs) -> Result<Option<String>, String> {
+    let password = read_password(args.password.as_deref(), args.password_file.as_deref())?;
+    if password.is_some() && args.archive_format != ArchiveFormat::Zip {
+        return Err("Password protection is only supported for zip archives.".to_string());
+    }
+    Ok(password)
+}
+
+/// 子命令的归档密码，无法读取时以退出码 1 结束
+fn password_or_exit(password: Option<&str>, password_file: Option<&Path>) -> Option<String> {
+    read_password(password, password_file).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    })
+}
+
+/// 在终端中显示备份事件：消息按级别写入标准输出或标准错误，扫描和归档的进度显示为状态行和进度条
+///
+/// 静默模式下只输出 [`BackupEvent::Alert`] 和 [`BackupEvent::Error`]。
+struct Console {
+    silent: bool,
+    /// 正在显示的扫描状态行或归档进度条，与日志的控制台输出共用
+    progress: Arc<Mutex<Option<ProgressBar>>>,
+}
+
+impl Console {
+    /// 消息交给日志输出（控制台和日志文件），进度显示为状态行或进度条
+    fn handle(&self, event: BackupEvent<'_>) {
+        match event {
+            BackupEvent::Info(message) => info!("{}", message),
+            BackupEvent::Warning(message) => warn!("{}", message),
+            BackupEvent::Alert(message) => warn!(target: logging::ALERT_TARGET, "{}", message),
+            BackupEvent::Error(message) => error!("{}", message),
+            BackupEvent::ScanProgress(progress) => {
+                if !self.silent {
+                    self.progress
+                        .lock()
+                        .unwrap()
+                        .get_or_insert_with(new_scan_spinner)
+                        .set_message(backup::describe_scan_progress(&progress));
                 }
             }
-            Err(e) => {
-                if !args.s {
-                    eprintln!(
-                        "Error scanning files for {:04}-{:02}: {}",
-                        month.year, month.month, e
-                    );
+            BackupEvent::ArchiveProgress(progress) => {
+                if !self.silent {
+                    let mut current = self.progress.lock().unwrap();
+                    let progress_bar = current.get_or_insert_with(new_progress_bar);
+                    progress_bar.set_length(progress.bytes_total);
+                    progress_bar.set_position(progress.bytes_done);
+                    progress_bar.set_message(format!(
+                        "{}/{} {}",
+                        progress.files_done,
+                        progress.files_total,
+                        progress.current_file.display()
+                    ));
+                }
+            }
+            BackupEvent::ScanFinished | BackupEvent::ArchiveFinished => {
+                if let Some(progress_bar) = self.progress.lock().unwrap().take() {
+                    progress_bar.finish_and_clear();
                 }
             }
+            BackupEvent::CleanupFinished { report, dry_run } => {
+                print_cleanup_report(report, dry_run)
+            }
+            BackupEvent::DryRunFiles { .. } => {}
         }
     }
+}
+
+/// 日志在控制台的显示方式
+enum ConsoleOutput {
+    /// 正在显示的扫描状态行或归档进度条
+    Text(Arc<Mutex<Option<ProgressBar>>>),
+    /// --output json：不显示，警告收集到 JSON 文档中；错误已包含在 [`BackupReport`] 中
+    Json(Arc<Mutex<Vec<String>>>),
+}
+
+/// 在控制台显示一条日志：信息输出到 stdout，警告和错误输出到 stderr；
+/// 先隐藏进度条再输出，避免消息与进度条混在同一行
+fn write_console(progress: &Mutex<Option<ProgressBar>>, level: Level, message: &str) {
+    let print = || match level {
+        Level::Error => eprintln!("Error: {}", message),
+        Level::Warn => eprintln!("Warning: {}", message),
+        _ => println!("{}", message),
+    };
+    match &*progress.lock().unwrap() {
+        Some(progress_bar) => progress_bar.suspend(print),
+        None => print(),
+    }
+}
 
-    // 6. 滚动删除旧备份
-    if args.keep_months > 0 {
-        if let Err(e) = cleaner::cleanup_old_backups(&args.to, args.keep_months, args.s) {
-            if !args.s {
-                eprintln!("\nAn error occurred during cleanup: {}", e);
+/// 安装日志：控制台的级别由 -s/-v 决定，日志文件（--log-file，静默时默认为缓存目录中的
+/// backup.log）至少记录信息级别的消息
+fn init_logging(args: &BackupArgs, destination_path: &Path, console: ConsoleOutput) {
+    let json = args.output == OutputFormat::Json;
+    let verbose_level = match args.verbose {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let console_level = if json {
+        LevelFilter::Warn
+    } else if args.s {
+        LevelFilter::Error
+    } else {
+        verbose_level
+    };
+    // 默认的日志文件在目标目录创建后才写入；试运行不写入目标目录
+    let log_file = match &args.log_file {
+        Some(path) => Some(LogFile::new(path, logging::DEFAULT_MAX_LOG_SIZE, None)),
+        None if (args.s || json) && !args.dry_run => {
+            let cache_folder = args
+                .cache_dir
+                .clone()
+                .unwrap_or_else(|| destination_path.join(".cache"));
+            Some(LogFile::new(
+                cache_folder.join(logging::DEFAULT_LOG_FILE_NAME),
+                logging::DEFAULT_MAX_LOG_SIZE,
+                Some(destination_path.to_path_buf()),
+            ))
+        }
+        None => None,
+    };
+    let console: logging::ConsoleWriter = match console {
+        ConsoleOutput::Text(progress) => {
+            Box::new(move |level, message| write_console(&progress, level, message))
+        }
+        ConsoleOutput::Json(warnings) => Box::new(move |level, message| {
+            if level == Level::Warn {
+                warnings.lock().unwrap().push(message.to_string());
             }
+        }),
+    };
+    let logger = Logger::new(
+        console_level,
+        console,
+        log_file.map(|file| (file, verbose_level)),
+    );
+    if let Err(e) = logger.install() {
+        eprintln!("Warning: Failed to set up logging: {}", e);
+    }
+}
+
+/// 把命令行参数转换为 [`BackupOptions`]，不包括事件和确认回调
+fn backup_options(
+    args: &BackupArgs,
+    destination_path: PathBuf,
+    mode: BackupMode,
+    password: Option<String>,
+) -> BackupOptions {
+    let mut options = BackupOptions {
+        from_label: args.from_label.clone(),
+        source_label: args.source_label.clone(),
+        allow_nested_destination: args.allow_nested_destination,
+        stale_grace_hours: args.stale_grace_hours,
+        exclude: args.exclude.clone(),
+        no_default_excludes: args.no_default_excludes,
+        include_own_artifacts: args.include_own_artifacts,
+        subdir: args.subdir.clone(),
+        max_depth: args.max_depth,
+        month_source: args.month_source,
+        month_pattern: args.month_pattern.clone(),
+        settle_seconds: args.settle_seconds,
+        future_skew_seconds: args.future_skew_seconds,
+        include_future_mtimes: args.include_future_mtimes,
+        no_backupignore: args.no_backupignore,
+        include: args.include.clone(),
+        ext: args.ext.clone(),
+        min_file_size: args.min_file_size,
+        max_file_size: args.max_file_size,
+        months: args.month.clone(),
+        since: args.since,
+        until: args.until,
+        weekly: args.weekly,
+        dynamic_threshold_days: args.dynamic_threshold_days,
+        dynamic_lookahead_days: args.dynamic_lookahead_days,
+        dynamic_week_threshold_days: args.dynamic_week_threshold_days,
+        as_of: args.as_of,
+        month_boundary_tz: args.month_boundary_tz.clone(),
+        ignore_cache_cutoff: args.ignore_cache_cutoff,
+        verbose: args.verbose > 0,
+        dry_run: args.dry_run,
+        cleanup_dry_run: args.cleanup_dry_run,
+        lock_wait_seconds: args.lock_wait_seconds,
+        cache_history_limit: args.cache_history_limit,
+        rebuild_cache: args.rebuild_cache,
+        cache_dir: args.cache_dir.clone(),
+        cache_backend: args.cache_backend,
+        compression_level: args.compression_level,
+        password,
+        symlinks: args.symlinks,
+        archive_format: args.archive_format,
+        split_size: args.split_size,
+        store_extensions: args.store_extensions.clone(),
+        no_store_heuristic: args.no_store_heuristic,
+        no_verify: args.no_verify,
+        locked_file_retries: args.locked_file_retries,
+        overlap_seconds: args.overlap_seconds,
+        fail_on_skip: args.fail_on_skip,
+        write_skip_report: args.write_skip_report,
+        retry_delay_ms: args.retry_delay_ms,
+        append: args.append,
+        reproducible: args.reproducible,
+        full: args.full,
+        incremental: args.incremental,
+        encrypt_to: args.encrypt_to.clone(),
+        split_by_top_dir: args.split_by_top_dir,
+        dedup: args.dedup,
+        detect_changes: args.detect_changes,
+        max_parallel_bytes: args.max_parallel_bytes,
+        scan_threads: args.scan_threads,
+        max_files: args.max_files,
+        max_total_size: args.max_total_size,
+        force: args.force,
+        min_free_space: args.min_free_space,
+        expected_compression_ratio: args.expected_compression_ratio,
+        ..BackupOptions::new(args.from.clone(), destination_path, mode)
+    };
+    args.retention.apply(&mut options);
+    options
+}
+
+/// 只包含备份选项的命令，用于合并配置文件
+fn backup_command() -> clap::Command {
+    <BackupArgs as clap::Args>::augment_args(clap::Command::new(env!("CARGO_PKG_NAME")))
+}
+
+/// 把写在子命令之前的 `--config <PATH>` 移到子命令之后，例如 `--config c.toml backup -n`；
+/// 子命令的选项不能写在子命令之前
+fn config_after_subcommand(mut argv: Vec<OsString>) -> Vec<OsString> {
+    let config_len = match argv.get(1).and_then(|arg| arg.to_str()) {
+        Some("--config") => 2,
+        Some(arg) if arg.starts_with("--config=") => 1,
+        _ => return argv,
+    };
+    let subcommand = 1 + config_len;
+    if argv
+        .get(subcommand)
+        .and_then(|arg| arg.to_str())
+        .is_some_and(|name| Cli::command().find_subcommand(name).is_some())
+    {
+        let config: Vec<OsString> = argv.drain(1..subcommand).collect();
+        argv.splice(2..2, config);
+    }
+    argv
+}
+
+/// 解析命令行参数；备份时（不带子命令或使用 `backup`）和 `clean` 合并 --config 指定的
+/// （或默认位置的）配置文件，--print-config 时输出合并后的配置并退出
+fn parse_args() -> Cli {
+    let argv = config_after_subcommand(std::env::args_os().collect());
+    // 读取配置文件的选项在 argv 中开始的位置及这些选项的定义；diff 与备份使用相同的选项，
+    // clean 只使用其中的滚动删除选项，其他子命令不读取配置文件
+    let (backup_start, command) = match argv.get(1).and_then(|arg| arg.to_str()) {
+        Some("backup") => (2, backup_command()),
+        Some("diff") => (
+            2,
+            <DiffArgs as clap::Args>::augment_args(clap::Command::new(env!("CARGO_PKG_NAME"))),
+        ),
+        Some("clean") => (
+            2,
+            <CleanArgs as clap::Args>::augment_args(clap::Command::new(env!("CARGO_PKG_NAME"))),
+        ),
+        Some(name) if name == "help" || Cli::command().find_subcommand(name).is_some() => {
+            return Cli::parse_from(argv);
+        }
+        _ => (1, backup_command()),
+    };
+    let backup_argv: Vec<OsString> = argv[..1]
+        .iter()
+        .chain(&argv[backup_start..])
+        .cloned()
+        .collect();
+    let config_path = config::config_arg(&backup_argv).or_else(|| {
+        config::default_locations()
+            .into_iter()
+            .find(|path| path.is_file())
+    });
+    let config_file = config_path.map(|path| match ConfigFile::load(&path) {
+        Ok(config_file) => config_file,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(EXIT_CONFIG_ERROR);
+        }
+    });
+    // 日志尚未安装，未知的键直接输出
+    for warning in config_file
+        .iter()
+        .flat_map(|config_file| &config_file.warnings)
+    {
+        eprintln!("Warning: {}", warning);
+    }
+    let merged = match config::merge_args(&command, &backup_argv, config_file.as_ref()) {
+        Ok(merged) => merged,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(EXIT_CONFIG_ERROR);
         }
+    };
+    let full_argv: Vec<OsString> = argv[..backup_start]
+        .iter()
+        .chain(&merged.args[1..])
+        .cloned()
+        .collect();
+    let matches = Cli::command().get_matches_from(full_argv);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let (args, backup_matches) = match (&cli.command, matches.subcommand()) {
+        (Some(Command::Backup(args)), Some((_, backup_matches))) => (&**args, backup_matches),
+        (Some(Command::Diff(diff)), Some((_, backup_matches))) => (&diff.backup, backup_matches),
+        _ => (&cli.backup, &matches),
+    };
+    if args.print_config {
+        print!(
+            "{}",
+            config::effective_config(&command, backup_matches, &merged)
+        );
+        process::exit(0);
+    }
+    cli
+}
+
+fn main() {
+    let cli = parse_args();
+    match cli.command {
+        None => backup(cli.backup),
+        Some(Command::Backup(args)) => backup(*args),
+        Some(Command::Clean(clean)) => clean_old_backups(&clean),
+        Some(Command::Status(status)) => show_status(&status),
+        Some(Command::Verify(verify)) => verify_destination(&verify),
+        Some(Command::Restore(restore)) => restore_command(&restore),
+        Some(Command::List(list)) => list_destination(&list),
+        Some(Command::Diff(diff)) => diff_command(&diff),
+    }
+}
+
+/// 根据 -p/-n/-d 等参数确定备份模式
+fn backup_mode(args: &BackupArgs) -> BackupMode {
+    if args.p {
+        BackupMode::PreviousMonth
+    } else if args.n {
+        BackupMode::CurrentMonth
+    } else if args.d {
+        BackupMode::Dynamic
+    } else if !args.month.is_empty() {
+        BackupMode::Explicit
+    } else if let Some(count) = args.months_back {
+        BackupMode::MonthsBack(count)
+    } else if args.catch_up {
+        BackupMode::CatchUp
+    } else if args.since.is_some() {
+        BackupMode::DateRange
+    } else {
+        // Clap 的 group 设置应该能防止这种情况，但作为安全措施我们还是处理一下
+        eprintln!(
+            "Error: You must specify exactly one of -p, -n, -d, --month, --months-back, --catch-up, or --since."
+        );
+        process::exit(1);
     }
+}
 
-    // 5. 如果创建了新的备份，则更新 .cache 文件
-    if !archives_created_this_run {
-        if !args.s {
-            println!("\nNo new backup archives were created. Cache will not be updated.");
-            println!("\nBackup process completed.");
+/// 运行备份（`backup` 子命令或不带子命令时）
+fn backup(args: BackupArgs) {
+    if let Some(archive_path) = &args.show_info {
+        show_archive_info(archive_path);
+        return;
+    }
+    let json = args.output == OutputFormat::Json;
+    let password = match resolve_password(&args) {
+        Ok(password) => password,
+        Err(e) if json && args.restore.is_none() => {
+            exit_with_json_error(&BackupError::ConfigError(e), args.dry_run, Vec::new())
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
         }
-        return; // 现在可以安全退出
+    };
+    if let (Some(archive_path), Some(target_dir)) = (&args.restore, &args.restore_to) {
+        restore_archive(archive_path, target_dir, password.as_deref(), args.s);
+        return;
     }
 
-    let script_end_time = Utc::now();
-    let backup_month_info = months_to_backup
-        .iter()
-        .map(|m| format!("{:04}-{:02}", m.year, m.month))
-        .collect::<Vec<_>>()
-        .join(", ");
+    // 未指定 --show-info 和 --restore 时 clap 保证两者都已提供
+    let Some(destination_path) = args.to.clone() else {
+        unreachable!("--from and --to are required without --show-info or --restore");
+    };
+
+    // 1. 根据参数确定备份模式
+    let mode = backup_mode(&args);
 
-    let new_record = cache::CacheRecord {
-        start_time: script_start_time,
-        end_time: script_end_time,
-        backup_info: format!("Backup for {}", backup_month_info),
+    let progress = Arc::new(Mutex::new(None));
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let console_output = if json {
+        ConsoleOutput::Json(Arc::clone(&warnings))
+    } else {
+        ConsoleOutput::Text(Arc::clone(&progress))
+    };
+    init_logging(&args, &destination_path, console_output);
+    let take_warnings = || std::mem::take(&mut *warnings.lock().unwrap());
+    // 日志文件中每次运行的开头
+    info!(
+        target: logging::FILE_TARGET,
+        "Starting dat-patch-rust {}: {:?} backup of {} to {}",
+        env!("CARGO_PKG_VERSION"),
+        mode,
+        args.from
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        destination_path.display()
+    );
+    // 打印参数时隐藏密码
+    let printable_args = BackupArgs {
+        password: args.password.as_ref().map(|_| "********".to_string()),
+        ..args.clone()
     };
+    debug!("Arguments parsed successfully:\n{:#?}", printable_args);
 
-    cache_records.push(new_record);
+    let mut options = backup_options(&args, destination_path, mode, password);
+    let console = Console {
+        silent: args.s || json,
+        progress,
+    };
+    options.on_event = Some(Box::new(move |event| console.handle(event)));
+    // 终端中列出待删除的归档并询问；静默模式、--output json 或不在终端中运行时
+    // 只有 --yes 才删除
+    if !args.s && !json && io::stdout().is_terminal() {
+        let trash = args.retention.trash;
+        options.confirm_cleanup = Some(Box::new(move |candidates| {
+            confirm_cleanup(candidates, trash)
+        }));
+    }
 
-    match cache::write_cache_records(&cache_file, &cache_records) {
-        Ok(_) => {
-            if !args.s {
-                println!(
-                    "\nSuccessfully updated cache file: {}",
-                    cache_file.display()
-                )
-            }
-        }
+    let report = match backup::run_backup(options) {
+        Ok(report) => report,
         Err(e) => {
-            if !args.s {
-                eprintln!("\nError writing to cache file: {}", e)
+            error!("{}", error_message(&e));
+            if json {
+                exit_with_json_error(&e, args.dry_run, take_warnings());
             }
+            process::exit(exit_code(&e));
         }
+    };
+
+    if report.months.is_empty() {
+        info!("No months to backup based on the selected mode. Exiting.");
+        if json {
+            exit_with_json(&report, args.dry_run, take_warnings());
+        }
+        return;
+    }
+    if args.dry_run {
+        let (files, bytes) = report.dry_run_totals();
+        info!(
+            "\nDry run: {} files ({}) would be backed up. Nothing was written or deleted.",
+            files,
+            HumanBytes(bytes)
+        );
+    } else if report.archives().next().is_none() {
+        if report.cache_updated {
+            info!("\nNo new backup archives were created.");
+        } else {
+            info!("\nNo new backup archives were created. Cache will not be updated.");
+        }
+        info!("\nBackup process completed.");
+    } else {
+        print_summary(&report, args.cleanup_dry_run);
+        info!("\nBackup process completed.");
     }
 
-    if !args.s {
-        println!("\nBackup process completed.");
+    if json {
+        exit_with_json(&report, args.dry_run, take_warnings());
     }
+    exit_on_failure(&report);
 }
@@ -0,0 +1,577 @@
+use crate::archiver::{self, ArchiveFormat, MANIFEST_NAME, Manifest};
+use crate::backup_logic::ArchiveKind;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// 打开 ZIP 归档
+fn open_zip(archive_path: &Path) -> io::Result<ZipArchive<BufReader<File>>> {
+    Ok(ZipArchive::new(BufReader::new(File::open(archive_path)?))?)
+}
+
+/// 读取 ZIP 归档内的 `MANIFEST.json`
+///
+/// 没有清单的归档（旧版本创建）视为独立归档。
+fn read_manifest(archive_path: &Path, password: Option<&str>) -> io::Result<Manifest> {
+    archiver::read_manifest(archive_path, ArchiveFormat::Zip, password)
+}
+
+/// 确定恢复 `archive_path` 所代表的状态需要依次重放的归档
+///
+/// 增量归档需要先重放其基础全量归档，再按文件名（即创建时间）顺序重放同一目录中
+/// 基于该全量归档、且不晚于 `archive_path` 的所有增量归档。只读取与 `archive_path`
+/// 属于同一系列（同一月份、分组和源）的 ZIP 归档的清单，其他月份已损坏的归档不影响恢复。
+/// 全量归档和独立归档只需重放自身。
+fn restore_chain(archive_path: &Path, password: Option<&str>) -> io::Result<Vec<PathBuf>> {
+    let manifest = read_manifest(archive_path, password)?;
+    if manifest.kind != Some(ArchiveKind::Incremental) {
+        return Ok(vec![archive_path.to_path_buf()]);
+    }
+
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let base_name = manifest.base_archive.ok_or_else(|| {
+        invalid(format!(
+            "Incremental archive '{}' does not name its base archive",
+            archive_path.display()
+        ))
+    })?;
+    let directory = archive_path.parent().unwrap_or(Path::new("."));
+    let base_path = directory.join(&base_name);
+    if read_manifest(&base_path, password)?.kind != Some(ArchiveKind::Full) {
+        return Err(invalid(format!(
+            "Base archive '{}' is not a full archive",
+            base_path.display()
+        )));
+    }
+
+    let target_name = archive_path.file_name().unwrap_or_default().to_os_string();
+    let target_series =
+        archiver::parse_archive_name(&target_name.to_string_lossy()).map(|info| info.series());
+    let mut incrementals = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_os_string();
+        let same_series =
+            archiver::parse_archive_name(&name.to_string_lossy()).is_some_and(|info| {
+                info.format == ArchiveFormat::Zip
+                    && !info.encrypted
+                    && !info.skip_report
+                    && target_series
+                        .as_ref()
+                        .is_none_or(|series| *series == info.series())
+            });
+        if !path.is_file() || !same_series || name > target_name {
+            continue;
+        }
+        let manifest = read_manifest(&path, password)?;
+        if manifest.kind == Some(ArchiveKind::Incremental)
+            && manifest.base_archive.as_deref() == Some(base_name.as_str())
+        {
+            incrementals.push(path);
+        }
+    }
+    incrementals.sort();
+
+    let mut chain = vec![base_path];
+    chain.extend(incrementals);
+    Ok(chain)
+}
+
+/// ZIP 条目的时间（archiver 以 UTC 写入）；ZIP 的最早时间表示创建归档时没有修改时间
+fn zip_entry_time(time: zip::DateTime) -> Option<DateTime<Utc>> {
+    if time == zip::DateTime::default() {
+        return None;
+    }
+    let date = NaiveDate::from_ymd_opt(time.year().into(), time.month().into(), time.day().into())?;
+    Some(
+        date.and_hms_opt(
+            time.hour().into(),
+            time.minute().into(),
+            time.second().into(),
+        )?
+        .and_utc(),
+    )
+}
+
+/// 将单个归档中的文件解压到 `target_dir`，覆盖已存在的同名文件
+///
+/// 文件的修改时间取自清单（精确到纳秒），没有清单的旧归档取自 ZIP 条目（精确到 2 秒）。
+/// 在 Unix 上同时恢复条目中记录的权限。
+///
+/// # Arguments
+/// * `only` - 只解压该条目（`/` 分隔的相对路径）
+///
+/// # Returns
+/// 解压的文件的条目名
+fn extract_zip(
+    archive_path: &Path,
+    target_dir: &Path,
+    password: Option<&str>,
+    only: Option<&str>,
+) -> io::Result<Vec<String>> {
+    let modified: HashMap<String, DateTime<Utc>> = read_manifest(archive_path, password)?
+        .entries
+        .into_iter()
+        .filter_map(|entry| Some((entry.path, entry.modified?)))
+        .collect();
+    let mut archive = open_zip(archive_path)?;
+    let mut written = Vec::new();
+    for index in 0..archive.len() {
+        let mut entry = match password {
+            Some(password) => archive.by_index_decrypt(index, password.as_bytes())?,
+            None => archive.by_index(index)?,
+        };
+        if entry.name() == MANIFEST_NAME || only.is_some_and(|only| entry.name() != only) {
+            continue;
+        }
+        // 拒绝包含 `..` 或绝对路径的条目，避免写到目标目录之外
+        let Some(relative_path) = entry.enclosed_name() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsafe entry name in archive: {}", entry.name()),
+            ));
+        };
+        let output_path = target_dir.join(relative_path);
+        if entry.is_dir() {
+            fs::create_dir_all(&output_path)?;
+            continue;
+        }
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(&output_path)?;
+        io::copy(&mut entry, &mut file)?;
+        let name = entry.name().to_string();
+        if let Some(time) = modified
+            .get(&name)
+            .copied()
+            .or_else(|| entry.last_modified().and_then(zip_entry_time))
+        {
+            file.set_modified(time.into())?;
+        }
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&output_path, fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+        written.push(name);
+    }
+    Ok(written)
+}
+
+/// 恢复归档所代表的备份状态
+///
+/// 对增量归档，先解压其基础全量归档，再按顺序重放之后的增量归档，
+/// 后面的归档覆盖前面归档中的同名文件。仅支持 ZIP 格式。
+///
+/// # Arguments
+/// * `archive_path` - 需要恢复到的归档（全量、增量或独立归档）
+/// * `target_dir` - 恢复的目标目录
+/// * `password` - 归档的密码
+///
+/// # Returns
+/// 按重放顺序返回使用的归档路径
+pub fn restore_archive(
+    archive_path: &Path,
+    target_dir: &Path,
+    password: Option<&str>,
+) -> io::Result<Vec<PathBuf>> {
+    if archive_path.extension().is_none_or(|ext| ext != "zip") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Only ZIP archives can be restored",
+        ));
+    }
+
+    let chain = restore_chain(archive_path, password)?;
+    fs::create_dir_all(target_dir)?;
+    for path in &chain {
+        extract_zip(path, target_dir, password, None)?;
+    }
+    Ok(chain)
+}
+
+/// [`restore_months`] 的结果
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreSummary {
+    /// 按解压顺序排列的归档
+    pub archives: Vec<PathBuf>,
+    /// 写入的文件数，同一相对路径只计一次
+    pub files_written: usize,
+    /// 被之后的归档中较新的版本覆盖的文件数
+    pub conflicts_resolved: usize,
+}
+
+/// 从备份的目标目录中恢复若干月份的文件
+///
+/// 使用 [`archiver::find_archives`] 找出 `destination` 中属于这些月份的归档，按创建时间
+/// （同一时间按分卷编号）依次解压到 `target_dir`，之后的归档覆盖之前归档中同一相对路径的文件。
+/// 目录结构和文件的修改时间与备份时相同。仅支持 ZIP 格式。
+///
+/// # Arguments
+/// * `destination` - 备份的目标目录（--to）
+/// * `target_dir` - 恢复的目标目录
+/// * `months` - 归档的月份 `YYYY-MM`；为空时恢复所有归档
+/// * `file` - 只恢复这个相对路径的文件
+/// * `source` - 多个源共享目标目录时要恢复的源的标记（见 [`archiver::source_tag`]）；
+///   没有带该标记的归档时恢复不带标记的归档，即最早备份到该目录的源
+/// * `password` - 归档的密码
+///
+/// # Returns
+/// 没有匹配的归档、匹配的归档不是未加密的 ZIP 归档、匹配的归档属于多个源而没有给出 `source`，
+/// 或者没有归档包含 `file` 时返回错误，此时没有写入任何文件（归档损坏除外）
+pub fn restore_months(
+    destination: &Path,
+    target_dir: &Path,
+    months: &[String],
+    file: Option<&Path>,
+    source: Option<&str>,
+    password: Option<&str>,
+) -> io::Result<RestoreSummary> {
+    let mut found = archiver::find_archives(destination)?;
+    found.retain(|(_, info)| months.is_empty() || months.contains(&info.month));
+    // 不同源的同名文件不能合并到一个目录中
+    let sources: BTreeSet<Option<String>> =
+        found.iter().map(|(_, info)| info.source.clone()).collect();
+    let selected = match source {
+        Some(tag) if sources.contains(&Some(tag.to_string())) => Some(tag.to_string()),
+        Some(_) => None,
+        None if sources.len() > 1 => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The archives in '{}' belong to {} sources; choose one with --source",
+                    destination.display(),
+                    sources.len()
+                ),
+            ));
+        }
+        None => sources.into_iter().next().flatten(),
+    };
+
+    let mut archives = Vec::new();
+    for (path, info) in found {
+        if info.source != selected {
+            continue;
+        }
+        if info.encrypted || info.format != ArchiveFormat::Zip {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "Only unencrypted ZIP archives can be restored: {}",
+                    path.display()
+                ),
+            ));
+        }
+        archives.push(path);
+    }
+    if archives.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No backup archives of {} found in '{}'",
+                if months.is_empty() {
+                    "any month".to_string()
+                } else {
+                    months.join(", ")
+                },
+                destination.display()
+            ),
+        ));
+    }
+
+    let only = file.map(archiver::entry_name);
+    if let Some(only) = &only {
+        let mut found = false;
+        for path in &archives {
+            if open_zip(path)?.index_for_name(only).is_some() {
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("'{}' is not in any of the archives", only),
+            ));
+        }
+    }
+
+    fs::create_dir_all(target_dir)?;
+    let mut summary = RestoreSummary::default();
+    let mut written = BTreeSet::new();
+    for path in &archives {
+        let names = extract_zip(path, target_dir, password, only.as_deref())?;
+        if names.is_empty() {
+            continue;
+        }
+        for name in names {
+            if !written.insert(name) {
+                summary.conflicts_resolved += 1;
+            }
+        }
+        summary.archives.push(path.clone());
+    }
+    summary.files_written = written.len();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archiver::{ArchiveOptions, create_archive};
+    use crate::backup_logic::{BackupMonth, BackupPeriod};
+    use crate::file_scanner::FileEntry;
+
+    fn archive(
+        source: &Path,
+        files: &[&str],
+        destination: &Path,
+        options: &ArchiveOptions,
+    ) -> PathBuf {
+        let entries: Vec<FileEntry> = files
+            .iter()
+            .map(|name| {
+                let path = source.join(name);
+                let metadata = fs::metadata(&path).unwrap();
+                FileEntry {
+                    path,
+                    relative_path: PathBuf::from(name),
+                    size: metadata.len(),
+                    modified: metadata.modified().unwrap().into(),
+                    source_label: None,
+                }
+            })
+            .collect();
+        let month = BackupPeriod::Month(BackupMonth {
+            year: 2025,
+            month: 7,
+        });
+        let stats =
+            create_archive(source, &entries, destination, &month, options, &mut |_| {}).unwrap();
+        // 归档文件名精确到秒，避免同一秒内创建的归档重名
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        stats.archive_paths[0].clone()
+    }
+
+    #[test]
+    fn incrementals_are_replayed_on_top_of_their_full_archive() {
+        let root = std::env::temp_dir().join(format!("dat-patch-restore-{}", uuid::Uuid::new_v4()));
+        let source = root.join("source");
+        let destination = root.join("backups");
+        fs::create_dir_all(source.join("Msg")).unwrap();
+        fs::create_dir_all(&destination).unwrap();
+
+        fs::write(source.join("Msg").join("a.dat"), "a v1").unwrap();
+        fs::write(source.join("Msg").join("b.dat"), "b v1").unwrap();
+        let full_options = ArchiveOptions {
+            compression_level: 6,
+            kind: Some(ArchiveKind::Full),
+            ..Default::default()
+        };
+        let full = archive(
+            &source,
+            &["Msg/a.dat", "Msg/b.dat"],
+            &destination,
+            &full_options,
+        );
+
+        let incremental_options = ArchiveOptions {
+            kind: Some(ArchiveKind::Incremental),
+            base_archive: Some(full.file_name().unwrap().to_str().unwrap().to_string()),
+            ..full_options.clone()
+        };
+        fs::write(source.join("Msg").join("a.dat"), "a v2").unwrap();
+        let first = archive(&source, &["Msg/a.dat"], &destination, &incremental_options);
+        fs::write(source.join("Msg").join("c.dat"), "c v1").unwrap();
+        let second = archive(&source, &["Msg/c.dat"], &destination, &incremental_options);
+        fs::write(source.join("Msg").join("b.dat"), "b v3").unwrap();
+        let third = archive(&source, &["Msg/b.dat"], &destination, &incremental_options);
+        // 其他月份已损坏的归档不读取
+        fs::write(
+            destination.join("2025-06_backup_20250701000000.zip"),
+            "not a zip",
+        )
+        .unwrap();
+
+        // 恢复到第二个增量归档时的状态：第三个增量不参与重放
+        let target = root.join("restored");
+        let chain = restore_archive(&second, &target, None).unwrap();
+        assert_eq!(chain, vec![full.clone(), first, second]);
+        let read = |name: &str| fs::read_to_string(target.join("Msg").join(name)).unwrap();
+        assert_eq!(read("a.dat"), "a v2");
+        assert_eq!(read("b.dat"), "b v1");
+        assert_eq!(read("c.dat"), "c v1");
+        assert!(!target.join(MANIFEST_NAME).exists());
+
+        // 全量归档只恢复自身
+        let full_target = root.join("restored-full");
+        assert_eq!(
+            restore_archive(&full, &full_target, None).unwrap(),
+            vec![full]
+        );
+        assert!(!full_target.join("Msg").join("c.dat").exists());
+        assert!(third.exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn months_are_restored_in_chronological_order() {
+        let root = std::env::temp_dir().join(format!("dat-patch-restore-{}", uuid::Uuid::new_v4()));
+        let source = root.join("source");
+        let destination = root.join("backups");
+        fs::create_dir_all(source.join("Msg")).unwrap();
+        fs::create_dir_all(&destination).unwrap();
+        let options = ArchiveOptions {
+            compression_level: 6,
+            ..Default::default()
+        };
+
+        fs::write(source.join("Msg").join("a.dat"), "a v1").unwrap();
+        fs::write(source.join("Msg").join("b.dat"), "b v1").unwrap();
+        let first = archive(&source, &["Msg/a.dat", "Msg/b.dat"], &destination, &options);
+        fs::write(source.join("Msg").join("a.dat"), "a v2").unwrap();
+        let second = archive(&source, &["Msg/a.dat"], &destination, &options);
+
+        let target = root.join("restored");
+        let months = ["2025-07".to_string()];
+        let summary = restore_months(&destination, &target, &months, None, None, None).unwrap();
+        assert_eq!(
+            summary,
+            RestoreSummary {
+                archives: vec![first.clone(), second.clone()],
+                files_written: 2,
+                conflicts_resolved: 1,
+            }
+        );
+        let restored = target.join("Msg").join("a.dat");
+        assert_eq!(fs::read_to_string(&restored).unwrap(), "a v2");
+        assert_eq!(
+            fs::metadata(&restored).unwrap().modified().unwrap(),
+            fs::metadata(source.join("Msg").join("a.dat"))
+                .unwrap()
+                .modified()
+                .unwrap()
+        );
+
+        // 只恢复一个文件时跳过不包含它的归档
+        let single = root.join("single");
+        let file = Path::new("Msg").join("a.dat");
+        let summary = restore_months(&destination, &single, &[], Some(&file), None, None).unwrap();
+        assert_eq!(summary.files_written, 1);
+        assert!(!single.join("Msg").join("b.dat").exists());
+        assert!(
+            restore_months(
+                &destination,
+                &single,
+                &[],
+                Some(Path::new("c.dat")),
+                None,
+                None
+            )
+            .is_err()
+        );
+
+        let error = restore_months(
+            &destination,
+            &target,
+            &["2025-06".to_string()],
+            None,
+            None,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        // 另一个源共享目标目录时必须选择其中一个源
+        fs::write(source.join("Msg").join("c.dat"), "other source").unwrap();
+        let tag = archiver::source_tag("other");
+        let other = archive(
+            &source,
+            &["Msg/c.dat"],
+            &destination,
+            &ArchiveOptions {
+                source: Some(tag.clone()),
+                ..options.clone()
+            },
+        );
+        let error = restore_months(&destination, &root.join("mixed"), &months, None, None, None)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        let summary = restore_months(
+            &destination,
+            &root.join("other"),
+            &months,
+            None,
+            Some(&tag),
+            None,
+        )
+        .unwrap();
+        assert_eq!(summary.archives, vec![other]);
+        // 没有带该标记的归档时恢复最早的源（不带标记）的归档
+        let untagged = archiver::source_tag("first");
+        let summary = restore_months(
+            &destination,
+            &root.join("first"),
+            &months,
+            None,
+            Some(&untagged),
+            None,
+        )
+        .unwrap();
+        assert_eq!(summary.archives, vec![first, second]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_permissions_are_restored() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("dat-patch-restore-{}", uuid::Uuid::new_v4()));
+        let source = root.join("source");
+        let destination = root.join("backups");
+        fs::create_dir_all(source.join("Msg")).unwrap();
+        fs::create_dir_all(&destination).unwrap();
+        let script = source.join("Msg").join("run.sh");
+        fs::write(&script, "#!/bin/sh").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(source.join("Msg").join("a.dat"), "a").unwrap();
+        let options = ArchiveOptions {
+            compression_level: 6,
+            ..Default::default()
+        };
+        let zip = archive(
+            &source,
+            &["Msg/run.sh", "Msg/a.dat"],
+            &destination,
+            &options,
+        );
+
+        let target = root.join("restored");
+        restore_archive(&zip, &target, None).unwrap();
+        let mode = |name: &str| {
+            fs::metadata(target.join("Msg").join(name))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777
+        };
+        assert_eq!(mode("run.sh"), 0o755);
+        assert_eq!(
+            mode("a.dat"),
+            fs::metadata(source.join("Msg").join("a.dat"))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
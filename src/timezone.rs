@@ -0,0 +1,122 @@
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use chrono_tz::Tz;
+
+/// 计算月份（或周）边界使用的时区（--month-boundary-tz）
+///
+/// 备份机与产生数据的机器不在同一时区时，按本地时区划分月份会让月末几个小时内修改的文件
+/// 归入相邻的月份；指定数据所在的时区后，两台机器得到相同的划分。
+#[derive(Debug, Clone, Default)]
+pub enum MonthBoundaryTz {
+    /// 运行本工具的机器的本地时区（默认）
+    #[default]
+    Local,
+    Utc,
+    /// 内置时区数据库（`chrono-tz`）中的 IANA 时区，例如 `Asia/Shanghai`
+    Named(Tz),
+}
+
+impl MonthBoundaryTz {
+    /// 解析 --month-boundary-tz 的值：`local`、`utc`（不区分大小写）或 IANA 时区名
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value.eq_ignore_ascii_case("local") {
+            Ok(MonthBoundaryTz::Local)
+        } else if value.eq_ignore_ascii_case("utc") {
+            Ok(MonthBoundaryTz::Utc)
+        } else {
+            value
+                .parse::<Tz>()
+                .map(MonthBoundaryTz::Named)
+                .map_err(|_| format!("unknown time zone '{}'", value))
+        }
+    }
+
+    /// 记录在归档元数据和缓存中的名称：`local`、`UTC` 或 IANA 时区名
+    pub fn name(&self) -> &str {
+        match self {
+            MonthBoundaryTz::Local => "local",
+            MonthBoundaryTz::Utc => "UTC",
+            MonthBoundaryTz::Named(tz) => tz.name(),
+        }
+    }
+
+    /// `date` 在该时区中的第一个时刻，见 [`crate::backup_logic::start_of_day_utc`]
+    pub fn start_of_day_utc(&self, date: NaiveDate) -> DateTime<Utc> {
+        match self {
+            MonthBoundaryTz::Local => crate::backup_logic::start_of_day_utc(&Local, date),
+            MonthBoundaryTz::Utc => crate::backup_logic::start_of_day_utc(&Utc, date),
+            MonthBoundaryTz::Named(tz) => crate::backup_logic::start_of_day_utc(tz, date),
+        }
+    }
+
+    /// `time` 在该时区中的日期
+    pub fn date_of(&self, time: DateTime<Utc>) -> NaiveDate {
+        match self {
+            MonthBoundaryTz::Local => time.with_timezone(&Local).date_naive(),
+            MonthBoundaryTz::Utc => time.date_naive(),
+            MonthBoundaryTz::Named(tz) => time.with_timezone(tz).date_naive(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{LocalResult, TimeZone};
+
+    #[test]
+    fn named_zones_use_the_bundled_database() {
+        let tz = MonthBoundaryTz::parse("Asia/Shanghai").unwrap();
+        assert_eq!(tz.name(), "Asia/Shanghai");
+        let date = |m, d| NaiveDate::from_ymd_opt(2025, m, d).unwrap();
+        assert_eq!(
+            tz.start_of_day_utc(date(8, 1)),
+            Utc.with_ymd_and_hms(2025, 7, 31, 16, 0, 0).unwrap()
+        );
+        assert_eq!(
+            tz.date_of(Utc.with_ymd_and_hms(2025, 7, 31, 20, 0, 0).unwrap()),
+            date(8, 1)
+        );
+
+        // 夏令时开始时 02:00-03:00 不存在，结束时 01:00-02:00 出现两次
+        let MonthBoundaryTz::Named(new_york) = MonthBoundaryTz::parse("America/New_York").unwrap()
+        else {
+            panic!("America/New_York should be a named zone");
+        };
+        let local = |m, d, h| date(m, d).and_hms_opt(h, 30, 0).unwrap();
+        assert!(matches!(
+            new_york.from_local_datetime(&local(3, 9, 2)),
+            LocalResult::None
+        ));
+        let LocalResult::Ambiguous(earlier, later) = new_york.from_local_datetime(&local(11, 2, 1))
+        else {
+            panic!("01:30 on the day DST ends should be ambiguous");
+        };
+        assert_eq!(later - earlier, chrono::Duration::hours(1));
+        assert_eq!(
+            MonthBoundaryTz::Named(new_york).start_of_day_utc(date(7, 1)),
+            Utc.with_ymd_and_hms(2025, 7, 1, 4, 0, 0).unwrap()
+        );
+
+        // 在零点切换夏令时的时区，当天的第一个时刻是 01:00
+        let santiago = MonthBoundaryTz::parse("America/Santiago").unwrap();
+        assert_eq!(
+            santiago.start_of_day_utc(date(9, 7)),
+            Utc.with_ymd_and_hms(2025, 9, 7, 4, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_zone_names_are_rejected() {
+        assert!(matches!(
+            MonthBoundaryTz::parse("UTC"),
+            Ok(MonthBoundaryTz::Utc)
+        ));
+        assert!(matches!(
+            MonthBoundaryTz::parse("Local"),
+            Ok(MonthBoundaryTz::Local)
+        ));
+        for name in ["", "../etc/passwd", "/etc/localtime", "Asia/No_Such_City"] {
+            assert!(MonthBoundaryTz::parse(name).is_err(), "{}", name);
+        }
+    }
+}
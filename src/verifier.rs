@@ -0,0 +1,303 @@
+use crate::archiver::{self, ArchiveFormat, HashingReader, MANIFEST_NAME, Manifest};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// 校验归档文件的完整性
+///
+/// 重新打开已写入的归档，逐个解压所有条目：ZIP 条目依赖内置的 CRC32 校验，
+/// tar 归档依赖外层 gzip / zstd 流的校验和。
+///
+/// # Arguments
+/// * `archive_path` - 需要校验的归档文件路径
+/// * `format` - 归档格式
+/// * `password` - 加密 ZIP 的密码（如有）
+///
+/// # Returns
+/// 校验通过时返回归档中的条目数，任何条目损坏时返回错误
+pub fn verify_archive(
+    archive_path: &Path,
+    format: ArchiveFormat,
+    password: Option<&str>,
+) -> io::Result<usize> {
+    let file = BufReader::new(File::open(archive_path)?);
+    match format {
+        ArchiveFormat::Zip => verify_zip(file, password),
+        ArchiveFormat::TarGz => verify_tar(flate2::read::GzDecoder::new(file)),
+        ArchiveFormat::TarZst => verify_tar(zstd::Decoder::with_buffer(file)?),
+    }
+}
+
+fn verify_zip(file: BufReader<File>, password: Option<&str>) -> io::Result<usize> {
+    let mut archive = ZipArchive::new(file)?;
+    for i in 0..archive.len() {
+        let mut entry = match password {
+            Some(password) => archive.by_index_decrypt(i, password.as_bytes())?,
+            None => archive.by_index(i)?,
+        };
+        // 读取到末尾时 zip crate 会校验 CRC32，不匹配则返回错误
+        io::copy(&mut entry, &mut io::sink())
+            .map_err(|e| io::Error::new(e.kind(), format!("entry '{}': {}", entry.name(), e)))?;
+    }
+    Ok(archive.len())
+}
+
+fn verify_tar<R: io::Read>(stream: R) -> io::Result<usize> {
+    let mut tar = tar::Archive::new(stream);
+    let mut count = 0;
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        io::copy(&mut entry, &mut io::sink())?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// [`verify_archive_digests`] 的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DigestCheck {
+    /// 归档中的条目数
+    pub entries: usize,
+    /// 与清单核对了 SHA-256 的文件数；没有清单的旧归档为 `None`
+    pub digests_checked: Option<usize>,
+}
+
+/// 像 [`verify_archive`] 一样解压所有条目，并与 `MANIFEST.json` 中记录的 SHA-256 重新核对
+///
+/// 清单中的文件在归档中缺失或内容的摘要不符时返回错误；没有清单的旧归档只做 CRC 校验。
+/// 符号链接条目核对的是链接目标的摘要，与创建归档时一致。
+pub fn verify_archive_digests(
+    archive_path: &Path,
+    format: ArchiveFormat,
+    password: Option<&str>,
+) -> io::Result<DigestCheck> {
+    let file = BufReader::new(File::open(archive_path)?);
+    let (entries, digests, manifest) = match format {
+        ArchiveFormat::Zip => zip_digests(file, password)?,
+        ArchiveFormat::TarGz => tar_digests(flate2::read::GzDecoder::new(file))?,
+        ArchiveFormat::TarZst => tar_digests(zstd::Decoder::with_buffer(file)?)?,
+    };
+    let Some(manifest) = manifest else {
+        return Ok(DigestCheck {
+            entries,
+            digests_checked: None,
+        });
+    };
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    for entry in &manifest.entries {
+        match digests.get(&entry.path) {
+            None => {
+                return Err(invalid(format!(
+                    "entry '{}' listed in the manifest is missing",
+                    entry.path
+                )));
+            }
+            Some(digest) if *digest != entry.sha256 => {
+                return Err(invalid(format!(
+                    "entry '{}' does not match its SHA-256 in the manifest",
+                    entry.path
+                )));
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(DigestCheck {
+        entries,
+        digests_checked: Some(manifest.entries.len()),
+    })
+}
+
+/// 各条目内容的 SHA-256（按条目名）及清单
+type Digests = (usize, HashMap<String, String>, Option<Manifest>);
+
+/// 读取清单条目；清单本身无法解析同样视为损坏
+fn parse_manifest(entry: impl Read) -> io::Result<Manifest> {
+    serde_json::from_reader(entry).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("entry '{}': {}", MANIFEST_NAME, e),
+        )
+    })
+}
+
+fn zip_digests(file: BufReader<File>, password: Option<&str>) -> io::Result<Digests> {
+    let mut archive = ZipArchive::new(file)?;
+    let mut digests = HashMap::new();
+    let mut manifest = None;
+    for i in 0..archive.len() {
+        let entry = match password {
+            Some(password) => archive.by_index_decrypt(i, password.as_bytes())?,
+            None => archive.by_index(i)?,
+        };
+        let name = entry.name().to_string();
+        if name == MANIFEST_NAME {
+            manifest = Some(parse_manifest(entry)?);
+            continue;
+        }
+        let mut reader = HashingReader::new(entry);
+        // 读取到末尾时 zip crate 会校验 CRC32，不匹配则返回错误
+        io::copy(&mut reader, &mut io::sink())
+            .map_err(|e| io::Error::new(e.kind(), format!("entry '{}': {}", name, e)))?;
+        digests.insert(name, reader.hex_digest());
+    }
+    Ok((archive.len(), digests, manifest))
+}
+
+fn tar_digests<R: Read>(stream: R) -> io::Result<Digests> {
+    let mut tar = tar::Archive::new(stream);
+    let mut count = 0;
+    let mut digests = HashMap::new();
+    let mut manifest = None;
+    for entry in tar.entries()? {
+        let entry = entry?;
+        count += 1;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        let entry_type = entry.header().entry_type();
+        if name == MANIFEST_NAME {
+            manifest = Some(parse_manifest(entry)?);
+        } else if entry_type.is_symlink() {
+            let target = entry.link_name_bytes().unwrap_or_default();
+            digests.insert(
+                name,
+                archiver::link_digest(&String::from_utf8_lossy(&target)),
+            );
+        } else if entry_type.is_file() {
+            let mut reader = HashingReader::new(entry);
+            io::copy(&mut reader, &mut io::sink())?;
+            digests.insert(name, reader.hex_digest());
+        }
+    }
+    Ok((count, digests, manifest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archiver::{ArchiveOptions, create_archive};
+    use crate::backup_logic::{BackupMonth, BackupPeriod};
+    use crate::file_scanner::FileEntry;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// 创建一个包含单个文件的归档，返回 (测试根目录, 归档路径)
+    fn create_test_archive(format: ArchiveFormat, compression_level: u32) -> (PathBuf, PathBuf) {
+        let root =
+            std::env::temp_dir().join(format!("dat-patch-verifier-{}", uuid::Uuid::new_v4()));
+        let source = root.join("in");
+        fs::create_dir_all(source.join("sub")).unwrap();
+        let file = source.join("sub").join("data.txt");
+        fs::write(&file, "verify me ".repeat(200)).unwrap();
+        let metadata = fs::metadata(&file).unwrap();
+        let entry = FileEntry {
+            relative_path: PathBuf::from("sub").join("data.txt"),
+            path: file,
+            size: metadata.len(),
+            modified: metadata.modified().unwrap().into(),
+            source_label: None,
+        };
+
+        let stats = create_archive(
+            &source,
+            std::slice::from_ref(&entry),
+            &root,
+            &BackupPeriod::Month(BackupMonth {
+                year: 2025,
+                month: 7,
+            }),
+            &ArchiveOptions {
+                format,
+                compression_level,
+                ..Default::default()
+            },
+            &mut |_| {},
+        )
+        .unwrap();
+        (root, stats.archive_paths[0].clone())
+    }
+
+    #[test]
+    fn intact_archives_pass() {
+        for format in [
+            ArchiveFormat::Zip,
+            ArchiveFormat::TarGz,
+            ArchiveFormat::TarZst,
+        ] {
+            let (root, archive_path) = create_test_archive(format, 6);
+            assert!(verify_archive(&archive_path, format, None).unwrap() > 0);
+            fs::remove_dir_all(&root).unwrap();
+        }
+    }
+
+    #[test]
+    fn truncated_archive_fails() {
+        let (root, archive_path) = create_test_archive(ArchiveFormat::Zip, 6);
+        let content = fs::read(&archive_path).unwrap();
+        fs::write(&archive_path, &content[..content.len() / 2]).unwrap();
+
+        assert!(verify_archive(&archive_path, ArchiveFormat::Zip, None).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn corrupted_entry_fails_crc_check() {
+        // 使用 Stored 方式，便于直接定位并篡改条目内容
+        let (root, archive_path) = create_test_archive(ArchiveFormat::Zip, 0);
+        let mut content = fs::read(&archive_path).unwrap();
+        let offset = content.windows(9).position(|w| w == b"verify me").unwrap();
+        content[offset] ^= 0xff;
+        fs::write(&archive_path, &content).unwrap();
+
+        assert!(verify_archive(&archive_path, ArchiveFormat::Zip, None).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn digests_are_checked_against_the_manifest() {
+        for format in [
+            ArchiveFormat::Zip,
+            ArchiveFormat::TarGz,
+            ArchiveFormat::TarZst,
+        ] {
+            let (root, archive_path) = create_test_archive(format, 6);
+            let check = verify_archive_digests(&archive_path, format, None).unwrap();
+            assert_eq!(check.digests_checked, Some(1), "{:?}", format);
+            fs::remove_dir_all(&root).unwrap();
+        }
+
+        // CRC 正确但内容与清单不符，例如归档被其他工具重新打包
+        let root =
+            std::env::temp_dir().join(format!("dat-patch-verifier-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        let write_zip = |path: &Path, manifest_path: &str| {
+            let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            zip.start_file("a.txt", options).unwrap();
+            io::Write::write_all(&mut zip, b"changed").unwrap();
+            let manifest = Manifest {
+                entries: vec![archiver::ManifestEntry {
+                    path: manifest_path.to_string(),
+                    size: 8,
+                    modified: None,
+                    sha256: archiver::link_digest("original"),
+                }],
+                ..Default::default()
+            };
+            zip.start_file(MANIFEST_NAME, options).unwrap();
+            serde_json::to_writer(&mut zip, &manifest).unwrap();
+            zip.finish().unwrap();
+        };
+        let changed = root.join("changed.zip");
+        write_zip(&changed, "a.txt");
+        assert!(verify_archive(&changed, ArchiveFormat::Zip, None).is_ok());
+        let error = verify_archive_digests(&changed, ArchiveFormat::Zip, None).unwrap_err();
+        assert!(error.to_string().contains("SHA-256"), "{}", error);
+
+        let missing = root.join("missing.zip");
+        write_zip(&missing, "b.txt");
+        let error = verify_archive_digests(&missing, ArchiveFormat::Zip, None).unwrap_err();
+        assert!(error.to_string().contains("'b.txt'"), "{}", error);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
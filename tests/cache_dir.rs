@@ -0,0 +1,103 @@
+//! --cache-dir 的测试：缓存、索引和锁文件都位于指定的目录，目标目录中不创建 .cache。
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// 辅助函数：以当月模式运行备份，缓存位于 `cache_dir`
+fn run_backup(source_dir: &Path, dest_dir: &Path, cache_dir: &Path) -> Output {
+    common::backup_command(source_dir, dest_dir)
+        .arg("--cache-dir")
+        .arg(cache_dir)
+        .args(["-n", "-s", "--overlap-seconds", "0", "--dedup"])
+        .args(["--detect-changes", "size+mtime"])
+        .output()
+        .expect("Failed to execute command")
+}
+
+// 辅助函数：按文件名排序列出目标目录中各归档的文件条目
+fn archive_entries(dest_dir: &Path) -> Vec<Vec<String>> {
+    let mut archives: Vec<PathBuf> = fs::read_dir(dest_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    archives.sort();
+    archives
+        .iter()
+        .map(|path| {
+            let archive = zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+            archive
+                .file_names()
+                .filter(|name| !name.ends_with('/') && *name != "MANIFEST.json")
+                .map(str::to_string)
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn cache_is_kept_outside_the_destination() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-cache-dir-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    let cache_dir = test_root.join("state").join("cache");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &cache_dir);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!dest_dir.join(".cache").exists());
+    for name in [
+        "backupEvents.json",
+        "fileHashes.json",
+        "scanIndex.json",
+        "lock",
+    ] {
+        assert!(cache_dir.join(name).is_file(), "{} is missing", name);
+    }
+
+    // 截止时间从指定的缓存读取，只有新文件被归档；归档文件名精确到秒
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    fs::write(source_dir.join("b.dat"), "b").unwrap();
+    let output = run_backup(&source_dir, &dest_dir, &cache_dir);
+    assert!(output.status.success());
+    assert_eq!(archive_entries(&dest_dir), [["a.dat"], ["b.dat"]]);
+    assert!(!dest_dir.join(".cache").exists());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .args(["status", "--json", "--cache-dir"])
+        .arg(&cache_dir)
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(status["Records"].as_array().unwrap().len(), 2);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn cache_dir_inside_the_source_is_not_backed_up() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-cache-dir-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    let cache_dir = source_dir.join("backup-state");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &cache_dir);
+    assert!(output.status.success());
+    assert_eq!(archive_entries(&dest_dir), [["a.dat"]]);
+    assert!(cache_dir.join("backupEvents.json").is_file());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,139 @@
+//! 清理删除归档后标记缓存记录及 --cache-history-limit 的测试。
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以当月模式运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(source_dir, dest_dir, &[&["-n", "-s"], extra_args].concat())
+}
+
+// 辅助函数：读取缓存记录
+fn read_records(dest_dir: &Path) -> Vec<serde_json::Value> {
+    let cache = fs::read_to_string(dest_dir.join(".cache").join("backupEvents.json")).unwrap();
+    let cache: serde_json::Value = serde_json::from_str(&cache).unwrap();
+    assert_eq!(cache["Version"], 2);
+    serde_json::from_value(cache["Records"].clone()).unwrap()
+}
+
+#[test]
+fn records_of_deleted_archives_are_marked_and_history_is_capped() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-history-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+    fs::create_dir_all(dest_dir.join(".cache")).unwrap();
+    let old_archive = "2020-01_backup_20200201000000.zip";
+    fs::write(dest_dir.join(old_archive), "old").unwrap();
+    // 旧版本的记录没有 ArchiveFiles 字段，不能被修改
+    let legacy = serde_json::json!({
+        "StartTime": "2020-01-01T08:00:00Z",
+        "EndTime": "2020-01-01T09:00:00Z",
+        "BackupInfo": "legacy"
+    });
+    let old = serde_json::json!({
+        "StartTime": "2020-02-01T08:00:00Z",
+        "EndTime": "2020-02-01T09:00:00Z",
+        "BackupInfo": "old",
+        "ArchiveFiles": [old_archive]
+    });
+    fs::write(
+        dest_dir.join(".cache").join("backupEvents.json"),
+        serde_json::to_string(&vec![legacy.clone(), old]).unwrap(),
+    )
+    .unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &["--keep-months", "1", "--yes"]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!dest_dir.join(old_archive).exists());
+
+    let records = read_records(&dest_dir);
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0], legacy);
+    assert!(records[1]["ArchivesDeletedTime"].is_string());
+    let created = records[2]["ArchiveFiles"].clone();
+    let created = created.as_array().unwrap();
+    assert_eq!(created.len(), 1);
+    assert!(dest_dir.join(created[0].as_str().unwrap()).is_file());
+    assert!(records[2].get("ArchivesDeletedTime").is_none());
+
+    let output = run_backup(&source_dir, &dest_dir, &["--cache-history-limit", "2"]);
+    assert!(output.status.success());
+    let records = read_records(&dest_dir);
+    assert_eq!(records.len(), 2);
+    // 移出的是最旧的记录；"old" 的截止时间仍适用于新记录没有处理的月份，被保留
+    assert_eq!(records[0]["BackupInfo"], "old");
+    assert_eq!(records[1]["ArchiveFiles"], serde_json::json!(created));
+    let archived = fs::read_to_string(
+        dest_dir
+            .join(".cache")
+            .join("backupEvents-archive-2020.json"),
+    )
+    .unwrap();
+    let archived: serde_json::Value = serde_json::from_str(&archived).unwrap();
+    assert_eq!(archived["Records"], serde_json::json!([legacy]));
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn history_limit_zero_keeps_every_record() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-history-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(dest_dir.join(".cache")).unwrap();
+    let legacy: Vec<serde_json::Value> = (1..=3)
+        .map(|day| {
+            let time = format!("2020-01-0{}T08:00:00Z", day);
+            serde_json::json!({ "StartTime": time, "EndTime": time, "BackupInfo": "legacy" })
+        })
+        .collect();
+    fs::write(
+        dest_dir.join(".cache").join("backupEvents.json"),
+        serde_json::to_string(&legacy).unwrap(),
+    )
+    .unwrap();
+
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+    let output = run_backup(&source_dir, &dest_dir, &["--cache-history-limit", "0"]);
+    assert!(output.status.success());
+    assert_eq!(read_records(&dest_dir).len(), 4);
+
+    // 归档文件名精确到秒
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    fs::write(source_dir.join("b.dat"), "b").unwrap();
+    let output = run_backup(&source_dir, &dest_dir, &["--cache-history-limit", "1"]);
+    assert!(output.status.success());
+    // 最新的旧记录仍提供其他月份的截止时间，与本次运行的记录一起保留
+    let records = read_records(&dest_dir);
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["StartTime"], "2020-01-03T08:00:00Z");
+    // 移出的记录按年份追加到不同的文件
+    let archived = fs::read_to_string(
+        dest_dir
+            .join(".cache")
+            .join("backupEvents-archive-2020.json"),
+    )
+    .unwrap();
+    let archived: serde_json::Value = serde_json::from_str(&archived).unwrap();
+    assert_eq!(archived["Records"], serde_json::json!(legacy[..2]));
+    let archive_files: Vec<String> = fs::read_dir(dest_dir.join(".cache"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("backupEvents-archive-"))
+        .collect();
+    assert_eq!(archive_files.len(), 2, "{:?}", archive_files);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,168 @@
+//! --catch-up 的测试：备份上次成功运行以来的每个月份。
+
+mod common;
+
+use chrono::{Duration, Local, Months, Utc};
+use common::archive_names;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+// 辅助函数：以 --catch-up 模式运行备份
+fn run_catch_up(source_dir: &Path, dest_dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("--from")
+        .arg(source_dir)
+        .arg("--to")
+        .arg(dest_dir)
+        .args(["--catch-up", "-s"])
+        .output()
+        .expect("Failed to execute command")
+}
+
+#[test]
+fn catch_up_backs_up_every_month_since_the_last_successful_run() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-catch-up-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(dest_dir.join(".cache")).unwrap();
+
+    // 上次成功运行在两个月前，之后的每个月都有修改过的文件
+    let now = Local::now();
+    let last_run = now.checked_sub_months(Months::new(2)).unwrap();
+    let mut labels = Vec::new();
+    for back in 0..=2 {
+        let modified = now.checked_sub_months(Months::new(back)).unwrap() - Duration::seconds(60);
+        let modified = modified.max(last_run + Duration::seconds(60));
+        let label = modified.format("%Y-%m").to_string();
+        let path = source_dir.join(format!("{}.dat", label));
+        fs::write(&path, &label).unwrap();
+        filetime::set_file_mtime(
+            &path,
+            filetime::FileTime::from_system_time(std::time::SystemTime::from(modified)),
+        )
+        .unwrap();
+        labels.push(label);
+    }
+    labels.sort();
+    labels.dedup();
+    let cutoff = last_run.to_utc();
+    let record = serde_json::json!([{
+        "StartTime": cutoff,
+        "EndTime": cutoff,
+        "BackupInfo": "Backup",
+        "CutoffTime": cutoff,
+        "Status": "Success",
+    }]);
+    fs::write(
+        dest_dir.join(".cache").join("backupEvents.json"),
+        serde_json::to_string_pretty(&record).unwrap(),
+    )
+    .unwrap();
+
+    let output = run_catch_up(&source_dir, &dest_dir);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let names = archive_names(&dest_dir);
+    assert_eq!(names.len(), labels.len(), "{:?}", names);
+    for (name, label) in names.iter().zip(&labels) {
+        assert!(name.starts_with(label.as_str()), "{} {}", name, label);
+    }
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn catch_up_includes_months_skipped_by_a_later_explicit_month_run() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-catch-up-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(dest_dir.join(".cache")).unwrap();
+
+    // 三个月前的一次常规运行，之后只用 --month 备份了两个月前的月份
+    let now = Local::now();
+    let last_run = now.checked_sub_months(Months::new(3)).unwrap().to_utc();
+    let record = serde_json::json!([{
+        "StartTime": last_run,
+        "EndTime": last_run,
+        "BackupInfo": "Backup",
+        "CutoffTime": last_run,
+        "Status": "Success",
+    }]);
+    fs::write(
+        dest_dir.join(".cache").join("backupEvents.json"),
+        serde_json::to_string_pretty(&record).unwrap(),
+    )
+    .unwrap();
+    let mut labels = Vec::new();
+    for back in 1..=2 {
+        let modified = now.checked_sub_months(Months::new(back)).unwrap();
+        let label = modified.format("%Y-%m").to_string();
+        let path = source_dir.join(format!("{}.dat", label));
+        fs::write(&path, &label).unwrap();
+        filetime::set_file_mtime(
+            &path,
+            filetime::FileTime::from_system_time(std::time::SystemTime::from(modified)),
+        )
+        .unwrap();
+        labels.push(label);
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("--from")
+        .arg(&source_dir)
+        .arg("--to")
+        .arg(&dest_dir)
+        .args(["--month", &labels[1], "-s"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(archive_names(&dest_dir).len(), 1);
+
+    // 上个月的文件在 --month 运行的截止时间之前修改，但从未被归档
+    let output = run_catch_up(&source_dir, &dest_dir);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let names = archive_names(&dest_dir);
+    assert_eq!(names.len(), 2, "{:?}", names);
+    assert!(
+        names
+            .iter()
+            .any(|name| name.starts_with(labels[0].as_str())),
+        "{:?}",
+        names
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn catch_up_without_a_previous_run_backs_up_the_current_month() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-catch-up-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("new.dat"), "new").unwrap();
+    let old = source_dir.join("old.dat");
+    fs::write(&old, "old").unwrap();
+    let modified = std::time::SystemTime::from(Utc::now() - Duration::days(400));
+    filetime::set_file_mtime(&old, filetime::FileTime::from_system_time(modified)).unwrap();
+
+    let output = run_catch_up(&source_dir, &dest_dir);
+    assert!(output.status.success());
+    let names = archive_names(&dest_dir);
+    assert_eq!(names.len(), 1, "{:?}", names);
+    assert!(names[0].starts_with(&Local::now().format("%Y-%m").to_string()));
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,45 @@
+//! 非交互环境中没有 --yes 时不删除旧备份的测试。
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以当月模式运行备份，标准输出不是终端
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(
+        source_dir,
+        dest_dir,
+        &[&["-n", "-s", "--keep-months", "1"], extra_args].concat(),
+    )
+}
+
+#[test]
+fn old_backups_are_only_removed_with_yes() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-confirm-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+    let old_archive = dest_dir.join("2020-01_backup_20200201000000.zip");
+    fs::write(&old_archive, "old").unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("pass --yes"), "{}", stderr);
+    assert!(old_archive.exists());
+
+    let output = run_backup(&source_dir, &dest_dir, &["--yes"]);
+    assert!(output.status.success());
+    assert!(!old_archive.exists());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
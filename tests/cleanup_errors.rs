@@ -0,0 +1,67 @@
+//! 旧备份未能删除时退出状态的测试。
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以当月模式运行备份并把旧备份移入回收站
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(
+        source_dir,
+        dest_dir,
+        &[
+            &["-n", "-s", "--yes", "--trash", "--keep-months", "1"],
+            extra_args,
+        ]
+        .concat(),
+    )
+}
+
+#[test]
+fn failed_removals_cause_a_partial_failure_exit_code() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-cleanup-errors-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+    let old_archive = dest_dir.join("2020-01_backup_20200201000000.zip");
+    fs::write(&old_archive, "old").unwrap();
+    // 回收站的位置被一个文件占用，旧备份无法移入
+    fs::write(dest_dir.join(".trash"), "").unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(old_archive.exists());
+
+    let output = run_backup(&source_dir, &dest_dir, &["--ignore-cleanup-errors"]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(old_archive.exists());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn trash_is_refused_with_a_destination_size_quota() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-cleanup-errors-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &["--max-destination-size", "1KB"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--max-destination-size"), "{}", stderr);
+    assert!(!dest_dir.exists());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
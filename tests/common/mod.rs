@@ -0,0 +1,48 @@
+//! 集成测试共用的辅助函数。每个测试文件只用到其中一部分。
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// 辅助函数：备份 `source_dir` 到 `dest_dir` 的命令，其余参数由调用者添加
+pub fn backup_command(source_dir: &Path, dest_dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"));
+    command
+        .arg("--from")
+        .arg(source_dir)
+        .arg("--to")
+        .arg(dest_dir);
+    command
+}
+
+// 辅助函数：以给定参数运行备份并返回输出
+pub fn run_backup(source_dir: &Path, dest_dir: &Path, args: &[&str]) -> Output {
+    backup_command(source_dir, dest_dir)
+        .args(args)
+        .output()
+        .expect("Failed to execute command")
+}
+
+// 辅助函数：按文件名排序列出目标目录中的归档文件；目标目录不存在时为空
+pub fn archives(dest_dir: &Path) -> Vec<PathBuf> {
+    if !dest_dir.exists() {
+        return Vec::new();
+    }
+    let mut archives: Vec<PathBuf> = fs::read_dir(dest_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    archives.sort();
+    archives
+}
+
+// 辅助函数：按文件名排序列出目标目录中的归档文件名
+pub fn archive_names(dest_dir: &Path) -> Vec<String> {
+    archives(dest_dir)
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect()
+}
@@ -0,0 +1,184 @@
+//! --config 的测试：配置文件提供选项的值，命令行上的选项优先。
+
+mod common;
+
+use common::archive_names;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+// 辅助函数：不读取用户配置目录中的配置文件
+fn run(test_root: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .env("XDG_CONFIG_HOME", test_root.join("no-config"))
+        .env("APPDATA", test_root.join("no-config"))
+        .args(args)
+        .output()
+        .expect("Failed to execute command")
+}
+
+#[test]
+fn backup_runs_from_the_config_file_with_command_line_overrides() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-config-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("MicroMsg.db"), "messages").unwrap();
+    fs::write(source_dir.join("notes.tmp"), "scratch").unwrap();
+    let config_path = test_root.join("dat-patch.toml");
+    fs::write(
+        &config_path,
+        format!(
+            r#"
+[paths]
+from = [{:?}]
+to = {:?}
+
+[mode]
+mode = "previous"
+
+[excludes]
+exclude = ["*.tmp"]
+
+[retention]
+keep-months = 9
+color = "blue"
+
+[run]
+silent = true
+"#,
+            source_dir.to_str().unwrap(),
+            dest_dir.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+    let config = config_path.to_str().unwrap();
+
+    // 合并后的配置注明每个值的来源
+    let output = run(&test_root, &["--config", config, "-n", "--print-config"]);
+    assert!(output.status.success());
+    let printed = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        printed.contains("mode = \"current\"  # command line"),
+        "{}",
+        printed
+    );
+    assert!(
+        printed.contains("keep-months = 9  # config file"),
+        "{}",
+        printed
+    );
+    assert!(printed.contains("keep-min = 1  # default"), "{}", printed);
+    // 未知的键只是警告
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Warning: Unknown key 'color'"),
+        "{}",
+        stderr
+    );
+
+    // 命令行上的 -n 替换文件中的 mode，其余的值来自文件
+    let output = run(&test_root, &["--config", config, "-n"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.stdout.is_empty(), "silent = true applies");
+    let names = archive_names(&dest_dir);
+    assert_eq!(names.len(), 1, "{:?}", names);
+    let current_month = chrono::Local::now().format("%Y-%m").to_string();
+    assert!(names[0].starts_with(&current_month), "{:?}", names);
+    let archive = zip::ZipArchive::new(fs::File::open(dest_dir.join(&names[0])).unwrap()).unwrap();
+    let entries: Vec<&str> = archive.file_names().collect();
+    assert!(
+        entries.iter().any(|name| name.ends_with("MicroMsg.db")),
+        "{:?}",
+        entries
+    );
+    assert!(
+        !entries.iter().any(|name| name.ends_with("notes.tmp")),
+        "{:?}",
+        entries
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn invalid_config_files_are_config_errors() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-config-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&test_root).unwrap();
+
+    let missing = test_root.join("missing.toml");
+    let output = run(&test_root, &["--config", missing.to_str().unwrap(), "-n"]);
+    assert_eq!(output.status.code(), Some(4));
+
+    let broken = test_root.join("broken.toml");
+    fs::write(&broken, "[paths\nto = 1\n").unwrap();
+    let output = run(&test_root, &["--config", broken.to_str().unwrap(), "-n"]);
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid config file"), "{}", stderr);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn config_before_the_subcommand_applies_to_backup_and_clean() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-config-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
+    fs::write(source_dir.join("MicroMsg.db"), "messages").unwrap();
+    let config_path = test_root.join("dat-patch.toml");
+    fs::write(
+        &config_path,
+        format!(
+            r#"
+[paths]
+from = [{:?}]
+to = {:?}
+
+[archive]
+compression-level = 9
+
+[retention]
+keep-months = 1
+yes = true
+
+[run]
+silent = true
+"#,
+            source_dir.to_str().unwrap(),
+            dest_dir.to_str().unwrap()
+        ),
+    )
+    .unwrap();
+    let config = config_path.to_str().unwrap();
+
+    // backup 的选项来自写在子命令之前的 --config
+    let output = run(&test_root, &["--config", config, "backup", "-n"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(archive_names(&dest_dir).len(), 1);
+    let old_archive = dest_dir.join("2020-01_backup_20200201000000.zip");
+    fs::write(&old_archive, "old").unwrap();
+
+    // clean 使用文件中的 to 和 [retention]，忽略其他节的键
+    let config_arg = format!("--config={}", config);
+    let output = run(&test_root, &[config_arg.as_str(), "clean"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!old_archive.exists());
+    assert_eq!(archive_names(&dest_dir).len(), 1);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
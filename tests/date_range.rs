@@ -0,0 +1,102 @@
+//! --since/--until 的测试：归档任意时间范围内修改的文件，不按月份划分。
+
+mod common;
+
+use chrono::{Local, TimeZone};
+use common::archive_names;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：运行备份，模式参数由调用者给出
+fn run_backup(source_dir: &Path, dest_dir: &Path, mode_args: &[&str]) -> Output {
+    common::run_backup(source_dir, dest_dir, &[&["-s"], mode_args].concat())
+}
+
+// 辅助函数：创建文件并把修改时间设为本地时间的指定日期中午
+fn file_modified_on(path: &Path, year: i32, month: u32, day: u32) {
+    fs::write(path, path.to_string_lossy().as_bytes()).unwrap();
+    let modified = Local.with_ymd_and_hms(year, month, day, 12, 0, 0).unwrap();
+    filetime::set_file_mtime(
+        path,
+        filetime::FileTime::from_system_time(std::time::SystemTime::from(modified)),
+    )
+    .unwrap();
+}
+
+#[test]
+fn files_in_the_range_are_archived_together() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-date-range-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    file_modified_on(&source_dir.join("before.dat"), 2024, 6, 14);
+    file_modified_on(&source_dir.join("june.dat"), 2024, 6, 15);
+    file_modified_on(&source_dir.join("august.dat"), 2024, 8, 31);
+    file_modified_on(&source_dir.join("after.dat"), 2024, 9, 1);
+
+    let range_args = ["--since", "2024-06-15", "--until", "2024-09-01"];
+    let output = run_backup(
+        &source_dir,
+        &dest_dir,
+        &[&range_args[..], &["--keep-months", "1"]].concat(),
+    );
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let names = archive_names(&dest_dir);
+    assert_eq!(names.len(), 1, "{:?}", names);
+    assert!(
+        names[0].starts_with("2024-06-15_to_2024-09-01_backup_"),
+        "{}",
+        names[0]
+    );
+    let archive = zip::ZipArchive::new(fs::File::open(dest_dir.join(&names[0])).unwrap()).unwrap();
+    let mut entries: Vec<&str> = archive
+        .file_names()
+        .filter(|name| name.ends_with(".dat"))
+        .collect();
+    entries.sort();
+    assert_eq!(entries, ["august.dat", "june.dat"]);
+
+    // 再次运行仍然归档范围内的所有文件，之前的范围归档不会被滚动删除；归档文件名精确到秒
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let output = run_backup(
+        &source_dir,
+        &dest_dir,
+        &[&range_args[..], &["--keep-months", "1", "--yes"]].concat(),
+    );
+    assert!(output.status.success());
+    assert_eq!(archive_names(&dest_dir).len(), 2);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn invalid_ranges_are_rejected() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-date-range-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+
+    for args in [
+        &["--since", "2024-09-01", "--until", "2024-06-15"][..],
+        &["--since", "2024-09-01", "--until", "2024-09-01"],
+        &["--since", "2999-01-01"],
+        &["--since", "2024-13-01"],
+        &["--until", "2024-09-01"],
+        &["-n", "--since", "2024-06-15"],
+        &["--since", "2024-06-15", "--month-source", "path"],
+    ] {
+        let output = run_backup(&source_dir, &dest_dir, args);
+        assert!(!output.status.success(), "{:?}", args);
+    }
+    assert!(!dest_dir.exists() || archive_names(&dest_dir).is_empty());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,90 @@
+//! diff 子命令的测试：像试运行一样扫描，并将待备份的文件与已有归档的清单比较。
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+// 辅助函数：运行 diff 子命令，备份当月
+fn run_diff(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("diff")
+        .arg("--from")
+        .arg(source_dir)
+        .arg("--to")
+        .arg(dest_dir)
+        .arg("-n")
+        .args(extra_args)
+        .output()
+        .expect("Failed to execute command")
+}
+
+// 辅助函数：--json 输出中每个文件的变化
+fn change_of(output: &serde_json::Value, path: &str) -> String {
+    output["Files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|file| file["Path"] == path)
+        .unwrap_or_else(|| panic!("{} is not listed: {}", path, output))["Change"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn changes_are_grouped_against_the_latest_archive() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-diff-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(source_dir.join("Msg")).unwrap();
+    fs::write(source_dir.join("Msg").join("touched.db"), "same").unwrap();
+    fs::write(source_dir.join("Msg").join("edited.db"), "before").unwrap();
+    fs::write(source_dir.join("Msg").join("kept.db"), "kept").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("--from")
+        .arg(&source_dir)
+        .arg("--to")
+        .arg(&dest_dir)
+        .args(["-n", "-s"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // 修改时间晚于本次备份的截止时间
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    fs::write(source_dir.join("Msg").join("touched.db"), "same").unwrap();
+    fs::write(source_dir.join("Msg").join("edited.db"), "after!").unwrap();
+    fs::write(source_dir.join("Msg").join("new.db"), "new").unwrap();
+
+    let output = run_diff(&source_dir, &dest_dir, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("New files (1):"), "{}", stdout);
+    assert!(stdout.contains("Msg/new.db"), "{}", stdout);
+    assert!(stdout.contains("1 new, "), "{}", stdout);
+
+    let output = run_diff(&source_dir, &dest_dir, &["--json"]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["New"], 1);
+    assert_eq!(change_of(&json, "Msg/new.db"), "New");
+    assert_eq!(change_of(&json, "Msg/edited.db"), "Modified");
+    assert_eq!(change_of(&json, "Msg/touched.db"), "Retouched");
+    // 重叠窗口内再次扫描到、但没有变化的文件不列出
+    let files = json["Files"].as_array().unwrap();
+    assert!(
+        files.iter().all(|file| file["Path"] != "Msg/kept.db"),
+        "{}",
+        json
+    );
+    assert_eq!(files.len(), 3, "{}", json);
+
+    // diff 不写入目标目录
+    assert_eq!(fs::read_dir(&dest_dir).unwrap().count(), 2);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
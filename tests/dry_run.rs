@@ -0,0 +1,85 @@
+//! --dry-run 只报告将要备份和删除的内容、不写入任何文件的测试。
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// 辅助函数：以当月模式试运行备份
+fn run_dry(source_dir: &Path, dest_dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("--from")
+        .arg(source_dir)
+        .arg("--to")
+        .arg(dest_dir)
+        .args(["-n", "-v", "--dry-run"])
+        .output()
+        .expect("Failed to execute command")
+}
+
+// 辅助函数：递归读取目录中所有文件的内容
+fn snapshot(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.append(&mut snapshot(&path));
+        } else {
+            files.insert(path.clone(), fs::read(&path).unwrap());
+        }
+    }
+    files
+}
+
+#[test]
+fn dry_run_leaves_the_destination_untouched() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-dry-run-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("WeChat Files");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(source_dir.join("Msg")).unwrap();
+    fs::write(source_dir.join("Msg").join("MicroMsg.db"), "data").unwrap();
+    fs::create_dir_all(dest_dir.join(".cache")).unwrap();
+    fs::write(
+        dest_dir.join(".cache").join("backupEvents.json"),
+        r#"[{ "StartTime": "2020-01-01T00:00:00Z", "EndTime": "2020-01-01T00:00:00Z", "BackupInfo": "Initial" }]"#,
+    )
+    .unwrap();
+    let old_archive = "2019-12_backup_20200101000000.zip";
+    fs::write(dest_dir.join(old_archive), "old").unwrap();
+    // 最新的归档受 --keep-min 保护
+    let newest_archive = "2020-01_backup_20200201000000.zip";
+    fs::write(dest_dir.join(newest_archive), "newest").unwrap();
+    // 中断的运行遗留的临时文件也不会被清理
+    fs::write(
+        dest_dir.join("2019-11_backup_20191201000000.zip.partial"),
+        "partial",
+    )
+    .unwrap();
+    let before = snapshot(&dest_dir);
+
+    let output = run_dry(&source_dir, &dest_dir);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Would back up 1 files"), "{}", stdout);
+    assert!(stdout.contains("MicroMsg.db"), "{}", stdout);
+    assert!(
+        stdout.contains(&format!("Would remove old backup: {}", old_archive)),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains(newest_archive), "{}", stdout);
+    assert_eq!(snapshot(&dest_dir), before);
+
+    // 目标目录不存在时也不会被创建
+    let missing = test_root.join("new-backups");
+    let output = run_dry(&source_dir, &missing);
+    assert!(output.status.success());
+    assert!(!missing.exists());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
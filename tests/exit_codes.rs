@@ -0,0 +1,88 @@
+//! 退出码的测试：无法开始备份时，每种错误使用不同的退出码。
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：运行备份并返回输出
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(source_dir, dest_dir, &[&["-n", "-s"], extra_args].concat())
+}
+
+#[test]
+fn each_kind_of_error_has_its_own_exit_code() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-exit-codes-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("MicroMsg.db"), "messages").unwrap();
+
+    // 选项无效
+    let dest_dir = test_root.join("backups");
+    let output = common::run_backup(
+        &source_dir,
+        &dest_dir,
+        &["-s", "--since", "2024-09-01", "--until", "2024-06-15"],
+    );
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--since"));
+
+    // 源目录不存在
+    let output = run_backup(&test_root.join("missing"), &dest_dir, &[]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not exist"));
+
+    // 目标目录无法创建
+    let blocker = test_root.join("blocker");
+    fs::write(&blocker, "not a directory").unwrap();
+    let output = run_backup(&source_dir, &blocker.join("backups"), &[]);
+    assert_eq!(output.status.code(), Some(6));
+
+    // 缓存文件的格式版本过新，缓存保持不变
+    let cache_file = dest_dir.join(".cache").join("backupEvents.json");
+    fs::create_dir_all(cache_file.parent().unwrap()).unwrap();
+    let future = r#"{ "Version": 99, "Records": [] }"#;
+    fs::write(&cache_file, future).unwrap();
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert_eq!(output.status.code(), Some(7));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("upgrade"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&cache_file).unwrap(), future);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn all_archives_failing_exits_with_one() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-exit-codes-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    for account in ["wxid_a", "wxid_b"] {
+        fs::create_dir_all(source_dir.join(account)).unwrap();
+        fs::write(source_dir.join(account).join("data.dat"), vec![7u8; 4096]).unwrap();
+    }
+
+    // 每个账号的归档都因剩余空间不足而失败：退出码 1，静默模式不影响退出码
+    let output = run_backup(
+        &source_dir,
+        &dest_dir,
+        &[
+            "--split-by-top-dir",
+            "--split-size",
+            "1KB",
+            "--min-free-space",
+            "1000000TB",
+        ],
+    );
+    assert_eq!(
+        output.status.code(),
+        Some(1),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
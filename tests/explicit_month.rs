@@ -0,0 +1,75 @@
+//! --month 的测试：重新归档指定月份的所有文件，不受上次备份的截止时间限制。
+
+mod common;
+
+use chrono::{Local, Months};
+use common::archives;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：运行备份，模式参数由调用者给出
+fn run_backup(source_dir: &Path, dest_dir: &Path, mode_args: &[&str]) -> Output {
+    common::run_backup(source_dir, dest_dir, &[&["-s"], mode_args].concat())
+}
+
+#[test]
+fn explicit_month_is_archived_again_despite_the_cutoff() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-explicit-month-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+
+    let old = Local::now().checked_sub_months(Months::new(3)).unwrap();
+    let label = old.format("%Y-%m").to_string();
+    let path = source_dir.join("old.dat");
+    fs::write(&path, "old").unwrap();
+    filetime::set_file_mtime(
+        &path,
+        filetime::FileTime::from_system_time(std::time::SystemTime::from(old)),
+    )
+    .unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &["--month", &label]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(archives(&dest_dir).len(), 1);
+
+    // 截止时间已经晚于文件的修改时间，仍然重新归档；归档文件名精确到秒
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let output = run_backup(&source_dir, &dest_dir, &["--month", &label]);
+    assert!(output.status.success());
+    let archives = archives(&dest_dir);
+    assert_eq!(archives.len(), 2);
+    let name = archives[1].file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with(&label), "{}", name);
+
+    // 与 -p/-n/-d 冲突
+    let output = run_backup(&source_dir, &dest_dir, &["-n", "--month", &label]);
+    assert!(!output.status.success());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn months_back_is_limited_to_a_hundred_years() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-explicit-month-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+
+    // 超出范围的值被拒绝，而不是在往前推算月份时溢出
+    for count in ["0", "1201", "4000000"] {
+        let output = run_backup(&source_dir, &dest_dir, &["--months-back", count]);
+        assert_eq!(output.status.code(), Some(2), "{}", count);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("--months-back"));
+    }
+    assert!(!dest_dir.exists());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -8,6 +8,19 @@ fn set_file_mtime(path: &PathBuf, time: SystemTime) {
     filetime::set_file_mtime(path, filetime::FileTime::from_system_time(time)).unwrap();
 }
 
+// 辅助函数：生成一个时间戳为若干天前的备份文件名
+fn backup_name_days_ago(days: i64) -> String {
+    let time = chrono::Local::now() - chrono::Duration::days(days);
+    format!("{}_backup_{}.zip", time.format("%Y-%m"), time.format("%Y%m%d%H%M%S"))
+}
+
+// 辅助函数：生成一个时间戳为若干个日历月前再往后若干天的备份文件名
+fn backup_name_months_ago(months: u32, plus_days: i64) -> String {
+    let time = chrono::Local::now().checked_sub_months(chrono::Months::new(months)).unwrap()
+        + chrono::Duration::days(plus_days);
+    format!("{}_backup_{}.zip", time.format("%Y-%m"), time.format("%Y%m%d%H%M%S"))
+}
+
 #[test]
 fn test_full_backup_and_cleanup_flow() {
     // --- 1. SETUP ---
@@ -30,17 +43,21 @@ fn test_full_backup_and_cleanup_flow() {
     fs::write(cache_dir.join("backupEvents.json"), initial_cache_content).unwrap();
 
     // 创建一个应该被清理的旧备份 (4个月前)
-    let old_backup_name = "2025-03_backup_20250310100000.zip";
-    fs::write(dest_dir.join(old_backup_name), "old").unwrap();
+    let old_backup_name = backup_name_days_ago(120);
+    fs::write(dest_dir.join(&old_backup_name), "old").unwrap();
 
     // 创建一个应该被保留的新备份 (2个月前)
-    let recent_backup_name = "2025-05_backup_20250515100000.zip";
-    fs::write(dest_dir.join(recent_backup_name), "recent").unwrap();
+    let recent_backup_name = backup_name_days_ago(60);
+    fs::write(dest_dir.join(&recent_backup_name), "recent").unwrap();
+
+    // 按日历月计算仍在3个月内的备份，3个月可能长于90天，不能按 30 天一个月删除
+    let boundary_backup_name = backup_name_months_ago(3, 1);
+    fs::write(dest_dir.join(&boundary_backup_name), "boundary").unwrap();
 
-    // 创建一个应该被备份的新文件 (10天前)
+    // 创建一个应该被备份的新文件 (1分钟前，保证落在当月)
     let new_file_path = source_dir.join("new_file.txt");
     fs::write(&new_file_path, "new content").unwrap();
-    set_file_mtime(&new_file_path, SystemTime::now() - Duration::from_secs(10 * 24 * 3600));
+    set_file_mtime(&new_file_path, SystemTime::now() - Duration::from_secs(60));
 
     // 创建一个不应被备份的旧文件 (30天前)
     let old_file_path = source_dir.join("old_file.txt");
@@ -56,7 +73,8 @@ fn test_full_backup_and_cleanup_flow() {
         .arg(&dest_dir)
         .arg("-n") // 备份当月
         .arg("--keep-months")
-        .arg("3"); // 保留3个月
+        .arg("3") // 保留3个月
+        .arg("--yes"); // 非交互环境中不询问直接删除
 
     let output = cmd.output().expect("Failed to execute command");
     assert!(output.status.success(), "Command executed with error: {:?}", String::from_utf8_lossy(&output.stderr));
@@ -68,20 +86,27 @@ fn test_full_backup_and_cleanup_flow() {
         .map(|res| res.unwrap().file_name().into_string().unwrap())
         .collect();
 
-    assert!(!dest_files.contains(&old_backup_name.to_string()), "Old backup was not deleted");
-    assert!(dest_files.contains(&recent_backup_name.to_string()), "Recent backup was deleted");
+    assert!(!dest_files.contains(&old_backup_name), "Old backup was not deleted");
+    assert!(dest_files.contains(&recent_backup_name), "Recent backup was deleted");
+    assert!(dest_files.contains(&boundary_backup_name), "Backup within 3 calendar months was deleted");
 
     // 3.2 验证新备份
     let new_backup_file = dest_files
         .iter()
-        .find(|name| name.contains("_backup_") && !name.contains("2025-03") && !name.contains("2025-05"));
+        .find(|name| {
+            name.contains("_backup_")
+                && **name != old_backup_name
+                && **name != recent_backup_name
+                && **name != boundary_backup_name
+        });
     assert!(new_backup_file.is_some(), "No new backup archive was created");
 
     // 3.3 验证缓存更新
     let final_cache_content = fs::read_to_string(cache_dir.join("backupEvents.json")).unwrap();
-    let final_records: Vec<serde_json::Value> = serde_json::from_str(&final_cache_content).unwrap();
+    let final_cache: serde_json::Value = serde_json::from_str(&final_cache_content).unwrap();
+    let final_records = final_cache["Records"].as_array().unwrap();
     assert_eq!(final_records.len(), 2, "Cache file was not updated with a new record");
 
     // --- 4. TEARDOWN ---
     fs::remove_dir_all(&test_root).unwrap();
-}
\ No newline at end of file
+}
@@ -0,0 +1,125 @@
+//! --output json 的测试：运行结束时在 stdout 输出一个描述整个运行的 JSON 文档。
+
+mod common;
+
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以当月模式运行备份并输出 JSON
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(
+        source_dir,
+        dest_dir,
+        &[&["-n", "--output", "json"], extra_args].concat(),
+    )
+}
+
+// 辅助函数：stdout 必须是一个完整的 JSON 文档，stderr 为空
+fn parse_output(output: &Output) -> Value {
+    assert!(
+        output.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    serde_json::from_slice(&output.stdout)
+        .unwrap_or_else(|e| panic!("{}: {}", e, String::from_utf8_lossy(&output.stdout)))
+}
+
+#[test]
+fn successful_run_is_reported_as_a_single_document() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-json-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("MicroMsg.db"), "messages").unwrap();
+    fs::write(source_dir.join("Media.db"), "media").unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert!(output.status.success());
+    let json = parse_output(&output);
+    assert_eq!(json["Version"], 1);
+    assert_eq!(json["Status"], "Succeeded");
+    assert_eq!(json["ExitCode"], 0);
+    assert_eq!(json["Mode"], "CurrentMonth");
+    assert_eq!(json["SucceededArchives"], 1);
+    assert_eq!(json["FailedArchives"], 0);
+    assert_eq!(json["CacheUpdated"], true);
+    assert_eq!(json["Errors"], Value::Array(Vec::new()));
+    assert!(json["Error"].is_null());
+
+    let months = json["Months"].as_array().unwrap();
+    assert_eq!(months.len(), 1);
+    let month = &months[0];
+    assert_eq!(
+        month["Period"],
+        chrono::Local::now().format("%Y-%m").to_string()
+    );
+    assert_eq!(month["Status"], "Succeeded");
+    assert_eq!(month["SkippedFiles"], Value::Array(Vec::new()));
+    let archive = &month["Archives"][0];
+    assert_eq!(archive["ArchivedFiles"], 2);
+    assert_eq!(archive["UncompressedBytes"], 13);
+    assert!(archive["ElapsedSeconds"].is_f64());
+    // 清单记录很大且已写入归档，不重复输出
+    assert!(archive.get("ArchivedEntries").is_none());
+    let archive_path = archive["ArchivePaths"][0].as_str().unwrap();
+    assert!(Path::new(archive_path).is_file(), "{}", archive_path);
+
+    // 滚动删除保留了刚创建的归档
+    let kept = json["Cleanup"]["Kept"].as_array().unwrap();
+    assert_eq!(kept, &vec![Value::from(archive_path)]);
+    assert_eq!(json["Cleanup"]["Deleted"], Value::Array(Vec::new()));
+
+    // 文字输出写入了日志文件
+    let log = fs::read_to_string(dest_dir.join(".cache").join("backup.log")).unwrap();
+    assert!(log.contains("Successfully created archive"), "{}", log);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn errors_are_structured_entries() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-json-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+
+    // 源目录不存在：无法开始备份
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert_eq!(output.status.code(), Some(5));
+    let json = parse_output(&output);
+    assert_eq!(json["Status"], "Error");
+    assert_eq!(json["ExitCode"], 5);
+    assert_eq!(json["Error"]["Kind"], "ScanError");
+    assert_eq!(json["Error"]["IoKind"], "NotFound");
+    assert_eq!(json["Error"]["Path"], source_dir.to_str().unwrap());
+    assert!(json["Error"]["Message"].is_string());
+    assert!(json.get("Months").is_none());
+
+    // 扫描结果超过 --max-files：该月份失败，错误记录在月份中
+    fs::create_dir_all(&source_dir).unwrap();
+    for name in ["a.db", "b.db"] {
+        fs::write(source_dir.join(name), name).unwrap();
+    }
+    let output = run_backup(&source_dir, &dest_dir, &["--max-files", "1"]);
+    assert_eq!(output.status.code(), Some(1));
+    let json = parse_output(&output);
+    assert_eq!(json["Status"], "Failed");
+    assert_eq!(json["FailedArchives"], 1);
+    // 失败的运行同样记录在缓存中，但不推进截止时间
+    assert_eq!(json["CacheUpdated"], true);
+    let cache = fs::read_to_string(dest_dir.join(".cache").join("backupEvents.json")).unwrap();
+    let cache: serde_json::Value = serde_json::from_str(&cache).unwrap();
+    let records = cache["Records"].as_array().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["Status"], "Failed");
+    let month = &json["Months"][0];
+    assert_eq!(month["Status"], "Failed");
+    assert_eq!(month["Errors"][0]["Kind"], "ConfigError");
+    assert!(month["Errors"][0]["IoKind"].is_null());
+    let message = month["Errors"][0]["Message"].as_str().unwrap();
+    assert!(message.contains("--max-files 1"), "{}", message);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
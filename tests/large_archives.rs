@@ -0,0 +1,76 @@
+//! 大归档（Zip64）相关测试。
+//!
+//! 这些测试会写入数 GB 的稀疏文件或数万个小文件，耗时较长，
+//! 默认忽略，需要时使用 `cargo test -- --ignored` 运行。
+
+mod common;
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+// 辅助函数：创建测试目录结构
+fn setup(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-{}-{}", name, uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("in");
+    let dest_dir = test_root.join("out");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
+    (test_root, source_dir, dest_dir)
+}
+
+// 辅助函数：以当月模式运行备份，并返回生成的归档路径
+fn run_backup(source_dir: &Path, dest_dir: &Path) -> PathBuf {
+    let output = common::run_backup(source_dir, dest_dir, &["-n", "-s"]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    fs::read_dir(dest_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "zip"))
+        .expect("No archive was created")
+}
+
+#[test]
+#[ignore = "writes a 4.5 GB sparse file"]
+fn archives_file_larger_than_4gb() {
+    let (test_root, source_dir, dest_dir) = setup("zip64-large");
+
+    let big_file = source_dir.join("big.dat");
+    let size = 4 * 1024 * 1024 * 1024 + 512 * 1024 * 1024;
+    File::create(&big_file).unwrap().set_len(size).unwrap();
+
+    let zip_path = run_backup(&source_dir, &dest_dir);
+    let mut archive = zip::ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+    let entry = archive.by_name("big.dat").unwrap();
+    assert_eq!(entry.size(), size);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+#[ignore = "creates more than 65535 files"]
+fn archives_more_than_65535_entries() {
+    let (test_root, source_dir, dest_dir) = setup("zip64-entries");
+
+    let count = 70_000;
+    for i in 0..count {
+        let dir = source_dir.join(format!("{:03}", i % 100));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(format!("{}.txt", i)), i.to_string()).unwrap();
+    }
+
+    let zip_path = run_backup(&source_dir, &dest_dir);
+    let archive = zip::ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+    let files = archive
+        .file_names()
+        .filter(|n| !n.ends_with('/') && *n != "MANIFEST.json")
+        .count();
+    assert_eq!(files, count);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
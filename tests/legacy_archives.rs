@@ -0,0 +1,62 @@
+//! --cleanup-extra-pattern 轮换旧版 PowerShell 脚本生成的归档的测试。
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以当月模式运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(
+        source_dir,
+        dest_dir,
+        &[&["-n", "-s", "--yes"], extra_args].concat(),
+    )
+}
+
+#[test]
+fn old_powershell_archives_are_rotated() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-legacy-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+    let legacy = dest_dir.join("WeChatBackup-2024-11-20241130_235901.zip");
+    fs::write(&legacy, "old").unwrap();
+    let pattern = r"^WeChatBackup-(?P<month>\d{4}-\d{2})-(?P<ts>\d{8}_\d{6})\.zip$";
+
+    // 没有 ts 捕获组的正则在解析参数时被拒绝
+    let output = run_backup(
+        &source_dir,
+        &dest_dir,
+        &["--cleanup-extra-pattern", r"^WeChatBackup-.*\.zip$"],
+    );
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("(?P<ts>"), "{}", stderr);
+
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert!(output.status.success());
+    assert!(legacy.exists());
+
+    let output = run_backup(
+        &source_dir,
+        &dest_dir,
+        &[
+            "--cleanup-extra-pattern",
+            pattern,
+            "--cleanup-extra-format",
+            "%Y%m%d_%H%M%S",
+        ],
+    );
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!legacy.exists());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
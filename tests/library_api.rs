@@ -0,0 +1,218 @@
+//! 库接口的测试：不经过命令行调用 run_backup，通过回调接收消息。
+
+use dat_patch_rust::backup_logic::BackupMode;
+use dat_patch_rust::cache::{self, MonthStatus};
+use dat_patch_rust::disk_space::FreeSpaceProvider;
+use dat_patch_rust::{BackupError, BackupEvent, BackupOptions, run_backup};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[test]
+fn run_backup_reports_months_and_events_through_the_callback() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-library-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("MicroMsg.db"), "messages").unwrap();
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let mut options = BackupOptions::new(
+        vec![source_dir.clone()],
+        &dest_dir,
+        BackupMode::CurrentMonth,
+    );
+    let received = Arc::clone(&messages);
+    options.on_event = Some(Box::new(move |event| {
+        if let BackupEvent::Info(message) = event {
+            received.lock().unwrap().push(message);
+        }
+    }));
+
+    let report = run_backup(options).unwrap();
+    assert_eq!(report.months.len(), 1);
+    assert_eq!(report.months[0].status, MonthStatus::Succeeded);
+    assert_eq!(report.succeeded_archives, 1);
+    assert_eq!(report.failed_archives, 0);
+    assert!(report.cache_updated);
+    let (_, stats) = report.archives().next().unwrap();
+    assert_eq!(stats.archived_files(), 1);
+    assert!(stats.archive_paths[0].is_file());
+    let messages = messages.lock().unwrap();
+    assert!(
+        messages
+            .iter()
+            .any(|m| m.starts_with("Successfully created archive:")),
+        "{:?}",
+        messages
+    );
+
+    // 没有回调时同样可以运行；没有新文件时不创建归档（不留重叠窗口，刚写入的文件不再归档）
+    let mut options = BackupOptions::new(vec![source_dir], &dest_dir, BackupMode::CurrentMonth);
+    options.overlap_seconds = 0;
+    let report = run_backup(options).unwrap();
+    assert_eq!(report.months[0].status, MonthStatus::Succeeded);
+    assert_eq!(report.archives().count(), 0);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn invalid_options_are_returned_as_errors() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-library-{}", uuid::Uuid::new_v4()));
+    let missing = test_root.join("missing");
+    let result = run_backup(BackupOptions::new(
+        vec![missing.clone()],
+        test_root.join("backups"),
+        BackupMode::CurrentMonth,
+    ));
+    match result {
+        Err(BackupError::ScanError { path, source }) => {
+            assert_eq!(path, missing);
+            assert_eq!(source.kind(), io::ErrorKind::NotFound);
+        }
+        other => panic!("unexpected result: {:?}", other.map(|r| r.months.len())),
+    }
+
+    // 时间范围需要开始时间
+    let result = run_backup(BackupOptions::new(
+        vec![test_root.clone()],
+        test_root.join("backups"),
+        BackupMode::DateRange,
+    ));
+    assert!(matches!(result, Err(BackupError::ConfigError(_))));
+
+    // 命令行上互斥的选项在库接口中同样被拒绝
+    let conflicting: [fn(&mut BackupOptions); 5] = [
+        |o| {
+            o.reproducible = true;
+            o.split_size = Some(5000);
+        },
+        |o| {
+            o.append = true;
+            o.split_size = Some(5000);
+        },
+        |o| {
+            o.reproducible = true;
+            o.append = true;
+        },
+        |o| {
+            o.incremental = true;
+            o.append = true;
+        },
+        |o| {
+            o.append = true;
+            o.encrypt_to = vec![age::x25519::Identity::generate().to_public()];
+        },
+    ];
+    for set in conflicting {
+        let mut options = BackupOptions::new(
+            vec![test_root.clone()],
+            test_root.join("backups"),
+            BackupMode::CurrentMonth,
+        );
+        set(&mut options);
+        let result = run_backup(options);
+        assert!(
+            matches!(&result, Err(BackupError::ConfigError(message)) if message.contains("cannot be used with")),
+            "{:?}",
+            result.map(|r| r.months.len())
+        );
+    }
+    assert!(!test_root.exists());
+}
+
+#[test]
+fn induced_failures_are_returned_as_specific_variants() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-library-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("MicroMsg.db"), "messages").unwrap();
+    let options = |dest_dir: &Path| {
+        BackupOptions::new(vec![source_dir.clone()], dest_dir, BackupMode::CurrentMonth)
+    };
+
+    // 目标目录无法创建：它的上级是一个文件
+    let blocker = test_root.join("blocker");
+    fs::write(&blocker, "not a directory").unwrap();
+    let dest_dir = blocker.join("backups");
+    match run_backup(options(&dest_dir)) {
+        Err(BackupError::ArchiveError { path, .. }) => assert_eq!(path, dest_dir),
+        other => panic!("unexpected result: {:?}", other.map(|r| r.months.len())),
+    }
+
+    // 缓存文件的格式版本比本程序新，底层错误保留为 source
+    let dest_dir = test_root.join("backups");
+    let cache_file = dest_dir.join(".cache").join("backupEvents.json");
+    fs::create_dir_all(cache_file.parent().unwrap()).unwrap();
+    fs::write(&cache_file, r#"{ "Version": 99, "Records": [] }"#).unwrap();
+    let error = run_backup(options(&dest_dir)).unwrap_err();
+    assert!(
+        matches!(&error, BackupError::CacheError { path, .. } if *path == cache_file),
+        "{:?}",
+        error
+    );
+    assert_eq!(error.kind(), Some(io::ErrorKind::InvalidData));
+    let source = std::error::Error::source(&error).unwrap();
+    assert!(source.to_string().contains("version 99"), "{}", source);
+    fs::remove_file(&cache_file).unwrap();
+
+    // 另一个实例持有锁
+    let lock = cache::acquire_run_lock(&dest_dir.join(".cache"), Duration::ZERO).unwrap();
+    match run_backup(options(&dest_dir)) {
+        Err(BackupError::AlreadyRunning { path, .. }) => assert_eq!(path, dest_dir),
+        other => panic!("unexpected result: {:?}", other.map(|r| r.months.len())),
+    }
+    drop(lock);
+    run_backup(options(&dest_dir)).unwrap();
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+// 辅助函数：报告固定剩余空间的 FreeSpaceProvider，结果与运行测试的机器无关
+struct FixedFreeSpace(u64);
+
+impl FreeSpaceProvider for FixedFreeSpace {
+    fn available_space(&self, _path: &Path) -> io::Result<u64> {
+        Ok(self.0)
+    }
+}
+
+#[test]
+fn archives_that_do_not_fit_the_free_space_fail() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-library-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("in");
+    let dest_dir = test_root.join("out");
+    fs::create_dir_all(source_dir.join("wxid_a_small")).unwrap();
+    fs::create_dir_all(source_dir.join("wxid_b_large")).unwrap();
+    // 账号按名称顺序归档：先归档小账号，再归档大账号
+    fs::write(source_dir.join("wxid_a_small").join("small.dat"), "small").unwrap();
+    fs::write(
+        source_dir.join("wxid_b_large").join("large.dat"),
+        vec![0u8; 4096],
+    )
+    .unwrap();
+
+    // 剩余空间只够归档 wxid_a_small，wxid_b_large 失败
+    let mut options = BackupOptions::new(vec![source_dir], &dest_dir, BackupMode::CurrentMonth);
+    options.split_by_top_dir = true;
+    options.free_space = Box::new(FixedFreeSpace(1024));
+    let report = run_backup(options).unwrap();
+    assert_eq!(report.succeeded_archives, 1);
+    assert_eq!(report.failed_archives, 1);
+    let archives: Vec<String> = fs::read_dir(&dest_dir)
+        .unwrap()
+        .map(|res| res.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.contains("_backup_"))
+        .collect();
+    assert_eq!(archives.len(), 1, "Unexpected archives: {:?}", archives);
+    assert!(archives[0].contains("_wxid_a_small_backup_"));
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,96 @@
+//! 日志文件的测试：静默运行时消息写入缓存目录中的 backup.log，--log-file 指定其他位置。
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以当月模式运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(source_dir, dest_dir, &[&["-n"], extra_args].concat())
+}
+
+#[test]
+fn silent_run_writes_the_default_log_file() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-log-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("MicroMsg.db"), "messages").unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &["-q"]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.stdout.is_empty(), "{:?}", output.stdout);
+    let log = fs::read_to_string(dest_dir.join(".cache").join("backup.log")).unwrap();
+    let line = log
+        .lines()
+        .find(|line| line.contains("Successfully created archive"))
+        .unwrap_or_else(|| panic!("{}", log));
+    // 每行以时间和级别开头
+    assert!(
+        chrono::NaiveDateTime::parse_from_str(&line[..19], "%Y-%m-%d %H:%M:%S").is_ok(),
+        "{}",
+        line
+    );
+    assert!(line[19..].starts_with(" [INFO "), "{}", line);
+    assert!(log.contains("Starting dat-patch-rust"), "{}", log);
+
+    // 扫描结果超过限制时错误同样写入日志；下一次运行追加到同一个文件
+    fs::write(source_dir.join("second.db"), "more messages").unwrap();
+    let output = run_backup(&source_dir, &dest_dir, &["-s", "--max-files", "1"]);
+    assert_eq!(output.status.code(), Some(1));
+    let log = fs::read_to_string(dest_dir.join(".cache").join("backup.log")).unwrap();
+    assert!(log.contains("Successfully created archive"), "{}", log);
+    assert!(
+        log.lines()
+            .any(|line| line.contains("[ERROR]") && line.contains("--max-files 1")),
+        "{}",
+        log
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn log_file_option_logs_alongside_the_console() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-log-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    let log_path = test_root.join("logs").join("run.log");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("MicroMsg.db"), "messages").unwrap();
+
+    let output = run_backup(
+        &source_dir,
+        &dest_dir,
+        &["--log-file", log_path.to_str().unwrap(), "-v"],
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Successfully created archive"),
+        "{}",
+        stdout
+    );
+    // -v 还显示调试信息，日志文件也按同样的级别记录
+    assert!(
+        stdout.contains("Arguments parsed successfully"),
+        "{}",
+        stdout
+    );
+    let log = fs::read_to_string(&log_path).unwrap();
+    assert!(log.contains("Successfully created archive"), "{}", log);
+    assert!(
+        log.contains("[DEBUG] Arguments parsed successfully"),
+        "{}",
+        log
+    );
+    assert!(!dest_dir.join(".cache").join("backup.log").exists());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
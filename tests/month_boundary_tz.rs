@@ -0,0 +1,86 @@
+//! --month-boundary-tz 的测试：月份的边界按指定的时区计算。
+
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::archives;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以 UTC 作为本地时区运行备份，结果与运行测试的机器的时区无关
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::backup_command(source_dir, dest_dir)
+        .env("TZ", "UTC")
+        .arg("-s")
+        .args(extra_args)
+        .output()
+        .expect("Failed to execute command")
+}
+
+#[test]
+fn file_near_the_month_end_moves_to_the_next_month_in_shanghai() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-boundary-tz-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    fs::create_dir_all(&source_dir).unwrap();
+    // UTC 的 7 月 31 日 20:00，上海的 8 月 1 日 04:00
+    let path = source_dir.join("late.dat");
+    fs::write(&path, "late").unwrap();
+    let modified = Utc.with_ymd_and_hms(2025, 7, 31, 20, 0, 0).unwrap();
+    filetime::set_file_mtime(
+        &path,
+        filetime::FileTime::from_system_time(std::time::SystemTime::from(modified)),
+    )
+    .unwrap();
+
+    let cases = [
+        ("local", "2025-07", 1),
+        ("local", "2025-08", 0),
+        ("Asia/Shanghai", "2025-07", 0),
+        ("Asia/Shanghai", "2025-08", 1),
+    ];
+    for (tz, month, expected) in cases {
+        let dest_dir = test_root.join(format!("{}-{}", tz.replace('/', "-"), month));
+        let output = run_backup(
+            &source_dir,
+            &dest_dir,
+            &["--month", month, "--month-boundary-tz", tz],
+        );
+        assert!(
+            output.status.success(),
+            "Command executed with error: {:?}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let archives = archives(&dest_dir);
+        assert_eq!(archives.len(), expected, "{} {}", tz, month);
+
+        // 所用的时区记录在归档元数据和缓存中
+        if let Some(archive) = archives.first() {
+            let cache =
+                fs::read_to_string(dest_dir.join(".cache").join("backupEvents.json")).unwrap();
+            let cache: serde_json::Value = serde_json::from_str(&cache).unwrap();
+            assert_eq!(cache["Records"][0]["MonthBoundaryTz"], tz);
+            let archive = zip::ZipArchive::new(fs::File::open(archive).unwrap()).unwrap();
+            let info: serde_json::Value = serde_json::from_slice(archive.comment()).unwrap();
+            assert_eq!(info["MonthBoundaryTz"], tz);
+        }
+    }
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn unknown_time_zone_is_rejected() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-boundary-tz-{}", uuid::Uuid::new_v4()));
+    for tz in ["Mars/Olympus_Mons", "../zoneinfo", ""] {
+        let output = run_backup(
+            &test_root,
+            &test_root.join("backups"),
+            &["-n", "--month-boundary-tz", tz],
+        );
+        assert!(!output.status.success(), "{}", tz);
+    }
+    assert!(!test_root.exists());
+}
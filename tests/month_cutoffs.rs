@@ -0,0 +1,107 @@
+//! 按月份记录截止时间的测试：失败的月份在下次运行时从头重新扫描。
+
+mod common;
+
+use chrono::{Duration, Local, Months, Utc};
+use common::archives;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以指定模式（-n 或 -p）运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, mode: &str) -> Output {
+    common::run_backup(source_dir, dest_dir, &[mode, "-s"])
+}
+
+#[test]
+fn failed_month_is_rescanned_from_its_own_cutoff() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-month-cutoffs-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(dest_dir.join(".cache")).unwrap();
+
+    let now = Local::now();
+    let previous = now.checked_sub_months(Months::new(1)).unwrap();
+    let previous_label = previous.format("%Y-%m").to_string();
+    let current_label = now.format("%Y-%m").to_string();
+    let set_mtime = |name: &str, time: chrono::DateTime<Local>| {
+        let path = source_dir.join(name);
+        fs::write(&path, name).unwrap();
+        let time = std::time::SystemTime::from(time);
+        filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(time)).unwrap();
+    };
+    set_mtime("previous.dat", previous);
+    set_mtime("current.dat", now - Duration::hours(1));
+
+    // 上次运行：当月成功，上月失败（例如磁盘空间不足）。
+    // 全局截止时间是本次运行开始的时间，按它扫描会永久遗漏上月的文件
+    let cutoff = Utc::now().to_rfc3339();
+    let record = serde_json::json!([{
+        "StartTime": cutoff,
+        "EndTime": cutoff,
+        "BackupInfo": "Partial backup",
+        "CutoffTime": cutoff,
+        "Months": [
+            {
+                "Month": previous_label,
+                "Cutoff": "1970-01-01T00:00:00Z",
+                "Status": "Failed",
+            },
+            {
+                "Month": current_label,
+                "Cutoff": cutoff,
+                "Status": "Succeeded",
+            },
+        ],
+    }]);
+    fs::write(
+        dest_dir.join(".cache").join("backupEvents.json"),
+        serde_json::to_string_pretty(&record).unwrap(),
+    )
+    .unwrap();
+
+    // 当月的截止时间已推进，没有需要备份的文件
+    let output = run_backup(&source_dir, &dest_dir, "-n");
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(archives(&dest_dir).is_empty());
+
+    // 上月从自己的截止时间重新扫描
+    let output = run_backup(&source_dir, &dest_dir, "-p");
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let archives = archives(&dest_dir);
+    assert_eq!(archives.len(), 1);
+    let name = archives[0]
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned();
+    assert!(name.starts_with(&previous_label), "{}", name);
+    let archive = zip::ZipArchive::new(fs::File::open(&archives[0]).unwrap()).unwrap();
+    assert!(archive.file_names().any(|name| name == "previous.dat"));
+
+    // 新记录中上月已成功，截止时间随之推进
+    let cache = fs::read_to_string(dest_dir.join(".cache").join("backupEvents.json")).unwrap();
+    let records = &serde_json::from_str::<serde_json::Value>(&cache).unwrap()["Records"];
+    // 没有创建归档的当月运行同样有记录
+    assert_eq!(records[1]["Status"], "Success");
+    assert_eq!(records[1]["FilesArchived"], 0);
+    let months = &records[2]["Months"];
+    assert_eq!(months[0]["Month"], serde_json::json!(previous_label));
+    assert_eq!(months[0]["Status"], "Succeeded");
+    assert_eq!(months[0]["ArchiveFiles"], serde_json::json!([name]));
+    assert_eq!(records[2]["Status"], "Success");
+    assert_eq!(records[2]["FilesArchived"], 1);
+    assert_eq!(records[2]["ToolVersion"], env!("CARGO_PKG_VERSION"));
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
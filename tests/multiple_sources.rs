@@ -0,0 +1,254 @@
+//! 在一次运行中备份多个源目录（重复的 --from）的测试。
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// 辅助函数：创建两个各包含一个文件的源目录，返回 (测试根目录, 源目录列表, 目标目录)
+fn setup() -> (PathBuf, Vec<PathBuf>, PathBuf) {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-sources-{}", uuid::Uuid::new_v4()));
+    let sources = vec![
+        test_root.join("C").join("WeChat Files"),
+        test_root.join("D").join("WeChat Files"),
+    ];
+    for (source, name) in sources.iter().zip(["a.dat", "b.dat"]) {
+        fs::create_dir_all(source.join("Msg")).unwrap();
+        fs::write(source.join("Msg").join(name), name).unwrap();
+    }
+    let dest_dir = test_root.join("backups");
+    (test_root, sources, dest_dir)
+}
+
+// 辅助函数：以当月模式备份所有源目录
+fn run_backup(sources: &[PathBuf], dest_dir: &Path, extra_args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"));
+    for source in sources {
+        command.arg("--from").arg(source);
+    }
+    command
+        .arg("--to")
+        .arg(dest_dir)
+        .args(["-n", "-s"])
+        .args(extra_args)
+        .output()
+        .expect("Failed to execute command")
+}
+
+// 辅助函数：读取目标目录中唯一归档的文件条目
+fn archived_files(dest_dir: &Path) -> Vec<String> {
+    let archives: Vec<PathBuf> = fs::read_dir(dest_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    assert_eq!(archives.len(), 1);
+    let archive = zip::ZipArchive::new(fs::File::open(&archives[0]).unwrap()).unwrap();
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/') && *name != "MANIFEST.json")
+        .map(str::to_string)
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn each_source_gets_its_own_top_level_folder() {
+    let (test_root, sources, dest_dir) = setup();
+
+    let output = run_backup(&sources, &dest_dir, &[]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        archived_files(&dest_dir),
+        vec!["source0/Msg/a.dat", "source1/Msg/b.dat"]
+    );
+
+    let cache = fs::read_to_string(dest_dir.join(".cache").join("backupEvents.json")).unwrap();
+    let records = &serde_json::from_str::<serde_json::Value>(&cache).unwrap()["Records"];
+    let expected: Vec<String> = sources.iter().map(|s| s.display().to_string()).collect();
+    assert_eq!(records[0]["SourcePaths"], serde_json::json!(expected));
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn labels_name_the_top_level_folders() {
+    let (test_root, sources, dest_dir) = setup();
+
+    let output = run_backup(
+        &sources,
+        &dest_dir,
+        &["--from-label", "c-drive", "--from-label", "d-drive"],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        archived_files(&dest_dir),
+        vec!["c-drive/Msg/a.dat", "d-drive/Msg/b.dat"]
+    );
+
+    // 标签数量必须与 --from 一致
+    let output = run_backup(&sources, &dest_dir, &["--from-label", "c-drive"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--from-label"));
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn all_missing_sources_are_reported() {
+    let (test_root, mut sources, dest_dir) = setup();
+    let missing = [test_root.join("E"), test_root.join("F")];
+    sources.extend(missing.iter().cloned());
+
+    let output = run_backup(&sources, &dest_dir, &[]);
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for path in &missing {
+        assert!(
+            stderr.contains(&path.display().to_string()),
+            "Missing source not reported: {}",
+            stderr
+        );
+    }
+    assert!(!dest_dir.exists());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn sources_backed_up_separately_keep_their_own_cutoffs() {
+    let (test_root, sources, dest_dir) = setup();
+    // 辅助函数：按创建顺序列出各归档中的数据文件
+    let archive_entries = || {
+        let mut archives: Vec<PathBuf> = fs::read_dir(&dest_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file())
+            .collect();
+        archives.sort();
+        archives
+            .iter()
+            .map(|path| {
+                let archive = zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+                archive
+                    .file_names()
+                    .filter(|name| name.ends_with(".dat"))
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    // 归档文件名精确到秒
+    let pause = || std::thread::sleep(std::time::Duration::from_millis(1100));
+
+    let output = run_backup(&sources[..1], &dest_dir, &["--overlap-seconds", "0"]);
+    assert!(output.status.success());
+    pause();
+    // b.dat 早于第一个源的截止时间，但从未被归档
+    let output = run_backup(&sources[1..], &dest_dir, &["--overlap-seconds", "0"]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(archive_entries(), [["Msg/a.dat"], ["Msg/b.dat"]]);
+
+    // 每个源只归档自己的新文件
+    pause();
+    fs::write(sources[0].join("Msg").join("c.dat"), "c").unwrap();
+    let output = run_backup(&sources[..1], &dest_dir, &["--overlap-seconds", "0"]);
+    assert!(output.status.success());
+    assert_eq!(archive_entries().len(), 3);
+    assert_eq!(archive_entries()[2], ["Msg/c.dat"]);
+    let output = run_backup(&sources[1..], &dest_dir, &["--overlap-seconds", "0"]);
+    assert!(output.status.success());
+    assert_eq!(archive_entries().len(), 3);
+
+    let cache = fs::read_to_string(dest_dir.join(".cache").join("backupEvents.json")).unwrap();
+    let records = &serde_json::from_str::<serde_json::Value>(&cache).unwrap()["Records"];
+    let canonical = fs::canonicalize(&sources[1]).unwrap();
+    assert_eq!(records[1]["SourceId"], canonical.display().to_string());
+
+    // --source-label 取代路径作为标识，新的标识没有截止时间
+    pause();
+    let output = run_backup(
+        &sources[1..],
+        &dest_dir,
+        &["--overlap-seconds", "0", "--source-label", "d-drive"],
+    );
+    assert!(output.status.success());
+    assert_eq!(archive_entries().len(), 4);
+    assert_eq!(archive_entries()[3], ["Msg/b.dat"]);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn sources_sharing_a_destination_keep_separate_archives() {
+    let (test_root, sources, dest_dir) = setup();
+
+    for source in &sources {
+        let output = run_backup(std::slice::from_ref(source), &dest_dir, &[]);
+        assert!(
+            output.status.success(),
+            "Command executed with error: {:?}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    // 第一个源的归档名不变，第二个源的归档名带有源标记
+    let mut names: Vec<String> = fs::read_dir(&dest_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".zip"))
+        .collect();
+    names.sort();
+    assert_eq!(names.len(), 2);
+    assert_eq!(names.iter().filter(|name| name.contains('@')).count(), 1);
+
+    // 按月份去重时每个源各自保留最新的归档
+    let output = run_backup(&sources[..1], &dest_dir, &["--dedupe-months"]);
+    assert!(output.status.success());
+    for name in &names {
+        assert!(dest_dir.join(name).exists(), "{} was removed", name);
+    }
+
+    // 恢复时必须选择一个源，只恢复该源的文件
+    let restore = |to_dir: &Path, extra_args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+            .arg("restore")
+            .arg("--to")
+            .arg(&dest_dir)
+            .arg("--all")
+            .arg("--to-dir")
+            .arg(to_dir)
+            .args(extra_args)
+            .output()
+            .unwrap()
+    };
+    let mixed = test_root.join("mixed");
+    let output = restore(&mixed, &[]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("--source"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for (source, (name, other)) in sources.iter().zip([("a.dat", "b.dat"), ("b.dat", "a.dat")]) {
+        let to_dir = test_root.join(format!("restored-{}", name));
+        let output = restore(&to_dir, &["--source", source.to_str().unwrap()]);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(to_dir.join("Msg").join(name).exists());
+        assert!(!to_dir.join("Msg").join(other).exists());
+    }
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
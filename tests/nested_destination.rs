@@ -0,0 +1,102 @@
+//! 目标目录位于源目录内部时的行为测试。
+
+mod common;
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+// 辅助函数：创建目标目录位于源目录内部的测试结构，目标目录中已有旧归档和缓存
+fn setup() -> (PathBuf, PathBuf, PathBuf) {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-nested-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("WeChat Files");
+    let dest_dir = source_dir.join("backups");
+    fs::create_dir_all(source_dir.join("Msg")).unwrap();
+    fs::create_dir_all(dest_dir.join(".cache")).unwrap();
+
+    fs::write(source_dir.join("Msg").join("new.dat"), "new content").unwrap();
+    let previous =
+        (chrono::Local::now() - chrono::Duration::hours(1)).format("%Y-%m_backup_%Y%m%d%H%M%S.zip");
+    fs::write(dest_dir.join(previous.to_string()), "previous archive").unwrap();
+    fs::write(dest_dir.join(".cache").join("backupEvents.json"), "[]").unwrap();
+    (test_root, source_dir, dest_dir)
+}
+
+// 辅助函数：以当月模式运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(
+        source_dir,
+        dest_dir,
+        &[&["-n", "-s", "--keep-months", "0"], extra_args].concat(),
+    )
+}
+
+#[test]
+fn nested_destination_is_refused_by_default() {
+    let (test_root, source_dir, dest_dir) = setup();
+
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--allow-nested-destination"));
+    // 没有创建新的归档
+    assert_eq!(fs::read_dir(&dest_dir).unwrap().count(), 2);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn nested_destination_is_excluded_when_allowed() {
+    let (test_root, source_dir, dest_dir) = setup();
+    let existing: Vec<PathBuf> = fs::read_dir(&dest_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+
+    let output = run_backup(&source_dir, &dest_dir, &["--allow-nested-destination"]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let new_archive = fs::read_dir(&dest_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.is_file() && !existing.contains(path))
+        .expect("No new archive was created");
+    let archive = zip::ZipArchive::new(File::open(&new_archive).unwrap()).unwrap();
+    let names: Vec<&str> = archive.file_names().collect();
+    assert!(names.contains(&"Msg/new.dat"));
+    assert!(
+        names.iter().all(|name| !name.starts_with("backups")),
+        "Destination content was archived: {:?}",
+        names
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn source_as_destination_is_refused_even_when_nesting_is_allowed() {
+    let (test_root, source_dir, _) = setup();
+
+    for extra_args in [&[][..], &["--allow-nested-destination"][..]] {
+        let output = run_backup(&source_dir, &source_dir, extra_args);
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("is the source path itself"),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    // 源目录中没有新的归档
+    assert!(fs::read_dir(&source_dir).unwrap().all(|entry| {
+        !entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .contains("_backup_")
+    }));
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
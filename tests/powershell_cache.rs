@@ -0,0 +1,55 @@
+//! 原 PowerShell 脚本写入的 backupEvents.json 的兼容性测试。
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以当月模式运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(source_dir, dest_dir, &[&["-n", "-s"], extra_args].concat())
+}
+
+#[test]
+fn powershell_cache_is_kept_and_rewritten() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-powershell-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    let cache_dir = dest_dir.join(".cache");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&cache_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+    fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/powershell/backupEvents-ps51.json"),
+        cache_dir.join("backupEvents.json"),
+    )
+    .unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("corrupt"));
+    let leftovers: Vec<_> = fs::read_dir(&cache_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.contains("corrupt"))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+
+    // 旧记录以当前的格式保留，新记录追加在后面
+    let cache = fs::read_to_string(cache_dir.join("backupEvents.json")).unwrap();
+    let cache: serde_json::Value = serde_json::from_str(&cache).unwrap();
+    let records = cache["Records"].as_array().unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0]["StartTime"], "2024-05-01T00:13:04Z");
+    assert_eq!(records[1]["BackupInfo"], "Backup for 2024-05 2024-06");
+    assert!(records[2]["ArchiveFiles"].is_array());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,92 @@
+//! --rebuild-cache 的测试：.cache 丢失后从目标目录中的归档恢复截止时间。
+
+mod common;
+
+use common::archives;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以当月模式运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(
+        source_dir,
+        dest_dir,
+        &[&["-n", "-s", "--overlap-seconds", "0"], extra_args].concat(),
+    )
+}
+
+// 辅助函数：列出归档中的数据文件
+fn entries(archive: &Path) -> Vec<String> {
+    let mut zip = zip::ZipArchive::new(fs::File::open(archive).unwrap()).unwrap();
+    (0..zip.len())
+        .map(|i| zip.by_index(i).unwrap().name().to_string())
+        .filter(|name| name.ends_with(".dat"))
+        .collect()
+}
+
+#[test]
+fn lost_cache_is_rebuilt_from_archives() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-rebuild-cache-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert!(output.status.success());
+    fs::remove_dir_all(dest_dir.join(".cache")).unwrap();
+
+    // 没有缓存记录时提示使用 --rebuild-cache；--dry-run 不写入缓存
+    let output = run_backup(&source_dir, &dest_dir, &["--dry-run"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--rebuild-cache"));
+
+    // 归档文件名精确到秒：把第一个归档改名为当月第一天创建，避免与下一个归档重名；
+    // 截止时间来自归档元数据中的扫描开始时间，与文件名无关
+    let first = archives(&dest_dir).remove(0);
+    let name = first.file_name().unwrap().to_str().unwrap();
+    let month = &name[..7];
+    let renamed = format!("{}_backup_{}01000000.zip", month, month.replace('-', ""));
+    fs::rename(&first, dest_dir.join(&renamed)).unwrap();
+
+    // b.dat 在扫描期间（扫描开始之后、归档创建之前）被修改
+    let zip = zip::ZipArchive::new(fs::File::open(dest_dir.join(&renamed)).unwrap()).unwrap();
+    let info: serde_json::Value = serde_json::from_slice(zip.comment()).unwrap();
+    let time = |key: &str| {
+        chrono::DateTime::parse_from_rfc3339(info[key].as_str().unwrap())
+            .unwrap()
+            .to_utc()
+    };
+    let modified = time("NextCutoff") + chrono::Duration::microseconds(1);
+    assert!(modified < time("CreatedAt"), "{}", info);
+    fs::write(source_dir.join("b.dat"), "b").unwrap();
+    filetime::set_file_mtime(
+        source_dir.join("b.dat"),
+        filetime::FileTime::from_system_time(modified.into()),
+    )
+    .unwrap();
+    let output = run_backup(&source_dir, &dest_dir, &["--rebuild-cache"]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.stderr.is_empty(), "{:?}", output.stderr);
+
+    // 截止时间来自第一个归档的元数据，只有扫描开始后修改的文件被归档
+    let archives = archives(&dest_dir);
+    assert_eq!(archives.len(), 2);
+    assert_eq!(entries(&archives[1]), ["b.dat"]);
+
+    let cache = fs::read_to_string(dest_dir.join(".cache").join("backupEvents.json")).unwrap();
+    let cache: serde_json::Value = serde_json::from_str(&cache).unwrap();
+    let records = cache["Records"].as_array().unwrap();
+    assert_eq!(records.len(), 2);
+    let first_archive = archives[0].file_name().unwrap().to_str().unwrap();
+    assert_eq!(records[0]["ArchiveFiles"][0], first_archive);
+    assert_eq!(records[0]["FilesArchived"], 1);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
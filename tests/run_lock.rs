@@ -0,0 +1,43 @@
+//! 同一目标目录同时只能运行一个备份的测试。
+
+mod common;
+
+use fs2::FileExt;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以当月模式运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(source_dir, dest_dir, &[&["-n", "-s"], extra_args].concat())
+}
+
+#[test]
+fn concurrent_run_exits_with_a_distinct_code() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-lock-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(dest_dir.join(".cache")).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+
+    // 模拟另一个正在运行的实例持有锁
+    let lock = fs::File::create(dest_dir.join(".cache").join("lock")).unwrap();
+    lock.lock_exclusive().unwrap();
+    let output = run_backup(&source_dir, &dest_dir, &["--lock-wait-seconds", "1"]);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("another backup is running"), "{}", stderr);
+    assert!(!dest_dir.join(".cache").join("backupEvents.json").exists());
+
+    // 持有锁的进程结束后（包括崩溃）锁即被释放
+    drop(lock);
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,66 @@
+//! --max-files/--max-total-size 限制扫描结果规模的测试。
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以当月模式运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(source_dir, dest_dir, &[&["-n", "-s"], extra_args].concat())
+}
+
+#[test]
+fn oversized_scan_aborts_unless_forced() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-limits-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    for (name, size) in [
+        ("a.bin", 100),
+        ("b.bin", 400),
+        ("c.bin", 300),
+        ("d.bin", 200),
+    ] {
+        fs::write(source_dir.join(name), vec![0u8; size]).unwrap();
+    }
+
+    let output = run_backup(&source_dir, &dest_dir, &["--max-files", "3"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("found 4 files"), "{}", stderr);
+    assert!(stderr.contains("--max-files 3"), "{}", stderr);
+    // 只列出最大的三个文件
+    for name in ["b.bin", "c.bin", "d.bin"] {
+        assert!(stderr.contains(name), "{}", stderr);
+    }
+    assert!(!stderr.contains("a.bin"), "{}", stderr);
+    let archives = fs::read_dir(&dest_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().is_file())
+        .count();
+    assert_eq!(archives, 0);
+
+    let output = run_backup(&source_dir, &dest_dir, &["--max-total-size", "500"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--max-total-size"));
+
+    let output = run_backup(
+        &source_dir,
+        &dest_dir,
+        &["--max-files", "3", "--max-total-size", "500", "--force"],
+    );
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let archives = fs::read_dir(&dest_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().is_file())
+        .count();
+    assert_eq!(archives, 1);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
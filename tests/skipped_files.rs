@@ -0,0 +1,236 @@
+//! 被跳过文件的测试：记录在缓存中，下次运行不论修改时间都会重新尝试。
+
+mod common;
+
+use chrono::{Duration, Local, Utc};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// 辅助函数：以当月模式运行备份；不留重叠窗口，刚归档的文件不会被再次归档
+fn run_backup(source_dir: &Path, dest_dir: &Path) -> Output {
+    common::run_backup(
+        source_dir,
+        dest_dir,
+        &["-n", "-s", "--overlap-seconds", "0"],
+    )
+}
+
+// 辅助函数：运行 status 子命令并解析 JSON 输出
+fn status(dest_dir: &Path) -> serde_json::Value {
+    let output = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .args(["status", "--json", "--to"])
+        .arg(dest_dir)
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+// 辅助函数：按文件名排序列出目标目录中各归档的数据文件
+fn archive_entries(dest_dir: &Path) -> Vec<Vec<String>> {
+    let mut archives: Vec<PathBuf> = fs::read_dir(dest_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    archives.sort();
+    archives
+        .iter()
+        .map(|path| {
+            let archive = zip::ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+            archive
+                .file_names()
+                .filter(|name| name.ends_with(".dat"))
+                .map(str::to_string)
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn previously_skipped_file_is_retried_despite_the_cutoff() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-skipped-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(source_dir.join("Msg")).unwrap();
+    fs::create_dir_all(dest_dir.join(".cache")).unwrap();
+    let modified = std::time::SystemTime::from(Utc::now() - Duration::seconds(60));
+    for name in ["locked.dat", "archived.dat"] {
+        let path = source_dir.join("Msg").join(name);
+        fs::write(&path, name).unwrap();
+        filetime::set_file_mtime(&path, filetime::FileTime::from_system_time(modified)).unwrap();
+    }
+
+    // 上次运行：该月成功，截止时间晚于两个文件的修改时间，但 locked.dat 被占用而跳过
+    let month = Local::now().format("%Y-%m").to_string();
+    let cutoff = Utc::now() - Duration::seconds(30);
+    let record = serde_json::json!([{
+        "StartTime": cutoff,
+        "EndTime": cutoff,
+        "BackupInfo": "Partial backup",
+        "CutoffTime": cutoff,
+        "Months": [{ "Month": month, "Cutoff": cutoff, "Status": "Succeeded" }],
+        "Status": "Partial",
+        "SourceId": fs::canonicalize(&source_dir).unwrap().display().to_string(),
+        "SkippedFiles": [{
+            "Month": month,
+            "Path": "Msg/locked.dat",
+            "Reason": "The process cannot access the file because it is being used by another process.",
+        }],
+    }]);
+    fs::write(
+        dest_dir.join(".cache").join("backupEvents.json"),
+        serde_json::to_string_pretty(&record).unwrap(),
+    )
+    .unwrap();
+    let outstanding = &status(&dest_dir)["OutstandingSkippedFiles"];
+    assert_eq!(outstanding.as_array().unwrap().len(), 1);
+    assert_eq!(outstanding[0]["Path"], "Msg/locked.dat");
+
+    // 文件已解除占用：只有它被归档，之后没有尚未归档的文件
+    let output = run_backup(&source_dir, &dest_dir);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(archive_entries(&dest_dir), [["Msg/locked.dat"]]);
+    assert!(
+        status(&dest_dir)["OutstandingSkippedFiles"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_file_is_archived_once_it_becomes_readable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-skipped-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    let locked = source_dir.join("locked.dat");
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+    fs::write(&locked, "locked").unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    // root 用户不受文件权限限制，无法模拟读取失败
+    if fs::File::open(&locked).is_ok() {
+        fs::remove_dir_all(&test_root).unwrap();
+        return;
+    }
+
+    let output = run_backup(&source_dir, &dest_dir);
+    assert!(output.status.success());
+    assert_eq!(archive_entries(&dest_dir), [["a.dat"]]);
+    let outstanding = &status(&dest_dir)["OutstandingSkippedFiles"];
+    assert_eq!(outstanding[0]["Path"], "locked.dat");
+
+    // 归档文件名精确到秒
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o644)).unwrap();
+    let output = run_backup(&source_dir, &dest_dir);
+    assert!(output.status.success());
+    assert_eq!(archive_entries(&dest_dir)[1], ["locked.dat"]);
+    assert!(
+        status(&dest_dir)["OutstandingSkippedFiles"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn files_of_months_not_backed_up_by_path_are_recorded() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-skipped-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    let old_month = source_dir.join("Image").join("2020-01");
+    fs::create_dir_all(&old_month).unwrap();
+    // 路径中是 2020-01 的图片刚被重新下载，修改时间在当月
+    fs::write(old_month.join("redownloaded.dat"), "image").unwrap();
+    fs::write(source_dir.join("msg0.dat"), "msg").unwrap();
+    let run = |mode_args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+            .arg("--from")
+            .arg(&source_dir)
+            .arg("--to")
+            .arg(&dest_dir)
+            .args(["-s", "--month-source", "path"])
+            .args(mode_args)
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    // 当月的备份不包含它，但截止时间越过了它的修改时间：记为尚未归档
+    let output = run(&["-n"]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(archive_entries(&dest_dir), [["msg0.dat"]]);
+    let outstanding = &status(&dest_dir)["OutstandingSkippedFiles"];
+    assert_eq!(outstanding.as_array().unwrap().len(), 1);
+    assert_eq!(outstanding[0]["Path"], "Image/2020-01/redownloaded.dat");
+    assert_eq!(outstanding[0]["Month"], "2020-01");
+
+    // 备份该月份后不再有尚未归档的文件
+    let output = run(&["--month", "2020-01"]);
+    assert!(output.status.success());
+    assert!(
+        archive_entries(&dest_dir)
+            .iter()
+            .any(|entries| entries == &["Image/2020-01/redownloaded.dat"])
+    );
+    assert!(
+        status(&dest_dir)["OutstandingSkippedFiles"]
+            .as_array()
+            .unwrap()
+            .is_empty()
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn skips_of_a_run_without_archives_are_recorded() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-skipped-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    let old_month = source_dir.join("Image").join("2020-01");
+    fs::create_dir_all(&old_month).unwrap();
+    fs::write(old_month.join("redownloaded.dat"), "image").unwrap();
+
+    // 当月没有需要归档的文件，本次运行不创建归档，但被跳过的文件仍然记录
+    let output = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("--from")
+        .arg(&source_dir)
+        .arg("--to")
+        .arg(&dest_dir)
+        .args(["-n", "-s", "--month-source", "path"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(archive_entries(&dest_dir).is_empty());
+    let outstanding = &status(&dest_dir)["OutstandingSkippedFiles"];
+    assert_eq!(outstanding.as_array().unwrap().len(), 1);
+    assert_eq!(outstanding[0]["Path"], "Image/2020-01/redownloaded.dat");
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,78 @@
+//! 按顶层目录（微信账号）拆分归档的测试。
+
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn one_archive_per_account_is_created() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-split-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("WeChat Files");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(source_dir.join("wxid_abc123").join("Msg")).unwrap();
+    fs::create_dir_all(source_dir.join("wxid_def456")).unwrap();
+    fs::write(
+        source_dir.join("wxid_abc123").join("Msg").join("a.dat"),
+        "a",
+    )
+    .unwrap();
+    fs::write(source_dir.join("wxid_def456").join("b.dat"), "b").unwrap();
+    fs::write(source_dir.join("config.ini"), "c").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("--from")
+        .arg(&source_dir)
+        .arg("--to")
+        .arg(&dest_dir)
+        .args(["-n", "-s", "--split-by-top-dir"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let month = chrono::Local::now().format("%Y-%m").to_string();
+    let mut archives: Vec<(String, Vec<String>)> = fs::read_dir(&dest_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .map(|path| {
+            let archive = zip::ZipArchive::new(fs::File::open(&path).unwrap()).unwrap();
+            let names = archive
+                .file_names()
+                .filter(|name| !name.ends_with('/') && *name != "MANIFEST.json")
+                .map(str::to_string)
+                .collect();
+            (
+                path.file_name().unwrap().to_str().unwrap().to_string(),
+                names,
+            )
+        })
+        .collect();
+    archives.sort();
+
+    assert_eq!(archives.len(), 3);
+    let expected = [
+        ("_root", "config.ini"),
+        ("wxid_abc123", "wxid_abc123/Msg/a.dat"),
+        ("wxid_def456", "wxid_def456/b.dat"),
+    ];
+    for ((file_name, names), (account, entry)) in archives.iter().zip(expected) {
+        assert!(
+            file_name.starts_with(&format!("{}_{}_backup_", month, account)),
+            "Unexpected archive name: {}",
+            file_name
+        );
+        assert_eq!(names, &vec![entry.to_string()]);
+    }
+
+    let cache = fs::read_to_string(dest_dir.join(".cache").join("backupEvents.json")).unwrap();
+    let records = &serde_json::from_str::<serde_json::Value>(&cache).unwrap()["Records"];
+    assert_eq!(
+        records[0]["Accounts"],
+        serde_json::json!(["_root", "wxid_abc123", "wxid_def456"])
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,68 @@
+//! --cache-backend sqlite 的测试：已有的 JSON 缓存被导入，之后的运行使用数据库。
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+// 辅助函数：以当月模式运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(source_dir, dest_dir, &[&["-n", "-s"], extra_args].concat())
+}
+
+// 辅助函数：统计目标目录中的归档数
+fn archive_count(dest_dir: &Path) -> usize {
+    fs::read_dir(dest_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().is_file())
+        .count()
+}
+
+#[test]
+fn json_cache_is_imported_into_sqlite() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-sqlite-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert!(output.status.success());
+    assert_eq!(archive_count(&dest_dir), 1);
+    let json = fs::read(dest_dir.join(".cache").join("backupEvents.json")).unwrap();
+
+    // 导入的截止时间生效：没有新文件，不创建归档；归档文件名精确到秒
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let sqlite = ["--cache-backend", "sqlite", "--overlap-seconds", "0"];
+    let output = run_backup(&source_dir, &dest_dir, &sqlite);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(archive_count(&dest_dir), 1);
+    assert!(dest_dir.join(".cache").join("cache.sqlite3").is_file());
+
+    // 新文件的记录只写入数据库，JSON 文件保持不变
+    fs::write(source_dir.join("b.dat"), "b").unwrap();
+    let output = run_backup(&source_dir, &dest_dir, &sqlite);
+    assert!(output.status.success());
+    assert_eq!(archive_count(&dest_dir), 2);
+    assert_eq!(
+        fs::read(dest_dir.join(".cache").join("backupEvents.json")).unwrap(),
+        json
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .args(["status", "--json", "--cache-backend", "sqlite", "--to"])
+        .arg(&dest_dir)
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    // 导入的记录，以及数据库中没有创建归档和创建了归档的两次运行
+    assert_eq!(status["Records"].as_array().unwrap().len(), 3);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
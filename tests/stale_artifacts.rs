@@ -0,0 +1,80 @@
+//! 清理之前被中断的运行在目标目录中遗留的临时目录和 .partial 文件的测试。
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+use std::time::{Duration, SystemTime};
+
+// 辅助函数：以当月模式运行备份，不按时间删除旧归档
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(
+        source_dir,
+        dest_dir,
+        &[&["-n", "-s", "--keep-months", "0"], extra_args].concat(),
+    )
+}
+
+// 辅助函数：把文件或目录的修改时间设为若干小时前
+fn age_by_hours(path: &Path, hours: u64) {
+    let time = SystemTime::now() - Duration::from_secs(hours * 60 * 60);
+    filetime::set_file_mtime(path, filetime::FileTime::from_system_time(time)).unwrap();
+}
+
+#[test]
+fn stale_temp_dirs_and_partials_are_removed() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-stale-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("WeChat Files");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "data").unwrap();
+
+    let stale_dir = dest_dir.join(uuid::Uuid::new_v4().to_string());
+    fs::create_dir_all(stale_dir.join("Msg")).unwrap();
+    fs::write(stale_dir.join("Msg").join("a.dat"), "data").unwrap();
+    let stale_partial = dest_dir.join("2025-01_backup_20250201000000.zip.partial");
+    fs::write(&stale_partial, "truncated").unwrap();
+    // 修改时间在宽限期内的可能属于另一个正在运行的实例，保留
+    let fresh_dir = dest_dir.join(uuid::Uuid::new_v4().to_string());
+    fs::create_dir_all(&fresh_dir).unwrap();
+    let fresh_partial = dest_dir.join("2025-02_backup_20250301000000.zip.partial");
+    fs::write(&fresh_partial, "writing").unwrap();
+    age_by_hours(&stale_dir, 48);
+    age_by_hours(&stale_partial, 48);
+    age_by_hours(&fresh_dir, 2);
+    age_by_hours(&fresh_partial, 2);
+
+    let output = run_backup(&source_dir, &dest_dir, &[]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!stale_dir.exists());
+    assert!(!stale_partial.exists());
+    assert!(fresh_dir.exists());
+    assert!(fresh_partial.exists());
+
+    // 宽限期极长时什么都不删除
+    let output = run_backup(
+        &source_dir,
+        &dest_dir,
+        &["--stale-grace-hours", &u64::MAX.to_string()],
+    );
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(fresh_dir.exists());
+    assert!(fresh_partial.exists());
+
+    // 缩短宽限期后较新的也会被删除
+    let output = run_backup(&source_dir, &dest_dir, &["--stale-grace-hours", "1"]);
+    assert!(output.status.success());
+    assert!(!fresh_dir.exists());
+    assert!(!fresh_partial.exists());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,134 @@
+//! status 子命令打印缓存中备份历史的测试。
+
+use chrono::{Duration, Utc};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+// 辅助函数：运行 status 子命令
+fn run_status(dest_dir: &Path, extra_args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("status")
+        .arg("--to")
+        .arg(dest_dir)
+        .args(extra_args)
+        .output()
+        .expect("Failed to execute command")
+}
+
+// 辅助函数：写入缓存记录，`days_ago` 为各次运行距今的天数
+fn write_records(dest_dir: &Path, records: &[(i64, &str, &str)]) {
+    let records: Vec<serde_json::Value> = records
+        .iter()
+        .map(|&(days_ago, status, archive)| {
+            let start = Utc::now() - Duration::days(days_ago);
+            serde_json::json!({
+                "StartTime": start,
+                "EndTime": start + Duration::seconds(75),
+                "BackupInfo": "",
+                "ArchiveFiles": [archive],
+                "Status": status,
+                "FilesArchived": 3,
+            })
+        })
+        .collect();
+    fs::create_dir_all(dest_dir.join(".cache")).unwrap();
+    fs::write(
+        dest_dir.join(".cache").join("backupEvents.json"),
+        serde_json::to_string(&records).unwrap(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn history_is_printed_newest_first() {
+    let dest_dir = std::env::temp_dir().join(format!("dat-patch-status-{}", uuid::Uuid::new_v4()));
+    write_records(
+        &dest_dir,
+        &[
+            (3, "Success", "oldest.zip"),
+            (1, "Failed", "newest.zip"),
+            (2, "Partial", "middle.zip"),
+        ],
+    );
+
+    let output = run_status(&dest_dir, &[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rows: Vec<&str> = stdout.lines().skip(1).take(3).collect();
+    assert!(
+        rows[0].contains("Failed") && rows[0].ends_with("newest.zip"),
+        "{}",
+        stdout
+    );
+    assert!(
+        rows[1].contains("Partial") && rows[1].ends_with("middle.zip"),
+        "{}",
+        stdout
+    );
+    assert!(rows[2].contains("0:01:15"), "{}", stdout);
+    assert!(stdout.contains("Last successful backup:"), "{}", stdout);
+    // 最近一次成功的备份是 3 天前，默认阈值为 7 天
+    assert!(output.stderr.is_empty());
+
+    let output = run_status(
+        &dest_dir,
+        &["--last", "1", "--stale-after-days", "2", "--json"],
+    );
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["Records"].as_array().unwrap().len(), 1);
+    assert_eq!(json["Records"][0]["ArchiveFiles"][0], "newest.zip");
+    assert_eq!(json["Stale"], true);
+    assert!(json["LastSuccessfulBackup"].is_string());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("last 2 days"));
+
+    // 阈值极大时不会溢出
+    let output = run_status(
+        &dest_dir,
+        &["--stale-after-days", &u64::MAX.to_string(), "--json"],
+    );
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["Stale"], false);
+
+    fs::remove_dir_all(&dest_dir).unwrap();
+}
+
+#[test]
+fn backup_flags_still_work_without_a_subcommand() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-status-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("--from")
+        .arg(&source_dir)
+        .arg("--to")
+        .arg(&dest_dir)
+        .args(["-n", "-s"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    // 没有 --to 时仍然报错
+    let output = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("--from")
+        .arg(&source_dir)
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = run_status(&dest_dir, &[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.lines().nth(1).unwrap().contains("Success"),
+        "{}",
+        stdout
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,354 @@
+//! 子命令的测试：backup、clean、verify、restore 和 list，以及不带子命令的旧用法。
+
+mod common;
+
+use common::archives;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::SystemTime;
+
+// 辅助函数：以给定的参数运行
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .args(args)
+        .output()
+        .expect("Failed to execute command")
+}
+
+// 辅助函数：创建源目录并备份当月
+fn back_up(test_root: &Path) -> (PathBuf, PathBuf) {
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(source_dir.join("Msg")).unwrap();
+    fs::write(source_dir.join("Msg").join("MicroMsg.db"), "messages").unwrap();
+    let output = run(&[
+        "backup",
+        "--from",
+        source_dir.to_str().unwrap(),
+        "--to",
+        dest_dir.to_str().unwrap(),
+        "-n",
+        "-s",
+    ]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    (source_dir, dest_dir)
+}
+
+// 辅助函数：目录中每个文件的相对路径、内容和修改时间
+fn tree(dir: &Path) -> BTreeMap<PathBuf, (String, SystemTime)> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                files.insert(
+                    path.strip_prefix(dir).unwrap().to_path_buf(),
+                    (
+                        fs::read_to_string(&path).unwrap(),
+                        fs::metadata(&path).unwrap().modified().unwrap(),
+                    ),
+                );
+            }
+        }
+    }
+    files
+}
+
+#[test]
+fn backup_runs_with_and_without_the_subcommand() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-commands-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("MicroMsg.db"), "messages").unwrap();
+
+    for (dest_name, command) in [("old-style", None), ("subcommand", Some("backup"))] {
+        let dest_dir = test_root.join(dest_name);
+        let mut args: Vec<&str> = command.into_iter().collect();
+        args.extend([
+            "--from",
+            source_dir.to_str().unwrap(),
+            "--to",
+            dest_dir.to_str().unwrap(),
+            "-n",
+        ]);
+        let output = run(&args);
+        assert!(
+            output.status.success(),
+            "{:?}: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(
+            String::from_utf8_lossy(&output.stdout).contains("Successfully created archive"),
+            "{:?}",
+            command
+        );
+        assert_eq!(archives(&dest_dir).len(), 1, "{:?}", command);
+    }
+
+    // 备份选项不能与其他子命令混用
+    let output = run(&[
+        "--from",
+        source_dir.to_str().unwrap(),
+        "status",
+        "--to",
+        test_root.join("old-style").to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn clean_only_removes_old_backups() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-commands-{}", uuid::Uuid::new_v4()));
+    let (_, dest_dir) = back_up(&test_root);
+    let current = archives(&dest_dir);
+    for name in [
+        "2020-01_backup_20200201000000.zip",
+        "2020-02_backup_20200301000000.zip",
+    ] {
+        fs::write(dest_dir.join(name), "old").unwrap();
+    }
+    let clean = |extra_args: &[&str]| {
+        let mut args = vec!["clean", "--to", dest_dir.to_str().unwrap()];
+        args.extend(extra_args);
+        run(&args)
+    };
+
+    // 试运行只列出将要删除的归档
+    let output = clean(&["--dry-run"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Would remove old backup: 2020-01_backup_20200201000000.zip"),
+        "{}",
+        stdout
+    );
+    assert_eq!(archives(&dest_dir).len(), 3);
+
+    let output = clean(&["--keep-months", "6", "--yes"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Removed 2 old backups"),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    assert_eq!(archives(&dest_dir), current);
+
+    // 目标目录不存在时无法清理
+    let output = run(&["clean", "--to", test_root.join("missing").to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(8));
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn verify_reports_damaged_archives() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-commands-{}", uuid::Uuid::new_v4()));
+    let (_, dest_dir) = back_up(&test_root);
+    let verify = || run(&["verify", "--to", dest_dir.to_str().unwrap()]);
+
+    let output = verify();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("OK      "), "{}", stdout);
+    assert!(stdout.contains("Verified 1 archives"), "{}", stdout);
+
+    let damaged = "2021-01_backup_20210201000000.zip";
+    fs::write(dest_dir.join(damaged), "not a zip archive").unwrap();
+    let output = verify();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("FAILED  {}", damaged)),
+        "{}",
+        stdout
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(damaged), "{}", stderr);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn list_groups_parts_and_marks_superseded_archives() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-commands-{}", uuid::Uuid::new_v4()));
+    let (_, dest_dir) = back_up(&test_root);
+    let current = archives(&dest_dir)[0].clone();
+    // 同一月份的旧分卷归档被当前的归档取代
+    fs::copy(
+        &current,
+        dest_dir.join("2000-01_backup_20000201000000.part1.zip"),
+    )
+    .unwrap();
+    fs::copy(
+        &current,
+        dest_dir.join("2000-01_backup_20000201000000.part2.zip"),
+    )
+    .unwrap();
+    fs::write(
+        dest_dir.join("2000-01_backup_20000202000000.zip.age"),
+        "age",
+    )
+    .unwrap();
+    let list = |extra_args: &[&str]| {
+        let mut args = vec!["list", "--to", dest_dir.to_str().unwrap()];
+        args.extend(extra_args);
+        run(&args)
+    };
+
+    let output = list(&[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("2000-01_backup_20000201000000.part1.zip (+1 parts)"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("3 archives"), "{}", stdout);
+
+    let output = list(&["--json", "--month", "2000-01"]);
+    assert!(output.status.success());
+    let listings: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let listings = listings.as_array().unwrap();
+    assert_eq!(listings.len(), 2);
+    let part_size = fs::metadata(&current).unwrap().len();
+    assert_eq!(listings[0]["Size"], 2 * part_size);
+    let part_entries = zip::ZipArchive::new(fs::File::open(&current).unwrap())
+        .unwrap()
+        .len();
+    assert_eq!(listings[0]["Entries"], 2 * part_entries);
+    assert_eq!(listings[0]["Superseded"], true);
+    assert_eq!(listings[0]["Created"], "2000-02-01T00:00:00");
+    assert_eq!(listings[1]["Encrypted"], true);
+    assert_eq!(listings[1]["Entries"], serde_json::Value::Null);
+    assert_eq!(listings[1]["Superseded"], false);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn restore_extracts_an_archive() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-commands-{}", uuid::Uuid::new_v4()));
+    let (_, dest_dir) = back_up(&test_root);
+    let restore_dir = test_root.join("restored");
+    let archive = &archives(&dest_dir)[0];
+
+    let output = run(&[
+        "restore",
+        "--archive",
+        archive.to_str().unwrap(),
+        "--to-dir",
+        restore_dir.to_str().unwrap(),
+    ]);
+    assert!(
+        output.status.success(),
+        "Restore failed: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(restore_dir.join("Msg").join("MicroMsg.db")).unwrap(),
+        "messages"
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn restore_months_recreates_the_deleted_source() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-commands-{}", uuid::Uuid::new_v4()));
+    let (source_dir, dest_dir) = back_up(&test_root);
+    fs::create_dir_all(source_dir.join("FileStorage").join("Image")).unwrap();
+    fs::write(
+        source_dir
+            .join("FileStorage")
+            .join("Image")
+            .join("photo.dat"),
+        "photo",
+    )
+    .unwrap();
+    // 第二个归档包含同一文件的新版本；归档名精确到秒
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    fs::write(source_dir.join("Msg").join("MicroMsg.db"), "more messages").unwrap();
+    let output = run(&[
+        "--from",
+        source_dir.to_str().unwrap(),
+        "--to",
+        dest_dir.to_str().unwrap(),
+        "-n",
+        "-s",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(archives(&dest_dir).len(), 2);
+    let expected = tree(&source_dir);
+    fs::remove_dir_all(&source_dir).unwrap();
+
+    let restore_dir = test_root.join("restored");
+    let month = chrono::Local::now().format("%Y-%m").to_string();
+    let restore = |extra_args: &[&str]| {
+        let mut args = vec![
+            "restore",
+            "--to",
+            dest_dir.to_str().unwrap(),
+            "--month",
+            &month,
+            "--to-dir",
+            restore_dir.to_str().unwrap(),
+        ];
+        args.extend(extra_args);
+        run(&args)
+    };
+    let output = restore(&[]);
+    assert!(
+        output.status.success(),
+        "Restore failed: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Restored 2 files from 2 archives"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("1 files were replaced by newer versions"),
+        "{}",
+        stdout
+    );
+    assert_eq!(tree(&restore_dir), expected);
+
+    // 目标目录不为空时需要 --force
+    fs::write(restore_dir.join("Msg").join("MicroMsg.db"), "edited").unwrap();
+    let output = restore(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("--force"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = restore(&["--force", "--file", "Msg/MicroMsg.db"]);
+    assert!(output.status.success());
+    assert_eq!(tree(&restore_dir), expected);
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
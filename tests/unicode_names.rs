@@ -0,0 +1,96 @@
+//! 中文等非 ASCII 文件名经过归档和恢复后保持不变的测试。
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn run(args: &[&std::ffi::OsStr]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .args(args)
+        .output()
+        .expect("Failed to execute command")
+}
+
+// 辅助函数：在归档的原始字节中找到条目的本地文件头，返回其通用标志位
+fn local_header_flags(archive: &[u8], name: &str) -> u16 {
+    let name = name.as_bytes();
+    let offset = archive
+        .windows(name.len())
+        .enumerate()
+        .find_map(|(i, window)| {
+            let start = i.checked_sub(30)?;
+            (window == name && archive[start..start + 4] == [0x50, 0x4b, 0x03, 0x04])
+                .then_some(start)
+        })
+        .expect("local header not found");
+    u16::from_le_bytes([archive[offset + 6], archive[offset + 7]])
+}
+
+#[test]
+fn unicode_file_names_survive_archive_and_restore() {
+    let test_root =
+        std::env::temp_dir().join(format!("dat-patch-unicode-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("WeChat Files");
+    let dest_dir = test_root.join("backups");
+    let restore_dir = test_root.join("restored");
+    let files = [
+        Path::new("wxid_a").join("聊天记录（重要）.dat"),
+        Path::new("wxid_a").join("照片").join("照片 2025.JPG"),
+    ];
+    for file in &files {
+        let path = source_dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, file.to_str().unwrap()).unwrap();
+    }
+
+    let output = run(&[
+        "--from".as_ref(),
+        source_dir.as_os_str(),
+        "--to".as_ref(),
+        dest_dir.as_os_str(),
+        "-n".as_ref(),
+        "-s".as_ref(),
+        // 扩展名过滤不区分大小写
+        "--ext".as_ref(),
+        "dat,jpg".as_ref(),
+    ]);
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let archives: Vec<PathBuf> = fs::read_dir(&dest_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    assert_eq!(archives.len(), 1);
+
+    let bytes = fs::read(&archives[0]).unwrap();
+    let archive = zip::ZipArchive::new(std::io::Cursor::new(&bytes)).unwrap();
+    for name in ["wxid_a/聊天记录（重要）.dat", "wxid_a/照片/照片 2025.JPG"] {
+        assert!(archive.file_names().any(|n| n == name), "{} missing", name);
+        assert_ne!(local_header_flags(&bytes, name) & (1 << 11), 0, "{}", name);
+    }
+
+    let output = run(&[
+        "--restore".as_ref(),
+        archives[0].as_os_str(),
+        "--restore-to".as_ref(),
+        restore_dir.as_os_str(),
+        "-s".as_ref(),
+    ]);
+    assert!(
+        output.status.success(),
+        "Restore failed: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for file in &files {
+        assert_eq!(
+            fs::read_to_string(restore_dir.join(file)).unwrap(),
+            file.to_str().unwrap()
+        );
+    }
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
@@ -0,0 +1,93 @@
+//! verify 子命令的测试：校验目标目录中的归档，与清单核对 SHA-256，并可写入校验记录。
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+// 辅助函数：运行 verify 子命令
+fn run_verify(dest_dir: &Path, extra_args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("verify")
+        .arg("--to")
+        .arg(dest_dir)
+        .args(extra_args)
+        .output()
+        .expect("Failed to execute command")
+}
+
+#[test]
+fn archives_are_checked_against_their_manifests() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-verify-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("MicroMsg.db"), "messages").unwrap();
+    fs::write(source_dir.join("Media.db"), "media").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_dat-patch-rust"))
+        .arg("--from")
+        .arg(&source_dir)
+        .arg("--to")
+        .arg(&dest_dir)
+        .args(["-n", "-s", "--archive-format", "tar-gz"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let output = run_verify(&dest_dir, &[]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(".tar.gz (3 entries, 2 digests)"),
+        "{}",
+        stdout
+    );
+
+    let output = run_verify(&dest_dir, &["--quick"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(".tar.gz (3 entries)"), "{}", stdout);
+
+    // 损坏的归档被列出；--month 只校验指定的月份
+    let damaged = "2021-01_backup_20210201000000.zip";
+    fs::write(dest_dir.join(damaged), "not a zip archive").unwrap();
+    let month = chrono::Local::now().format("%Y-%m").to_string();
+    let output = run_verify(&dest_dir, &["--month", &month]);
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("Verified 1 archives"),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    let output = run_verify(&dest_dir, &["--record"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 archives are damaged or unreadable"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains(&format!("  {}: ", damaged)), "{}", stderr);
+    let records: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(dest_dir.join(".cache").join("verifyEvents.json")).unwrap(),
+    )
+    .unwrap();
+    let record = &records[0];
+    assert_eq!(record["Quick"], false);
+    assert_eq!(record["ArchivesPassed"], 1);
+    assert_eq!(record["Failed"][0]["Name"], damaged);
+
+    // SQLite 后端导入已有的记录并追加新的记录
+    let output = run_verify(
+        &dest_dir,
+        &["--month", &month, "--record", "--cache-backend", "sqlite"],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(dest_dir.join(".cache").join("cache.sqlite3").is_file());
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
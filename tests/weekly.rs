@@ -0,0 +1,66 @@
+//! --weekly 的测试：按 ISO 周创建和清理归档。
+
+mod common;
+
+use chrono::{Duration, Local};
+use common::archive_names;
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+// 辅助函数：以 --weekly 运行备份
+fn run_backup(source_dir: &Path, dest_dir: &Path, extra_args: &[&str]) -> Output {
+    common::run_backup(
+        source_dir,
+        dest_dir,
+        &[&["--weekly", "-s"], extra_args].concat(),
+    )
+}
+
+#[test]
+fn current_week_is_archived_and_old_weekly_archives_are_removed() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-weekly-{}", uuid::Uuid::new_v4()));
+    let source_dir = test_root.join("profile");
+    let dest_dir = test_root.join("backups");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::create_dir_all(&dest_dir).unwrap();
+    fs::write(source_dir.join("a.dat"), "a").unwrap();
+    // 一个月前创建的每周归档
+    let old = (Local::now() - Duration::days(30)).format("%Y%m%d%H%M%S");
+    let old_weekly = format!("2020-W10_backup_{}.zip", old);
+    fs::write(dest_dir.join(&old_weekly), "old").unwrap();
+
+    let output = run_backup(
+        &source_dir,
+        &dest_dir,
+        &["-n", "--keep-weeks", "2", "--yes"],
+    );
+    assert!(
+        output.status.success(),
+        "Command executed with error: {:?}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let names = archive_names(&dest_dir);
+    assert_eq!(names.len(), 1, "{:?}", names);
+    let week = Local::now().format("%G-W%V_backup_").to_string();
+    assert!(names[0].starts_with(&week), "{}", names[0]);
+
+    let cache = fs::read_to_string(dest_dir.join(".cache").join("backupEvents.json")).unwrap();
+    let cache: serde_json::Value = serde_json::from_str(&cache).unwrap();
+    assert_eq!(
+        cache["Records"][0]["Months"][0]["Month"],
+        Local::now().format("%G-W%V").to_string()
+    );
+
+    fs::remove_dir_all(&test_root).unwrap();
+}
+
+#[test]
+fn weekly_needs_a_week_mode() {
+    let test_root = std::env::temp_dir().join(format!("dat-patch-weekly-{}", uuid::Uuid::new_v4()));
+    for args in [&[][..], &["--catch-up"], &["-n", "--month-source", "path"]] {
+        let output = run_backup(&test_root, &test_root.join("backups"), args);
+        assert!(!output.status.success(), "{:?}", args);
+    }
+    assert!(!test_root.exists());
+}